@@ -0,0 +1,105 @@
+// Two consoles playing over a simulated network with rollback: each sends
+// its local input to the other, which receives it a few frames late. Both
+// end up in the same state once all input arrived.
+//
+// With GGRS the library keeps the input and decides when to go back
+// instead of Rollback. Its requests map to the core as:
+//   SaveGameState { cell, frame }  psx.snapshot_into, saved in the cell
+//   LoadGameState { cell, .. }     psx.restore with the cell's snapshot
+//   AdvanceFrame { inputs }        psx.set_input for each port, then
+//                                  psx.run_frame
+// with psx.set_deterministic(true) on every peer.
+
+use psx::psx::exe::Exe;
+use psx::psx::rollback::Rollback;
+use psx::psx::sio::{Button, DigitalPad, InputState};
+use psx::psx::Psx;
+
+use std::collections::VecDeque;
+
+// Frames input takes to reach the other peer
+const LATENCY: u64 = 3;
+const FRAMES: u64 = 120;
+
+// Bare-metal program started on both consoles, in place of a game
+fn program() -> Exe {
+    let mut data = vec![0u8; 0x1000];
+    data[..8].copy_from_slice(b"PS-X EXE");
+    for &(offset, val) in [(0x10, 0x80010000), (0x18, 0x80010000), (0x1c, 0x800)].iter() {
+        data[offset..offset + 4].copy_from_slice(&u32::to_le_bytes(val));
+    }
+    Exe::parse(&data).unwrap()
+}
+
+// What player `port` holds on frame `frame`
+fn local_input(port: usize, frame: u64) -> InputState {
+    let mut input = InputState::new();
+    input.set(Button::Cross, (frame / 7 + port as u64).is_multiple_of(2));
+    input.set(Button::Right, frame % 11 < 4);
+    input
+}
+
+struct Peer {
+    psx: Psx,
+    rollback: Rollback,
+    // Port of the local player
+    port: usize,
+    // Input on its way from the other peer, with its frame
+    incoming: VecDeque<(u64, InputState)>,
+}
+
+impl Peer {
+    fn new(port: usize) -> Self {
+        let mut psx = Psx::new();
+        psx.set_deterministic(true);
+        psx.set_controller(0, Some(Box::new(DigitalPad::new())));
+        psx.set_controller(1, Some(Box::new(DigitalPad::new())));
+        psx.boot_exe(&program()).unwrap();
+        Self {
+            psx,
+            rollback: Rollback::new(8),
+            port,
+            incoming: VecDeque::new(),
+        }
+    }
+
+    // Take the remote input that arrived by frame `now`
+    fn receive(&mut self, now: u64) {
+        while let Some(&(frame, input)) = self.incoming.front() {
+            if frame + LATENCY > now {
+                break;
+            }
+            self.incoming.pop_front();
+            self.rollback
+                .set_input(frame, 1 - self.port, input)
+                .unwrap();
+        }
+    }
+}
+
+fn main() {
+    let mut peers = [Peer::new(0), Peer::new(1)];
+    for frame in 0..FRAMES + LATENCY {
+        for i in 0..2 {
+            peers[i].receive(frame);
+            if frame < FRAMES {
+                let input = local_input(peers[i].port, frame);
+                peers[i]
+                    .rollback
+                    .set_input(frame, peers[i].port, input)
+                    .unwrap();
+                peers[1 - i].incoming.push_back((frame, input));
+            }
+        }
+        for peer in peers.iter_mut() {
+            peer.rollback.advance(&mut peer.psx).unwrap();
+        }
+    }
+
+    let states: Vec<_> = peers.iter_mut().map(|peer| peer.psx.snapshot()).collect();
+    assert_eq!(states[0].as_bytes(), states[1].as_bytes());
+    println!(
+        "both peers in the same state after {} frames",
+        peers[0].psx.frame_count()
+    );
+}
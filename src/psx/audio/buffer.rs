@@ -0,0 +1,70 @@
+use std::collections::VecDeque;
+
+// Keep at most half a second of stereo output around
+const DEFAULT_CAPACITY: usize = 44100;
+
+// Queue of interleaved stereo samples produced by the SPU and drained by the
+// frontend. When the frontend doesn't keep up the oldest frames are dropped.
+pub struct SampleBuffer {
+    samples: VecDeque<i16>,
+    // Maximum number of samples (not frames) held
+    capacity: usize,
+}
+
+impl SampleBuffer {
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        // Always hold whole stereo frames
+        let capacity = (capacity.max(2) + 1) & !1;
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    // Push a stereo frame
+    pub fn push(&mut self, left: i16, right: i16) {
+        if self.samples.len() >= self.capacity {
+            self.samples.drain(..2);
+        }
+        self.samples.push_back(left);
+        self.samples.push_back(right);
+    }
+
+    // Maximum number of samples (not frames) held
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Number of samples (not frames) available
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    // Move up to `out.len()` samples into `out`, rounded down to whole frames.
+    // Returns the number of samples written.
+    pub fn take(&mut self, out: &mut [i16]) -> usize {
+        let count = self.samples.len().min(out.len()) & !1;
+        for (dst, src) in out.iter_mut().zip(self.samples.drain(..count)) {
+            *dst = src;
+        }
+        count
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+}
+
+impl Default for SampleBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
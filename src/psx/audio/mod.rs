@@ -0,0 +1,10 @@
+mod buffer;
+mod resampler;
+mod wav;
+
+pub use buffer::SampleBuffer;
+pub use resampler::Resampler;
+pub use wav::{AudioDump, WavWriter};
+
+// Native output rate of the SPU
+pub const SPU_SAMPLE_RATE: u32 = 44100;
@@ -0,0 +1,92 @@
+use super::SPU_SAMPLE_RATE;
+
+// Default maximum deviation from the nominal ratio (0.5%)
+const DEFAULT_MAX_DELTA: f64 = 0.005;
+
+// How quickly the fill level estimate follows the reported value
+const FILL_SMOOTHING: f64 = 0.05;
+
+// Resampler from the SPU rate to the host device rate.
+//
+// The host and guest clocks never match exactly, so a fixed ratio slowly
+// drains or overflows the host buffer. The ratio is nudged based on the
+// reported buffer fill level to keep the buffer hovering around half full.
+pub struct Resampler {
+    // Input frames consumed per output frame
+    step: f64,
+    // Maximum adjustment applied to the step
+    max_delta: f64,
+    // Smoothed host buffer fill level in [0, 1]
+    fill: f64,
+    // Position between the previous and next input frames
+    pos: f64,
+    // Previous input frame (left, right)
+    prev: [i16; 2],
+    // Next input frame (left, right)
+    next: [i16; 2],
+}
+
+impl Resampler {
+    pub fn new(host_rate: u32) -> Self {
+        Self {
+            step: SPU_SAMPLE_RATE as f64 / host_rate as f64,
+            max_delta: DEFAULT_MAX_DELTA,
+            fill: 0.5,
+            pos: 0.0,
+            prev: [0; 2],
+            next: [0; 2],
+        }
+    }
+
+    // Change the host device rate
+    pub fn set_host_rate(&mut self, host_rate: u32) {
+        self.step = SPU_SAMPLE_RATE as f64 / host_rate as f64;
+    }
+
+    // Set the maximum ratio deviation, e.g. 0.005 for 0.5%
+    pub fn set_max_delta(&mut self, max_delta: f64) {
+        self.max_delta = max_delta.max(0.0);
+    }
+
+    // Current ratio of output to input samples, including the adjustment
+    pub fn ratio(&self) -> f64 {
+        1.0 / self.adjusted_step()
+    }
+
+    // Resample interleaved stereo `input` at 44100Hz, appending interleaved
+    // stereo frames at the host rate to `output`. `fill` is the fill level of
+    // the host buffer in [0, 1] at the time of the call.
+    pub fn process(&mut self, input: &[i16], fill: f64, output: &mut Vec<i16>) {
+        let fill = fill.clamp(0.0, 1.0);
+        self.fill += (fill - self.fill) * FILL_SMOOTHING;
+
+        let step = self.adjusted_step();
+        for frame in input.chunks_exact(2) {
+            self.prev = self.next;
+            self.next = [frame[0], frame[1]];
+            while self.pos < 1.0 {
+                output.push(lerp(self.prev[0], self.next[0], self.pos));
+                output.push(lerp(self.prev[1], self.next[1], self.pos));
+                self.pos += step;
+            }
+            self.pos -= 1.0;
+        }
+    }
+
+    // Discard any buffered state
+    pub fn reset(&mut self) {
+        self.fill = 0.5;
+        self.pos = 0.0;
+        self.prev = [0; 2];
+        self.next = [0; 2];
+    }
+
+    // A fuller buffer consumes input faster, producing fewer output frames
+    fn adjusted_step(&self) -> f64 {
+        self.step * (1.0 + self.max_delta * (2.0 * self.fill - 1.0))
+    }
+}
+
+fn lerp(a: i16, b: i16, t: f64) -> i16 {
+    (a as f64 + (b as f64 - a as f64) * t) as i16
+}
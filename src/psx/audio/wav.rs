@@ -0,0 +1,150 @@
+use super::SPU_SAMPLE_RATE;
+use crate::psx::spu::NUM_VOICES;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// Size of the RIFF/WAVE header
+const HEADER_SIZE: u32 = 44;
+
+// Largest data chunk whose RIFF size still fits in 32 bits
+const MAX_DATA_SIZE: u32 = u32::MAX - (HEADER_SIZE - 8);
+
+// 16 bit PCM WAV file writer
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    channels: u16,
+    // Bytes of sample data written so far
+    data_size: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, channels: u16) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), channels)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(out: W, channels: u16) -> io::Result<Self> {
+        let mut wav = Self {
+            out,
+            channels,
+            data_size: 0,
+        };
+        wav.write_header()?;
+        Ok(wav)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let block_align = self.channels * 2;
+        let byte_rate = SPU_SAMPLE_RATE * block_align as u32;
+
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(b"RIFF")?;
+        self.out
+            .write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.out.write_all(b"WAVEfmt ")?;
+        self.out.write_all(&16u32.to_le_bytes())?;
+        // PCM
+        self.out.write_all(&1u16.to_le_bytes())?;
+        self.out.write_all(&self.channels.to_le_bytes())?;
+        self.out.write_all(&SPU_SAMPLE_RATE.to_le_bytes())?;
+        self.out.write_all(&byte_rate.to_le_bytes())?;
+        self.out.write_all(&block_align.to_le_bytes())?;
+        self.out.write_all(&16u16.to_le_bytes())?;
+        self.out.write_all(b"data")?;
+        self.out.write_all(&self.data_size.to_le_bytes())
+    }
+
+    // Write one sample per channel. Fails without writing anything once the
+    // file reaches the 4 GiB limit, so the header stays valid
+    pub fn write_frame(&mut self, samples: &[i16]) -> io::Result<()> {
+        let data_size = self
+            .data_size
+            .checked_add(self.channels as u32 * 2)
+            .filter(|&size| size <= MAX_DATA_SIZE)
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::FileTooLarge, "WAV file size limit reached")
+            })?;
+        for sample in samples.iter().take(self.channels as usize) {
+            self.out.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_size = data_size;
+        Ok(())
+    }
+
+    // Patch the sizes in the header and flush
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_header()?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+// Dump of the mixed output, and optionally every voice, to WAV files
+pub struct AudioDump {
+    mix: WavWriter<BufWriter<File>>,
+    stems: Vec<WavWriter<BufWriter<File>>>,
+    // First error hit while writing; the dump stops at that point
+    error: Option<io::Error>,
+}
+
+impl AudioDump {
+    // Create `path`, plus `<stem>_voiceNN.wav` next to it when `stems` is set
+    pub fn create<P: AsRef<Path>>(path: P, stems: bool) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mix = WavWriter::create(path, 2)?;
+        let mut stem_writers = Vec::new();
+        if stems {
+            for voice in 0..NUM_VOICES {
+                stem_writers.push(WavWriter::create(stem_path(path, voice), 2)?);
+            }
+        }
+        Ok(Self {
+            mix,
+            stems: stem_writers,
+            error: None,
+        })
+    }
+
+    // Are per-voice stems being written?
+    pub fn has_stems(&self) -> bool {
+        !self.stems.is_empty()
+    }
+
+    // Write one output sample and the matching per-voice samples
+    pub fn write(&mut self, mix: [i16; 2], voices: &[[i16; 2]]) {
+        if self.error.is_some() {
+            return;
+        }
+        let mut result = self.mix.write_frame(&mix);
+        for (stem, samples) in self.stems.iter_mut().zip(voices.iter()) {
+            result = result.and_then(|_| stem.write_frame(samples));
+        }
+        if let Err(e) = result {
+            self.error = Some(e);
+        }
+    }
+
+    // Finalize all files, reporting any error hit while dumping
+    pub fn finish(self) -> io::Result<()> {
+        let mut result = self.mix.finish().map(|_| ());
+        for stem in self.stems {
+            result = result.and(stem.finish().map(|_| ()));
+        }
+        match self.error {
+            Some(e) => Err(e),
+            None => result,
+        }
+    }
+}
+
+fn stem_path(path: &Path, voice: usize) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!("{}_voice{:02}.wav", stem, voice))
+}
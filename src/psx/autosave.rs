@@ -0,0 +1,130 @@
+use super::slots::{self, SlotInfo};
+use super::{Error, Psx};
+
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+// Save states made on their own every so often and when the frontend
+// exits, to get back to after a crash. Each game keeps its `keep` latest
+// ones, numbered in the order they were made: SLUS-01234.auto.0042.state.
+// They're written to a temporary file renamed once complete, so a crash
+// while saving leaves the earlier ones intact.
+pub struct AutoSave {
+    dir: PathBuf,
+    // Frames between saves
+    interval: u64,
+    keep: usize,
+    // Frames run since the last save
+    elapsed: u64,
+    // Why the last periodic save failed, until the frontend takes it
+    error: Option<Error>,
+}
+
+impl AutoSave {
+    // Save every `interval` frames of emulation, keeping the `keep` latest
+    pub fn new<P: AsRef<Path>>(dir: P, interval: u64, keep: usize) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            interval: interval.max(1),
+            keep: keep.max(1),
+            elapsed: 0,
+            error: None,
+        }
+    }
+
+    // Auto-saves of a game, latest first. The slot number is the save's
+    // sequence number.
+    pub fn list(&self, serial: &str) -> Result<Vec<SlotInfo>, Error> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Error::Io(e)),
+        };
+        let prefix = format!("{}.auto.", slots::file_name(serial));
+        let mut saves = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(Error::Io)?;
+            let name = entry.file_name();
+            let number = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(&prefix))
+                .and_then(|rest| rest.strip_suffix(".state"))
+                .and_then(|number| number.parse().ok());
+            if let Some(slot) = number {
+                let metadata = entry.metadata().map_err(Error::Io)?;
+                saves.push(SlotInfo {
+                    slot,
+                    path: entry.path(),
+                    modified: metadata.modified().map_err(Error::Io)?,
+                    size: metadata.len(),
+                });
+            }
+        }
+        saves.sort_by_key(|info| std::cmp::Reverse(info.slot));
+        Ok(saves)
+    }
+
+    // Save now, dropping the auto-saves past the latest `keep`
+    pub fn save(&mut self, psx: &mut Psx) -> Result<PathBuf, Error> {
+        self.elapsed = 0;
+        let serial = slots::serial(psx)?;
+        let saves = self.list(&serial)?;
+        let number = saves.first().map_or(0, |info| info.slot + 1);
+        let path = self.dir.join(format!(
+            "{}.auto.{:04}.state",
+            slots::file_name(&serial),
+            number
+        ));
+        fs::create_dir_all(&self.dir).map_err(Error::Io)?;
+        slots::write_atomic(&path, |file| psx.save_state(file))?;
+        for old in saves.iter().skip(self.keep - 1) {
+            fs::remove_file(&old.path).map_err(Error::Io)?;
+        }
+        Ok(path)
+    }
+
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+}
+
+// Save if it's time, at the end of each frame
+pub fn frame(psx: &mut Psx) {
+    let mut autosave = match psx.autosave.take() {
+        Some(autosave) => autosave,
+        None => return,
+    };
+    autosave.elapsed += 1;
+    if autosave.elapsed >= autosave.interval {
+        if let Err(e) = autosave.save(psx) {
+            autosave.error = Some(e);
+        }
+    }
+    psx.autosave = Some(autosave);
+}
+
+// Save now, as the frontend exits
+pub fn save(psx: &mut Psx) -> Result<Option<PathBuf>, Error> {
+    let mut autosave = match psx.autosave.take() {
+        Some(autosave) => autosave,
+        None => return Ok(None),
+    };
+    let result = autosave.save(psx);
+    psx.autosave = Some(autosave);
+    result.map(Some)
+}
+
+// Load the auto-save `index` places from the latest
+pub fn load(psx: &mut Psx, index: usize) -> Result<(), Error> {
+    let serial = slots::serial(psx)?;
+    let saves = match psx.autosave.as_ref() {
+        Some(autosave) => autosave.list(&serial)?,
+        None => return Err(Error::Config("auto-save isn't enabled".into())),
+    };
+    let save = saves
+        .get(index)
+        .ok_or_else(|| Error::Config("no such auto-save".into()))?;
+    let file = File::open(&save.path).map_err(Error::Io)?;
+    psx.load_state(BufReader::new(file))
+}
@@ -0,0 +1,301 @@
+use super::disc::Region;
+use super::sha1::sha1;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Size of a BIOS ROM image
+pub const BIOS_SIZE: usize = 512 * 1024;
+
+// Text preceding the version, date and region letter, e.g.
+// "System ROM Version 4.1 12/16/97 A"
+const VERSION_PREFIX: &[u8] = b"System ROM Version ";
+
+// Present in every BIOS, including the first Japanese one which has no
+// version string
+const KERNEL_SIGNATURE: &[u8] = b"Sony Computer Entertainment Inc.";
+
+// Things about a dump that the emulator or a frontend may need to know
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quirk {
+    // There's no version string, the dump can only be recognized by its hash
+    NoVersionString,
+}
+
+// A good dump of a retail BIOS
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KnownBios {
+    // Console models it shipped in
+    pub models: &'static str,
+    // Version and date, as in BiosInfo
+    pub version: &'static str,
+    pub region: Region,
+    pub quirks: &'static [Quirk],
+    sha1: &'static str,
+}
+
+// Dumps from the public BIOS dump lists
+const KNOWN_BIOSES: [KnownBios; 6] = [
+    KnownBios {
+        models: "SCPH-1000",
+        version: "1.0 09/22/94",
+        region: Region::NtscJ,
+        quirks: &[Quirk::NoVersionString],
+        sha1: "343883a7b555646da8cee54aadd2795b6e7dd070",
+    },
+    KnownBios {
+        models: "SCPH-1001",
+        version: "2.2 12/04/95",
+        region: Region::NtscU,
+        quirks: &[],
+        sha1: "10155d8d6e6e832d6ea66db9bc098321fb5e8ebf",
+    },
+    KnownBios {
+        models: "SCPH-5500",
+        version: "3.0 09/09/96",
+        region: Region::NtscJ,
+        quirks: &[],
+        sha1: "b05def971d8ec59f346f2d9ac21fb742e3eb6917",
+    },
+    KnownBios {
+        models: "SCPH-5501, SCPH-5503, SCPH-7003",
+        version: "3.0 11/18/96",
+        region: Region::NtscU,
+        quirks: &[],
+        sha1: "0555c6fae8906f3f09baf5988f00e55f88e9f30b",
+    },
+    KnownBios {
+        models: "SCPH-5502, SCPH-5552",
+        version: "3.0 01/06/97",
+        region: Region::Pal,
+        quirks: &[],
+        sha1: "f6bc2d1f5eb6593de7d089c425ac681d6fffd3f0",
+    },
+    KnownBios {
+        models: "SCPH-7001, SCPH-7501, SCPH-7503, SCPH-9001",
+        version: "4.1 12/16/97",
+        region: Region::NtscU,
+        quirks: &[],
+        sha1: "14df4f6c1e367ce097c11deae21566b4fe5647a9",
+    },
+];
+
+// How a BIOS image compares to the known dumps
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BiosCheck {
+    // A known good dump
+    Known(&'static KnownBios),
+    // Has the version string of a known dump but different data: patched,
+    // or a bad dump. It may still work.
+    Modified(&'static KnownBios),
+    // Not in the database, e.g. another model or a homebrew BIOS
+    Unknown,
+}
+
+// Look a BIOS image up in the database of known dumps
+pub fn check(data: &[u8]) -> BiosCheck {
+    let hash: String = sha1(data).iter().map(|b| format!("{:02x}", b)).collect();
+    if let Some(known) = KNOWN_BIOSES.iter().find(|known| known.sha1 == hash) {
+        return BiosCheck::Known(known);
+    }
+    let info = match identify(data) {
+        Some(info) => info,
+        None => return BiosCheck::Unknown,
+    };
+    KNOWN_BIOSES
+        .iter()
+        .find(|known| info.version.as_deref() == Some(known.version) && info.region == known.region)
+        .map_or(BiosCheck::Unknown, BiosCheck::Modified)
+}
+
+// Entries of the ROM directory at the start of PlayStation 2 BIOS images
+const PS2_ROMDIR: &[u8] = b"ROMDIR";
+const PS2_EXTINFO: &[u8] = b"EXTINFO";
+
+// Refuse files that are obviously not a PlayStation BIOS, with a message
+// saying what they are
+fn reject(data: &[u8]) -> Option<String> {
+    let head = &data[..data.len().min(0x10000)];
+    if find(head, PS2_ROMDIR).is_some() && find(head, PS2_EXTINFO).is_some() {
+        return Some(
+            "this is a PlayStation 2 BIOS, a 512 KB PlayStation one (e.g. SCPH-1001) is needed"
+                .to_string(),
+        );
+    }
+    if data.len() != BIOS_SIZE {
+        return Some(format!(
+            "image is {} bytes, a PlayStation BIOS is {}",
+            data.len(),
+            BIOS_SIZE
+        ));
+    }
+    if data.iter().all(|&b| b == data[0]) {
+        return Some("image is blank, the dump failed".to_string());
+    }
+    None
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BiosInfo {
+    // Version and date, e.g. "4.1 12/16/97"
+    pub version: Option<String>,
+    pub region: Region,
+}
+
+// Identify a BIOS image from its version string. Returns None for data
+// that doesn't look like a PlayStation BIOS.
+pub fn identify(data: &[u8]) -> Option<BiosInfo> {
+    if data.len() != BIOS_SIZE || find(data, KERNEL_SIGNATURE).is_none() {
+        return None;
+    }
+    let start = match find(data, VERSION_PREFIX) {
+        Some(offset) => offset + VERSION_PREFIX.len(),
+        None => {
+            return Some(BiosInfo {
+                version: None,
+                region: Region::NtscJ,
+            })
+        }
+    };
+    let end = data[start..].iter().position(|&b| b == 0 || b == b'\n')?;
+    let text = String::from_utf8_lossy(&data[start..start + end]);
+    let (version, region) = text.trim_end().rsplit_once(' ')?;
+    let region = match region {
+        "J" => Region::NtscJ,
+        "A" => Region::NtscU,
+        "E" => Region::Pal,
+        _ => return None,
+    };
+    Some(BiosInfo {
+        version: Some(version.to_string()),
+        region,
+    })
+}
+
+// The shell, which plays the intro and runs the disc's executable once it
+// returns, starts at this offset in every BIOS
+const SHELL_OFFSET: usize = 0x18000;
+
+// Fast boot replacement for the start of the shell: turn the display on,
+// as the shell leaves it, and return straight to the kernel, which then
+// boots the disc
+const FAST_BOOT_PATCH: [u32; 5] = [
+    0x3c011f80, // lui at, 0x1f80
+    0x3c0a0300, // lui t2, 0x0300
+    0xac2a1814, // sw t2, 0x1814(at), GP1(03h) display on
+    0x03e00008, // jr ra
+    0x00000000, // nop
+];
+
+// BIOS ROM mapped at 1FC00000h
+pub struct Bios {
+    rom: Box<[u8]>,
+    // Original start of the shell, restored when fast boot is turned off
+    shell: [u8; 20],
+    fast_boot: bool,
+    // Computed on load, before any patching
+    check: BiosCheck,
+}
+
+impl Bios {
+    pub fn new(data: Vec<u8>) -> io::Result<Self> {
+        if let Some(message) = reject(&data) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+        let mut shell = [0; 20];
+        shell.copy_from_slice(&data[SHELL_OFFSET..SHELL_OFFSET + 20]);
+        Ok(Self {
+            check: check(&data),
+            rom: data.into_boxed_slice(),
+            shell,
+            fast_boot: false,
+        })
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::new(fs::read(path)?)
+    }
+
+    pub fn info(&self) -> Option<BiosInfo> {
+        match self.check {
+            // Fill in what the version string doesn't say
+            BiosCheck::Known(known) if known.quirks.contains(&Quirk::NoVersionString) => {
+                Some(BiosInfo {
+                    version: Some(known.version.to_string()),
+                    region: known.region,
+                })
+            }
+            _ => identify(&self.rom),
+        }
+    }
+
+    pub fn check(&self) -> BiosCheck {
+        self.check
+    }
+
+    pub fn fast_boot(&self) -> bool {
+        self.fast_boot
+    }
+
+    // Patch the shell to skip the intro, or restore it
+    pub fn set_fast_boot(&mut self, fast_boot: bool) {
+        self.fast_boot = fast_boot;
+        let shell = &mut self.rom[SHELL_OFFSET..SHELL_OFFSET + 20];
+        if fast_boot {
+            for (bytes, word) in shell.chunks_mut(4).zip(FAST_BOOT_PATCH.iter()) {
+                bytes.copy_from_slice(&word.to_le_bytes());
+            }
+        } else {
+            shell.copy_from_slice(&self.shell);
+        }
+    }
+
+    // Read `width` bytes at `offset` from 1FC00000h
+    pub fn load(&self, offset: u32, width: usize) -> u32 {
+        let mut val = 0;
+        for i in 0..width {
+            let offset = (offset as usize + i) & (BIOS_SIZE - 1);
+            val |= (self.rom[offset] as u32) << (i * 8);
+        }
+        val
+    }
+}
+
+// Find a BIOS image for `region` in `dir`. Newer versions are preferred.
+pub fn find_bios<P: AsRef<Path>>(dir: P, region: Region) -> io::Result<Option<PathBuf>> {
+    let mut best: Option<(String, PathBuf)> = None;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_candidate = fs::metadata(&path)
+            .map(|meta| meta.is_file() && meta.len() == BIOS_SIZE as u64)
+            .unwrap_or(false);
+        if !is_candidate {
+            continue;
+        }
+        let info = match identify(&fs::read(&path)?) {
+            Some(info) if info.region == region => info,
+            _ => continue,
+        };
+        let version = info.version.unwrap_or_default();
+        if best
+            .as_ref()
+            .is_none_or(|(best, _)| version_key(&version) > version_key(best))
+        {
+            best = Some((version, path));
+        }
+    }
+    Ok(best.map(|(_, path)| path))
+}
+
+// Sort key of a "4.1 12/16/97" version
+fn version_key(version: &str) -> (u32, u32) {
+    let number = version.split(' ').next().unwrap_or("");
+    let (major, minor) = number.split_once('.').unwrap_or((number, "0"));
+    (major.parse().unwrap_or(0), minor.parse().unwrap_or(0))
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len())
+        .position(|window| window == needle)
+}
@@ -0,0 +1,447 @@
+use super::Psx;
+
+use std::io::{self, Write};
+
+// Calls still waiting for their return to be logged. Calls that never
+// return, like longjmp or exit, eventually drop out.
+const MAX_PENDING: usize = 64;
+
+const A_NAMES: [&str; 0xb5] = [
+    "open",
+    "lseek",
+    "read",
+    "write",
+    "close",
+    "ioctl",
+    "exit",
+    "isatty",
+    "getc",
+    "putc",
+    "todigit",
+    "atof",
+    "strtoul",
+    "strtol",
+    "abs",
+    "labs",
+    "atoi",
+    "atol",
+    "atob",
+    "setjmp",
+    "longjmp",
+    "strcat",
+    "strncat",
+    "strcmp",
+    "strncmp",
+    "strcpy",
+    "strncpy",
+    "strlen",
+    "index",
+    "rindex",
+    "strchr",
+    "strrchr",
+    "strpbrk",
+    "strspn",
+    "strcspn",
+    "strtok",
+    "strstr",
+    "toupper",
+    "tolower",
+    "bcopy",
+    "bzero",
+    "bcmp",
+    "memcpy",
+    "memset",
+    "memmove",
+    "memcmp",
+    "memchr",
+    "rand",
+    "srand",
+    "qsort",
+    "strtod",
+    "malloc",
+    "free",
+    "lsearch",
+    "bsearch",
+    "calloc",
+    "realloc",
+    "InitHeap",
+    "_exit",
+    "getchar",
+    "putchar",
+    "gets",
+    "puts",
+    "printf",
+    "SystemErrorUnresolvedException",
+    "LoadTest",
+    "Load",
+    "Exec",
+    "FlushCache",
+    "init_a0_b0_c0_vectors",
+    "GPU_dw",
+    "gpu_send_dma",
+    "SendGP1Command",
+    "GPU_cw",
+    "GPU_cwp",
+    "send_gpu_linked_list",
+    "gpu_abort_dma",
+    "GetGPUStatus",
+    "gpu_sync",
+    "SystemError",
+    "SystemError",
+    "LoadExec",
+    "GetSysSp",
+    "SystemError",
+    "_96_init",
+    "_bu_init",
+    "_96_remove",
+    "return_0",
+    "return_0",
+    "return_0",
+    "return_0",
+    "dev_tty_init",
+    "dev_tty_open",
+    "dev_tty_in_out",
+    "dev_tty_ioctl",
+    "dev_cd_open",
+    "dev_cd_read",
+    "dev_cd_close",
+    "dev_cd_firstfile",
+    "dev_cd_nextfile",
+    "dev_cd_chdir",
+    "dev_card_open",
+    "dev_card_read",
+    "dev_card_write",
+    "dev_card_close",
+    "dev_card_firstfile",
+    "dev_card_nextfile",
+    "dev_card_erase",
+    "dev_card_undelete",
+    "dev_card_format",
+    "dev_card_rename",
+    "card_clear_error",
+    "_bu_init",
+    "_96_init",
+    "_96_remove",
+    "return_0",
+    "return_0",
+    "return_0",
+    "return_0",
+    "return_0",
+    "CdAsyncSeekL",
+    "return_0",
+    "return_0",
+    "return_0",
+    "CdAsyncGetStatus",
+    "return_0",
+    "CdAsyncReadSector",
+    "return_0",
+    "return_0",
+    "CdAsyncSetMode",
+    "return_0",
+    "return_0",
+    "return_0",
+    "return_0",
+    "CdromIoIrqFunc2",
+    "return_0",
+    "return_0",
+    "CdromDmaIrqFunc2",
+    "return_0",
+    "return_0",
+    "return_0",
+    "return_0",
+    "return_0",
+    "return_0",
+    "CdromIoIrqFunc1",
+    "CdromDmaIrqFunc1",
+    "CdromIoIrqFunc2",
+    "CdromDmaIrqFunc2",
+    "CdromGetInt5errCode",
+    "CdInitSubFunc",
+    "AddCDROMDevice",
+    "AddMemCardDevice",
+    "AddDuartTtyDevice",
+    "AddDummyTtyDevice",
+    "SystemError",
+    "SystemError",
+    "SetConf",
+    "GetConf",
+    "SetCdromIrqAutoAbort",
+    "SetMemSize",
+    "WarmBoot",
+    "SystemErrorBootOrDiskFailure",
+    "EnqueueCdIntr",
+    "DequeueCdIntr",
+    "CdGetLbn",
+    "CdReadSector",
+    "CdGetStatus",
+    "bu_callback_okay",
+    "bu_callback_err_write",
+    "bu_callback_err_busy",
+    "bu_callback_err_prev_write",
+    "_card_info",
+    "_card_load",
+    "set_card_auto_format",
+    "bu_callback_err_prev_write",
+    "card_write_test",
+    "return_0",
+    "return_0",
+    "ioabort_raw",
+    "return_0",
+    "GetSystemInfo",
+];
+
+const B_NAMES: [&str; 0x5e] = [
+    "alloc_kernel_memory",
+    "free_kernel_memory",
+    "init_timer",
+    "get_timer",
+    "enable_timer_irq",
+    "disable_timer_irq",
+    "restart_timer",
+    "DeliverEvent",
+    "OpenEvent",
+    "CloseEvent",
+    "WaitEvent",
+    "TestEvent",
+    "EnableEvent",
+    "DisableEvent",
+    "OpenThread",
+    "CloseThread",
+    "ChangeThread",
+    "jump_to_00000000h",
+    "InitPad",
+    "StartPad",
+    "StopPad",
+    "OutdatedPadInitAndStart",
+    "OutdatedPadGetButtons",
+    "ReturnFromException",
+    "SetDefaultExitFromException",
+    "SetCustomExitFromException",
+    "SystemError",
+    "SystemError",
+    "SystemError",
+    "SystemError",
+    "SystemError",
+    "SystemError",
+    "UnDeliverEvent",
+    "SystemError",
+    "SystemError",
+    "SystemError",
+    "jump_to_00000000h",
+    "jump_to_00000000h",
+    "jump_to_00000000h",
+    "jump_to_00000000h",
+    "jump_to_00000000h",
+    "jump_to_00000000h",
+    "SystemError",
+    "SystemError",
+    "jump_to_00000000h",
+    "jump_to_00000000h",
+    "jump_to_00000000h",
+    "jump_to_00000000h",
+    "jump_to_00000000h",
+    "jump_to_00000000h",
+    "open",
+    "lseek",
+    "read",
+    "write",
+    "close",
+    "ioctl",
+    "exit",
+    "isatty",
+    "getc",
+    "putc",
+    "getchar",
+    "putchar",
+    "gets",
+    "puts",
+    "cd",
+    "format",
+    "firstfile",
+    "nextfile",
+    "rename",
+    "erase",
+    "undelete",
+    "AddDrv",
+    "DelDrv",
+    "PrintInstalledDevices",
+    "InitCard",
+    "StartCard",
+    "StopCard",
+    "_card_info_subfunc",
+    "write_card_sector",
+    "read_card_sector",
+    "allow_new_card",
+    "Krom2RawAdd",
+    "SystemError",
+    "Krom2Offset",
+    "GetLastError",
+    "GetLastFileError",
+    "GetC0Table",
+    "GetB0Table",
+    "get_bu_callback_port",
+    "testdevice",
+    "SystemError",
+    "ChangeClearPad",
+    "get_card_status",
+    "wait_card_status",
+];
+
+const C_NAMES: [&str; 0x1e] = [
+    "EnqueueTimerAndVblankIrqs",
+    "EnqueueSyscallHandler",
+    "SysEnqIntRP",
+    "SysDeqIntRP",
+    "get_free_EvCB_slot",
+    "get_free_TCB_slot",
+    "ExceptionHandler",
+    "InstallExceptionHandlers",
+    "SysInitMemory",
+    "SysInitKernelVariables",
+    "ChangeClearRCnt",
+    "SystemError",
+    "InitDefInt",
+    "SetIrqAutoAck",
+    "return_0",
+    "return_0",
+    "return_0",
+    "return_0",
+    "InstallDevices",
+    "FlushStdInOutPut",
+    "return_0",
+    "tty_cdevinput",
+    "tty_cdevscan",
+    "tty_circgetc",
+    "tty_circputc",
+    "ioabort",
+    "set_card_find_mode",
+    "KernelRedirect",
+    "AdjustA0Table",
+    "get_card_find_mode",
+];
+
+// Name of function `function` of the A, B or C table
+pub fn function_name(table: char, function: u32) -> &'static str {
+    let names: &[&str] = match table {
+        'A' => &A_NAMES,
+        'B' => &B_NAMES,
+        _ => &C_NAMES,
+    };
+    names.get(function as usize).copied().unwrap_or("unknown")
+}
+
+struct Pending {
+    table: char,
+    function: u32,
+    ra: u32,
+}
+
+// Log of the kernel calls made through the A0h, B0h and C0h tables, one line
+// per call with the CPU cycle, name, arguments and caller:
+//   1234 A(3Fh) printf(80020000, 00000005, 00000000, 00000000) from 80010120
+//   1300 A(3Fh) printf -> 0000000c
+// Returns are logged when the CPU gets back to the caller.
+pub struct BiosTrace {
+    out: Box<dyn Write + Send>,
+    returns: bool,
+    pending: Vec<Pending>,
+    // First error hit while writing; the trace stops at that point
+    error: Option<io::Error>,
+}
+
+impl BiosTrace {
+    pub fn new(out: Box<dyn Write + Send>, returns: bool) -> Self {
+        Self {
+            out,
+            returns,
+            pending: Vec::new(),
+            error: None,
+        }
+    }
+
+    fn write(&mut self, line: &str) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = writeln!(self.out, "{}", line) {
+            self.error = Some(e);
+        }
+    }
+
+    // Flush the output, reporting any error hit while tracing
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+}
+
+// Log the call the CPU is entering at one of the tables
+pub fn call(psx: &mut Psx) {
+    let cycle = psx.scheduler.now();
+    let cpu = &psx.cpu;
+    let trace = match psx.bios_trace.as_mut() {
+        Some(trace) => trace,
+        None => return,
+    };
+    let table = match cpu.pc & 0x1fffffff {
+        0xa0 => 'A',
+        0xb0 => 'B',
+        _ => 'C',
+    };
+    let function = cpu.regs[9] & 0xff;
+    let ra = cpu.regs[31];
+    // Calls the HLE BIOS repeats while busy-waiting are logged once
+    let repeated = trace
+        .pending
+        .last()
+        .is_some_and(|p| p.table == table && p.function == function && p.ra == ra);
+    if repeated {
+        return;
+    }
+    trace.write(&format!(
+        "{} {}({:02X}h) {}({:08x}, {:08x}, {:08x}, {:08x}) from {:08x}",
+        cycle,
+        table,
+        function,
+        function_name(table, function),
+        cpu.regs[4],
+        cpu.regs[5],
+        cpu.regs[6],
+        cpu.regs[7],
+        ra
+    ));
+    if trace.pending.len() == MAX_PENDING {
+        trace.pending.remove(0);
+    }
+    trace.pending.push(Pending {
+        table,
+        function,
+        ra,
+    });
+}
+
+// Log the return value once the CPU is back at the caller of the last call
+pub fn step(psx: &mut Psx) {
+    let cycle = psx.scheduler.now();
+    let cpu = &psx.cpu;
+    let trace = match psx.bios_trace.as_mut() {
+        Some(trace) => trace,
+        None => return,
+    };
+    let call = match trace.pending.pop_if(|call| call.ra == cpu.pc) {
+        Some(call) => call,
+        None => return,
+    };
+    if trace.returns {
+        trace.write(&format!(
+            "{} {}({:02X}h) {} -> {:08x}",
+            cycle,
+            call.table,
+            call.function,
+            function_name(call.table, call.function),
+            cpu.regs[2]
+        ));
+    }
+}
@@ -0,0 +1,295 @@
+use super::bios::Bios;
+use super::cartridge::CheatCartridge;
+use super::disc::{self, Region};
+use super::enhancements::Enhancements;
+use super::exe::Exe;
+use super::gpu::VideoStandard;
+use super::mdec::Idct;
+use super::sync::SyncMode;
+use super::{Error, Psx};
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+// Host audio rates accepted, in Hz
+const MIN_AUDIO_RATE: u32 = 8000;
+const MAX_AUDIO_RATE: u32 = 192000;
+
+enum BiosSource {
+    Path(PathBuf),
+    Bytes(Vec<u8>),
+}
+
+// Settings of a new Psx, checked together when it's built:
+//   let psx = PsxBuilder::new()
+//       .bios_path("scph1001.bin")
+//       .disc("game.cue")
+//       .fast_boot(true)
+//       .build()?;
+pub struct PsxBuilder {
+    bios: Option<BiosSource>,
+    // Boot with the HLE BIOS instead of a BIOS image
+    hle: bool,
+    disc: Option<PathBuf>,
+    // Bare-metal executable started in quick test mode
+    exe: Option<PathBuf>,
+    // Firmware image of a cartridge on the parallel port
+    cartridge: Option<PathBuf>,
+    region: Option<Region>,
+    video_standard: VideoStandard,
+    fast_boot: bool,
+    sync_mode: SyncMode,
+    host_audio_rate: Option<u32>,
+    spu_threaded: bool,
+    mdec_idct: Idct,
+    mdec_threaded: bool,
+    edc_check: bool,
+    modchip: bool,
+    enhancements: Enhancements,
+}
+
+impl PsxBuilder {
+    pub fn new() -> Self {
+        Self {
+            bios: None,
+            hle: false,
+            disc: None,
+            exe: None,
+            cartridge: None,
+            region: None,
+            video_standard: VideoStandard::Auto,
+            fast_boot: false,
+            sync_mode: SyncMode::default(),
+            host_audio_rate: None,
+            spu_threaded: false,
+            mdec_idct: Idct::default(),
+            mdec_threaded: false,
+            edc_check: false,
+            modchip: false,
+            enhancements: Enhancements::new(),
+        }
+    }
+
+    pub fn bios_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.bios = Some(BiosSource::Path(path.into()));
+        self
+    }
+
+    // A BIOS image already in memory, e.g. bundled with the frontend
+    pub fn bios_bytes(mut self, data: Vec<u8>) -> Self {
+        self.bios = Some(BiosSource::Bytes(data));
+        self
+    }
+
+    // Boot the disc with the HLE BIOS. Needs a disc and no BIOS image.
+    pub fn hle_bios(mut self, hle: bool) -> Self {
+        self.hle = hle;
+        self
+    }
+
+    // Disc image to insert, in any format disc::open supports
+    pub fn disc<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.disc = Some(path.into());
+        self
+    }
+
+    // Start the executable at `path` in quick test mode, on the HLE kernel
+    // with no BIOS. A disc is optional.
+    pub fn exe<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.exe = Some(path.into());
+        self
+    }
+
+    // Plug in a cartridge with the firmware image at `path`, e.g. a cheat
+    // cartridge or flash cart firmware that takes over boot
+    pub fn cartridge<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.cartridge = Some(path.into());
+        self
+    }
+
+    // Console region, instead of the BIOS's or the disc's
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    // NTSC or PAL timing, by default following the disc or the BIOS
+    pub fn video_standard(mut self, standard: VideoStandard) -> Self {
+        self.video_standard = standard;
+        self
+    }
+
+    pub fn fast_boot(mut self, fast_boot: bool) -> Self {
+        self.fast_boot = fast_boot;
+        self
+    }
+
+    pub fn sync_mode(mut self, mode: SyncMode) -> Self {
+        self.sync_mode = mode;
+        self
+    }
+
+    pub fn host_audio_rate(mut self, rate: u32) -> Self {
+        self.host_audio_rate = Some(rate);
+        self
+    }
+
+    pub fn spu_threaded(mut self, threaded: bool) -> Self {
+        self.spu_threaded = threaded;
+        self
+    }
+
+    pub fn mdec_idct(mut self, idct: Idct) -> Self {
+        self.mdec_idct = idct;
+        self
+    }
+
+    pub fn mdec_threaded(mut self, threaded: bool) -> Self {
+        self.mdec_threaded = threaded;
+        self
+    }
+
+    pub fn edc_check(mut self, check: bool) -> Self {
+        self.edc_check = check;
+        self
+    }
+
+    pub fn modchip(mut self, modchip: bool) -> Self {
+        self.modchip = modchip;
+        self
+    }
+
+    pub fn enhancements(mut self, enhancements: Enhancements) -> Self {
+        self.enhancements = enhancements;
+        self
+    }
+
+    pub fn build(self) -> Result<Psx, Error> {
+        if let Some(rate) = self.host_audio_rate {
+            if !(MIN_AUDIO_RATE..=MAX_AUDIO_RATE).contains(&rate) {
+                return Err(Error::Config(format!(
+                    "host audio rate {} Hz is outside {}-{} Hz",
+                    rate, MIN_AUDIO_RATE, MAX_AUDIO_RATE
+                )));
+            }
+        }
+        if self.hle && self.bios.is_some() {
+            return Err(Error::Config(
+                "the HLE BIOS can't be used with a BIOS image".into(),
+            ));
+        }
+        if self.exe.is_some() && self.bios.is_some() {
+            return Err(Error::Config(
+                "quick test mode can't be used with a BIOS image".into(),
+            ));
+        }
+        let cartridge = match &self.cartridge {
+            Some(path) => Some(CheatCartridge::open(path).map_err(|e| {
+                Error::Config(format!("can't open cartridge {}: {}", path.display(), e))
+            })?),
+            None => None,
+        };
+        let bootable_cartridge = cartridge.as_ref().is_some_and(|c| c.boot_hook().is_some());
+        if self.hle && self.disc.is_none() && self.exe.is_none() && !bootable_cartridge {
+            return Err(Error::Config(
+                "the HLE BIOS needs a disc or a bootable cartridge".into(),
+            ));
+        }
+        if self.fast_boot && self.bios.is_none() {
+            return Err(Error::Config("fast boot needs a BIOS image".into()));
+        }
+
+        let bios = match self.bios {
+            Some(source) => Some(load_bios(source)?),
+            None => None,
+        };
+        let bios_region = bios
+            .as_ref()
+            .and_then(|bios| bios.info())
+            .map(|info| info.region);
+        if let (Some(region), Some(bios_region)) = (self.region, bios_region) {
+            if region != bios_region {
+                return Err(Error::Config(format!(
+                    "region {:?} doesn't match the {:?} BIOS",
+                    region, bios_region
+                )));
+            }
+        }
+        let pal = match self.video_standard {
+            VideoStandard::Auto => None,
+            standard => Some(standard == VideoStandard::Pal),
+        };
+        if let (Some(pal), Some(region)) = (pal, self.region.or(bios_region)) {
+            if pal != (region == Region::Pal) {
+                return Err(Error::Config(format!(
+                    "{:?} timing doesn't match the {:?} region",
+                    self.video_standard, region
+                )));
+            }
+        }
+        let exe = match &self.exe {
+            Some(path) => Some(
+                Exe::open(path)
+                    .map_err(|e| Error::Config(format!("can't load {}: {}", path.display(), e)))?,
+            ),
+            None => None,
+        };
+        let disc = match self.disc {
+            Some(path) => Some(disc::open(&path).map_err(|e| {
+                Error::Disc(io::Error::new(
+                    e.kind(),
+                    format!("can't open {}: {}", path.display(), e),
+                ))
+            })?),
+            None => None,
+        };
+
+        let mut psx = Psx::new();
+        psx.bios = bios;
+        psx.set_cartridge(cartridge);
+        if let Some(disc) = disc {
+            psx.insert_disc(disc);
+        }
+        if let Some(region) = self.region.or(bios_region).or_else(|| psx.disc_region()) {
+            psx.set_console_region(region);
+        }
+        psx.set_video_standard(self.video_standard);
+        psx.set_fast_boot(self.fast_boot);
+        psx.set_sync_mode(self.sync_mode);
+        if let Some(rate) = self.host_audio_rate {
+            psx.set_host_audio_rate(rate);
+        }
+        psx.set_spu_threaded(self.spu_threaded);
+        psx.set_mdec_idct(self.mdec_idct);
+        psx.set_mdec_threaded(self.mdec_threaded);
+        psx.set_edc_check(self.edc_check);
+        psx.set_modchip(self.modchip);
+        psx.set_enhancements(self.enhancements);
+        if let Some(exe) = exe {
+            psx.boot_exe(&exe)?;
+        } else if self.hle {
+            psx.boot_hle()?;
+        }
+        Ok(psx)
+    }
+}
+
+impl Default for PsxBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn load_bios(source: BiosSource) -> Result<Bios, Error> {
+    let data = match source {
+        BiosSource::Path(path) => fs::read(&path).map_err(|e| {
+            Error::Bios(io::Error::new(
+                e.kind(),
+                format!("can't read {}: {}", path.display(), e),
+            ))
+        })?,
+        BiosSource::Bytes(data) => data,
+    };
+    Bios::new(data).map_err(Error::Bios)
+}
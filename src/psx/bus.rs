@@ -0,0 +1,183 @@
+use super::{cdrom, dma, gpu, kernel_watch, mdec, sio, sio1, spu, timers, tty};
+use super::{map, Addressable, BusWidth, Psx};
+
+// Extra CPU cycles taken by a 16 bit SPU register access
+const SPU_ACCESS_CYCLES: u64 = 16;
+
+// Extra CPU cycles taken by an 8 bit CD-ROM register access
+const CDROM_ACCESS_CYCLES: u64 = 6;
+
+// Extra CPU cycles taken by other I/O register accesses
+const IO_ACCESS_CYCLES: u64 = 2;
+
+impl Psx {
+    // Read a value of the given width from the bus
+    pub fn load<W: Addressable>(&mut self, addr: u32) -> W {
+        if addr == 0xfffe0130 {
+            return W::from_u32(self.cache_control);
+        }
+
+        let addr = map::mask(addr);
+        match addr {
+            0x00000000..=0x007fffff => self.ram.load(addr),
+            0x1f800000..=0x1f8003ff => self.scratchpad.load(addr - 0x1f800000),
+            0x1f801040..=0x1f80104f => {
+                self.tick(IO_ACCESS_CYCLES);
+                W::from_u32(sio::load(self, addr - 0x1f801040))
+            }
+            0x1f801050..=0x1f80105f => {
+                self.tick(IO_ACCESS_CYCLES);
+                W::from_u32(sio1::load(self, addr - 0x1f801050))
+            }
+            0x1f801070..=0x1f801077 => {
+                self.tick(IO_ACCESS_CYCLES);
+                let val = if addr & 4 == 0 {
+                    self.irq.status()
+                } else {
+                    self.irq.mask()
+                };
+                W::from_u32(val as u32)
+            }
+            0x1f801080..=0x1f8010ff => {
+                self.tick(IO_ACCESS_CYCLES);
+                let val = dma::load(self, addr - 0x1f801080);
+                W::from_u32(val >> ((addr & 3) * 8))
+            }
+            0x1f801100..=0x1f80112f => {
+                self.tick(IO_ACCESS_CYCLES);
+                let val = timers::load(self, addr - 0x1f801100);
+                W::from_u32(val >> ((addr & 3) * 8))
+            }
+            0x1f801810..=0x1f801817 => {
+                self.tick(IO_ACCESS_CYCLES);
+                let val = gpu::load(self, addr - 0x1f801810);
+                W::from_u32(val >> ((addr & 3) * 8))
+            }
+            0x1f801820..=0x1f801827 => {
+                self.tick(IO_ACCESS_CYCLES);
+                let val = mdec::load(self, addr - 0x1f801820);
+                W::from_u32(val >> ((addr & 3) * 8))
+            }
+            0x1f801800..=0x1f801803 => {
+                // Wider reads pop several bytes from the same register
+                let mut val = 0;
+                for i in 0..W::WIDTH as u32 {
+                    self.tick(CDROM_ACCESS_CYCLES);
+                    val |= (cdrom::load(self, addr & 3) as u32) << (i * 8);
+                }
+                W::from_u32(val)
+            }
+            0x1f801c00..=0x1f801fff => {
+                let offset = addr - 0x1f801c00;
+                let lo = self.load_spu(offset);
+                let val = match W::WIDTH {
+                    BusWidth::DoubleWord => lo | (self.load_spu(offset + 2) << 16),
+                    _ => lo >> ((addr & 1) * 8),
+                };
+                W::from_u32(val)
+            }
+            0x1f802020..=0x1f80202f => W::from_u32(tty::duart_load(self, addr - 0x1f802020) as u32),
+            0x1fc00000..=0x1fc7ffff => match self.bios.as_ref() {
+                Some(bios) => W::from_u32(bios.load(addr - 0x1fc00000, W::WIDTH as usize)),
+                None => W::from_u32(0),
+            },
+            // EXP1 is an 8 bit bus, wider reads are split into bytes.
+            // Unconnected, it reads as all ones.
+            0x1f000000..=0x1f7fffff => match self.cartridge.as_mut() {
+                Some(cartridge) => {
+                    let offset = addr - 0x1f000000;
+                    let mut val = 0;
+                    for i in 0..W::WIDTH as u32 {
+                        val |= (cartridge.load(offset + i) as u32) << (i * 8);
+                    }
+                    W::from_u32(val)
+                }
+                None => W::from_u32(!0),
+            },
+            _ => W::from_u32(0),
+        }
+    }
+
+    // Write a value of the given width to the bus
+    pub fn store<W: Addressable>(&mut self, addr: u32, val: W) {
+        if addr == 0xfffe0130 {
+            self.cache_control = val.as_u32();
+            return;
+        }
+
+        let addr = map::mask(addr);
+        let val = val.as_u32();
+        match addr {
+            0x00000000..=0x007fffff => {
+                if self.kernel_watch.is_some() {
+                    kernel_watch::store(self, addr, val, W::WIDTH as u32);
+                }
+                self.ram.store(addr, W::from_u32(val));
+            }
+            0x1f800000..=0x1f8003ff => self.scratchpad.store(addr - 0x1f800000, W::from_u32(val)),
+            0x1f801040..=0x1f80104f => {
+                self.tick(IO_ACCESS_CYCLES);
+                sio::store(self, addr - 0x1f801040, val);
+            }
+            0x1f801050..=0x1f80105f => {
+                self.tick(IO_ACCESS_CYCLES);
+                sio1::store(self, addr - 0x1f801050, val);
+            }
+            0x1f801070..=0x1f801077 => {
+                self.tick(IO_ACCESS_CYCLES);
+                if addr & 4 == 0 {
+                    self.irq.acknowledge(val as u16);
+                } else {
+                    self.irq.set_mask(val as u16);
+                }
+            }
+            0x1f801080..=0x1f8010ff => {
+                self.tick(IO_ACCESS_CYCLES);
+                dma::store(self, addr - 0x1f801080, val << ((addr & 3) * 8));
+            }
+            0x1f801100..=0x1f80112f => {
+                self.tick(IO_ACCESS_CYCLES);
+                timers::store(self, addr - 0x1f801100, val << ((addr & 3) * 8));
+            }
+            0x1f801810..=0x1f801817 => {
+                self.tick(IO_ACCESS_CYCLES);
+                gpu::store(self, addr - 0x1f801810, val << ((addr & 3) * 8));
+            }
+            0x1f801820..=0x1f801827 => {
+                self.tick(IO_ACCESS_CYCLES);
+                mdec::store(self, addr - 0x1f801820, val << ((addr & 3) * 8));
+            }
+            0x1f801800..=0x1f801803 => {
+                self.tick(CDROM_ACCESS_CYCLES);
+                cdrom::store(self, addr & 3, val as u8);
+            }
+            0x1f801c00..=0x1f801fff => {
+                let offset = addr - 0x1f801c00;
+                self.store_spu(offset, val as u16);
+                if let BusWidth::DoubleWord = W::WIDTH {
+                    self.store_spu(offset + 2, (val >> 16) as u16);
+                }
+            }
+            0x1f802020..=0x1f80202f => tty::duart_store(self, addr - 0x1f802020, val as u8),
+            0x1f000000..=0x1f7fffff => {
+                if let Some(cartridge) = self.cartridge.as_mut() {
+                    let offset = addr - 0x1f000000;
+                    for i in 0..W::WIDTH as u32 {
+                        cartridge.store(offset + i, (val >> (i * 8)) as u8);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn load_spu(&mut self, offset: u32) -> u32 {
+        self.tick(SPU_ACCESS_CYCLES);
+        spu::load(self, offset) as u32
+    }
+
+    fn store_spu(&mut self, offset: u32, val: u16) {
+        self.tick(SPU_ACCESS_CYCLES);
+        spu::store(self, offset, val);
+    }
+}
@@ -0,0 +1,284 @@
+use super::state::{Savestate, StateError, StateReader, StateWriter};
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+// Largest firmware image, the 256 KB flash of later cartridges
+pub const MAX_ROM_SIZE: usize = 256 * 1024;
+
+// Cartridge RAM: 1F040000h, 32 KB mirrored up to 1F05FFFFh
+const RAM_START: u32 = 0x40000;
+const RAM_END: u32 = 0x5ffff;
+const RAM_SIZE: usize = 32 * 1024;
+
+// I/O: 1F060000h parallel port input from a PC (nothing connected reads as
+// FFh), 1F060008h output, 1F060018h switch (bit 0 set when on)
+const IO_START: u32 = 0x60000;
+const IO_END: u32 = 0x7ffff;
+const IO_PORT_IN: u32 = 0x00;
+const IO_SWITCH: u32 = 0x18;
+
+// ID following each boot hook in an expansion ROM header. The BIOS only
+// calls hooks whose ID matches.
+const LICENSE_ID: &[u8] = b"Licensed by Sony Computer Entertainment Inc.";
+
+// Boot hooks of an expansion ROM, in the order the BIOS calls them: midboot
+// once the kernel is set up, before the intro, and postboot before the
+// disc's executable is loaded
+const MIDBOOT_HOOK: usize = 0x80;
+const POSTBOOT_HOOK: usize = 0x00;
+
+// Expansion region 1, where the ROM is mapped
+const EXP1_BASE: u32 = 0x1f000000;
+
+// Flash command addresses, within the low 32 KB
+const UNLOCK_ADDR1: u32 = 0x5555;
+const UNLOCK_ADDR2: u32 = 0x2aaa;
+const ADDR_MASK: u32 = 0x7fff;
+
+// Flash page size for writes
+const PAGE_SIZE: usize = 128;
+
+// Product ID reported by the flash (Atmel AT29C010A, or AT29C020 for 256 KB)
+const MANUFACTURER_ID: u8 = 0x1f;
+const DEVICE_ID_128K: u8 = 0xd5;
+const DEVICE_ID_256K: u8 = 0xda;
+
+// Flash command sequence state: AAh to 5555h, 55h to 2AAAh, then the
+// command to 5555h
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum FlashState {
+    Read,
+    Unlock1,
+    Unlock2,
+    // 80h received, waiting for a second unlock and 10h to erase the chip
+    Erase,
+    EraseUnlock1,
+    EraseUnlock2,
+    // A0h received, the first byte written picks the page
+    PageStart,
+    // Writing bytes to the page, until the next read
+    PageWrite(usize),
+    // Product ID mode, left with F0h
+    ProductId,
+}
+
+// Action Replay / GameShark Pro style cheat cartridge on the parallel port
+// (EXP1 at 1F000000h). The BIOS runs its firmware from the flash ROM at boot,
+// and the firmware keeps codes in the flash using the chip's write
+// sequence. The switch on the back turns the cheat engine on or off.
+pub struct CheatCartridge {
+    rom: Box<[u8]>,
+    ram: Box<[u8]>,
+    switch: bool,
+    flash: FlashState,
+    // Whether the firmware changed the ROM since it was loaded or flushed
+    dirty: bool,
+    // File the ROM came from, where changes are written back
+    path: Option<PathBuf>,
+}
+
+impl CheatCartridge {
+    // Cartridge with the firmware `rom`, which is padded to a power of two
+    // and mirrored over the ROM area
+    pub fn new(rom: &[u8]) -> io::Result<Self> {
+        if rom.is_empty() || rom.len() > MAX_ROM_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "cartridge ROM must be at most 256 KB",
+            ));
+        }
+        let mut data = vec![0xff; rom.len().next_power_of_two()];
+        data[..rom.len()].copy_from_slice(rom);
+        Ok(Self {
+            rom: data.into_boxed_slice(),
+            ram: vec![0; RAM_SIZE].into_boxed_slice(),
+            switch: true,
+            flash: FlashState::Read,
+            dirty: false,
+            path: None,
+        })
+    }
+
+    // Cartridge with the firmware image at `path`. Codes saved by the
+    // firmware are written back to it on flush.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut rom = Vec::new();
+        File::open(path)?.read_to_end(&mut rom)?;
+        let mut cartridge = Self::new(&rom)?;
+        cartridge.path = Some(path.to_path_buf());
+        Ok(cartridge)
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    // Address of the first boot hook the BIOS calls, if the ROM has a valid
+    // header. Firmware like Caetla or Unirom takes over boot from there.
+    pub fn boot_hook(&self) -> Option<u32> {
+        [MIDBOOT_HOOK, POSTBOOT_HOOK]
+            .iter()
+            .copied()
+            .find(|&hook| {
+                let id = hook + 4;
+                self.rom.get(id..id + LICENSE_ID.len()) == Some(LICENSE_ID)
+            })
+            .map(|hook| EXP1_BASE + hook as u32)
+    }
+
+    // Flip the switch on the back of the cartridge
+    pub fn set_switch(&mut self, on: bool) {
+        self.switch = on;
+    }
+
+    pub fn switch(&self) -> bool {
+        self.switch
+    }
+
+    // Write the ROM back to its file if the firmware changed it
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let (Some(path), true) = (&self.path, self.dirty) {
+            fs::write(path, &self.rom)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    fn device_id(&self) -> u8 {
+        if self.rom.len() > 128 * 1024 {
+            DEVICE_ID_256K
+        } else {
+            DEVICE_ID_128K
+        }
+    }
+
+    fn load_rom(&mut self, offset: u32) -> u8 {
+        if let FlashState::PageStart | FlashState::PageWrite(_) = self.flash {
+            self.flash = FlashState::Read;
+        }
+        if self.flash == FlashState::ProductId {
+            return match offset & 1 {
+                0 => MANUFACTURER_ID,
+                _ => self.device_id(),
+            };
+        }
+        self.rom[offset as usize & (self.rom.len() - 1)]
+    }
+
+    fn store_rom(&mut self, offset: u32, val: u8) {
+        let offset = offset as usize & (self.rom.len() - 1);
+        let command = offset as u32 & ADDR_MASK;
+        self.flash = match (self.flash, command, val) {
+            (FlashState::PageWrite(page), _, _) if offset / PAGE_SIZE == page => {
+                self.rom[offset] = val;
+                self.dirty = true;
+                FlashState::PageWrite(page)
+            }
+            (FlashState::Read, UNLOCK_ADDR1, 0xaa) => FlashState::Unlock1,
+            (FlashState::PageWrite(_), UNLOCK_ADDR1, 0xaa) => FlashState::Unlock1,
+            (FlashState::ProductId, UNLOCK_ADDR1, 0xaa) => FlashState::Unlock1,
+            (FlashState::Unlock1, UNLOCK_ADDR2, 0x55) => FlashState::Unlock2,
+            (FlashState::Unlock2, UNLOCK_ADDR1, 0xa0) => FlashState::PageStart,
+            (FlashState::Unlock2, UNLOCK_ADDR1, 0x80) => FlashState::Erase,
+            (FlashState::Unlock2, UNLOCK_ADDR1, 0x90) => FlashState::ProductId,
+            (FlashState::Unlock2, UNLOCK_ADDR1, 0xf0) => FlashState::Read,
+            (FlashState::Erase, UNLOCK_ADDR1, 0xaa) => FlashState::EraseUnlock1,
+            (FlashState::EraseUnlock1, UNLOCK_ADDR2, 0x55) => FlashState::EraseUnlock2,
+            (FlashState::EraseUnlock2, UNLOCK_ADDR1, 0x10) => {
+                self.rom.fill(0xff);
+                self.dirty = true;
+                FlashState::Read
+            }
+            (FlashState::PageStart, _, _) => {
+                self.rom[offset] = val;
+                self.dirty = true;
+                FlashState::PageWrite(offset / PAGE_SIZE)
+            }
+            (FlashState::ProductId, _, _) => FlashState::ProductId,
+            _ => FlashState::Read,
+        };
+    }
+
+    // Read a byte at `offset` from 1F000000h
+    pub fn load(&mut self, offset: u32) -> u8 {
+        match offset {
+            0..=0x3ffff => self.load_rom(offset),
+            RAM_START..=RAM_END => self.ram[offset as usize & (RAM_SIZE - 1)],
+            IO_START..=IO_END => match offset & 0x1f {
+                IO_PORT_IN => 0xff,
+                IO_SWITCH => self.switch as u8,
+                _ => 0xff,
+            },
+            _ => 0xff,
+        }
+    }
+
+    // Write a byte at `offset` from 1F000000h
+    pub fn store(&mut self, offset: u32, val: u8) {
+        match offset {
+            0..=0x3ffff => self.store_rom(offset, val),
+            RAM_START..=RAM_END => self.ram[offset as usize & (RAM_SIZE - 1)] = val,
+            // Output to a PC on the parallel port, with nothing connected
+            _ => {}
+        }
+    }
+}
+
+// The switch is the user's and isn't saved. The ROM is, as the firmware
+// keeps its codes there.
+impl Savestate for CheatCartridge {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u32(self.rom.len() as u32);
+        w.write_bytes(&self.rom);
+        w.write_bytes(&self.ram);
+        let (state, page) = match self.flash {
+            FlashState::Read => (0, 0),
+            FlashState::Unlock1 => (1, 0),
+            FlashState::Unlock2 => (2, 0),
+            FlashState::Erase => (3, 0),
+            FlashState::EraseUnlock1 => (4, 0),
+            FlashState::EraseUnlock2 => (5, 0),
+            FlashState::PageStart => (6, 0),
+            FlashState::PageWrite(page) => (7, page),
+            FlashState::ProductId => (8, 0),
+        };
+        w.write_u8(state);
+        w.write_u32(page as u32);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        if r.read_u32()? as usize != self.rom.len() {
+            return Err(StateError::Invalid("cartridge ROM size"));
+        }
+        let rom = r.read_bytes(self.rom.len())?;
+        if *rom != *self.rom {
+            self.rom.copy_from_slice(rom);
+            self.dirty = true;
+        }
+        r.read_into(&mut self.ram)?;
+        let state = r.read_u8()?;
+        let page = r.read_u32()? as usize;
+        self.flash = match state {
+            0 => FlashState::Read,
+            1 => FlashState::Unlock1,
+            2 => FlashState::Unlock2,
+            3 => FlashState::Erase,
+            4 => FlashState::EraseUnlock1,
+            5 => FlashState::EraseUnlock2,
+            6 => FlashState::PageStart,
+            7 if page < self.rom.len() / PAGE_SIZE => FlashState::PageWrite(page),
+            8 => FlashState::ProductId,
+            _ => return Err(StateError::Invalid("cartridge flash state")),
+        };
+        Ok(())
+    }
+}
+
+impl Drop for CheatCartridge {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
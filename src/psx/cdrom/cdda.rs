@@ -0,0 +1,147 @@
+use super::{respond, DriveState, INT1_DATA_READY, INT4_DATA_END};
+use crate::psx::disc::{self, SECTOR_SIZE};
+use crate::psx::scheduler::Event;
+use crate::psx::{spu, Psx};
+
+// Sectors skipped per sector time while fast forwarding or rewinding
+const SCAN_STEP: u32 = 8;
+
+// Start playing audio from the Setloc position, or from the start of `track`
+// when given. Returns false if the track doesn't exist.
+pub fn play(psx: &mut Psx, track: Option<u8>) -> bool {
+    let cd = &mut psx.cdrom;
+    let target = match track {
+        Some(number) => {
+            let start = cd
+                .disc()
+                .and_then(|disc| disc.toc().track(number))
+                .map(|track| track.start.to_lba());
+            match start {
+                Some(start) => {
+                    cd.seek_target = None;
+                    start
+                }
+                None => return false,
+            }
+        }
+        None => cd.seek_target.take().unwrap_or(cd.position),
+    };
+
+    let delay = cd.seek_cycles(target) + cd.sector_cycles();
+    cd.move_head(target);
+    cd.play_track = current_track(cd.disc(), cd.position);
+    cd.scan = 0;
+    cd.state = DriveState::Playing;
+    psx.scheduler.schedule(Event::CdromSector, delay);
+    true
+}
+
+// Fast forward (1) or rewind (-1) while playing
+pub fn scan(psx: &mut Psx, direction: i8) {
+    psx.cdrom.scan = direction;
+}
+
+fn current_track(disc: Option<&dyn disc::Disc>, lba: u32) -> u8 {
+    disc.and_then(|disc| disc.toc().find(lba))
+        .map_or(0, |track| track.number)
+}
+
+// Play the audio sector under the head
+pub fn sector(psx: &mut Psx) {
+    let cd = &mut psx.cdrom;
+    let lba = cd.position;
+    let track = current_track(cd.disc(), lba);
+
+    // End of the track with auto pause, or end of the disc
+    let end_of_disc = track == 0;
+    if end_of_disc || (cd.mode & 0x02 != 0 && track != cd.play_track) {
+        cd.state = DriveState::Idle;
+        cd.scan = 0;
+        let stat = cd.stat();
+        respond(psx, INT4_DATA_END, vec![stat]);
+        return;
+    }
+    cd.play_track = track;
+
+    let sector = match cd.read_sector(lba) {
+        Some(sector) => sector,
+        None => {
+            cd.state = DriveState::Idle;
+            let stat = cd.stat();
+            respond(psx, INT4_DATA_END, vec![stat]);
+            return;
+        }
+    };
+    cd.position = match cd.scan {
+        0 => lba + 1,
+        1 => lba + SCAN_STEP,
+        _ => lba.saturating_sub(SCAN_STEP),
+    };
+
+    // Audio plays at the drive's speed, so every other sample is skipped at
+    // double speed. Scanning plays short bursts at a lower level.
+    let double_speed = cd.double_speed();
+    let muted = cd.muted;
+    let scanning = cd.scan != 0;
+    let step = if double_speed { 8 } else { 4 };
+    let mut peak = [0u16; 2];
+    for frame in sector.data[..SECTOR_SIZE].chunks_exact(step) {
+        let left = i16::from_le_bytes([frame[0], frame[1]]);
+        let right = i16::from_le_bytes([frame[2], frame[3]]);
+        peak[0] = peak[0].max(left.unsigned_abs());
+        peak[1] = peak[1].max(right.unsigned_abs());
+        let (left, right) = match (muted, scanning) {
+            (true, _) => (0, 0),
+            (false, true) => (left >> 2, right >> 2),
+            (false, false) => (left, right),
+        };
+        let (left, right) = psx.cdrom.mix(left, right);
+        spu::push_cd_sample(psx, left, right);
+    }
+
+    if psx.cdrom.mode & 0x04 != 0 {
+        report(psx, &sector, peak);
+    }
+
+    let delay = psx.cdrom.sector_cycles();
+    psx.scheduler.schedule(Event::CdromSector, delay);
+}
+
+// Report mode: send the position with INT1 every 10 sectors, alternating
+// between the absolute and the relative position
+fn report(psx: &mut Psx, sector: &disc::RawSector, peak: [u16; 2]) {
+    let q = match sector.subq {
+        Some(q) => q,
+        None => return,
+    };
+    // Absolute positions are at frames 0, 20, 40 and 60, relative ones at
+    // 10, 30, 50 and 70 with bit 7 of the seconds set
+    let frame = disc::from_bcd(q[9]).unwrap_or(0);
+    if !frame.is_multiple_of(10) {
+        return;
+    }
+    let (mm, ss, sect) = if frame.is_multiple_of(20) {
+        (q[7], q[8], q[9])
+    } else {
+        (q[3], q[4] | 0x80, q[5])
+    };
+
+    // The peak alternates between the channels, bit 15 tells which
+    let cd = &mut psx.cdrom;
+    let channel = cd.report_channel as usize;
+    cd.report_channel = !cd.report_channel;
+    let peak = (peak[channel] >> 1) | ((channel as u16) << 15);
+
+    let stat = cd.stat();
+    let response = vec![
+        stat,
+        q[1],
+        q[2],
+        mm,
+        ss,
+        sect,
+        peak as u8,
+        (peak >> 8) as u8,
+    ];
+    respond(psx, INT1_DATA_READY, response);
+}
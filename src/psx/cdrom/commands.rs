@@ -0,0 +1,380 @@
+use super::cdda;
+use super::{
+    ack, error, respond, start_reading, stop_reading, Completion, DriveState,
+    ERROR_INVALID_COMMAND, ERROR_INVALID_PARAMETER, ERROR_NOT_READY, ERROR_PARAMETER_COUNT,
+    INT2_COMPLETE, INT3_ACKNOWLEDGE, INT5_ERROR, SECTOR_CYCLES,
+};
+use crate::psx::disc::{from_bcd, to_bcd, Msf, TrackType};
+use crate::psx::scheduler::Event;
+use crate::psx::Psx;
+
+// Delay between a command being written and its first response
+const ACK_CYCLES: u64 = 0xc4e1;
+const INIT_ACK_CYCLES: u64 = 0x13cce;
+
+// Delays before the second response of slow commands
+const INIT_CYCLES: u64 = 0xd_bba0;
+const GET_ID_CYCLES: u64 = 0x4a00;
+const MOTOR_ON_CYCLES: u64 = SECTOR_CYCLES;
+const STOP_CYCLES: u64 = 0xd3_8aca;
+const PAUSE_IDLE_CYCLES: u64 = 0x1df2;
+const READ_TOC_CYCLES: u64 = 0x100_0000;
+const SET_SESSION_CYCLES: u64 = 0x4a00;
+
+// Response to Test(20h): BIOS date and version (94/09/19 vC0)
+const TEST_VERSION: [u8; 4] = [0x94, 0x09, 0x19, 0xc0];
+
+// Region string sent by GetID for licensed discs, followed by the region
+// letter
+const LICENSE_PREFIX: [u8; 3] = *b"SCE";
+
+// Cycles before the first response to `command`
+pub fn ack_delay(command: u8) -> u64 {
+    match command {
+        0x0a => INIT_ACK_CYCLES,
+        _ => ACK_CYCLES,
+    }
+}
+
+// Number of parameters each command accepts
+fn param_count(command: u8) -> Option<(usize, usize)> {
+    let count = match command {
+        0x01 | 0x04..=0x0c | 0x0f..=0x11 | 0x13 | 0x15 | 0x16 | 0x1a..=0x1c | 0x1e => (0, 0),
+        0x03 => (0, 1),
+        0x02 => (3, 3),
+        0x0d => (2, 2),
+        0x0e | 0x12 | 0x14 => (1, 1),
+        0x19 => (1, 4),
+        _ => return None,
+    };
+    Some(count)
+}
+
+// Run the command written to the command register
+pub fn execute(psx: &mut Psx) {
+    let command = match psx.cdrom.command.take() {
+        Some(command) => command,
+        None => return,
+    };
+    let params: Vec<u8> = psx.cdrom.params.drain(..).collect();
+
+    let (min, max) = match param_count(command) {
+        Some(count) => count,
+        None => return error(psx, ERROR_INVALID_COMMAND),
+    };
+    if params.len() < min || params.len() > max {
+        return error(psx, ERROR_PARAMETER_COUNT);
+    }
+
+    match command {
+        // Getstat
+        0x01 => {
+            ack(psx);
+            // The shell open bit stays set until it was reported once with
+            // the lid closed
+            if !psx.cdrom.lid_open {
+                psx.cdrom.shell_open = false;
+            }
+        }
+        // Setloc(amm, ass, asect)
+        0x02 => {
+            let msf = match Msf::from_bcd(params[0], params[1], params[2]) {
+                Some(msf) => msf,
+                None => return error(psx, ERROR_INVALID_PARAMETER),
+            };
+            psx.cdrom.seek_target = Some(msf.to_lba());
+            ack(psx);
+        }
+        // Play(track)
+        0x03 => {
+            if !psx.cdrom.disc_ready() {
+                return error(psx, ERROR_NOT_READY);
+            }
+            // Track 0 or no parameter plays from the Setloc position
+            let track = match params.first().map(|&track| from_bcd(track)) {
+                Some(Some(0)) | None => None,
+                Some(Some(track)) => Some(track),
+                Some(None) => return error(psx, ERROR_INVALID_PARAMETER),
+            };
+            if !cdda::play(psx, track) {
+                return error(psx, ERROR_INVALID_PARAMETER);
+            }
+            ack(psx);
+        }
+        // Forward, Backward
+        0x04 | 0x05 => {
+            if psx.cdrom.state != DriveState::Playing {
+                return error(psx, ERROR_NOT_READY);
+            }
+            cdda::scan(psx, if command == 0x04 { 1 } else { -1 });
+            ack(psx);
+        }
+        // ReadN, ReadS
+        0x06 | 0x1b => {
+            if !psx.cdrom.disc_ready() {
+                return error(psx, ERROR_NOT_READY);
+            }
+            ack(psx);
+            start_reading(psx);
+        }
+        // MotorOn
+        0x07 => {
+            if psx.cdrom.motor_on {
+                return error(psx, ERROR_PARAMETER_COUNT);
+            }
+            ack(psx);
+            psx.cdrom.motor_on = true;
+            complete_after(psx, Completion::MotorOn, MOTOR_ON_CYCLES);
+        }
+        // Stop
+        0x08 => {
+            ack(psx);
+            stop_reading(psx);
+            let delay = if psx.cdrom.motor_on {
+                let speed = if psx.cdrom.double_speed() { 2 } else { 1 };
+                STOP_CYCLES * speed
+            } else {
+                PAUSE_IDLE_CYCLES
+            };
+            complete_after(psx, Completion::Stop, delay);
+        }
+        // Pause
+        0x09 => {
+            ack(psx);
+            // Pausing takes about a sector's time when the drive is busy
+            let delay = match psx.cdrom.state {
+                DriveState::Idle => PAUSE_IDLE_CYCLES,
+                _ => psx.cdrom.sector_cycles(),
+            };
+            psx.scheduler.cancel(Event::CdromSector);
+            complete_after(psx, Completion::Pause, delay);
+        }
+        // Init
+        0x0a => {
+            let cd = &mut psx.cdrom;
+            cd.mode = 0x20;
+            cd.motor_on = true;
+            cd.seek_target = None;
+            cd.pending.clear();
+            ack(psx);
+            stop_reading(psx);
+            complete_after(psx, Completion::Init, INIT_CYCLES);
+        }
+        // Mute
+        0x0b => {
+            psx.cdrom.muted = true;
+            ack(psx);
+        }
+        // Demute
+        0x0c => {
+            psx.cdrom.muted = false;
+            ack(psx);
+        }
+        // Setfilter(file, channel)
+        0x0d => {
+            psx.cdrom.filter_file = params[0];
+            psx.cdrom.filter_channel = params[1];
+            ack(psx);
+        }
+        // Setmode(mode)
+        0x0e => {
+            psx.cdrom.mode = params[0];
+            ack(psx);
+        }
+        // Getparam
+        0x0f => {
+            let cd = &psx.cdrom;
+            let response = vec![cd.stat(), cd.mode, 0, cd.filter_file, cd.filter_channel];
+            respond(psx, INT3_ACKNOWLEDGE, response);
+        }
+        // GetlocL: header and subheader of the last data sector read. There
+        // is none after a seek or while playing audio.
+        0x10 => {
+            let header = match psx.cdrom.header {
+                Some(header) => header.to_vec(),
+                None => return error(psx, ERROR_NOT_READY),
+            };
+            respond(psx, INT3_ACKNOWLEDGE, header);
+        }
+        // GetlocP: track, index, relative and absolute position from the
+        // last valid subchannel Q, or from the TOC when there's none yet
+        0x11 => {
+            if let Some(q) = psx.cdrom.subq {
+                let mut response = q[1..6].to_vec();
+                response.extend_from_slice(&q[7..10]);
+                return respond(psx, INT3_ACKNOWLEDGE, response);
+            }
+            let position = psx.cdrom.position;
+            let track = psx
+                .cdrom
+                .disc()
+                .and_then(|disc| disc.toc().find(position))
+                .map(|track| (track.number, track.index_at(position), track.start.to_lba()));
+            let (number, index, start) = match track {
+                Some(track) => track,
+                None => return error(psx, ERROR_NOT_READY),
+            };
+            // The relative position counts down to index 1 in the pregap
+            let relative = position.abs_diff(start);
+            let mut response = vec![to_bcd(number), to_bcd(index)];
+            response.extend_from_slice(&Msf::from_lba(relative).to_bcd());
+            response.extend_from_slice(&Msf::from_lba(position).to_bcd());
+            respond(psx, INT3_ACKNOWLEDGE, response);
+        }
+        // SetSession(session)
+        0x12 => {
+            if params[0] == 0 {
+                return error(psx, ERROR_INVALID_PARAMETER);
+            }
+            ack(psx);
+            complete_after(psx, Completion::SetSession, SET_SESSION_CYCLES);
+        }
+        // GetTN: first and last track numbers
+        0x13 => {
+            let toc = match psx.cdrom.disc() {
+                Some(disc) if psx.cdrom.disc_ready() => disc.toc(),
+                _ => return error(psx, ERROR_NOT_READY),
+            };
+            let response = vec![
+                psx.cdrom.stat(),
+                to_bcd(toc.first_track()),
+                to_bcd(toc.last_track()),
+            ];
+            respond(psx, INT3_ACKNOWLEDGE, response);
+        }
+        // GetTD(track): start of a track, or the lead-out for track 0
+        0x14 => {
+            let toc = match psx.cdrom.disc() {
+                Some(disc) if psx.cdrom.disc_ready() => disc.toc(),
+                _ => return error(psx, ERROR_NOT_READY),
+            };
+            let start = match from_bcd(params[0]) {
+                Some(0) => Some(toc.lead_out),
+                Some(number) => toc.track(number).map(|track| track.start),
+                None => None,
+            };
+            let start = match start {
+                Some(start) => start,
+                None => return error(psx, ERROR_INVALID_PARAMETER),
+            };
+            let response = vec![psx.cdrom.stat(), to_bcd(start.m), to_bcd(start.s)];
+            respond(psx, INT3_ACKNOWLEDGE, response);
+        }
+        // SeekL, SeekP
+        0x15 | 0x16 => {
+            if !psx.cdrom.disc_ready() {
+                return error(psx, ERROR_NOT_READY);
+            }
+            stop_reading(psx);
+            let cd = &mut psx.cdrom;
+            let target = cd.seek_target.unwrap_or(cd.position);
+            let delay = cd.seek_cycles(target);
+            cd.state = DriveState::Seeking;
+            ack(psx);
+            complete_after(psx, Completion::Seek, delay);
+        }
+        // Test(sub_function, ...)
+        0x19 => match params[0] {
+            0x20 => respond(psx, INT3_ACKNOWLEDGE, TEST_VERSION.to_vec()),
+            _ => error(psx, ERROR_INVALID_PARAMETER),
+        },
+        // GetID
+        0x1a => {
+            if psx.cdrom.lid_open {
+                return error(psx, ERROR_NOT_READY);
+            }
+            ack(psx);
+            complete_after(psx, Completion::GetId, GET_ID_CYCLES);
+        }
+        // Reset
+        0x1c => {
+            let cd = &mut psx.cdrom;
+            cd.mode = 0;
+            cd.seek_target = None;
+            cd.pending.clear();
+            cd.motor_on = false;
+            stop_reading(psx);
+            psx.scheduler.cancel(Event::CdromComplete);
+            ack(psx);
+        }
+        // ReadTOC
+        0x1e => {
+            ack(psx);
+            complete_after(psx, Completion::ReadToc, READ_TOC_CYCLES);
+        }
+        _ => error(psx, ERROR_INVALID_COMMAND),
+    }
+}
+
+// Deliver the second response of the command in progress
+pub fn complete(psx: &mut Psx) {
+    let completion = match psx.cdrom.completion.take() {
+        Some(completion) => completion,
+        None => return,
+    };
+
+    match completion {
+        Completion::Init | Completion::MotorOn | Completion::SetSession => {}
+        Completion::Stop => psx.cdrom.motor_on = false,
+        Completion::Pause => psx.cdrom.state = DriveState::Idle,
+        Completion::Seek => {
+            let cd = &mut psx.cdrom;
+            if let Some(target) = cd.seek_target.take() {
+                cd.move_head(target);
+            }
+            cd.state = DriveState::Idle;
+        }
+        Completion::GetId => {
+            let audio = match psx.cdrom.disc() {
+                Some(disc) if psx.cdrom.disc_ready() => disc
+                    .toc()
+                    .tracks
+                    .first()
+                    .is_some_and(|track| track.kind == TrackType::Audio),
+                _ => {
+                    // No disc: stat with the ID error bit and the "no disc" flag
+                    psx.cdrom.disc_wanted = true;
+                    let stat = psx.cdrom.stat() | 0x08;
+                    let response = vec![stat, 0x40, 0, 0, 0, 0, 0, 0];
+                    return respond(psx, INT5_ERROR, response);
+                }
+            };
+            let stat = psx.cdrom.stat();
+            if audio {
+                // Audio CD: ID error with the "unlicensed" and "audio" flags
+                let response = vec![stat | 0x08, 0x90, 0, 0, 0, 0, 0, 0];
+                return respond(psx, INT5_ERROR, response);
+            }
+            // A modchip makes every disc look licensed for the console's
+            // region
+            let cd = &psx.cdrom;
+            let region = match cd.license {
+                _ if cd.modchip => cd.console_region,
+                Some(region) => region,
+                None => {
+                    // Unlicensed data disc: ID error with the "unlicensed" flag
+                    let response = vec![stat | 0x08, 0x80, 0x20, 0, 0, 0, 0, 0];
+                    return respond(psx, INT5_ERROR, response);
+                }
+            };
+            let mut response = vec![stat, 0x00, 0x20, 0x00];
+            response.extend_from_slice(&LICENSE_PREFIX);
+            response.push(region.license_letter());
+            return respond(psx, INT2_COMPLETE, response);
+        }
+        Completion::ReadToc => {
+            if !psx.cdrom.disc_ready() {
+                return error(psx, ERROR_NOT_READY);
+            }
+        }
+    }
+
+    let stat = psx.cdrom.stat();
+    respond(psx, INT2_COMPLETE, vec![stat]);
+}
+
+// Deliver the second response of the command in progress after `delay`
+fn complete_after(psx: &mut Psx, completion: Completion, delay: u64) {
+    psx.cdrom.completion = Some(completion);
+    psx.scheduler.schedule(Event::CdromComplete, delay);
+}
@@ -0,0 +1,916 @@
+mod cdda;
+mod commands;
+mod trace;
+mod xa;
+
+use super::disc::{
+    crc_valid, detect_region, edc_valid, license_region, read_system_cnf, Disc, Msf, RawSector,
+    Region, SystemCnf, SECTOR_SIZE,
+};
+use super::irq::Interrupt;
+use super::scheduler::Event;
+use super::state::{Savestate, StateError, StateReader, StateWriter};
+use super::Psx;
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+// CPU cycles per sector at single speed (75 sectors per second)
+const SECTOR_CYCLES: u64 = 33_868_800 / 75;
+
+// Seek timing: the shortest seek, the distance below which the drive just
+// reads up to the target, the fixed cost of moving the sled and the time it
+// takes to move it across the whole disc
+const SEEK_MIN_CYCLES: u64 = 20_000;
+const SHORT_SEEK_SECTORS: u64 = 8;
+const SLED_MOVE_CYCLES: u64 = SECTOR_CYCLES * 8;
+const FULL_SEEK_CYCLES: u64 = 33_868_800 * 3 / 5;
+const DISC_SECTORS: u64 = 74 * 60 * 75;
+
+// Time for the motor to reach speed when a command needs it
+const SPIN_UP_CYCLES: u64 = 33_868_800;
+
+// Extra tries at reading a sector before the drive reports an error
+const READ_RETRIES: u8 = 4;
+
+// Delay between a response being acknowledged and the next one arriving
+const DELIVER_DELAY: u64 = 0x800;
+
+// Size of the parameter and response FIFOs
+const FIFO_SIZE: usize = 16;
+
+// Interrupt types reported in the interrupt flag register
+pub const INT1_DATA_READY: u8 = 1;
+pub const INT2_COMPLETE: u8 = 2;
+pub const INT3_ACKNOWLEDGE: u8 = 3;
+pub const INT4_DATA_END: u8 = 4;
+pub const INT5_ERROR: u8 = 5;
+
+// Error codes sent after the status byte with INT5
+pub const ERROR_SEEK_FAILED: u8 = 0x04;
+pub const ERROR_DOOR_OPENED: u8 = 0x08;
+pub const ERROR_INVALID_PARAMETER: u8 = 0x10;
+pub const ERROR_PARAMETER_COUNT: u8 = 0x20;
+pub const ERROR_INVALID_COMMAND: u8 = 0x40;
+pub const ERROR_NOT_READY: u8 = 0x80;
+
+// What the drive mechanism is doing
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DriveState {
+    Idle,
+    Seeking,
+    Reading,
+    Playing,
+}
+
+// Second response of a command, computed when it's delivered
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Completion {
+    Init,
+    MotorOn,
+    Stop,
+    Pause,
+    Seek,
+    GetId,
+    ReadToc,
+    SetSession,
+}
+
+// A response waiting to be delivered to the CPU
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Response {
+    pub irq: u8,
+    pub bytes: Vec<u8>,
+}
+
+pub struct CdRom {
+    // 1F801800h Index register (bits 0-1)
+    index: u8,
+    // Parameter FIFO
+    params: VecDeque<u8>,
+    // Response FIFO
+    response: VecDeque<u8>,
+    // Data FIFO, filled from the sector buffer on request
+    data: VecDeque<u8>,
+    // Interrupt enable register
+    irq_enable: u8,
+    // Interrupt flag register (type of the pending interrupt)
+    irq_flag: u8,
+    // Command written but not yet acknowledged
+    command: Option<u8>,
+    // Second response of the command in progress
+    completion: Option<Completion>,
+    // Responses waiting for the current interrupt to be acknowledged
+    pending: VecDeque<Response>,
+    state: DriveState,
+    motor_on: bool,
+    // Is the lid physically open?
+    lid_open: bool,
+    // Shell open status bit, latched until reported by Getstat with the lid
+    // closed
+    shell_open: bool,
+    // Setmode value
+    mode: u8,
+    // Setfilter file and channel
+    filter_file: u8,
+    filter_channel: u8,
+    // Mute/Demute state for CD audio
+    muted: bool,
+    // Volume matrix applied to CD audio: left to left, left to right, right
+    // to right and right to left, 0x80 being full volume
+    volume: [u8; 4],
+    // Volumes written but not applied yet
+    pending_volume: [u8; 4],
+    // ADPCTL bit 0: mute XA-ADPCM
+    xa_muted: bool,
+    // Setloc target, applied by the next seek or read
+    seek_target: Option<u32>,
+    // Current head position (LBA)
+    position: u32,
+    // Failed reads of the sector under the head
+    retries: u8,
+    // Header and subheader of the last data sector read, reported by GetlocL
+    header: Option<[u8; 8]>,
+    // Last subchannel Q read with a valid CRC, reported by GetlocP. Sectors
+    // with a bad CRC leave it alone, which LibCrypt checks rely on.
+    subq: Option<[u8; 12]>,
+    // Track being played, for auto pause
+    play_track: u8,
+    // Fast forward (1) or rewind (-1) while playing
+    scan: i8,
+    // Channel whose peak level the next report carries
+    report_channel: bool,
+    // Decoder for XA-ADPCM audio sectors
+    xa: xa::XaDecoder,
+    // Sector announced by the last INT1, which data requests read
+    sector: Option<RawSector>,
+    // Sector read whose INT1 hasn't been delivered yet
+    pending_sector: Option<RawSector>,
+    disc: Option<Box<dyn Disc>>,
+    // Region of the inserted disc, detected when it's inserted
+    region: Option<Region>,
+    // SYSTEM.CNF of the inserted disc, with the boot executable's name
+    system_cnf: Option<SystemCnf>,
+    // Region of the license text of the inserted disc, None if unlicensed
+    license: Option<Region>,
+    // Region of the console, reported by GetID for discs of other regions
+    // when a modchip is installed
+    console_region: Region,
+    modchip: bool,
+    // Check the EDC of data sectors and fail reads with a bad one
+    edc_check: bool,
+    // Speed hack: every seek takes the shortest seek time
+    zero_seek: bool,
+    // Log of commands, responses and interrupts, if tracing
+    trace: Option<trace::CdTrace>,
+    // A command needed a disc while there was none or the lid was open
+    disc_wanted: bool,
+}
+
+impl CdRom {
+    pub fn new() -> Self {
+        Self {
+            index: 0,
+            params: VecDeque::with_capacity(FIFO_SIZE),
+            response: VecDeque::with_capacity(FIFO_SIZE),
+            data: VecDeque::new(),
+            irq_enable: 0,
+            irq_flag: 0,
+            command: None,
+            completion: None,
+            pending: VecDeque::new(),
+            state: DriveState::Idle,
+            motor_on: false,
+            lid_open: false,
+            shell_open: false,
+            mode: 0,
+            filter_file: 0,
+            filter_channel: 0,
+            muted: false,
+            volume: [0x80, 0, 0x80, 0],
+            pending_volume: [0x80, 0, 0x80, 0],
+            xa_muted: false,
+            seek_target: None,
+            position: 0,
+            retries: 0,
+            header: None,
+            subq: None,
+            play_track: 0,
+            scan: 0,
+            report_channel: false,
+            xa: xa::XaDecoder::new(),
+            sector: None,
+            pending_sector: None,
+            disc: None,
+            region: None,
+            system_cnf: None,
+            license: None,
+            console_region: Region::NtscU,
+            modchip: false,
+            edc_check: false,
+            zero_seek: false,
+            trace: None,
+            disc_wanted: false,
+        }
+    }
+
+    // Status byte sent with most responses
+    pub fn stat(&self) -> u8 {
+        let mut stat = 0;
+        if self.motor_on {
+            stat |= 0x02;
+        }
+        if self.shell_open {
+            stat |= 0x10;
+        }
+        stat |= match self.state {
+            DriveState::Idle => 0,
+            DriveState::Reading => 0x20,
+            DriveState::Seeking => 0x40,
+            DriveState::Playing => 0x80,
+        };
+        stat
+    }
+
+    pub fn state(&self) -> DriveState {
+        self.state
+    }
+
+    fn double_speed(&self) -> bool {
+        self.mode & 0x80 != 0
+    }
+
+    // Cycles between two sectors at the current speed
+    fn sector_cycles(&self) -> u64 {
+        if self.double_speed() {
+            SECTOR_CYCLES / 2
+        } else {
+            SECTOR_CYCLES
+        }
+    }
+
+    // Time to move the head from the current position to `target`, spinning
+    // up the motor first if it's off
+    fn seek_cycles(&mut self, target: u32) -> u64 {
+        let mut cycles = 0;
+        if !self.motor_on {
+            self.motor_on = true;
+            cycles += SPIN_UP_CYCLES;
+        }
+        let distance = target.abs_diff(self.position) as u64;
+        cycles += if self.zero_seek {
+            SEEK_MIN_CYCLES
+        } else if distance < SHORT_SEEK_SECTORS {
+            SEEK_MIN_CYCLES.max(distance * self.sector_cycles())
+        } else {
+            SLED_MOVE_CYCLES + distance * FULL_SEEK_CYCLES / DISC_SECTORS
+        };
+        cycles
+    }
+
+    pub fn insert_disc(&mut self, mut disc: Box<dyn Disc>) {
+        self.region = detect_region(disc.as_mut());
+        self.license = license_region(disc.as_mut());
+        self.system_cnf = read_system_cnf(disc.as_mut());
+        self.disc = Some(disc);
+        self.sector = None;
+        self.pending_sector = None;
+        self.header = None;
+        self.subq = None;
+    }
+
+    pub fn set_console_region(&mut self, region: Region) {
+        self.console_region = region;
+    }
+
+    // With a modchip GetID accepts unlicensed and other regions' discs
+    pub fn set_modchip(&mut self, modchip: bool) {
+        self.modchip = modchip;
+    }
+
+    pub fn set_edc_check(&mut self, check: bool) {
+        self.edc_check = check;
+    }
+
+    pub fn set_zero_seek(&mut self, zero_seek: bool) {
+        self.zero_seek = zero_seek;
+    }
+
+    // Trace commands, responses and interrupts to `out`. Any trace in
+    // progress is finished first.
+    pub fn start_trace(&mut self, out: Box<dyn Write + Send>) -> io::Result<()> {
+        self.stop_trace()?;
+        self.trace = Some(trace::CdTrace::new(out));
+        Ok(())
+    }
+
+    pub fn stop_trace(&mut self) -> io::Result<()> {
+        match self.trace.take() {
+            Some(trace) => trace.finish(),
+            None => Ok(()),
+        }
+    }
+
+    // Take the trace out, to put it back with set_trace, so frames run
+    // twice aren't logged twice
+    pub fn take_trace(&mut self) -> Option<trace::CdTrace> {
+        self.trace.take()
+    }
+
+    pub fn set_trace(&mut self, trace: Option<trace::CdTrace>) {
+        self.trace = trace;
+    }
+
+    pub fn lid_open(&self) -> bool {
+        self.lid_open
+    }
+
+    pub fn region(&self) -> Option<Region> {
+        self.region
+    }
+
+    pub fn serial(&self) -> Option<&str> {
+        self.system_cnf.as_ref().map(|cnf| cnf.serial.as_str())
+    }
+
+    pub fn system_cnf(&self) -> Option<&SystemCnf> {
+        self.system_cnf.as_ref()
+    }
+
+    pub fn disc(&self) -> Option<&dyn Disc> {
+        self.disc.as_deref()
+    }
+
+    // Did the game look for a disc that isn't there since the last call?
+    pub fn take_disc_wanted(&mut self) -> bool {
+        std::mem::take(&mut self.disc_wanted)
+    }
+
+    pub fn disc_mut(&mut self) -> Option<&mut (dyn Disc + 'static)> {
+        self.disc.as_deref_mut()
+    }
+
+    // Is a disc inserted and readable?
+    fn disc_ready(&self) -> bool {
+        self.disc.is_some() && !self.lid_open
+    }
+
+    // Is `sector` real-time XA audio that goes to the SPU instead of the
+    // data FIFO?
+    fn is_xa_audio(&self, sector: &RawSector) -> bool {
+        let submode = sector.subheader()[2];
+        self.mode & 0x40 != 0 && sector.mode() == 2 && submode & 0x44 == 0x44
+    }
+
+    // Read the sector at absolute position `lba`, keeping its subchannel Q
+    fn read_sector(&mut self, lba: u32) -> Option<RawSector> {
+        let disc = self.disc.as_mut()?;
+        let sector = disc.read_sector(Msf::from_lba(lba)).ok()?;
+        if let Some(q) = sector.subq.filter(crc_valid) {
+            self.subq = Some(q);
+        }
+        Some(sector)
+    }
+
+    // Move the head to `target`. Nothing has been read there yet.
+    fn move_head(&mut self, target: u32) {
+        self.position = target;
+        self.header = None;
+        self.subq = None;
+    }
+
+    // 1F801800h Status register
+    fn status(&self) -> u8 {
+        let mut status = self.index;
+        if self.params.is_empty() {
+            status |= 0x08;
+        }
+        if self.params.len() < FIFO_SIZE {
+            status |= 0x10;
+        }
+        if !self.response.is_empty() {
+            status |= 0x20;
+        }
+        if !self.data.is_empty() {
+            status |= 0x40;
+        }
+        if self.command.is_some() {
+            status |= 0x80;
+        }
+        status
+    }
+
+    // Apply the volume matrix to a CD audio sample
+    fn mix(&self, left: i16, right: i16) -> (i16, i16) {
+        let [ll, lr, rr, rl] = self.volume.map(|v| v as i32);
+        let (left, right) = (left as i32, right as i32);
+        let out_left = (left * ll + right * rl) >> 7;
+        let out_right = (left * lr + right * rr) >> 7;
+        (
+            out_left.clamp(-0x8000, 0x7fff) as i16,
+            out_right.clamp(-0x8000, 0x7fff) as i16,
+        )
+    }
+
+    // Load the sector buffer into the data FIFO. The drive picks the size
+    // from the mode register alone; the form in the subheader doesn't matter.
+    fn load_data(&mut self) {
+        self.data.clear();
+        if let Some(sector) = self.sector.as_ref() {
+            let bytes = if self.mode & 0x20 != 0 {
+                // Whole sector except the sync bytes: header, subheader and
+                // the 2324 bytes of a Form 2 payload or Form 1 data with its
+                // error correction
+                &sector.data[12..12 + 0x924]
+            } else {
+                // The first 0x800 bytes of data, after the header (and the
+                // subheader in Mode 2)
+                let start = if sector.mode() == 1 { 16 } else { 24 };
+                &sector.data[start..start + 0x800]
+            };
+            self.data.extend(bytes.iter());
+        }
+    }
+
+    // Pop a byte from the data FIFO
+    pub fn read_data(&mut self) -> u8 {
+        self.data.pop_front().unwrap_or(0)
+    }
+}
+
+// Reset the controller with the console's reset button. The disc, the lid
+// and the drive's settings stay as they are.
+pub fn reset(psx: &mut Psx) {
+    for &event in EVENTS.iter() {
+        psx.scheduler.cancel(event);
+    }
+    let old = std::mem::take(&mut psx.cdrom);
+    psx.cdrom = CdRom {
+        lid_open: old.lid_open,
+        shell_open: old.lid_open,
+        disc: old.disc,
+        region: old.region,
+        system_cnf: old.system_cnf,
+        license: old.license,
+        console_region: old.console_region,
+        modchip: old.modchip,
+        edc_check: old.edc_check,
+        zero_seek: old.zero_seek,
+        trace: old.trace,
+        ..CdRom::new()
+    };
+}
+
+// Events the controller schedules, saved with their remaining time
+const EVENTS: [Event; 4] = [
+    Event::CdromCommand,
+    Event::CdromComplete,
+    Event::CdromSector,
+    Event::CdromDeliver,
+];
+
+const COMPLETIONS: [Completion; 8] = [
+    Completion::Init,
+    Completion::MotorOn,
+    Completion::Stop,
+    Completion::Pause,
+    Completion::Seek,
+    Completion::GetId,
+    Completion::ReadToc,
+    Completion::SetSession,
+];
+
+const DRIVE_STATES: [DriveState; 4] = [
+    DriveState::Idle,
+    DriveState::Seeking,
+    DriveState::Reading,
+    DriveState::Playing,
+];
+
+fn write_fifo<'a, I: ExactSizeIterator<Item = &'a u8>>(w: &mut StateWriter, bytes: I) {
+    w.write_u32(bytes.len() as u32);
+    for &byte in bytes {
+        w.write_u8(byte);
+    }
+}
+
+fn read_fifo(r: &mut StateReader, fifo: &mut VecDeque<u8>, max: usize) -> Result<(), StateError> {
+    let len = r.read_u32()? as usize;
+    if len > max {
+        return Err(StateError::Invalid("CD-ROM FIFO length"));
+    }
+    fifo.clear();
+    fifo.extend(r.read_bytes(len)?.iter());
+    Ok(())
+}
+
+fn write_sector(w: &mut StateWriter, sector: Option<&RawSector>) {
+    w.write_bool(sector.is_some());
+    if let Some(sector) = sector {
+        w.write_bytes(&sector.data);
+        w.write_bool(sector.subq.is_some());
+        w.write_bytes(&sector.subq.unwrap_or([0; 12]));
+    }
+}
+
+fn read_sector(r: &mut StateReader) -> Result<Option<RawSector>, StateError> {
+    if !r.read_bool()? {
+        return Ok(None);
+    }
+    let mut sector = RawSector::new();
+    r.read_into(&mut sector.data)?;
+    let has_subq = r.read_bool()?;
+    let mut subq = [0u8; 12];
+    r.read_into(&mut subq)?;
+    sector.subq = if has_subq { Some(subq) } else { None };
+    Ok(Some(sector))
+}
+
+// The inserted disc and settings such as the console region aren't part of
+// the state, the same disc has to be inserted before loading it
+impl Savestate for CdRom {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.index);
+        write_fifo(w, self.params.iter());
+        write_fifo(w, self.response.iter());
+        write_fifo(w, self.data.iter());
+        w.write_u8(self.irq_enable);
+        w.write_u8(self.irq_flag);
+        w.write_bool(self.command.is_some());
+        w.write_u8(self.command.unwrap_or(0));
+        let completion = self
+            .completion
+            .and_then(|c| COMPLETIONS.iter().position(|&other| other == c));
+        w.write_u8(completion.map_or(0xff, |index| index as u8));
+        w.write_u32(self.pending.len() as u32);
+        for response in self.pending.iter() {
+            w.write_u8(response.irq);
+            write_fifo(w, response.bytes.iter());
+        }
+        let state = DRIVE_STATES.iter().position(|&s| s == self.state);
+        w.write_u8(state.unwrap_or(0) as u8);
+        w.write_bool(self.motor_on);
+        w.write_bool(self.lid_open);
+        w.write_bool(self.shell_open);
+        w.write_u8(self.mode);
+        w.write_u8(self.filter_file);
+        w.write_u8(self.filter_channel);
+        w.write_bool(self.muted);
+        w.write_bytes(&self.volume);
+        w.write_bytes(&self.pending_volume);
+        w.write_bool(self.xa_muted);
+        w.write_bool(self.seek_target.is_some());
+        w.write_u32(self.seek_target.unwrap_or(0));
+        w.write_u32(self.position);
+        w.write_u8(self.retries);
+        w.write_bool(self.header.is_some());
+        w.write_bytes(&self.header.unwrap_or([0; 8]));
+        w.write_bool(self.subq.is_some());
+        w.write_bytes(&self.subq.unwrap_or([0; 12]));
+        w.write_u8(self.play_track);
+        w.write_u8(self.scan as u8);
+        w.write_bool(self.report_channel);
+        self.xa.save_state(w);
+        write_sector(w, self.sector.as_ref());
+        write_sector(w, self.pending_sector.as_ref());
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.index = r.read_u8()? & 3;
+        read_fifo(r, &mut self.params, FIFO_SIZE)?;
+        read_fifo(r, &mut self.response, FIFO_SIZE)?;
+        read_fifo(r, &mut self.data, SECTOR_SIZE)?;
+        self.irq_enable = r.read_u8()?;
+        self.irq_flag = r.read_u8()?;
+        let has_command = r.read_bool()?;
+        let command = r.read_u8()?;
+        self.command = if has_command { Some(command) } else { None };
+        self.completion = match r.read_u8()? {
+            0xff => None,
+            index => Some(
+                *COMPLETIONS
+                    .get(index as usize)
+                    .ok_or(StateError::Invalid("CD-ROM completion"))?,
+            ),
+        };
+        let pending = r.read_u32()? as usize;
+        if pending > FIFO_SIZE {
+            return Err(StateError::Invalid("CD-ROM pending responses"));
+        }
+        self.pending.clear();
+        for _ in 0..pending {
+            let irq = r.read_u8()?;
+            let mut bytes = VecDeque::new();
+            read_fifo(r, &mut bytes, FIFO_SIZE)?;
+            self.pending.push_back(Response {
+                irq,
+                bytes: bytes.into(),
+            });
+        }
+        self.state = *DRIVE_STATES
+            .get(r.read_u8()? as usize)
+            .ok_or(StateError::Invalid("CD-ROM drive state"))?;
+        self.motor_on = r.read_bool()?;
+        self.lid_open = r.read_bool()?;
+        self.shell_open = r.read_bool()?;
+        self.mode = r.read_u8()?;
+        self.filter_file = r.read_u8()?;
+        self.filter_channel = r.read_u8()?;
+        self.muted = r.read_bool()?;
+        r.read_into(&mut self.volume)?;
+        r.read_into(&mut self.pending_volume)?;
+        self.xa_muted = r.read_bool()?;
+        let has_target = r.read_bool()?;
+        let target = r.read_u32()?;
+        self.seek_target = if has_target { Some(target) } else { None };
+        self.position = r.read_u32()?;
+        self.retries = r.read_u8()?;
+        let has_header = r.read_bool()?;
+        let mut header = [0u8; 8];
+        r.read_into(&mut header)?;
+        self.header = if has_header { Some(header) } else { None };
+        let has_subq = r.read_bool()?;
+        let mut subq = [0u8; 12];
+        r.read_into(&mut subq)?;
+        self.subq = if has_subq { Some(subq) } else { None };
+        self.play_track = r.read_u8()?;
+        self.scan = r.read_u8()? as i8;
+        self.report_channel = r.read_bool()?;
+        self.xa.load_state(r)?;
+        self.sector = read_sector(r)?;
+        self.pending_sector = read_sector(r)?;
+        Ok(())
+    }
+}
+
+// Save the controller state along with its pending events
+pub fn save_state(psx: &Psx, w: &mut StateWriter) {
+    psx.cdrom.save_state(w);
+    for &event in EVENTS.iter() {
+        let remaining = psx.scheduler.remaining(event);
+        w.write_bool(remaining.is_some());
+        w.write_u64(remaining.unwrap_or(0));
+    }
+}
+
+pub fn load_state(psx: &mut Psx, r: &mut StateReader) -> Result<(), StateError> {
+    psx.cdrom.load_state(r)?;
+    for &event in EVENTS.iter() {
+        let pending = r.read_bool()?;
+        let remaining = r.read_u64()?;
+        if pending {
+            psx.scheduler.schedule(event, remaining);
+        } else {
+            psx.scheduler.cancel(event);
+        }
+    }
+    Ok(())
+}
+
+impl Default for CdRom {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Read an 8 bit register at `offset` from 1F801800h
+pub fn load(psx: &mut Psx, offset: u32) -> u8 {
+    let cd = &mut psx.cdrom;
+    match offset & 3 {
+        0 => cd.status(),
+        1 => cd.response.pop_front().unwrap_or(0),
+        2 => cd.read_data(),
+        _ => match cd.index & 1 {
+            0 => cd.irq_enable | 0xe0,
+            _ => cd.irq_flag | 0xe0,
+        },
+    }
+}
+
+// Write an 8 bit register at `offset` from 1F801800h
+pub fn store(psx: &mut Psx, offset: u32, val: u8) {
+    let now = psx.scheduler.now();
+    let cd = &mut psx.cdrom;
+    match (offset & 3, cd.index) {
+        (0, _) => cd.index = val & 3,
+        (1, 0) => {
+            if let Some(trace) = cd.trace.as_mut() {
+                let params: Vec<u8> = cd.params.iter().copied().collect();
+                trace.command(now, val, &params);
+            }
+            cd.command = Some(val);
+            let delay = commands::ack_delay(val);
+            psx.scheduler.schedule(Event::CdromCommand, delay);
+        }
+        (2, 0) if cd.params.len() < FIFO_SIZE => cd.params.push_back(val),
+        (2, 1) => cd.irq_enable = val & 0x1f,
+        (3, 0) => {
+            // BFRD: load the sector buffer into the data FIFO, or clear it
+            if val & 0x80 != 0 {
+                if cd.data.is_empty() {
+                    cd.load_data();
+                }
+            } else {
+                cd.data.clear();
+            }
+        }
+        (3, 1) => {
+            let acknowledged = cd.irq_flag & val & 0x1f;
+            if let (Some(trace), true) = (cd.trace.as_mut(), acknowledged != 0) {
+                trace.acknowledge(now, acknowledged);
+            }
+            cd.irq_flag &= !(val & 0x1f);
+            if val & 0x40 != 0 {
+                cd.params.clear();
+            }
+            if cd.irq_flag == 0 && !cd.pending.is_empty() {
+                psx.scheduler.schedule(Event::CdromDeliver, DELIVER_DELAY);
+            }
+        }
+        // Volume matrix: ATV0 (L->L), ATV1 (L->R), ATV2 (R->R), ATV3 (R->L)
+        (2, 2) => cd.pending_volume[0] = val,
+        (3, 2) => cd.pending_volume[1] = val,
+        (1, 3) => cd.pending_volume[2] = val,
+        (2, 3) => cd.pending_volume[3] = val,
+        (3, 3) => {
+            // ADPCTL: bit 0 mutes XA-ADPCM, bit 5 applies the pending volumes
+            cd.xa_muted = val & 0x01 != 0;
+            if val & 0x20 != 0 {
+                cd.volume = cd.pending_volume;
+            }
+        }
+        _ => {}
+    }
+}
+
+// Queue a response, delivering it right away if no interrupt is pending
+pub fn respond(psx: &mut Psx, irq: u8, bytes: Vec<u8>) {
+    if let Some(trace) = psx.cdrom.trace.as_mut() {
+        trace.response(psx.scheduler.now(), irq, &bytes);
+    }
+    psx.cdrom.pending.push_back(Response { irq, bytes });
+    deliver(psx);
+}
+
+// Deliver the next queued response once the previous one was acknowledged
+pub fn deliver(psx: &mut Psx) {
+    let cd = &mut psx.cdrom;
+    if cd.irq_flag != 0 {
+        return;
+    }
+    if let Some(response) = cd.pending.pop_front() {
+        cd.response.clear();
+        cd.response.extend(response.bytes.iter());
+        cd.irq_flag = response.irq;
+        if let Some(trace) = cd.trace.as_mut() {
+            trace.interrupt(psx.scheduler.now(), response.irq, &response.bytes);
+        }
+        // Data requests now read the sector this INT1 announces
+        if response.irq == INT1_DATA_READY && cd.pending_sector.is_some() {
+            cd.sector = cd.pending_sector.take();
+        }
+        if cd.irq_flag & cd.irq_enable != 0 {
+            psx.irq.request(Interrupt::Cdrom);
+        }
+    }
+}
+
+// Respond with INT3 and the status byte
+fn ack(psx: &mut Psx) {
+    let stat = psx.cdrom.stat();
+    respond(psx, INT3_ACKNOWLEDGE, vec![stat]);
+}
+
+// Respond with INT5, the status byte with the error bit and an error code
+fn error(psx: &mut Psx, code: u8) {
+    if code == ERROR_NOT_READY && !psx.cdrom.disc_ready() {
+        psx.cdrom.disc_wanted = true;
+    }
+    let stat = psx.cdrom.stat() | 0x01;
+    respond(psx, INT5_ERROR, vec![stat, code]);
+}
+
+// Open the lid: the motor stops and any read or play is aborted
+pub fn open_lid(psx: &mut Psx) {
+    let cd = &mut psx.cdrom;
+    if cd.lid_open {
+        return;
+    }
+    let busy = cd.state != DriveState::Idle;
+    cd.lid_open = true;
+    cd.shell_open = true;
+    cd.motor_on = false;
+    cd.sector = None;
+    cd.pending_sector = None;
+    stop_reading(psx);
+    if busy {
+        error(psx, ERROR_DOOR_OPENED);
+    }
+}
+
+// Replace the disc while the lid is open. Returns the previous disc.
+pub fn swap_disc(psx: &mut Psx, disc: Option<Box<dyn Disc>>) -> Option<Box<dyn Disc>> {
+    let cd = &mut psx.cdrom;
+    let old = cd.disc.take();
+    cd.region = None;
+    cd.license = None;
+    cd.system_cnf = None;
+    if let Some(disc) = disc {
+        cd.insert_disc(disc);
+    }
+    old
+}
+
+// Close the lid. The head goes back to the start of the disc and the motor
+// spins up on the next command that needs it.
+pub fn close_lid(psx: &mut Psx) {
+    let cd = &mut psx.cdrom;
+    cd.lid_open = false;
+    cd.move_head(0);
+    cd.seek_target = None;
+}
+
+// Start reading sectors from the Setloc position
+fn start_reading(psx: &mut Psx) {
+    let cd = &mut psx.cdrom;
+    let target = cd.seek_target.take().unwrap_or(cd.position);
+    // The first sector arrives once the head is there and has read it
+    let delay = cd.seek_cycles(target) + cd.sector_cycles();
+    cd.move_head(target);
+    cd.state = DriveState::Seeking;
+    cd.retries = 0;
+    cd.xa.reset();
+    psx.scheduler.schedule(Event::CdromSector, delay);
+}
+
+fn stop_reading(psx: &mut Psx) {
+    psx.cdrom.state = DriveState::Idle;
+    psx.scheduler.cancel(Event::CdromSector);
+}
+
+// Read the sector under the head and report it with INT1, or play it
+fn sector(psx: &mut Psx) {
+    let cd = &mut psx.cdrom;
+    match cd.state {
+        DriveState::Reading => {}
+        // Reached the target of a read
+        DriveState::Seeking => cd.state = DriveState::Reading,
+        DriveState::Playing => return cdda::sector(psx),
+        _ => return,
+    }
+
+    let lba = cd.position;
+    let sector = match cd.read_sector(lba) {
+        Some(sector) if !cd.edc_check || edc_valid(&sector.data) => sector,
+        // Unreadable sector
+        _ => return retry_sector(psx),
+    };
+    cd.position += 1;
+    cd.retries = 0;
+    let mut header = [0u8; 8];
+    header.copy_from_slice(&sector.data[12..20]);
+    cd.header = Some(header);
+
+    let delay = cd.sector_cycles();
+    if cd.is_xa_audio(&sector) {
+        xa::play(psx, &sector);
+    } else {
+        // The sector waits in the buffer until its INT1 is delivered. If the
+        // previous one's INT1 is still queued, the new sector takes its place
+        // and the old one is lost.
+        let queued = cd.pending.iter().any(|r| r.irq == INT1_DATA_READY);
+        cd.pending_sector = Some(sector);
+        if !queued {
+            let stat = cd.stat();
+            respond(psx, INT1_DATA_READY, vec![stat]);
+        }
+    }
+    psx.scheduler.schedule(Event::CdromSector, delay);
+}
+
+// Read the sector under the head again, giving up after a few tries
+fn retry_sector(psx: &mut Psx) {
+    let cd = &mut psx.cdrom;
+    if cd.retries < READ_RETRIES {
+        cd.retries += 1;
+        let delay = cd.sector_cycles();
+        psx.scheduler.schedule(Event::CdromSector, delay);
+        return;
+    }
+    cd.retries = 0;
+    stop_reading(psx);
+    // Error and seek error status bits
+    let stat = psx.cdrom.stat() | 0x05;
+    respond(psx, INT5_ERROR, vec![stat, ERROR_SEEK_FAILED]);
+}
+
+// Run a CD-ROM scheduler event
+pub fn handle_event(psx: &mut Psx, event: Event) {
+    match event {
+        Event::CdromCommand => commands::execute(psx),
+        Event::CdromComplete => commands::complete(psx),
+        Event::CdromSector => sector(psx),
+        Event::CdromDeliver => deliver(psx),
+        _ => {}
+    }
+}
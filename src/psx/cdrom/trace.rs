@@ -0,0 +1,129 @@
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+// Names of the commands, by command byte
+const COMMAND_NAMES: [&str; 0x20] = [
+    "Sync",
+    "Getstat",
+    "Setloc",
+    "Play",
+    "Forward",
+    "Backward",
+    "ReadN",
+    "MotorOn",
+    "Stop",
+    "Pause",
+    "Init",
+    "Mute",
+    "Demute",
+    "Setfilter",
+    "Setmode",
+    "Getparam",
+    "GetlocL",
+    "GetlocP",
+    "SetSession",
+    "GetTN",
+    "GetTD",
+    "SeekL",
+    "SeekP",
+    "SetClock",
+    "GetClock",
+    "Test",
+    "GetID",
+    "ReadS",
+    "Reset",
+    "GetQ",
+    "ReadTOC",
+    "VideoCD",
+];
+
+fn command_name(command: u8) -> &'static str {
+    COMMAND_NAMES
+        .get(command as usize)
+        .copied()
+        .unwrap_or("Unknown")
+}
+
+// Log of the traffic between the CPU and the controller, one JSON object per
+// line with the CPU cycle it happened at:
+//   {"cycle":1234,"event":"command","command":6,"name":"ReadN","params":[]}
+//   {"cycle":1300,"event":"response","irq":3,"bytes":[2]}
+//   {"cycle":1300,"event":"interrupt","irq":3,"bytes":[2]}
+//   {"cycle":1500,"event":"acknowledge","irq":3}
+// Responses are queued by the controller and become interrupts once the
+// previous one is acknowledged.
+pub struct CdTrace {
+    out: Box<dyn Write + Send>,
+    // First error hit while writing; the trace stops at that point
+    error: Option<io::Error>,
+}
+
+impl CdTrace {
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        Self { out, error: None }
+    }
+
+    pub fn command(&mut self, cycle: u64, command: u8, params: &[u8]) {
+        self.write(&format!(
+            r#"{{"cycle":{},"event":"command","command":{},"name":"{}","params":{}}}"#,
+            cycle,
+            command,
+            command_name(command),
+            json_bytes(params)
+        ));
+    }
+
+    pub fn response(&mut self, cycle: u64, irq: u8, bytes: &[u8]) {
+        self.write(&format!(
+            r#"{{"cycle":{},"event":"response","irq":{},"bytes":{}}}"#,
+            cycle,
+            irq,
+            json_bytes(bytes)
+        ));
+    }
+
+    pub fn interrupt(&mut self, cycle: u64, irq: u8, bytes: &[u8]) {
+        self.write(&format!(
+            r#"{{"cycle":{},"event":"interrupt","irq":{},"bytes":{}}}"#,
+            cycle,
+            irq,
+            json_bytes(bytes)
+        ));
+    }
+
+    pub fn acknowledge(&mut self, cycle: u64, irq: u8) {
+        self.write(&format!(
+            r#"{{"cycle":{},"event":"acknowledge","irq":{}}}"#,
+            cycle, irq
+        ));
+    }
+
+    fn write(&mut self, line: &str) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = writeln!(self.out, "{}", line) {
+            self.error = Some(e);
+        }
+    }
+
+    // Flush the output, reporting any error hit while tracing
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+}
+
+fn json_bytes(bytes: &[u8]) -> String {
+    let mut out = String::from("[");
+    for (i, byte) in bytes.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(out, "{}", byte);
+    }
+    out.push(']');
+    out
+}
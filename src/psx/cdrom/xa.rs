@@ -0,0 +1,196 @@
+use crate::psx::audio::SPU_SAMPLE_RATE;
+use crate::psx::disc::RawSector;
+use crate::psx::spu;
+use crate::psx::state::{Savestate, StateError, StateReader, StateWriter};
+use crate::psx::Psx;
+
+// ADPCM prediction filter coefficients
+const POS_TABLE: [i32; 4] = [0, 60, 115, 98];
+const NEG_TABLE: [i32; 4] = [0, 0, -52, -55];
+
+// Sound groups per sector and their size
+const GROUPS: usize = 18;
+const GROUP_SIZE: usize = 128;
+
+// Offset of the first sound group in a raw sector
+const DATA_OFFSET: usize = 24;
+
+// Samples per sound unit
+const UNIT_SAMPLES: usize = 28;
+
+// XA-ADPCM decoder for real-time audio sectors, with its output resampled to
+// the SPU rate
+#[derive(Clone)]
+pub struct XaDecoder {
+    // Last two decoded samples of each channel
+    history: [[i16; 2]; 2],
+    // Resampling position between the two last samples, 16 fractional bits
+    phase: u32,
+    // Last two decoded frames (left, right), for interpolation
+    previous: [i16; 2],
+    current: [i16; 2],
+}
+
+impl XaDecoder {
+    pub fn new() -> Self {
+        Self {
+            history: [[0; 2]; 2],
+            phase: 0,
+            previous: [0; 2],
+            current: [0; 2],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    // Decode the audio sector `sector` (raw 2352 bytes), appending the output
+    // frames at 44100Hz to `output`
+    pub fn decode(&mut self, sector: &[u8], output: &mut Vec<(i16, i16)>) {
+        let coding = sector[19];
+        let stereo = coding & 0x03 == 1;
+        let half_rate = (coding >> 2) & 0x03 == 1;
+        let eight_bit = (coding >> 4) & 0x03 == 1;
+
+        let mut left = Vec::with_capacity(GROUPS * UNIT_SAMPLES * 8);
+        let mut right = Vec::with_capacity(GROUPS * UNIT_SAMPLES * 4);
+        let units = if eight_bit { 4 } else { 8 };
+        for group in sector[DATA_OFFSET..DATA_OFFSET + GROUPS * GROUP_SIZE].chunks_exact(GROUP_SIZE)
+        {
+            for unit in 0..units {
+                // Stereo units alternate between left and right
+                let channel = if stereo { unit & 1 } else { 0 };
+                let samples = self.decode_unit(group, unit, eight_bit, channel);
+                if channel == 0 {
+                    left.extend_from_slice(&samples);
+                } else {
+                    right.extend_from_slice(&samples);
+                }
+            }
+        }
+
+        let rate = if half_rate { 18900 } else { 37800 };
+        let step = ((rate as u64) << 16) / SPU_SAMPLE_RATE as u64;
+        for (i, &sample) in left.iter().enumerate() {
+            let frame = if stereo {
+                [sample, right.get(i).copied().unwrap_or(0)]
+            } else {
+                [sample, sample]
+            };
+            self.previous = self.current;
+            self.current = frame;
+
+            // Linear interpolation between the two last input frames
+            while self.phase < 0x10000 {
+                let t = self.phase as i32;
+                let mix = |a: i16, b: i16| (a as i32 + (((b as i32 - a as i32) * t) >> 16)) as i16;
+                output.push((
+                    mix(self.previous[0], self.current[0]),
+                    mix(self.previous[1], self.current[1]),
+                ));
+                self.phase += step as u32;
+            }
+            self.phase -= 0x10000;
+        }
+    }
+
+    fn decode_unit(
+        &mut self,
+        group: &[u8],
+        unit: usize,
+        eight_bit: bool,
+        channel: usize,
+    ) -> [i16; UNIT_SAMPLES] {
+        let header = group[4 + unit];
+        let mut shift = (header & 0x0f) as u32;
+        if shift > 12 {
+            shift = 9;
+        }
+        let filter = ((header >> 4) & 0x03) as usize;
+
+        let history = &mut self.history[channel];
+        let mut samples = [0i16; UNIT_SAMPLES];
+        for (i, out) in samples.iter_mut().enumerate() {
+            let word = &group[16 + i * 4..16 + i * 4 + 4];
+            let raw = if eight_bit {
+                ((word[unit] as u16) << 8) as i16
+            } else {
+                let byte = word[unit / 2];
+                let nibble = if unit & 1 == 0 {
+                    byte & 0x0f
+                } else {
+                    byte >> 4
+                };
+                ((nibble as u16) << 12) as i16
+            };
+            let sample = (raw as i32) >> shift;
+            let predicted = (history[0] as i32 * POS_TABLE[filter]
+                + history[1] as i32 * NEG_TABLE[filter]
+                + 32)
+                >> 6;
+            let sample = (sample + predicted).clamp(-0x8000, 0x7fff) as i16;
+            history[1] = history[0];
+            history[0] = sample;
+            *out = sample;
+        }
+        samples
+    }
+}
+
+impl Savestate for XaDecoder {
+    fn save_state(&self, w: &mut StateWriter) {
+        for channel in self.history.iter() {
+            w.write_i16(channel[0]);
+            w.write_i16(channel[1]);
+        }
+        w.write_u32(self.phase);
+        for &sample in self.previous.iter().chain(self.current.iter()) {
+            w.write_i16(sample);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        for channel in self.history.iter_mut() {
+            channel[0] = r.read_i16()?;
+            channel[1] = r.read_i16()?;
+        }
+        self.phase = r.read_u32()?;
+        if self.phase >= 0x10000 {
+            return Err(StateError::Invalid("XA resampler phase"));
+        }
+        for sample in self.previous.iter_mut().chain(self.current.iter_mut()) {
+            *sample = r.read_i16()?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for XaDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Send the XA audio sector `sector` to the SPU's CD input, if it passes the
+// file/channel filter
+pub fn play(psx: &mut Psx, sector: &RawSector) {
+    let cd = &mut psx.cdrom;
+    let subheader = sector.subheader();
+    if cd.mode & 0x08 != 0 && (subheader[0] != cd.filter_file || subheader[1] != cd.filter_channel)
+    {
+        return;
+    }
+
+    let mut frames = Vec::new();
+    cd.xa.decode(&sector.data, &mut frames);
+    let muted = cd.muted || cd.xa_muted;
+    for (left, right) in frames {
+        if muted {
+            spu::push_cd_sample(psx, 0, 0);
+        } else {
+            let (left, right) = psx.cdrom.mix(left, right);
+            spu::push_cd_sample(psx, left, right);
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use super::{Error, Psx, Ram};
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// GameShark / Action Replay codes, applied to RAM at every vblank as the
+// cartridge's cheat engine does, without needing its firmware. Codes are
+// "XXXXXXXX YYYY": a code type and a RAM address, then a value.
+//   80aaaaaa vvvv  write the halfword vvvv
+//   30aaaaaa 00vv  write the byte vv
+//   10aaaaaa vvvv  add vvvv to the halfword, 11 subtracts it
+//   20aaaaaa 00vv  add vv to the byte, 21 subtracts it
+//   D0aaaaaa vvvv  run the next code only if the halfword equals vvvv, D1
+//                  if it differs, D2 if it's less, D3 if it's greater
+//   E0aaaaaa 00vv  the same for the byte, E0 to E3
+//   D4000000 vvvv  joker: run the next code only while the buttons held on
+//                  controller 1 are vvvv, L2 0001h, R2 0002h, L1 0004h, R1
+//                  0008h, Triangle 0010h, Circle 0020h, Cross 0040h,
+//                  Square 0080h, Select 0100h, L3 0200h, R3 0400h, Start
+//                  0800h, Up 1000h, Right 2000h, Down 4000h, Left 8000h
+//   C0aaaaaa vvvv  run the rest of the cheat only if the halfword equals
+//                  vvvv
+//   C1000000 vvvv  wait vvvv frames after the cheat is enabled before
+//                  running the rest of it
+//
+// Cheat files list cheats as in PCSX: a "[Name]" line, "[*Name]" for one
+// enabled from the start, then its codes, one per line.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compare {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+}
+
+impl Compare {
+    fn test(self, a: u16, b: u16) -> bool {
+        match self {
+            Compare::Equal => a == b,
+            Compare::NotEqual => a != b,
+            Compare::Less => a < b,
+            Compare::Greater => a > b,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Code {
+    Write16(u32, u16),
+    Write8(u32, u8),
+    // Value added, negative to subtract
+    Add16(u32, i32),
+    Add8(u32, i32),
+    If16(Compare, u32, u16),
+    If8(Compare, u32, u8),
+    IfButtons(u16),
+    // Rest of the cheat only if the halfword is equal
+    Master(u32, u16),
+    // Frames to wait
+    Delay(u16),
+}
+
+impl Code {
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let invalid = || Error::Config(format!("invalid cheat code \"{}\"", text));
+        let hex =
+            |word: &str, len| word.len() == len && word.bytes().all(|b| b.is_ascii_hexdigit());
+        let mut words = text.split_whitespace();
+        let (code, value) = match (words.next(), words.next(), words.next()) {
+            (Some(code), Some(value), None) if hex(code, 8) && hex(value, 4) => (
+                u32::from_str_radix(code, 16).map_err(|_| invalid())?,
+                u16::from_str_radix(value, 16).map_err(|_| invalid())?,
+            ),
+            _ => return Err(invalid()),
+        };
+        let address = code & 0xffffff;
+        let compare = |kind: u32| match kind & 3 {
+            0 => Compare::Equal,
+            1 => Compare::NotEqual,
+            2 => Compare::Less,
+            _ => Compare::Greater,
+        };
+        Ok(match code >> 24 {
+            0x80 => Code::Write16(address, value),
+            0x30 => Code::Write8(address, value as u8),
+            0x10 => Code::Add16(address, value as i32),
+            0x11 => Code::Add16(address, -(value as i32)),
+            0x20 => Code::Add8(address, value as u8 as i32),
+            0x21 => Code::Add8(address, -(value as u8 as i32)),
+            kind @ 0xd0..=0xd3 => Code::If16(compare(kind), address, value),
+            kind @ 0xe0..=0xe3 => Code::If8(compare(kind), address, value as u8),
+            0xd4 => Code::IfButtons(value),
+            0xc0 => Code::Master(address, value),
+            0xc1 => Code::Delay(value),
+            _ => return Err(invalid()),
+        })
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let compare = |compare: Compare| compare as u32;
+        let (code, value) = match *self {
+            Code::Write16(address, value) => (0x80 << 24 | address, value),
+            Code::Write8(address, value) => (0x30 << 24 | address, value as u16),
+            Code::Add16(address, value) if value < 0 => (0x11 << 24 | address, -value as u16),
+            Code::Add16(address, value) => (0x10 << 24 | address, value as u16),
+            Code::Add8(address, value) if value < 0 => (0x21 << 24 | address, -value as u16),
+            Code::Add8(address, value) => (0x20 << 24 | address, value as u16),
+            Code::If16(kind, address, value) => ((0xd0 | compare(kind)) << 24 | address, value),
+            Code::If8(kind, address, value) => {
+                ((0xe0 | compare(kind)) << 24 | address, value as u16)
+            }
+            Code::IfButtons(value) => (0xd4 << 24, value),
+            Code::Master(address, value) => (0xc0 << 24 | address, value),
+            Code::Delay(value) => (0xc1 << 24, value),
+        };
+        write!(f, "{:08X} {:04X}", code, value)
+    }
+}
+
+// A named group of codes, turned on and off together
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cheat {
+    pub name: String,
+    pub codes: Vec<Code>,
+    enabled: bool,
+    // Frames left before a delay code lets the rest run, once reached
+    wait: Option<u16>,
+}
+
+impl Cheat {
+    // Cheat with codes one per line
+    pub fn new(name: &str, codes: &str) -> Result<Self, Error> {
+        let codes = codes
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(Code::parse)
+            .collect::<Result<_, _>>()?;
+        Ok(Self::from_codes(name, codes))
+    }
+
+    pub fn from_codes(name: &str, codes: Vec<Code>) -> Self {
+        Self {
+            name: name.to_string(),
+            codes,
+            enabled: false,
+            wait: None,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn apply(&mut self, ram: &mut Ram, buttons: u16) {
+        let mut skip = false;
+        for code in self.codes.iter() {
+            if std::mem::take(&mut skip) {
+                continue;
+            }
+            match *code {
+                Code::Write16(address, value) => ram.store(address, value),
+                Code::Write8(address, value) => ram.store(address, value),
+                Code::Add16(address, value) => {
+                    let old = ram.load::<u16>(address);
+                    ram.store(address, old.wrapping_add(value as u16));
+                }
+                Code::Add8(address, value) => {
+                    let old = ram.load::<u8>(address);
+                    ram.store(address, old.wrapping_add(value as u8));
+                }
+                Code::If16(compare, address, value) => {
+                    skip = !compare.test(ram.load::<u16>(address), value)
+                }
+                Code::If8(compare, address, value) => {
+                    skip = !compare.test(ram.load::<u8>(address) as u16, value as u16)
+                }
+                Code::IfButtons(value) => skip = buttons != value,
+                Code::Master(address, value) => {
+                    if ram.load::<u16>(address) != value {
+                        return;
+                    }
+                }
+                Code::Delay(frames) => {
+                    let wait = self.wait.get_or_insert(frames);
+                    if *wait > 0 {
+                        *wait -= 1;
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut cheats = Self::new();
+        let mut current: Option<(String, bool, String)> = None;
+        for line in text.lines().map(str::trim) {
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                cheats.push(current.take())?;
+                current = Some(match name.strip_prefix('*') {
+                    Some(name) => (name.to_string(), true, String::new()),
+                    None => (name.to_string(), false, String::new()),
+                });
+            } else if !line.is_empty() {
+                let codes = match current.as_mut() {
+                    Some((_, _, codes)) => codes,
+                    None => return Err(Error::Config("cheat code before a cheat name".into())),
+                };
+                codes.push_str(line);
+                codes.push('\n');
+            }
+        }
+        cheats.push(current)?;
+        Ok(cheats)
+    }
+
+    fn push(&mut self, cheat: Option<(String, bool, String)>) -> Result<(), Error> {
+        if let Some((name, enabled, codes)) = cheat {
+            let mut cheat = Cheat::new(&name, &codes)?;
+            cheat.enabled = enabled;
+            self.cheats.push(cheat);
+        }
+        Ok(())
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::parse(&fs::read_to_string(path).map_err(Error::Io)?)
+    }
+
+    // The cheats in the format parse reads
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for cheat in self.cheats.iter() {
+            let star = if cheat.enabled { "*" } else { "" };
+            text.push_str(&format!("[{}{}]\n", star, cheat.name));
+            for code in cheat.codes.iter() {
+                text.push_str(&format!("{}\n", code));
+            }
+        }
+        text
+    }
+
+    // Add a cheat, disabled. Returns its index.
+    pub fn add(&mut self, cheat: Cheat) -> usize {
+        self.cheats.push(cheat);
+        self.cheats.len() - 1
+    }
+
+    pub fn remove(&mut self, index: usize) -> Cheat {
+        self.cheats.remove(index)
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn len(&self) -> usize {
+        self.cheats.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    // Turn a cheat on or off. Delays start over when it's turned on.
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        let cheat = &mut self.cheats[index];
+        if enabled && !cheat.enabled {
+            cheat.wait = None;
+        }
+        cheat.enabled = enabled;
+    }
+}
+
+// Run the enabled cheats and game patches, at the start of vblank
+pub fn vblank(psx: &mut Psx) {
+    let enabled = |cheats: &Cheats| cheats.cheats.iter().any(|cheat| cheat.enabled);
+    if !enabled(&psx.cheats) && !enabled(&psx.patches) {
+        return;
+    }
+    let buttons = psx
+        .sio
+        .input(0)
+        .map_or(0, |input| (!input.button_bits()).swap_bytes());
+    let cheats = psx.cheats.cheats.iter_mut();
+    for cheat in cheats.chain(psx.patches.cheats.iter_mut()) {
+        if cheat.enabled {
+            cheat.apply(&mut psx.ram, buttons);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_round_trip() {
+        let lines = [
+            "80012340 BEEF",
+            "30012341 00A5",
+            "10012340 0100",
+            "11012340 0100",
+            "20012341 0010",
+            "21012341 0010",
+            "D0012340 1234",
+            "D1012340 1234",
+            "D2012340 1234",
+            "D3012340 1234",
+            "E0012341 0012",
+            "E1012341 0012",
+            "E2012341 0012",
+            "E3012341 0012",
+            "D4000000 0840",
+            "C0012340 5678",
+            "C1000000 003C",
+        ];
+        for line in lines.iter() {
+            let code = Code::parse(line).unwrap();
+            assert_eq!(code.to_string(), *line);
+        }
+        // Lowercase digits and extra spaces are accepted
+        assert_eq!(
+            Code::parse("  d2012340   beef ").unwrap(),
+            Code::If16(Compare::Less, 0x012340, 0xbeef)
+        );
+    }
+
+    #[test]
+    fn subtracting_codes() {
+        assert_eq!(
+            Code::parse("11012340 0100").unwrap(),
+            Code::Add16(0x012340, -0x100)
+        );
+        assert_eq!(
+            Code::parse("21012341 0010").unwrap(),
+            Code::Add8(0x012341, -0x10)
+        );
+        assert_eq!(Code::Add16(0x012340, -0xffff).to_string(), "11012340 FFFF");
+        assert_eq!(Code::Add8(0x012341, -1).to_string(), "21012341 0001");
+        assert_eq!(Code::Add8(0x012341, 1).to_string(), "20012341 0001");
+    }
+
+    #[test]
+    fn malformed_codes_are_rejected() {
+        let lines = [
+            "",
+            "80012340",
+            "80012340 BEEF 0000",
+            "8001234 BEEF",
+            "800123400 BEEF",
+            "80012340 BEE",
+            "80012340 BEEFF",
+            "8001234G BEEF",
+            "80012340 BEEX",
+            "+8012340 BEEF",
+            "80012340 +EEF",
+            "40012340 BEEF",
+            "D5000000 0000",
+            "C2000000 0000",
+        ];
+        for line in lines.iter() {
+            assert!(Code::parse(line).is_err(), "accepted \"{}\"", line);
+        }
+    }
+
+    #[test]
+    fn cheat_files_round_trip() {
+        let text = "[Infinite health]\n\
+                    80012340 03E7\n\
+                    [*Jump with L2]\n\
+                    D4000000 0001\n\
+                    80012344 0000\n";
+        let cheats = Cheats::parse(text).unwrap();
+        assert_eq!(cheats.len(), 2);
+        assert!(!cheats.cheats()[0].enabled());
+        assert!(cheats.cheats()[1].enabled());
+        assert_eq!(cheats.cheats()[1].name, "Jump with L2");
+        assert_eq!(cheats.to_text(), text);
+
+        // Blank lines and indentation don't matter
+        let loose = "\n  [Infinite health]\n\n  80012340 03e7\n";
+        assert_eq!(
+            Cheats::parse(loose).unwrap().cheats()[0].codes,
+            [Code::Write16(0x012340, 0x03e7)]
+        );
+    }
+
+    #[test]
+    fn malformed_cheat_files_are_rejected() {
+        assert!(Cheats::parse("80012340 03E7\n").is_err());
+        assert!(Cheats::parse("[Broken]\n80012340 03E7 FF\n").is_err());
+        assert!(Cheats::parse("[Broken]\nwrite 03E7\n").is_err());
+    }
+}
@@ -1,4 +1,5 @@
-use super::Psx;
+use super::state::{Savestate, StateError, StateReader, StateWriter};
+use super::{bios_trace, hle, tty, Psx};
 
 use std::fmt;
 
@@ -39,6 +40,7 @@ impl Cpu {
     }
 
     // Set the given register
+    #[allow(dead_code)]
     fn set_reg(&mut self, reg: usize, val: u32) {
         self.regs[reg] = val;
         // R0 is always zero
@@ -46,6 +48,7 @@ impl Cpu {
     }
 
     // Perform a delayed load, if any
+    #[allow(dead_code)]
     fn delayed_load(&mut self) {
         if let Some((reg, val)) = self.delayed_load {
             self.set_reg(reg, val);
@@ -54,6 +57,60 @@ impl Cpu {
     }
 }
 
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Savestate for Cpu {
+    fn save_state(&self, w: &mut StateWriter) {
+        for &reg in self.regs.iter() {
+            w.write_u32(reg);
+        }
+        w.write_u32(self.current_pc);
+        w.write_u32(self.pc);
+        w.write_u32(self.next_pc);
+        let (reg, val) = self.delayed_load.unwrap_or((0, 0));
+        w.write_bool(self.delayed_load.is_some());
+        w.write_u8(reg as u8);
+        w.write_u32(val);
+        w.write_u32(self.hi);
+        w.write_u32(self.lo);
+        for line in self.icache.iter() {
+            w.write_u32(line.info);
+            for instruction in line.line.iter() {
+                w.write_u32(instruction.0);
+            }
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        for reg in self.regs.iter_mut() {
+            *reg = r.read_u32()?;
+        }
+        self.current_pc = r.read_u32()?;
+        self.pc = r.read_u32()?;
+        self.next_pc = r.read_u32()?;
+        let delayed = r.read_bool()?;
+        let reg = r.read_u8()? as usize;
+        let val = r.read_u32()?;
+        if reg >= 32 {
+            return Err(StateError::Invalid("CPU delayed load register"));
+        }
+        self.delayed_load = if delayed { Some((reg, val)) } else { None };
+        self.hi = r.read_u32()?;
+        self.lo = r.read_u32()?;
+        for line in self.icache.iter_mut() {
+            line.info = r.read_u32()?;
+            for instruction in line.line.iter_mut() {
+                *instruction = Instruction(r.read_u32()?);
+            }
+        }
+        Ok(())
+    }
+}
+
 impl fmt::Display for Cpu {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "pc: 0x{:08x}", self.pc)?;
@@ -64,16 +121,28 @@ impl fmt::Display for Cpu {
     }
 }
 
-pub fn step(psx: &mut Psx) {}
+pub fn step(psx: &mut Psx) {
+    // The HLE BIOS runs kernel calls natively when the CPU reaches a table,
+    // the real one only has its TTY output caught
+    if hle::is_call(psx.cpu.pc) {
+        bios_trace::call(psx);
+        match psx.hle {
+            Some(_) => hle::call(psx),
+            None => tty::bios_call(psx),
+        }
+    }
+    bios_trace::step(psx);
+}
 
 // TODO: Fetch an instruction from memory
+#[allow(dead_code)]
 fn fetch_instruction(psx: &mut Psx) -> Instruction {
     let pc = psx.cpu.current_pc;
     let cached = pc < 0xa0000000;
 
     if cached && psx.code_cache_enabled() {
         let line = ((pc >> 4) & 0xff) as usize;
-        let mut cache_line = psx.cpu.icache[line];
+        let cache_line = psx.cpu.icache[line];
 
         let tag = pc & 0x7ffff000;
         let index = (pc >> 2) & 3;
@@ -89,6 +158,7 @@ fn fetch_instruction(psx: &mut Psx) -> Instruction {
 
 // Instruction cache line
 #[derive(Clone, Copy)]
+#[allow(dead_code)]
 struct ICacheLine {
     // Tag and valid bits
     pub info: u32,
@@ -96,6 +166,7 @@ struct ICacheLine {
     pub line: [Instruction; 4],
 }
 
+#[allow(dead_code)]
 impl ICacheLine {
     pub fn new() -> Self {
         Self {
@@ -110,7 +181,7 @@ impl ICacheLine {
     }
 
     // Is the valid bit set?
-    pub fn is_valid(&self, index: u32) -> bool {
+    pub fn is_valid(&self, _index: u32) -> bool {
         true
     }
 }
@@ -160,7 +231,7 @@ impl Instruction {
     }
 }
 
-const REG_NAMES: [&'static str; 32] = [
+const REG_NAMES: [&str; 32] = [
     "r0", "at", "v0", "v1", "a0", "a1", "a2", "a3", "t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7",
     "s0", "s1", "s2", "s3", "s4", "s5", "s7", "s7", "t8", "t9", "k0", "k1", "gp", "sp", "fp", "ra",
 ];
@@ -0,0 +1,226 @@
+use super::frame;
+use super::{Error, Psx};
+
+use std::fmt::Write;
+use std::ops::Range;
+
+// Human readable dump of the machine state as JSON, for bug reports and for
+// diffing against other emulators: CPU registers, the I/O registers of each
+// peripheral and chosen memory ranges. Values are hex strings, so dumps
+// diff line by line. The CPU core has no COP0 or GTE state yet, so neither
+// is in the dump.
+//
+// I/O registers are read through the bus, as the CPU would, on a copy of
+// the state that's thrown away after: reads that pop FIFOs or clear flags
+// leave the machine as it was.
+
+const REG_NAMES: [&str; 32] = [
+    "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3", "t0", "t1", "t2", "t3", "t4", "t5", "t6",
+    "t7", "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "t8", "t9", "k0", "k1", "gp", "sp", "fp",
+    "ra",
+];
+
+const DMA_CHANNELS: [&str; 7] = ["MDECin", "MDECout", "GPU", "CDROM", "SPU", "PIO", "OTC"];
+
+const SPU_VOICE_REGS: [&str; 8] = [
+    "VOLL", "VOLR", "PITCH", "START", "ADSR_LO", "ADSR_HI", "ADSR_VOL", "REPEAT",
+];
+
+// SPU control registers at 1F801D80h and up
+const SPU_REGS: [(u32, &str); 22] = [
+    (0x1f801d80, "MAIN_VOLL"),
+    (0x1f801d82, "MAIN_VOLR"),
+    (0x1f801d84, "REVERB_VOLL"),
+    (0x1f801d86, "REVERB_VOLR"),
+    (0x1f801d88, "KON_LO"),
+    (0x1f801d8a, "KON_HI"),
+    (0x1f801d8c, "KOFF_LO"),
+    (0x1f801d8e, "KOFF_HI"),
+    (0x1f801d90, "PMON_LO"),
+    (0x1f801d92, "PMON_HI"),
+    (0x1f801d94, "NON_LO"),
+    (0x1f801d96, "NON_HI"),
+    (0x1f801d98, "EON_LO"),
+    (0x1f801d9a, "EON_HI"),
+    (0x1f801d9c, "ENDX_LO"),
+    (0x1f801d9e, "ENDX_HI"),
+    (0x1f801da2, "REVERB_BASE"),
+    (0x1f801da4, "IRQ_ADDRESS"),
+    (0x1f801da6, "TRANSFER_ADDRESS"),
+    (0x1f801daa, "SPUCNT"),
+    (0x1f801dac, "TRANSFER_CONTROL"),
+    (0x1f801dae, "SPUSTAT"),
+];
+
+// JSON object being written, with a comma before every member but the first
+struct Object<'a> {
+    out: &'a mut String,
+    first: bool,
+    // Nesting level, for indentation
+    depth: usize,
+}
+
+impl<'a> Object<'a> {
+    fn new(out: &'a mut String, depth: usize) -> Self {
+        out.push('{');
+        Self {
+            out,
+            first: true,
+            depth,
+        }
+    }
+
+    fn newline(&mut self, depth: usize) {
+        self.out.push('\n');
+        for _ in 0..depth {
+            self.out.push_str("  ");
+        }
+    }
+
+    fn key(&mut self, key: &str) -> &mut String {
+        if !self.first {
+            self.out.push(',');
+        }
+        self.first = false;
+        self.newline(self.depth + 1);
+        let _ = write!(self.out, "\"{}\": ", key);
+        self.out
+    }
+
+    fn hex(&mut self, key: &str, val: u32, digits: usize) {
+        let _ = write!(self.key(key), "\"0x{:01$x}\"", val, digits);
+    }
+
+    fn number(&mut self, key: &str, val: u64) {
+        let _ = write!(self.key(key), "{}", val);
+    }
+
+    fn object(&mut self, key: &str) -> Object<'_> {
+        let depth = self.depth + 1;
+        Object::new(self.key(key), depth)
+    }
+
+    fn end(mut self) {
+        self.newline(self.depth);
+        self.out.push('}');
+    }
+}
+
+pub fn dump(psx: &mut Psx, ranges: &[Range<u32>]) -> Result<String, Error> {
+    let snapshot = psx.snapshot();
+    let mut out = String::new();
+    frame::unseen(psx, |psx| write_dump(psx, ranges, &mut out));
+    psx.restore(&snapshot)
+        .map_err(|e| Error::Internal(format!("can't go back after a debug dump: {}", e)))?;
+    Ok(out)
+}
+
+fn write_dump(psx: &mut Psx, ranges: &[Range<u32>], out: &mut String) {
+    let mut root = Object::new(out, 0);
+    root.number("frame", psx.frames);
+    root.number("cycle", psx.scheduler.now());
+
+    let cpu = &psx.cpu;
+    let mut o = root.object("cpu");
+    o.hex("pc", cpu.pc, 8);
+    o.hex("next_pc", cpu.next_pc, 8);
+    o.hex("current_pc", cpu.current_pc, 8);
+    o.hex("hi", cpu.hi, 8);
+    o.hex("lo", cpu.lo, 8);
+    let mut regs = o.object("regs");
+    for (name, &val) in REG_NAMES.iter().zip(cpu.regs.iter()) {
+        regs.hex(name, val, 8);
+    }
+    regs.end();
+    match cpu.delayed_load {
+        Some((reg, val)) => {
+            let mut load = o.object("delayed_load");
+            load.hex(REG_NAMES[reg], val, 8);
+            load.end();
+        }
+        None => o.key("delayed_load").push_str("null"),
+    }
+    o.end();
+    root.hex("cache_control", psx.cache_control, 8);
+
+    let mut io = root.object("io");
+    let mut o = io.object("irq");
+    o.hex("I_STAT", psx.load::<u32>(0x1f801070), 4);
+    o.hex("I_MASK", psx.load::<u32>(0x1f801074), 4);
+    o.end();
+
+    let mut o = io.object("dma");
+    for (i, name) in DMA_CHANNELS.iter().enumerate() {
+        let base = 0x1f801080 + 0x10 * i as u32;
+        o.hex(&format!("{}_MADR", name), psx.load::<u32>(base), 8);
+        o.hex(&format!("{}_BCR", name), psx.load::<u32>(base + 4), 8);
+        o.hex(&format!("{}_CHCR", name), psx.load::<u32>(base + 8), 8);
+    }
+    o.hex("DPCR", psx.load::<u32>(0x1f8010f0), 8);
+    o.hex("DICR", psx.load::<u32>(0x1f8010f4), 8);
+    o.end();
+
+    let mut o = io.object("timers");
+    for i in 0..3 {
+        let base = 0x1f801100 + 0x10 * i;
+        o.hex(&format!("T{}_VALUE", i), psx.load::<u32>(base), 4);
+        o.hex(&format!("T{}_MODE", i), psx.load::<u32>(base + 4), 4);
+        o.hex(&format!("T{}_TARGET", i), psx.load::<u32>(base + 8), 4);
+    }
+    o.end();
+
+    let mut o = io.object("gpu");
+    o.hex("GPUSTAT", psx.load::<u32>(0x1f801814), 8);
+    o.end();
+
+    let mut o = io.object("mdec");
+    o.hex("MDEC_STATUS", psx.load::<u32>(0x1f801824), 8);
+    o.end();
+
+    let mut o = io.object("cdrom");
+    o.hex("STATUS", psx.load::<u8>(0x1f801800) as u32, 2);
+    o.end();
+
+    for &(name, base) in [("sio0", 0x1f801040), ("sio1", 0x1f801050)].iter() {
+        let mut o = io.object(name);
+        o.hex("STAT", psx.load::<u32>(base + 4), 8);
+        o.hex("MODE", psx.load::<u16>(base + 8) as u32, 4);
+        o.hex("CTRL", psx.load::<u16>(base + 0xa) as u32, 4);
+        o.hex("BAUD", psx.load::<u16>(base + 0xe) as u32, 4);
+        o.end();
+    }
+
+    let mut o = io.object("spu");
+    for &(addr, name) in SPU_REGS.iter() {
+        o.hex(name, psx.load::<u16>(addr) as u32, 4);
+    }
+    for voice in 0..24 {
+        let base = 0x1f801c00 + 0x10 * voice;
+        for (i, name) in SPU_VOICE_REGS.iter().enumerate() {
+            let val = psx.load::<u16>(base + 2 * i as u32) as u32;
+            o.hex(&format!("V{}_{}", voice, name), val, 4);
+        }
+    }
+    o.end();
+    io.end();
+
+    let memory = root.key("memory");
+    memory.push('[');
+    for (i, range) in ranges.iter().enumerate() {
+        if i > 0 {
+            memory.push(',');
+        }
+        let _ = write!(
+            memory,
+            "\n    {{\"start\": \"0x{:08x}\", \"data\": \"",
+            range.start
+        );
+        for addr in range.clone() {
+            let _ = write!(memory, "{:02x}", psx.load::<u8>(addr));
+        }
+        memory.push_str("\"}");
+    }
+    memory.push_str("\n  ]");
+    root.end();
+    out.push('\n');
+}
@@ -0,0 +1,123 @@
+// Disc images stored inside ZIP and 7z archives
+
+#[cfg(feature = "sevenz")]
+mod sevenz;
+#[cfg(feature = "zip")]
+mod zip;
+
+use super::{invalid_data, BinCue, Disc, ImageFile, Iso};
+
+use std::io::{self, Read};
+use std::path::Path;
+
+// Image types by preference when an archive holds several candidates
+const IMAGE_EXTENSIONS: [&str; 5] = ["cue", "chd", "iso", "bin", "img"];
+
+enum Archive {
+    #[cfg(feature = "zip")]
+    Zip(zip::ZipArchive),
+    #[cfg(feature = "sevenz")]
+    SevenZ(sevenz::SevenZArchive),
+}
+
+impl Archive {
+    fn names(&self) -> Vec<String> {
+        match *self {
+            #[cfg(feature = "zip")]
+            Archive::Zip(ref zip) => zip.names(),
+            #[cfg(feature = "sevenz")]
+            Archive::SevenZ(ref sevenz) => sevenz.names(),
+        }
+    }
+
+    // Open the entry `name`, ignoring case and path separator differences
+    fn open(&mut self, name: &str) -> io::Result<Box<dyn ImageFile>> {
+        let wanted = normalize(name);
+        let name = self
+            .names()
+            .into_iter()
+            .find(|entry| normalize(entry) == wanted)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} isn't in the archive", name),
+                )
+            })?;
+        match *self {
+            #[cfg(feature = "zip")]
+            Archive::Zip(ref mut zip) => zip.open_entry(&name),
+            #[cfg(feature = "sevenz")]
+            Archive::SevenZ(ref mut sevenz) => sevenz.open_entry(&name),
+        }
+    }
+}
+
+// Is `path` an archive that can hold a disc image?
+pub fn is_archive(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    match extension.as_deref() {
+        #[cfg(feature = "zip")]
+        Some("zip") => true,
+        #[cfg(feature = "sevenz")]
+        Some("7z") => true,
+        _ => false,
+    }
+}
+
+// Open the disc image inside the archive at `path`
+pub fn open(path: &Path) -> io::Result<Box<dyn Disc>> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    let mut archive = match extension.as_deref() {
+        #[cfg(feature = "zip")]
+        Some("zip") => Archive::Zip(zip::ZipArchive::open(path)?),
+        #[cfg(feature = "sevenz")]
+        Some("7z") => Archive::SevenZ(sevenz::SevenZArchive::open(path)?),
+        _ => return Err(invalid_data(format!("not an archive: {}", path.display()))),
+    };
+
+    let names = archive.names();
+    let image = IMAGE_EXTENSIONS
+        .iter()
+        .find_map(|&wanted| {
+            names.iter().find(|name| {
+                Path::new(name)
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case(wanted))
+            })
+        })
+        .cloned()
+        .ok_or_else(|| invalid_data("archive holds no disc image"))?;
+
+    let extension = image.rsplit('.').next().unwrap_or("").to_ascii_lowercase();
+    match extension.as_str() {
+        "cue" => {
+            let mut sheet = Vec::new();
+            archive.open(&image)?.read_to_end(&mut sheet)?;
+            let sheet = String::from_utf8_lossy(&sheet);
+            // Files are relative to the cue sheet's directory in the archive
+            let dir = match image.rfind(['/', '\\']) {
+                Some(end) => &image[..=end],
+                None => "",
+            };
+            let disc = BinCue::from_sheet(&sheet, |name| {
+                archive.open(&format!("{}{}", dir, name.display()))
+            })?;
+            Ok(Box::new(disc))
+        }
+        #[cfg(feature = "chd")]
+        "chd" => Ok(Box::new(super::ChdImage::from_file(archive.open(&image)?)?)),
+        #[cfg(not(feature = "chd"))]
+        "chd" => Err(invalid_data("CHD support isn't enabled")),
+        _ => Ok(Box::new(Iso::from_file(archive.open(&image)?)?)),
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.replace('\\', "/").to_ascii_lowercase()
+}
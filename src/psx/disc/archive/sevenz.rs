@@ -0,0 +1,57 @@
+use super::super::{invalid_data, ImageFile};
+
+use sevenz_rust::{Password, SevenZReader};
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+
+// 7z archive. Entries are decompressed into memory when opened, since solid
+// archives can't be read from the middle.
+pub struct SevenZArchive {
+    path: PathBuf,
+    names: Vec<String>,
+}
+
+impl SevenZArchive {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let reader = SevenZReader::open(path, Password::empty()).map_err(sevenz_error)?;
+        let names = reader
+            .archive()
+            .files
+            .iter()
+            .filter(|entry| !entry.is_directory())
+            .map(|entry| entry.name().to_string())
+            .collect();
+        Ok(Self {
+            path: path.to_owned(),
+            names,
+        })
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.names.clone()
+    }
+
+    pub fn open_entry(&mut self, name: &str) -> io::Result<Box<dyn ImageFile>> {
+        let mut reader = SevenZReader::open(&self.path, Password::empty()).map_err(sevenz_error)?;
+        let mut data = None;
+        reader
+            .for_each_entries(|entry, entry_reader| {
+                if entry.name() != name {
+                    return Ok(true);
+                }
+                let mut buf = Vec::with_capacity(entry.size() as usize);
+                entry_reader
+                    .read_to_end(&mut buf)
+                    .map_err(sevenz_rust::Error::io)?;
+                data = Some(buf);
+                Ok(false)
+            })
+            .map_err(sevenz_error)?;
+        let data = data.ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        Ok(Box::new(Cursor::new(data)))
+    }
+}
+
+fn sevenz_error(err: sevenz_rust::Error) -> io::Error {
+    invalid_data(format!("7z: {}", err))
+}
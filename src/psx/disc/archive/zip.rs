@@ -0,0 +1,219 @@
+use super::super::{invalid_data, ImageFile};
+
+use flate2::read::DeflateDecoder;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Take};
+use std::path::{Path, PathBuf};
+
+// Record signatures
+const END_OF_DIRECTORY: u32 = 0x0605_4b50;
+const DIRECTORY_ENTRY: u32 = 0x0201_4b50;
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+
+// Compression methods
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+
+// Bytes inflated at a time when a read goes past the decoded data
+const INFLATE_CHUNK: usize = 0x10000;
+
+struct Entry {
+    name: String,
+    method: u16,
+    compressed_size: u64,
+    size: u64,
+    // Offset of the local file header
+    header: u64,
+}
+
+// ZIP archive. Entries are read in place: stored ones are seeked directly,
+// deflated ones are inflated only as far as reads reach.
+pub struct ZipArchive {
+    path: PathBuf,
+    entries: Vec<Entry>,
+}
+
+impl ZipArchive {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let len = file.seek(SeekFrom::End(0))?;
+
+        // The end of directory record is followed by a comment of up to 64KB
+        let tail_len = len.min(22 + 0xffff);
+        let mut tail = vec![0u8; tail_len as usize];
+        file.seek(SeekFrom::Start(len - tail_len))?;
+        file.read_exact(&mut tail)?;
+        let end = (0..tail.len().saturating_sub(21))
+            .rev()
+            .find(|&i| u32_at(&tail, i) == END_OF_DIRECTORY)
+            .ok_or_else(|| invalid_data("not a ZIP archive"))?;
+        let count = u16_at(&tail, end + 10) as usize;
+        let directory_size = u32_at(&tail, end + 12) as usize;
+        let directory_offset = u32_at(&tail, end + 16) as u64;
+        if directory_offset == 0xffff_ffff {
+            return Err(invalid_data("ZIP64 archives aren't supported"));
+        }
+
+        let mut directory = vec![0u8; directory_size];
+        file.seek(SeekFrom::Start(directory_offset))?;
+        file.read_exact(&mut directory)?;
+
+        let mut entries = Vec::with_capacity(count);
+        let mut pos = 0;
+        for _ in 0..count {
+            if pos + 46 > directory.len() || u32_at(&directory, pos) != DIRECTORY_ENTRY {
+                return Err(invalid_data("corrupt ZIP central directory"));
+            }
+            let name_len = u16_at(&directory, pos + 28) as usize;
+            let extra_len = u16_at(&directory, pos + 30) as usize;
+            let comment_len = u16_at(&directory, pos + 32) as usize;
+            let name = directory
+                .get(pos + 46..pos + 46 + name_len)
+                .ok_or_else(|| invalid_data("corrupt ZIP central directory"))?;
+            entries.push(Entry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                method: u16_at(&directory, pos + 10),
+                compressed_size: u32_at(&directory, pos + 20) as u64,
+                size: u32_at(&directory, pos + 24) as u64,
+                header: u32_at(&directory, pos + 42) as u64,
+            });
+            pos += 46 + name_len + extra_len + comment_len;
+        }
+
+        Ok(Self {
+            path: path.to_owned(),
+            entries,
+        })
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .filter(|entry| !entry.name.ends_with('/'))
+            .map(|entry| entry.name.clone())
+            .collect()
+    }
+
+    pub fn open_entry(&mut self, name: &str) -> io::Result<Box<dyn ImageFile>> {
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+
+        let mut file = File::open(&self.path)?;
+        let mut header = [0u8; 30];
+        file.seek(SeekFrom::Start(entry.header))?;
+        file.read_exact(&mut header)?;
+        if u32_at(&header, 0) != LOCAL_HEADER {
+            return Err(invalid_data("corrupt ZIP local header"));
+        }
+        let data = entry.header + 30 + u16_at(&header, 26) as u64 + u16_at(&header, 28) as u64;
+
+        match entry.method {
+            METHOD_STORED => Ok(Box::new(Stored {
+                file,
+                start: data,
+                len: entry.size,
+                pos: 0,
+            })),
+            METHOD_DEFLATE => {
+                file.seek(SeekFrom::Start(data))?;
+                let compressed = BufReader::new(file).take(entry.compressed_size);
+                Ok(Box::new(Inflated {
+                    decoder: DeflateDecoder::new(compressed),
+                    data: Vec::new(),
+                    len: entry.size,
+                    pos: 0,
+                }))
+            }
+            method => Err(invalid_data(format!(
+                "unsupported ZIP compression method {}",
+                method
+            ))),
+        }
+    }
+}
+
+// Uncompressed entry, read straight from the archive
+struct Stored {
+    file: File,
+    start: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl Read for Stored {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.len.saturating_sub(self.pos);
+        let count = (buf.len() as u64).min(left) as usize;
+        self.file.seek(SeekFrom::Start(self.start + self.pos))?;
+        let count = self.file.read(&mut buf[..count])?;
+        self.pos += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for Stored {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = seek_position(pos, self.pos, self.len)?;
+        Ok(self.pos)
+    }
+}
+
+// Deflated entry, inflated into memory as far as reads have reached
+struct Inflated {
+    decoder: DeflateDecoder<Take<BufReader<File>>>,
+    data: Vec<u8>,
+    len: u64,
+    pos: u64,
+}
+
+impl Read for Inflated {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let end = (self.pos + buf.len() as u64).min(self.len) as usize;
+        while self.data.len() < end {
+            let start = self.data.len();
+            self.data.resize(start + INFLATE_CHUNK, 0);
+            let count = self.decoder.read(&mut self.data[start..])?;
+            self.data.truncate(start + count);
+            if count == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        let start = (self.pos as usize).min(end);
+        let count = end - start;
+        buf[..count].copy_from_slice(&self.data[start..end]);
+        self.pos += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for Inflated {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = seek_position(pos, self.pos, self.len)?;
+        Ok(self.pos)
+    }
+}
+
+fn seek_position(pos: SeekFrom, current: u64, len: u64) -> io::Result<u64> {
+    let pos = match pos {
+        SeekFrom::Start(pos) => Some(pos),
+        SeekFrom::End(delta) => len.checked_add_signed(delta),
+        SeekFrom::Current(delta) => current.checked_add_signed(delta),
+    };
+    pos.ok_or_else(|| invalid_data("seek before the start of the entry"))
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
@@ -0,0 +1,201 @@
+use super::{invalid_data, ImageFile, SECTOR_SIZE};
+
+use std::convert::TryInto;
+use std::io::{self, Read, Seek, SeekFrom};
+
+// Length of `bytes` of audio rounded up to whole sectors, the end of the last
+// one reading as silence
+fn padded_len(bytes: u64) -> u64 {
+    let sector = SECTOR_SIZE as u64;
+    bytes.div_ceil(sector) * sector
+}
+
+fn seek_to(pos: &mut u64, len: u64, from: SeekFrom) -> io::Result<u64> {
+    let new = match from {
+        SeekFrom::Start(pos) => Some(pos),
+        SeekFrom::End(delta) => len.checked_add_signed(delta),
+        SeekFrom::Current(delta) => pos.checked_add_signed(delta),
+    };
+    *pos = new.ok_or_else(|| invalid_data("seek before the start of the audio file"))?;
+    Ok(*pos)
+}
+
+// Audio track stored as a WAV file, read as raw CD audio. Only 44.1 kHz 16
+// bit stereo PCM is accepted, the format of CD audio.
+pub struct WavFile {
+    file: Box<dyn ImageFile>,
+    // Position and size of the sample data in the file
+    data_offset: u64,
+    data_len: u64,
+    pos: u64,
+}
+
+impl WavFile {
+    pub fn new(mut file: Box<dyn ImageFile>) -> io::Result<Self> {
+        let mut header = [0u8; 12];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut header)?;
+        if &header[..4] != b"RIFF" || &header[8..] != b"WAVE" {
+            return Err(invalid_data("not a WAV file"));
+        }
+
+        let mut format_checked = false;
+        let mut offset = 12u64;
+        loop {
+            let mut chunk = [0u8; 8];
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut chunk)
+                .map_err(|_| invalid_data("WAV file has no data chunk"))?;
+            let size = u32::from_le_bytes(chunk[4..].try_into().unwrap()) as u64;
+            match &chunk[..4] {
+                b"fmt " => {
+                    let mut fmt = [0u8; 16];
+                    file.read_exact(&mut fmt)?;
+                    let tag = u16::from_le_bytes([fmt[0], fmt[1]]);
+                    let channels = u16::from_le_bytes([fmt[2], fmt[3]]);
+                    let rate = u32::from_le_bytes(fmt[4..8].try_into().unwrap());
+                    let bits = u16::from_le_bytes([fmt[14], fmt[15]]);
+                    // 0xfffe is WAVE_FORMAT_EXTENSIBLE, assumed to hold PCM
+                    let pcm = matches!(tag, 1 | 0xfffe);
+                    if !pcm || channels != 2 || rate != 44100 || bits != 16 {
+                        return Err(invalid_data("WAV file isn't 44.1 kHz 16 bit stereo PCM"));
+                    }
+                    format_checked = true;
+                }
+                b"data" => {
+                    if !format_checked {
+                        return Err(invalid_data("WAV data chunk before its format"));
+                    }
+                    return Ok(Self {
+                        file,
+                        data_offset: offset + 8,
+                        data_len: size,
+                        pos: 0,
+                    });
+                }
+                _ => {}
+            }
+            // Chunks are padded to an even size
+            offset += 8 + size + (size & 1);
+        }
+    }
+}
+
+impl Read for WavFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = padded_len(self.data_len);
+        if self.pos >= len || buf.is_empty() {
+            return Ok(0);
+        }
+        let count = buf.len().min((len - self.pos) as usize);
+        let stored = count.min(self.data_len.saturating_sub(self.pos) as usize);
+        if stored > 0 {
+            self.file
+                .seek(SeekFrom::Start(self.data_offset + self.pos))?;
+            self.file.read_exact(&mut buf[..stored])?;
+        }
+        buf[stored..count].fill(0);
+        self.pos += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for WavFile {
+    fn seek(&mut self, from: SeekFrom) -> io::Result<u64> {
+        seek_to(&mut self.pos, padded_len(self.data_len), from)
+    }
+}
+
+// Audio track stored as a FLAC file, read as raw CD audio. Blocks are
+// decoded as reads reach them and kept, FLAC streams can't be entered in the
+// middle.
+#[cfg(feature = "flac")]
+pub struct FlacFile {
+    reader: claxon::FlacReader<Box<dyn ImageFile>>,
+    // Samples decoded so far, as 16 bit little endian stereo
+    decoded: Vec<u8>,
+    // Decoding reached the end of the stream
+    finished: bool,
+    // Size of the audio in bytes
+    data_len: u64,
+    pos: u64,
+    buffer: Vec<i32>,
+}
+
+#[cfg(feature = "flac")]
+impl FlacFile {
+    pub fn new(mut file: Box<dyn ImageFile>) -> io::Result<Self> {
+        file.seek(SeekFrom::Start(0))?;
+        let reader = claxon::FlacReader::new(file).map_err(flac_error)?;
+        let info = reader.streaminfo();
+        if info.channels != 2 || info.sample_rate != 44100 || info.bits_per_sample != 16 {
+            return Err(invalid_data("FLAC file isn't 44.1 kHz 16 bit stereo"));
+        }
+        let samples = info
+            .samples
+            .ok_or_else(|| invalid_data("FLAC file doesn't give its length"))?;
+        Ok(Self {
+            reader,
+            decoded: Vec::new(),
+            finished: false,
+            data_len: samples * 4,
+            pos: 0,
+            buffer: Vec::new(),
+        })
+    }
+
+    // Decode blocks until `end` bytes are available or the stream ends
+    fn decode_to(&mut self, end: u64) -> io::Result<()> {
+        while !self.finished && (self.decoded.len() as u64) < end {
+            let buffer = std::mem::take(&mut self.buffer);
+            match self.reader.blocks().read_next_or_eof(buffer) {
+                Ok(Some(block)) => {
+                    for (left, right) in block.stereo_samples() {
+                        self.decoded.extend_from_slice(&(left as i16).to_le_bytes());
+                        self.decoded
+                            .extend_from_slice(&(right as i16).to_le_bytes());
+                    }
+                    self.buffer = block.into_buffer();
+                }
+                Ok(None) => self.finished = true,
+                Err(err) => return Err(flac_error(err)),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "flac")]
+impl Read for FlacFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = padded_len(self.data_len);
+        if self.pos >= len || buf.is_empty() {
+            return Ok(0);
+        }
+        let count = buf.len().min((len - self.pos) as usize);
+        self.decode_to(self.pos + count as u64)?;
+        let stored = count.min((self.decoded.len() as u64).saturating_sub(self.pos) as usize);
+        if stored > 0 {
+            let start = self.pos as usize;
+            buf[..stored].copy_from_slice(&self.decoded[start..start + stored]);
+        }
+        buf[stored..count].fill(0);
+        self.pos += count as u64;
+        Ok(count)
+    }
+}
+
+#[cfg(feature = "flac")]
+impl Seek for FlacFile {
+    fn seek(&mut self, from: SeekFrom) -> io::Result<u64> {
+        seek_to(&mut self.pos, padded_len(self.data_len), from)
+    }
+}
+
+#[cfg(feature = "flac")]
+fn flac_error(err: claxon::Error) -> io::Error {
+    match err {
+        claxon::Error::IoError(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, format!("FLAC: {}", err)),
+    }
+}
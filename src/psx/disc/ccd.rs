@@ -0,0 +1,216 @@
+use super::{
+    invalid_data, Disc, Msf, RawSector, Toc, Track, TrackType, LEAD_IN_SECTORS, SECTOR_SIZE,
+};
+
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+// Bytes of subchannel data per sector in the .sub file
+const SUBCHANNEL_SIZE: usize = 96;
+
+// CloneCD image: a .ccd descriptor, the raw .img and an optional .sub file
+// with the subchannel data of every sector
+pub struct CloneCd {
+    img: File,
+    sub: Option<File>,
+    toc: Toc,
+}
+
+impl CloneCd {
+    // Open the image set from the path of any of its files
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let descriptor = fs::read_to_string(path.with_extension("ccd"))?;
+        let img = File::open(path.with_extension("img"))?;
+        let sub = File::open(path.with_extension("sub")).ok();
+        let sectors = (img.metadata()?.len() / SECTOR_SIZE as u64) as u32;
+        let toc = parse(&descriptor, sectors)?;
+        Ok(Self { img, sub, toc })
+    }
+}
+
+impl Disc for CloneCd {
+    fn toc(&self) -> &Toc {
+        &self.toc
+    }
+
+    fn read_sector(&mut self, msf: Msf) -> io::Result<RawSector> {
+        let lba = msf.to_lba();
+        if lba < LEAD_IN_SECTORS {
+            return Ok(RawSector::new());
+        }
+        if msf >= self.toc.lead_out {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("sector {} is past the end of the disc", msf),
+            ));
+        }
+
+        let index = (lba - LEAD_IN_SECTORS) as u64;
+        let mut sector = RawSector::new();
+        self.img.seek(SeekFrom::Start(index * SECTOR_SIZE as u64))?;
+        self.img.read_exact(&mut sector.data)?;
+
+        if let Some(sub) = self.sub.as_mut() {
+            // Channels are stored one after the other, Q follows P
+            let mut q = [0u8; 12];
+            sub.seek(SeekFrom::Start(index * SUBCHANNEL_SIZE as u64 + 12))?;
+            sub.read_exact(&mut q)?;
+            sector.subq = Some(q);
+        }
+        Ok(sector)
+    }
+}
+
+// [TRACK n] section
+struct CcdTrack {
+    number: u8,
+    mode: u32,
+    // INDEX 0 and INDEX 1, relative to the start of the image
+    index0: Option<u32>,
+    index1: Option<u32>,
+}
+
+// Build the table of contents from the descriptor. `sectors` is the length
+// of the .img file.
+fn parse(descriptor: &str, sectors: u32) -> io::Result<Toc> {
+    let mut tracks: Vec<CcdTrack> = Vec::new();
+    // Track starts from the [Entry n] sections, as (point, PLBA, control)
+    let mut entries: Vec<(u32, u32, u32)> = Vec::new();
+    let mut entry: Option<(u32, u32, u32, u32)> = None;
+    let mut section = String::new();
+
+    let mut flush = |entry: &mut Option<(u32, u32, u32, u32)>| {
+        // Only the first session holds the PlayStation tracks
+        if let Some((session, point, plba, control)) = entry.take() {
+            if session == 1 {
+                entries.push((point, plba, control));
+            }
+        }
+    };
+
+    for line in descriptor.lines() {
+        let line = line.trim();
+        if line.starts_with('[') && line.ends_with(']') {
+            flush(&mut entry);
+            section = line[1..line.len() - 1].to_ascii_uppercase();
+            if section.starts_with("ENTRY ") {
+                entry = Some((1, 0, 0, 0));
+            } else if let Some(number) = section.strip_prefix("TRACK ") {
+                let number = number
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid_data(format!("invalid CCD section [{}]", section)))?;
+                tracks.push(CcdTrack {
+                    number,
+                    mode: 2,
+                    index0: None,
+                    index1: None,
+                });
+            }
+            continue;
+        }
+
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim().to_ascii_uppercase(), value.trim()),
+            None => continue,
+        };
+        let value = match parse_number(value) {
+            Some(value) => value,
+            None => continue,
+        };
+
+        if let Some((session, point, plba, control)) = entry.as_mut() {
+            match key.as_str() {
+                "SESSION" => *session = value,
+                "POINT" => *point = value,
+                "PLBA" => *plba = value,
+                "CONTROL" => *control = value,
+                _ => {}
+            }
+        } else if section.starts_with("TRACK ") {
+            let track = tracks.last_mut().unwrap();
+            match key.as_str() {
+                "MODE" => track.mode = value,
+                "INDEX 0" => track.index0 = Some(value),
+                "INDEX 1" => track.index1 = Some(value),
+                _ => {}
+            }
+        }
+    }
+    flush(&mut entry);
+
+    // Lead-out position, or the end of the image
+    let lead_out = entries
+        .iter()
+        .find(|&&(point, _, _)| point == 0xa2)
+        .map_or(sectors, |&(_, plba, _)| plba.min(sectors));
+
+    let mut starts: Vec<(u8, u32, bool)> = entries
+        .iter()
+        .filter(|&&(point, _, _)| (1..=99).contains(&point))
+        .map(|&(point, plba, control)| (point as u8, plba, control & 4 != 0))
+        .collect();
+    starts.sort_by_key(|&(_, plba, _)| plba);
+    if starts.is_empty() {
+        return Err(invalid_data("CCD descriptor has no tracks"));
+    }
+
+    let mut toc = Toc::default();
+    for (i, &(number, plba, data)) in starts.iter().enumerate() {
+        let track = tracks.iter().find(|track| track.number == number);
+        let kind = match (data, track.map(|track| track.mode)) {
+            (false, _) | (_, Some(0)) => TrackType::Audio,
+            (_, Some(1)) => TrackType::Mode1,
+            _ => TrackType::Mode2,
+        };
+        let start = track.and_then(|track| track.index1).unwrap_or(plba);
+        let pregap = match track.and_then(|track| track.index0) {
+            Some(index0) if index0 <= start => start - index0,
+            _ => 0,
+        };
+        // The track runs up to the next one's pregap
+        let end = match starts.get(i + 1) {
+            Some(&(next, next_plba, _)) => {
+                let next = tracks.iter().find(|track| track.number == next);
+                next.and_then(|track| track.index0).unwrap_or(next_plba)
+            }
+            None => lead_out,
+        };
+        if end < start {
+            return Err(invalid_data(format!(
+                "track {} has a negative length",
+                number
+            )));
+        }
+
+        toc.tracks.push(Track {
+            number,
+            kind,
+            start: Msf::from_lba(start + LEAD_IN_SECTORS),
+            // Track 1 also owns the 2 second lead-in pregap
+            pregap: if i == 0 {
+                start + LEAD_IN_SECTORS
+            } else {
+                pregap
+            },
+            length: end - start,
+            indexes: Vec::new(),
+            isrc: None,
+        });
+    }
+    toc.lead_out = Msf::from_lba(lead_out + LEAD_IN_SECTORS);
+    Ok(toc)
+}
+
+// Parse a decimal or 0x prefixed hexadecimal value
+fn parse_number(value: &str) -> Option<u32> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
@@ -0,0 +1,276 @@
+use super::{
+    deinterleave_q, edc, invalid_data, Disc, ImageFile, Msf, RawSector, Toc, Track, TrackType,
+    LEAD_IN_SECTORS, SECTOR_SIZE, SYNC,
+};
+
+use ::chd::metadata::KnownMetadata;
+use ::chd::Chd;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+// Bytes per frame: sector data followed by 96 bytes of subchannel data
+const FRAME_SIZE: usize = SECTOR_SIZE + 96;
+
+// Tracks are padded to a multiple of this many frames
+const TRACK_PADDING: u32 = 4;
+
+// How a track's sectors are stored in the frames
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Raw,
+    // 2048 bytes of user data, the header is synthesized
+    Mode1,
+    // Big endian 16 bit samples
+    Audio,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Subchannel {
+    None,
+    // 12 bytes per channel, one channel after the other
+    Cooked,
+    // Interleaved, one bit of each channel per byte
+    Raw,
+}
+
+struct Layout {
+    format: Format,
+    subchannel: Subchannel,
+    // First absolute sector stored in the image
+    data_lba: u32,
+    // Frames stored for the track, pregap included
+    frames: u32,
+    // Frame of `data_lba` in the image
+    frame: u32,
+}
+
+// Compressed CHD image (version 5)
+pub struct ChdImage {
+    chd: Chd<Box<dyn ImageFile>>,
+    toc: Toc,
+    layouts: Vec<Layout>,
+    frames_per_hunk: u32,
+    // Most recently decompressed hunk
+    hunk: Vec<u8>,
+    hunk_index: Option<u32>,
+    compressed: Vec<u8>,
+}
+
+impl ChdImage {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_file(Box::new(File::open(path)?))
+    }
+
+    pub fn from_file(file: Box<dyn ImageFile>) -> io::Result<Self> {
+        let mut chd = Chd::open(file, None).map_err(chd_error)?;
+        if chd.header().unit_bytes() as usize != FRAME_SIZE {
+            return Err(invalid_data("CHD isn't a CD image"));
+        }
+        let frames_per_hunk = chd.header().hunk_size() / FRAME_SIZE as u32;
+
+        let refs: Vec<_> = chd.metadata_refs().collect();
+        let mut entries = Vec::new();
+        for r in refs.iter() {
+            let metadata = r.read(chd.inner()).map_err(chd_error)?;
+            if metadata.metatag == KnownMetadata::CdRomTrack2 as u32
+                || metadata.metatag == KnownMetadata::CdRomTrack as u32
+            {
+                entries.push(parse_metadata(&metadata.value)?);
+            }
+        }
+        if entries.is_empty() {
+            return Err(invalid_data("CHD has no CD track metadata"));
+        }
+        entries.sort_by_key(|entry| entry.number);
+
+        let mut toc = Toc::default();
+        let mut layouts = Vec::with_capacity(entries.len());
+        let mut lba = 0;
+        let mut frame = 0;
+        for (i, entry) in entries.iter().enumerate() {
+            // Track 1 always has the 2 second lead-in pregap
+            let mut pregap = entry.pregap;
+            if i == 0 {
+                pregap = pregap.max(LEAD_IN_SECTORS);
+            }
+            let stored_pregap = if entry.pregap_stored { entry.pregap } else { 0 };
+            let length = (entry.frames - stored_pregap) + entry.postgap;
+            let start = lba + pregap;
+
+            toc.tracks.push(Track {
+                number: entry.number,
+                kind: entry.kind,
+                start: Msf::from_lba(start),
+                pregap,
+                length,
+                indexes: Vec::new(),
+                isrc: None,
+            });
+            layouts.push(Layout {
+                format: entry.format,
+                subchannel: entry.subchannel,
+                data_lba: start - stored_pregap,
+                frames: entry.frames,
+                frame,
+            });
+
+            lba = start + length;
+            frame += entry.frames.div_ceil(TRACK_PADDING) * TRACK_PADDING;
+        }
+        toc.lead_out = Msf::from_lba(lba);
+
+        let hunk = chd.get_hunksized_buffer();
+        Ok(Self {
+            chd,
+            toc,
+            layouts,
+            frames_per_hunk,
+            hunk,
+            hunk_index: None,
+            compressed: Vec::new(),
+        })
+    }
+
+    // Read frame `frame` of the image
+    fn read_frame(&mut self, frame: u32) -> io::Result<&[u8]> {
+        let index = frame / self.frames_per_hunk;
+        if self.hunk_index != Some(index) {
+            self.hunk_index = None;
+            let mut hunk = self.chd.hunk(index).map_err(chd_error)?;
+            hunk.read_hunk_in(&mut self.compressed, &mut self.hunk)
+                .map_err(chd_error)?;
+            self.hunk_index = Some(index);
+        }
+        let offset = (frame % self.frames_per_hunk) as usize * FRAME_SIZE;
+        Ok(&self.hunk[offset..offset + FRAME_SIZE])
+    }
+}
+
+impl Disc for ChdImage {
+    fn toc(&self) -> &Toc {
+        &self.toc
+    }
+
+    fn read_sector(&mut self, msf: Msf) -> io::Result<RawSector> {
+        let lba = msf.to_lba();
+        let index = match self.toc.tracks.iter().position(|t| t.contains(lba)) {
+            Some(index) => index,
+            None if lba < LEAD_IN_SECTORS => return Ok(RawSector::new()),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("sector {} is past the end of the disc", msf),
+                ))
+            }
+        };
+        let kind = self.toc.tracks[index].kind;
+        let layout = &self.layouts[index];
+        let (format, subchannel) = (layout.format, layout.subchannel);
+
+        // Pregap and postgap sectors that aren't stored
+        if lba < layout.data_lba || lba - layout.data_lba >= layout.frames {
+            return Ok(match kind {
+                TrackType::Audio => RawSector::new(),
+                TrackType::Mode1 => RawSector::empty_data(msf, 1),
+                TrackType::Mode2 => RawSector::empty_data(msf, 2),
+            });
+        }
+
+        let frame = layout.frame + (lba - layout.data_lba);
+        let data = self.read_frame(frame)?;
+        let mut sector = RawSector::new();
+        match format {
+            Format::Raw => sector.data.copy_from_slice(&data[..SECTOR_SIZE]),
+            Format::Audio => {
+                for (dst, src) in sector.data.chunks_exact_mut(2).zip(data.chunks_exact(2)) {
+                    dst[0] = src[1];
+                    dst[1] = src[0];
+                }
+            }
+            Format::Mode1 => {
+                sector.data[..12].copy_from_slice(&SYNC);
+                sector.data[12..15].copy_from_slice(&msf.to_bcd());
+                sector.data[15] = 1;
+                sector.data[16..16 + 2048].copy_from_slice(&data[..2048]);
+                edc::generate_mode1(&mut sector.data);
+            }
+        }
+
+        let sub = &data[SECTOR_SIZE..];
+        sector.subq = match subchannel {
+            Subchannel::None => None,
+            Subchannel::Cooked => {
+                let mut q = [0u8; 12];
+                q.copy_from_slice(&sub[12..24]);
+                Some(q)
+            }
+            Subchannel::Raw => Some(deinterleave_q(sub)),
+        };
+        Ok(sector)
+    }
+}
+
+// CD track metadata entry
+struct Entry {
+    number: u8,
+    kind: TrackType,
+    format: Format,
+    subchannel: Subchannel,
+    frames: u32,
+    pregap: u32,
+    // Are the pregap sectors stored in the image?
+    pregap_stored: bool,
+    postgap: u32,
+}
+
+// Parse "TRACK:1 TYPE:MODE2_RAW SUBTYPE:NONE FRAMES:1234 ..." metadata
+fn parse_metadata(value: &[u8]) -> io::Result<Entry> {
+    let text = String::from_utf8_lossy(value);
+    let field = |name: &str| {
+        text.trim_end_matches('\0')
+            .split_whitespace()
+            .filter_map(|pair| pair.split_once(':'))
+            .find(|&(key, _)| key == name)
+            .map(|(_, value)| value.to_string())
+    };
+    let number = |name: &str| field(name).and_then(|value| value.parse::<u32>().ok());
+
+    let track = number("TRACK")
+        .filter(|&n| (1..=99).contains(&n))
+        .ok_or_else(|| invalid_data("CHD track metadata without a track number"))?;
+    let (kind, format) = match field("TYPE").as_deref() {
+        Some("AUDIO") => (TrackType::Audio, Format::Audio),
+        Some("MODE1_RAW") => (TrackType::Mode1, Format::Raw),
+        Some("MODE2_RAW") => (TrackType::Mode2, Format::Raw),
+        Some("MODE1") => (TrackType::Mode1, Format::Mode1),
+        Some(kind) => return Err(invalid_data(format!("unsupported CHD track type {}", kind))),
+        None => return Err(invalid_data("CHD track metadata without a type")),
+    };
+    let subchannel = match field("SUBTYPE").as_deref() {
+        Some("RW") => Subchannel::Cooked,
+        Some("RW_RAW") => Subchannel::Raw,
+        _ => Subchannel::None,
+    };
+    let frames = number("FRAMES").ok_or_else(|| invalid_data("CHD track without frames"))?;
+    let pregap = number("PREGAP").unwrap_or(0);
+    let pregap_stored = field("PGTYPE").is_some_and(|kind| kind.starts_with('V'));
+    if pregap_stored && pregap > frames {
+        return Err(invalid_data("CHD track pregap is longer than the track"));
+    }
+
+    Ok(Entry {
+        number: track as u8,
+        kind,
+        format,
+        subchannel,
+        frames,
+        pregap,
+        pregap_stored,
+        postgap: number("POSTGAP").unwrap_or(0),
+    })
+}
+
+fn chd_error(err: ::chd::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("CHD: {}", err))
+}
@@ -0,0 +1,78 @@
+use super::{read_file, Disc};
+
+// Boot settings of a disc, from SYSTEM.CNF in its root directory:
+//   BOOT = cdrom:\SLUS_012.34;1
+//   TCB = 4
+//   EVENT = 10
+//   STACK = 801FFF00
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SystemCnf {
+    // Path of the boot executable on the disc, e.g. "\SLUS_012.34"
+    pub boot: String,
+    // Executable name, which is the game's serial, e.g. "SLUS_012.34"
+    pub serial: String,
+    // Disc version from a VER line, which few discs have
+    pub version: Option<String>,
+    // Number of thread and event control blocks for the kernel to set up
+    pub tcb: Option<u32>,
+    pub event: Option<u32>,
+    // Initial stack pointer, for executables that don't set one
+    pub stack: Option<u32>,
+}
+
+impl SystemCnf {
+    // Parse the text of a SYSTEM.CNF. Returns None without a BOOT line.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut boot = None;
+        let mut version = None;
+        let (mut tcb, mut event, mut stack) = (None, None, None);
+        for line in text.lines() {
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => continue,
+            };
+            match key.to_ascii_uppercase().as_str() {
+                "BOOT" => {
+                    // Arguments may follow the path
+                    let value = value.split_whitespace().next().unwrap_or("");
+                    let value = value
+                        .strip_prefix("cdrom:")
+                        .or_else(|| value.strip_prefix("CDROM:"))
+                        .unwrap_or(value);
+                    boot = Some(value.split(';').next().unwrap_or(value).to_string());
+                }
+                "VER" => version = Some(value.to_string()),
+                "TCB" => tcb = parse_number(value),
+                "EVENT" => event = parse_number(value),
+                "STACK" => stack = parse_number(value),
+                _ => {}
+            }
+        }
+        let boot = boot?;
+        let serial = boot.rsplit(['\\', '/', ':']).next().unwrap_or(&boot);
+        Some(Self {
+            serial: serial.to_string(),
+            boot,
+            version,
+            tcb,
+            event,
+            stack,
+        })
+    }
+}
+
+// Values are hexadecimal, with or without a 0x prefix
+fn parse_number(value: &str) -> Option<u32> {
+    let value = value.split_whitespace().next()?;
+    let value = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    u32::from_str_radix(value, 16).ok()
+}
+
+// Read and parse the disc's SYSTEM.CNF, if it has one
+pub fn read_system_cnf(disc: &mut dyn Disc) -> Option<SystemCnf> {
+    let cnf = read_file(disc, "SYSTEM.CNF").ok()?;
+    SystemCnf::parse(&String::from_utf8_lossy(&cnf))
+}
@@ -0,0 +1,396 @@
+#[cfg(feature = "flac")]
+use super::audio::FlacFile;
+use super::audio::WavFile;
+use super::{
+    invalid_data, open_file, Disc, ImageFile, Msf, RawSector, Toc, Track, TrackType,
+    LEAD_IN_SECTORS, SECTOR_SIZE,
+};
+
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+// Format of a FILE entry
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum FileType {
+    // Raw sectors
+    Binary,
+    Wave,
+    Flac,
+}
+
+// FILE entry of a cue sheet
+struct CueFile {
+    path: PathBuf,
+    kind: FileType,
+}
+
+// TRACK entry of a cue sheet
+struct CueTrack {
+    number: u8,
+    kind: TrackType,
+    // Index into the FILE entries
+    file: usize,
+    // INDEX entries as (number, sector within the file)
+    indexes: Vec<(u8, u32)>,
+    // PREGAP and POSTGAP: silence not stored in the file
+    pregap: u32,
+    postgap: u32,
+    isrc: Option<String>,
+}
+
+impl CueTrack {
+    fn index(&self, number: u8) -> Option<u32> {
+        self.indexes
+            .iter()
+            .find(|&&(n, _)| n == number)
+            .map(|&(_, sector)| sector)
+    }
+
+    // First sector of the track stored in the file
+    fn file_start(&self) -> u32 {
+        self.indexes
+            .iter()
+            .map(|&(_, sector)| sector)
+            .min()
+            .unwrap_or(0)
+    }
+}
+
+// Where the sectors of a track come from
+struct Layout {
+    file: usize,
+    // Absolute position of the file's first sector
+    file_lba: u32,
+    // Absolute sectors stored in the file, the others are pregap and
+    // postgap silence
+    data_lba: u32,
+    data_end: u32,
+}
+
+// Disc image described by a cue sheet over one or more .bin files
+pub struct BinCue {
+    files: Vec<Box<dyn ImageFile>>,
+    toc: Toc,
+    layouts: Vec<Layout>,
+}
+
+impl BinCue {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let sheet = fs::read_to_string(path)?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        Self::from_sheet(&sheet, |name| open_file(&dir.join(name)))
+    }
+
+    // Build the image from the text of a cue sheet, using `open` to open the
+    // files it references
+    pub fn from_sheet<F>(sheet: &str, mut open: F) -> io::Result<Self>
+    where
+        F: FnMut(&Path) -> io::Result<Box<dyn ImageFile>>,
+    {
+        let (entries, tracks) = parse(sheet)?;
+
+        let mut files = Vec::with_capacity(entries.len());
+        let mut sizes = Vec::with_capacity(entries.len());
+        for entry in entries.iter() {
+            let name = entry.path.display();
+            let mut file = open(&entry.path)
+                .and_then(|file| decode(file, entry.kind))
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", name, e)))?;
+            let len = file.seek(SeekFrom::End(0))?;
+            sizes.push((len / SECTOR_SIZE as u64) as u32);
+            files.push(file);
+        }
+
+        let (toc, layouts) = layout(&tracks, &sizes)?;
+        Ok(Self {
+            files,
+            toc,
+            layouts,
+        })
+    }
+}
+
+impl Disc for BinCue {
+    fn toc(&self) -> &Toc {
+        &self.toc
+    }
+
+    fn read_sector(&mut self, msf: Msf) -> io::Result<RawSector> {
+        let lba = msf.to_lba();
+        let index = match self.toc.tracks.iter().position(|t| t.contains(lba)) {
+            Some(index) => index,
+            // The lead-in before track 1 reads as silence
+            None if lba < LEAD_IN_SECTORS => return Ok(RawSector::new()),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("sector {} is past the end of the disc", msf),
+                ))
+            }
+        };
+        let track = &self.toc.tracks[index];
+        let layout = &self.layouts[index];
+
+        if lba < layout.data_lba || lba >= layout.data_end {
+            return Ok(match track.kind {
+                TrackType::Audio => RawSector::new(),
+                TrackType::Mode1 => RawSector::empty_data(msf, 1),
+                TrackType::Mode2 => RawSector::empty_data(msf, 2),
+            });
+        }
+
+        let offset = (lba - layout.file_lba) as u64 * SECTOR_SIZE as u64;
+        let file = &mut self.files[layout.file];
+        let mut sector = RawSector::new();
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut sector.data)?;
+        Ok(sector)
+    }
+}
+
+// Wrap audio files so they read as raw sectors
+fn decode(file: Box<dyn ImageFile>, kind: FileType) -> io::Result<Box<dyn ImageFile>> {
+    match kind {
+        FileType::Binary => Ok(file),
+        FileType::Wave => Ok(Box::new(WavFile::new(file)?)),
+        #[cfg(feature = "flac")]
+        FileType::Flac => Ok(Box::new(FlacFile::new(file)?)),
+        #[cfg(not(feature = "flac"))]
+        FileType::Flac => Err(invalid_data("FLAC support isn't enabled")),
+    }
+}
+
+// Parse a cue sheet into its FILE names and types and its TRACK entries
+fn parse(sheet: &str) -> io::Result<(Vec<CueFile>, Vec<CueTrack>)> {
+    let mut files = Vec::new();
+    let mut tracks: Vec<CueTrack> = Vec::new();
+
+    for (line_number, line) in sheet.lines().enumerate() {
+        let error = |msg: &str| invalid_data(format!("cue line {}: {}", line_number + 1, msg));
+        let words = split_words(line);
+        let command = match words.first() {
+            Some(command) => command.to_ascii_uppercase(),
+            None => continue,
+        };
+
+        match command.as_str() {
+            "FILE" => {
+                let name = words.get(1).ok_or_else(|| error("missing file name"))?;
+                let kind = match words
+                    .get(2)
+                    .map(|kind| kind.to_ascii_uppercase())
+                    .as_deref()
+                {
+                    Some("BINARY") | Some("MOTOROLA") | None => FileType::Binary,
+                    Some("WAVE") => FileType::Wave,
+                    Some("FLAC") => FileType::Flac,
+                    Some(kind) => return Err(error(&format!("unsupported file type {}", kind))),
+                };
+                files.push(CueFile {
+                    path: PathBuf::from(name),
+                    kind,
+                });
+            }
+            "TRACK" => {
+                if files.is_empty() {
+                    return Err(error("TRACK before FILE"));
+                }
+                let number = words
+                    .get(1)
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| (1..=99).contains(&n))
+                    .ok_or_else(|| error("invalid track number"))?;
+                let mode = words.get(2).map(|m| m.to_ascii_uppercase());
+                let kind = match mode.as_deref() {
+                    Some("AUDIO") => TrackType::Audio,
+                    Some("MODE1/2352") => TrackType::Mode1,
+                    Some("MODE2/2352") => TrackType::Mode2,
+                    Some(mode) => return Err(error(&format!("unsupported track mode {}", mode))),
+                    None => return Err(error("missing track mode")),
+                };
+                tracks.push(CueTrack {
+                    number,
+                    kind,
+                    file: files.len() - 1,
+                    indexes: Vec::new(),
+                    pregap: 0,
+                    postgap: 0,
+                    isrc: None,
+                });
+            }
+            "INDEX" => {
+                let track = tracks
+                    .last_mut()
+                    .ok_or_else(|| error("INDEX before TRACK"))?;
+                let number = words
+                    .get(1)
+                    .and_then(|n| n.parse().ok())
+                    .ok_or_else(|| error("invalid index number"))?;
+                let msf = words
+                    .get(2)
+                    .and_then(|msf| parse_msf(msf))
+                    .ok_or_else(|| error("invalid index position"))?;
+                // Indexes come in order, each at or after the previous one
+                if let Some(&(previous, sector)) = track.indexes.last() {
+                    if number != previous + 1 || msf.to_lba() < sector {
+                        return Err(error("INDEX out of order"));
+                    }
+                }
+                track.indexes.push((number, msf.to_lba()));
+            }
+            "PREGAP" => {
+                let track = tracks
+                    .last_mut()
+                    .ok_or_else(|| error("PREGAP before TRACK"))?;
+                let msf = words
+                    .get(1)
+                    .and_then(|msf| parse_msf(msf))
+                    .ok_or_else(|| error("invalid pregap length"))?;
+                track.pregap = msf.to_lba();
+            }
+            "POSTGAP" => {
+                let track = tracks
+                    .last_mut()
+                    .ok_or_else(|| error("POSTGAP before TRACK"))?;
+                let msf = words
+                    .get(1)
+                    .and_then(|msf| parse_msf(msf))
+                    .ok_or_else(|| error("invalid postgap length"))?;
+                track.postgap = msf.to_lba();
+            }
+            "ISRC" => {
+                let track = tracks
+                    .last_mut()
+                    .ok_or_else(|| error("ISRC before TRACK"))?;
+                let isrc = words
+                    .get(1)
+                    .filter(|isrc| {
+                        isrc.len() == 12 && isrc.chars().all(|c| c.is_ascii_alphanumeric())
+                    })
+                    .ok_or_else(|| error("invalid ISRC"))?;
+                track.isrc = Some(isrc.to_ascii_uppercase());
+            }
+            // Metadata that doesn't affect the layout
+            _ => {}
+        }
+    }
+
+    if tracks.is_empty() {
+        return Err(invalid_data("cue sheet has no tracks"));
+    }
+    for track in tracks.iter() {
+        if track.index(1).is_none() {
+            return Err(invalid_data(format!(
+                "track {} has no INDEX 01",
+                track.number
+            )));
+        }
+    }
+    Ok((files, tracks))
+}
+
+// Place the tracks on the disc. `sizes` holds the length of each file in
+// sectors.
+fn layout(tracks: &[CueTrack], sizes: &[u32]) -> io::Result<(Toc, Vec<Layout>)> {
+    let mut toc = Toc::default();
+    let mut layouts = Vec::with_capacity(tracks.len());
+    // Absolute position of the next sector to place
+    let mut lba = LEAD_IN_SECTORS;
+    let mut file_lba = 0;
+
+    for (i, cue) in tracks.iter().enumerate() {
+        let index1 = cue.index(1).unwrap_or(0);
+        let first = cue.file_start();
+
+        // A new file starts right after the previous track. Within a file,
+        // gaps push the following tracks further.
+        if i == 0 || tracks[i - 1].file != cue.file {
+            file_lba = (lba + cue.pregap)
+                .checked_sub(first)
+                .ok_or_else(|| invalid_data("track overlaps the previous one"))?;
+        } else {
+            file_lba += tracks[i - 1].postgap + cue.pregap;
+        }
+        let data_lba = file_lba + first;
+        let start = file_lba + index1;
+
+        // The track ends where the next one's data starts in the same file,
+        // or at the end of its file
+        let end = match tracks.get(i + 1) {
+            Some(next) if next.file == cue.file => file_lba + next.file_start(),
+            _ => file_lba + sizes[cue.file],
+        };
+        if end < start {
+            return Err(invalid_data(format!(
+                "track {} extends past the end of its file",
+                cue.number
+            )));
+        }
+
+        // Track 1 also owns the 2 second lead-in pregap
+        let pregap_start = if i == 0 { 0 } else { lba };
+        toc.tracks.push(Track {
+            number: cue.number,
+            kind: cue.kind,
+            start: Msf::from_lba(start),
+            pregap: start - pregap_start,
+            length: end + cue.postgap - start,
+            indexes: cue
+                .indexes
+                .iter()
+                .filter(|&&(number, _)| number >= 2)
+                .map(|&(_, sector)| Msf::from_lba(file_lba + sector))
+                .collect(),
+            isrc: cue.isrc.clone(),
+        });
+        layouts.push(Layout {
+            file: cue.file,
+            file_lba,
+            data_lba,
+            data_end: end,
+        });
+        lba = end + cue.postgap;
+    }
+
+    toc.lead_out = Msf::from_lba(lba);
+    Ok((toc, layouts))
+}
+
+// Split a cue sheet line into words, keeping quoted strings together
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            words.push(chars.by_ref().take_while(|&c| c != '"').collect());
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            words.push(word);
+        }
+    }
+    words
+}
+
+// Parse a "mm:ss:ff" position
+fn parse_msf(text: &str) -> Option<Msf> {
+    let mut fields = text.split(':').map(|field| field.parse::<u8>().ok());
+    let m = fields.next()??;
+    let s = fields.next()??;
+    let f = fields.next()??;
+    if fields.next().is_some() || s >= 60 || f >= 75 {
+        return None;
+    }
+    Some(Msf::new(m, s, f))
+}
@@ -0,0 +1,231 @@
+use super::{
+    invalid_data, Disc, Msf, RawSector, Toc, Track, TrackType, LEAD_IN_SECTORS, SECTOR_SIZE,
+};
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+// ioctl requests from linux/cdrom.h and scsi/sg.h
+const CDROMREADTOCHDR: libc::c_ulong = 0x5305;
+const CDROMREADTOCENTRY: libc::c_ulong = 0x5306;
+const SG_IO: libc::c_ulong = 0x2285;
+
+const CDROM_LBA: u8 = 0x01;
+const CDROM_LEADOUT: u8 = 0xaa;
+// Control bit of data tracks in the TOC
+const CONTROL_DATA: u8 = 0x04;
+
+const SG_DXFER_FROM_DEV: libc::c_int = -3;
+
+// Bytes read per sector: the raw sector followed by formatted subchannel Q
+const READ_SIZE: usize = SECTOR_SIZE + 16;
+
+// Milliseconds before a read command times out
+const READ_TIMEOUT: u32 = 10_000;
+
+#[repr(C)]
+#[derive(Default)]
+struct TocHeader {
+    first_track: u8,
+    last_track: u8,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct TocEntry {
+    track: u8,
+    // ADR in the low nibble, control in the high one
+    adr_control: u8,
+    format: u8,
+    // Union of an MSF address and an LBA, always requested as an LBA
+    lba: libc::c_int,
+    data_mode: u8,
+}
+
+#[repr(C)]
+struct SgIoHeader {
+    interface_id: libc::c_int,
+    dxfer_direction: libc::c_int,
+    cmd_len: u8,
+    mx_sb_len: u8,
+    iovec_count: u16,
+    dxfer_len: u32,
+    dxferp: *mut libc::c_void,
+    cmdp: *mut u8,
+    sbp: *mut u8,
+    timeout: u32,
+    flags: u32,
+    pack_id: libc::c_int,
+    usr_ptr: *mut libc::c_void,
+    status: u8,
+    masked_status: u8,
+    msg_status: u8,
+    sb_len_wr: u8,
+    host_status: u16,
+    driver_status: u16,
+    resid: libc::c_int,
+    duration: u32,
+    info: u32,
+}
+
+// Disc in a real optical drive, read with raw SCSI READ CD commands so
+// sector headers and subchannel Q come from the disc itself
+pub struct PhysicalDrive {
+    file: File,
+    toc: Toc,
+}
+
+impl PhysicalDrive {
+    // Open a drive device such as /dev/sr0
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)?;
+        let toc = read_toc(&file)?;
+        Ok(Self { file, toc })
+    }
+}
+
+impl Disc for PhysicalDrive {
+    fn toc(&self) -> &Toc {
+        &self.toc
+    }
+
+    fn read_sector(&mut self, msf: Msf) -> io::Result<RawSector> {
+        let lba = msf.to_lba();
+        // The lead-in can't be read, it's silence like in the image formats
+        if lba < LEAD_IN_SECTORS {
+            return Ok(RawSector::new());
+        }
+        if msf >= self.toc.lead_out {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("sector {} is past the end of the disc", msf),
+            ));
+        }
+
+        let drive_lba = (lba - LEAD_IN_SECTORS).to_be_bytes();
+        // READ CD: any sector type, one sector, sync, header, subheader,
+        // user data and EDC/ECC, with formatted subchannel Q
+        let mut cdb = [
+            0xbe,
+            0x00,
+            drive_lba[0],
+            drive_lba[1],
+            drive_lba[2],
+            drive_lba[3],
+            0x00,
+            0x00,
+            0x01,
+            0xf8,
+            0x02,
+            0x00,
+        ];
+        let mut buf = [0u8; READ_SIZE];
+        let mut sense = [0u8; 32];
+        let mut header = SgIoHeader {
+            interface_id: b'S' as libc::c_int,
+            dxfer_direction: SG_DXFER_FROM_DEV,
+            cmd_len: cdb.len() as u8,
+            mx_sb_len: sense.len() as u8,
+            iovec_count: 0,
+            dxfer_len: buf.len() as u32,
+            dxferp: buf.as_mut_ptr() as *mut libc::c_void,
+            cmdp: cdb.as_mut_ptr(),
+            sbp: sense.as_mut_ptr(),
+            timeout: READ_TIMEOUT,
+            flags: 0,
+            pack_id: 0,
+            usr_ptr: std::ptr::null_mut(),
+            status: 0,
+            masked_status: 0,
+            msg_status: 0,
+            sb_len_wr: 0,
+            host_status: 0,
+            driver_status: 0,
+            resid: 0,
+            duration: 0,
+            info: 0,
+        };
+        // SAFETY: the header points at buffers that outlive the call and
+        // match the lengths given
+        let ret = unsafe { libc::ioctl(self.file.as_raw_fd(), SG_IO as _, &mut header) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if header.status != 0 || header.host_status != 0 || header.driver_status != 0 {
+            return Err(io::Error::other(format!(
+                "read error at {} (sense key {:x})",
+                msf,
+                sense[2] & 0x0f
+            )));
+        }
+
+        let mut sector = RawSector::new();
+        sector.data.copy_from_slice(&buf[..SECTOR_SIZE]);
+        let mut q = [0u8; 12];
+        q.copy_from_slice(&buf[SECTOR_SIZE..SECTOR_SIZE + 12]);
+        sector.subq = Some(q);
+        Ok(sector)
+    }
+}
+
+fn read_toc(file: &File) -> io::Result<Toc> {
+    let fd = file.as_raw_fd();
+    let mut header = TocHeader::default();
+    // SAFETY: the kernel fills in a struct cdrom_tochdr
+    if unsafe { libc::ioctl(fd, CDROMREADTOCHDR as _, &mut header) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if header.first_track == 0 || header.last_track < header.first_track {
+        return Err(invalid_data("drive reported an invalid TOC"));
+    }
+
+    let read_entry = |track: u8| -> io::Result<TocEntry> {
+        let mut entry = TocEntry {
+            track,
+            format: CDROM_LBA,
+            ..TocEntry::default()
+        };
+        // SAFETY: the kernel fills in a struct cdrom_tocentry
+        if unsafe { libc::ioctl(fd, CDROMREADTOCENTRY as _, &mut entry) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(entry)
+    };
+
+    let mut entries = Vec::new();
+    for track in header.first_track..=header.last_track {
+        entries.push(read_entry(track)?);
+    }
+    let lead_out = read_entry(CDROM_LEADOUT)?;
+
+    let mut toc = Toc::default();
+    for (i, entry) in entries.iter().enumerate() {
+        let start = entry.lba as u32 + LEAD_IN_SECTORS;
+        let end = entries.get(i + 1).map_or(lead_out.lba, |next| next.lba) as u32 + LEAD_IN_SECTORS;
+        // PlayStation discs use Mode 2 data tracks
+        let kind = if entry.adr_control >> 4 & CONTROL_DATA != 0 {
+            TrackType::Mode2
+        } else {
+            TrackType::Audio
+        };
+        // The drive only reports index 1, pregaps are counted in the
+        // previous track except for the lead-in of track 1
+        toc.tracks.push(Track {
+            number: entry.track,
+            kind,
+            start: Msf::from_lba(start),
+            pregap: if i == 0 { start } else { 0 },
+            length: end - start,
+            indexes: Vec::new(),
+            isrc: None,
+        });
+    }
+    toc.lead_out = Msf::from_lba(lead_out.lba as u32 + LEAD_IN_SECTORS);
+    Ok(toc)
+}
@@ -0,0 +1,210 @@
+use super::edc;
+use super::{invalid_data, SECTOR_SIZE, SYNC};
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"ECM\0";
+
+// Record types
+const TYPE_RAW: u8 = 0;
+const TYPE_MODE1: u8 = 1;
+const TYPE_FORM1: u8 = 2;
+const TYPE_FORM2: u8 = 3;
+
+// A run of bytes or sectors of the same type
+struct Record {
+    kind: u8,
+    count: u32,
+    // Offset of the first decoded byte
+    output: u64,
+    // Offset of the record's data in the ECM file
+    input: u64,
+}
+
+impl Record {
+    // Bytes stored and produced by each unit of the record
+    fn unit_sizes(&self) -> (u64, u64) {
+        match self.kind {
+            TYPE_MODE1 => (0x803, SECTOR_SIZE as u64),
+            TYPE_FORM1 => (0x804, 0x920),
+            TYPE_FORM2 => (0x918, 0x920),
+            _ => (1, 1),
+        }
+    }
+
+    fn output_len(&self) -> u64 {
+        self.count as u64 * self.unit_sizes().1
+    }
+}
+
+// ECM compressed image, decoded on the fly. ECM strips the sync pattern,
+// header, EDC and ECC from each sector, which are all regenerated.
+pub struct EcmFile {
+    file: File,
+    records: Vec<Record>,
+    len: u64,
+    pos: u64,
+    // Decoded sector cache, as (record, sector) and its bytes
+    cached: Option<(usize, u32)>,
+    sector: [u8; SECTOR_SIZE],
+}
+
+impl EcmFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let records = index(&mut BufReader::new(&mut file))?;
+        let len = records.last().map_or(0, |r| r.output + r.output_len());
+        Ok(Self {
+            file,
+            records,
+            len,
+            pos: 0,
+            cached: None,
+            sector: [0; SECTOR_SIZE],
+        })
+    }
+
+    // Decode sector `unit` of record `index` into the cache. Returns the
+    // offset of the decoded bytes in the cache.
+    fn decode(&mut self, index: usize, unit: u32) -> io::Result<usize> {
+        let record = &self.records[index];
+        let kind = record.kind;
+        // Mode 2 records start after the sync pattern and header
+        let start = if kind == TYPE_MODE1 { 0 } else { 0x10 };
+        if self.cached == Some((index, unit)) {
+            return Ok(start);
+        }
+        self.cached = None;
+
+        let (stored, _) = record.unit_sizes();
+        self.file
+            .seek(SeekFrom::Start(record.input + unit as u64 * stored))?;
+        let sector = &mut self.sector;
+        sector.fill(0);
+        match kind {
+            TYPE_MODE1 => {
+                sector[..12].copy_from_slice(&SYNC);
+                self.file.read_exact(&mut sector[0x0c..0x0f])?;
+                sector[0x0f] = 1;
+                self.file.read_exact(&mut sector[0x10..0x810])?;
+                edc::generate_mode1(sector);
+            }
+            TYPE_FORM1 => {
+                self.file.read_exact(&mut sector[0x14..0x818])?;
+                sector.copy_within(0x14..0x18, 0x10);
+                edc::generate_form1(sector);
+            }
+            _ => {
+                self.file.read_exact(&mut sector[0x14..0x92c])?;
+                sector.copy_within(0x14..0x18, 0x10);
+                edc::generate_form2(sector);
+            }
+        }
+        self.cached = Some((index, unit));
+        Ok(start)
+    }
+}
+
+impl Read for EcmFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let index = self
+            .records
+            .partition_point(|r| r.output + r.output_len() <= self.pos);
+        let record = &self.records[index];
+        let offset = self.pos - record.output;
+
+        let count = if record.kind == TYPE_RAW {
+            let count = buf.len().min((record.count as u64 - offset) as usize);
+            let input = record.input + offset;
+            self.file.seek(SeekFrom::Start(input))?;
+            self.file.read_exact(&mut buf[..count])?;
+            count
+        } else {
+            let (_, size) = record.unit_sizes();
+            let unit = (offset / size) as u32;
+            let within = (offset % size) as usize;
+            let start = self.decode(index, unit)?;
+            let count = buf.len().min(size as usize - within);
+            buf[..count].copy_from_slice(&self.sector[start + within..start + within + count]);
+            count
+        };
+        self.pos += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for EcmFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        self.pos = pos.ok_or_else(|| invalid_data("seek before the start of the image"))?;
+        Ok(self.pos)
+    }
+}
+
+// Scan the records of an ECM stream
+fn index<R: Read>(reader: &mut R) -> io::Result<Vec<Record>> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not an ECM file"));
+    }
+
+    let mut records = Vec::new();
+    let mut input = 4u64;
+    let mut output = 0u64;
+    loop {
+        let mut byte = [0u8; 1];
+        let mut read_byte = |input: &mut u64| -> io::Result<u8> {
+            reader.read_exact(&mut byte)?;
+            *input += 1;
+            Ok(byte[0])
+        };
+
+        // Type in bits 0-1, count minus one in the remaining bits
+        let mut c = read_byte(&mut input)?;
+        let kind = c & 3;
+        let mut count = ((c >> 2) & 0x1f) as u64;
+        let mut bits = 5;
+        while c & 0x80 != 0 {
+            c = read_byte(&mut input)?;
+            if bits > 31 {
+                return Err(invalid_data("corrupt ECM record"));
+            }
+            count |= ((c & 0x7f) as u64) << bits;
+            bits += 7;
+        }
+        if count == 0xffff_ffff {
+            break;
+        }
+        let count = count as u32 + 1;
+
+        let record = Record {
+            kind,
+            count,
+            output,
+            input,
+        };
+        let (stored, _) = record.unit_sizes();
+        let skip = stored * count as u64;
+        io::copy(&mut reader.by_ref().take(skip), &mut io::sink()).and_then(|copied| {
+            if copied == skip {
+                Ok(())
+            } else {
+                Err(io::ErrorKind::UnexpectedEof.into())
+            }
+        })?;
+        input += skip;
+        output += record.output_len();
+        records.push(record);
+    }
+    Ok(records)
+}
@@ -0,0 +1,137 @@
+// EDC (CRC-32 variant) and Reed-Solomon ECC of CD-ROM data sectors
+
+use super::SYNC;
+
+// Lookup tables, built at compile time
+const EDC_TABLE: [u32; 256] = edc_table();
+const ECC_F_TABLE: [u8; 256] = ecc_tables().0;
+const ECC_B_TABLE: [u8; 256] = ecc_tables().1;
+
+const fn edc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut edc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            edc = (edc >> 1) ^ if edc & 1 != 0 { 0xd801_8001 } else { 0 };
+            bit += 1;
+        }
+        table[i] = edc;
+        i += 1;
+    }
+    table
+}
+
+const fn ecc_tables() -> ([u8; 256], [u8; 256]) {
+    let mut forward = [0u8; 256];
+    let mut backward = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        let j = (i << 1) ^ if i & 0x80 != 0 { 0x11d } else { 0 };
+        forward[i] = j as u8;
+        backward[i ^ j] = i as u8;
+        i += 1;
+    }
+    (forward, backward)
+}
+
+// EDC of `data`
+pub fn edc(data: &[u8]) -> u32 {
+    data.iter().fold(0, |edc, &byte| {
+        (edc >> 8) ^ EDC_TABLE[((edc ^ byte as u32) & 0xff) as usize]
+    })
+}
+
+// Compute one set of parity bytes over the sector from the header on
+fn ecc_block(
+    sector: &mut [u8],
+    major_count: usize,
+    minor_count: usize,
+    major_mult: usize,
+    minor_inc: usize,
+    dest: usize,
+) {
+    let size = major_count * minor_count;
+    for major in 0..major_count {
+        let mut index = (major >> 1) * major_mult + (major & 1);
+        let mut ecc_a = 0u8;
+        let mut ecc_b = 0u8;
+        for _ in 0..minor_count {
+            let val = sector[0x0c + index];
+            index += minor_inc;
+            if index >= size {
+                index -= size;
+            }
+            ecc_a ^= val;
+            ecc_b ^= val;
+            ecc_a = ECC_F_TABLE[ecc_a as usize];
+        }
+        ecc_a = ECC_B_TABLE[(ECC_F_TABLE[ecc_a as usize] ^ ecc_b) as usize];
+        sector[dest + major] = ecc_a;
+        sector[dest + major + major_count] = ecc_a ^ ecc_b;
+    }
+}
+
+// Fill in the P and Q parity of a raw sector. Mode 2 sectors compute it
+// with a zeroed header.
+pub fn generate_ecc(sector: &mut [u8], zero_header: bool) {
+    let mut header = [0u8; 4];
+    if zero_header {
+        header.copy_from_slice(&sector[0x0c..0x10]);
+        sector[0x0c..0x10].fill(0);
+    }
+    ecc_block(sector, 86, 24, 2, 86, 0x81c);
+    ecc_block(sector, 52, 43, 86, 88, 0x8c8);
+    if zero_header {
+        sector[0x0c..0x10].copy_from_slice(&header);
+    }
+}
+
+// Fill in the EDC and ECC of a raw Mode 1 sector
+pub fn generate_mode1(sector: &mut [u8]) {
+    let edc = edc(&sector[..0x810]);
+    sector[0x810..0x814].copy_from_slice(&edc.to_le_bytes());
+    sector[0x814..0x81c].fill(0);
+    generate_ecc(sector, false);
+}
+
+// Fill in the EDC and ECC of a raw Mode 2 Form 1 sector
+pub fn generate_form1(sector: &mut [u8]) {
+    let edc = edc(&sector[0x10..0x818]);
+    sector[0x818..0x81c].copy_from_slice(&edc.to_le_bytes());
+    generate_ecc(sector, true);
+}
+
+// Fill in the EDC of a raw Mode 2 Form 2 sector
+pub fn generate_form2(sector: &mut [u8]) {
+    let edc = edc(&sector[0x10..0x92c]);
+    sector[0x92c..0x930].copy_from_slice(&edc.to_le_bytes());
+}
+
+// Does the EDC stored in a raw data sector match its contents? Form 2
+// sectors may leave it zero, meaning it wasn't computed. Audio sectors have
+// no EDC.
+pub fn edc_valid(sector: &[u8]) -> bool {
+    if sector[..12] != SYNC {
+        return true;
+    }
+    let stored = |offset: usize| {
+        let bytes = [
+            sector[offset],
+            sector[offset + 1],
+            sector[offset + 2],
+            sector[offset + 3],
+        ];
+        u32::from_le_bytes(bytes)
+    };
+    match sector[0x0f] {
+        1 => edc(&sector[..0x810]) == stored(0x810),
+        2 if sector[0x12] & 0x20 != 0 => {
+            let expected = stored(0x92c);
+            expected == 0 || edc(&sector[0x10..0x92c]) == expected
+        }
+        2 => edc(&sector[0x10..0x818]) == stored(0x818),
+        _ => true,
+    }
+}
@@ -0,0 +1,82 @@
+use super::{Disc, Msf, RawSector, Toc};
+
+use std::collections::HashMap;
+use std::io;
+
+// Simulated read error of a damaged sector
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Fault {
+    // The sector fails `failures` reads in a row before a retry gets it
+    Correctable { failures: u32 },
+    // The sector never reads
+    Uncorrectable,
+}
+
+// Disc wrapper failing reads of chosen sectors, to test how games and the
+// controller handle damaged discs
+pub struct FaultInjector {
+    disc: Box<dyn Disc>,
+    // Fault and failed reads left before the next success, by absolute
+    // sector
+    faults: HashMap<u32, (Fault, u32)>,
+}
+
+impl FaultInjector {
+    pub fn new(disc: Box<dyn Disc>) -> Self {
+        Self {
+            disc,
+            faults: HashMap::new(),
+        }
+    }
+
+    // Make reads of the sector at `msf` fail
+    pub fn add(&mut self, msf: Msf, fault: Fault) {
+        let failures = match fault {
+            Fault::Correctable { failures } => failures,
+            Fault::Uncorrectable => 0,
+        };
+        self.faults.insert(msf.to_lba(), (fault, failures));
+    }
+
+    pub fn remove(&mut self, msf: Msf) {
+        self.faults.remove(&msf.to_lba());
+    }
+
+    pub fn clear(&mut self) {
+        self.faults.clear();
+    }
+
+    pub fn into_inner(self) -> Box<dyn Disc> {
+        self.disc
+    }
+}
+
+impl Disc for FaultInjector {
+    fn toc(&self) -> &Toc {
+        self.disc.toc()
+    }
+
+    fn read_sector(&mut self, msf: Msf) -> io::Result<RawSector> {
+        let failed = match self.faults.get_mut(&msf.to_lba()) {
+            None => false,
+            Some((Fault::Uncorrectable, _)) => true,
+            // A successful read starts the count again for the next pass
+            Some((Fault::Correctable { failures }, left)) => {
+                if *left == 0 {
+                    *left = *failures;
+                    false
+                } else {
+                    *left -= 1;
+                    true
+                }
+            }
+        };
+        if failed {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("simulated read error at {}", msf),
+            ));
+        }
+        self.disc.read_sector(msf)
+    }
+}
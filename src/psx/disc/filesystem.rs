@@ -0,0 +1,102 @@
+use super::{invalid_data, Disc, Msf, LEAD_IN_SECTORS};
+
+use std::io;
+
+// Logical sector of the primary volume descriptor
+const VOLUME_DESCRIPTOR: u32 = 16;
+
+// Size of a logical sector
+const BLOCK_SIZE: usize = 2048;
+
+// Offset of the root directory record in the volume descriptor
+const ROOT_RECORD: usize = 156;
+
+// Directory flag of a directory record
+const FLAG_DIRECTORY: u8 = 0x02;
+
+// ISO 9660 directory record
+struct Record {
+    extent: u32,
+    size: u32,
+    directory: bool,
+}
+
+impl Record {
+    fn parse(data: &[u8]) -> Self {
+        Self {
+            extent: u32::from_le_bytes([data[2], data[3], data[4], data[5]]),
+            size: u32::from_le_bytes([data[10], data[11], data[12], data[13]]),
+            directory: data[25] & FLAG_DIRECTORY != 0,
+        }
+    }
+}
+
+// Read the file at `path` (e.g. "SYSTEM.CNF" or "\\DATA\\MOVIE.STR") from the
+// ISO 9660 filesystem of the first data track. Names are matched ignoring
+// case and the ";1" version suffix.
+pub fn read_file(disc: &mut dyn Disc, path: &str) -> io::Result<Vec<u8>> {
+    let descriptor = read_block(disc, VOLUME_DESCRIPTOR)?;
+    if descriptor[0] != 1 || &descriptor[1..6] != b"CD001" {
+        return Err(invalid_data("disc has no ISO 9660 filesystem"));
+    }
+    let mut record = Record::parse(&descriptor[ROOT_RECORD..]);
+
+    for name in path.split(['\\', '/']).filter(|name| !name.is_empty()) {
+        if !record.directory {
+            return Err(not_found(path));
+        }
+        record = find(disc, &record, name)?.ok_or_else(|| not_found(path))?;
+    }
+    if record.directory {
+        return Err(not_found(path));
+    }
+
+    let mut data = Vec::with_capacity(record.size as usize);
+    let mut block = record.extent;
+    while data.len() < record.size as usize {
+        let sector = read_block(disc, block)?;
+        let len = BLOCK_SIZE.min(record.size as usize - data.len());
+        data.extend_from_slice(&sector[..len]);
+        block += 1;
+    }
+    Ok(data)
+}
+
+// Look up `name` in the directory `dir`
+fn find(disc: &mut dyn Disc, dir: &Record, name: &str) -> io::Result<Option<Record>> {
+    let blocks = (dir.size as usize).div_ceil(BLOCK_SIZE) as u32;
+    for block in dir.extent..dir.extent + blocks {
+        let data = read_block(disc, block)?;
+        let mut offset = 0;
+        // Records don't cross sector boundaries, a zero length ends the sector
+        while offset < BLOCK_SIZE && data[offset] != 0 {
+            let len = data[offset] as usize;
+            let entry = match data.get(offset..offset + len) {
+                Some(entry) if len > 33 => entry,
+                _ => break,
+            };
+            let name_len = entry[32] as usize;
+            let entry_name = entry.get(33..33 + name_len).unwrap_or(&[]);
+            let entry_name = String::from_utf8_lossy(entry_name);
+            let entry_name = entry_name.split(';').next().unwrap_or("");
+            if entry_name.eq_ignore_ascii_case(name) {
+                return Ok(Some(Record::parse(entry)));
+            }
+            offset += len;
+        }
+    }
+    Ok(None)
+}
+
+// Read logical sector `block` of the filesystem
+fn read_block(disc: &mut dyn Disc, block: u32) -> io::Result<Vec<u8>> {
+    let sector = disc.read_sector(Msf::from_lba(LEAD_IN_SECTORS + block))?;
+    Ok(sector.user_data().to_vec())
+}
+
+fn not_found(path: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} not found on disc", path),
+    )
+}
@@ -0,0 +1,99 @@
+use super::{
+    edc, invalid_data, open_file, Disc, ImageFile, Msf, RawSector, Toc, Track, TrackType,
+    LEAD_IN_SECTORS, SECTOR_SIZE, SYNC,
+};
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+// Size of the user data of a Mode 2 Form 1 sector
+const DATA_SIZE: usize = 2048;
+
+// Submode of a synthesized sector: data
+const SUBMODE_DATA: u8 = 0x08;
+
+// Single track data image. Images holding only the 2048 bytes of user data
+// per sector get their headers and subheaders synthesized on read.
+pub struct Iso {
+    file: Box<dyn ImageFile>,
+    // Bytes per sector in the file, 2048 or 2352
+    sector_size: usize,
+    toc: Toc,
+}
+
+impl Iso {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_file(open_file(path.as_ref())?)
+    }
+
+    pub fn from_file(mut file: Box<dyn ImageFile>) -> io::Result<Self> {
+        let len = file.seek(SeekFrom::End(0))?;
+        file.seek(SeekFrom::Start(0))?;
+
+        // Raw images start with the sync pattern of the first sector
+        let mut sync = [0u8; 12];
+        let raw = file.read_exact(&mut sync).is_ok() && sync == SYNC;
+        let sector_size = if raw { SECTOR_SIZE } else { DATA_SIZE };
+        if len == 0 || len % sector_size as u64 != 0 {
+            return Err(invalid_data(format!(
+                "image size isn't a multiple of {} bytes",
+                sector_size
+            )));
+        }
+
+        let sectors = (len / sector_size as u64) as u32;
+        let start = Msf::from_lba(LEAD_IN_SECTORS);
+        let toc = Toc {
+            tracks: vec![Track {
+                number: 1,
+                kind: TrackType::Mode2,
+                start,
+                pregap: LEAD_IN_SECTORS,
+                length: sectors,
+                indexes: Vec::new(),
+                isrc: None,
+            }],
+            lead_out: Msf::from_lba(LEAD_IN_SECTORS + sectors),
+        };
+        Ok(Self {
+            file,
+            sector_size,
+            toc,
+        })
+    }
+}
+
+impl Disc for Iso {
+    fn toc(&self) -> &Toc {
+        &self.toc
+    }
+
+    fn read_sector(&mut self, msf: Msf) -> io::Result<RawSector> {
+        let lba = msf.to_lba();
+        if lba < LEAD_IN_SECTORS {
+            return Ok(RawSector::empty_data(msf, 2));
+        }
+        if msf >= self.toc.lead_out {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("sector {} is past the end of the disc", msf),
+            ));
+        }
+
+        let offset = (lba - LEAD_IN_SECTORS) as u64 * self.sector_size as u64;
+        self.file.seek(SeekFrom::Start(offset))?;
+        if self.sector_size == SECTOR_SIZE {
+            let mut sector = RawSector::new();
+            self.file.read_exact(&mut sector.data)?;
+            return Ok(sector);
+        }
+
+        // Mode 2 Form 1 sector with the subheader repeated twice
+        let mut sector = RawSector::empty_data(msf, 2);
+        sector.data[18] = SUBMODE_DATA;
+        sector.data[22] = SUBMODE_DATA;
+        self.file.read_exact(&mut sector.data[24..24 + DATA_SIZE])?;
+        edc::generate_form1(&mut sector.data);
+        Ok(sector)
+    }
+}
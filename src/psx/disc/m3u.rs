@@ -0,0 +1,56 @@
+use super::{invalid_data, open, Disc};
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// .m3u playlist listing the discs of a multi-disc game
+pub struct Playlist {
+    discs: Vec<PathBuf>,
+    // Index of the disc in the drive
+    current: usize,
+}
+
+impl Playlist {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        Self::from_text(&text, dir)
+    }
+
+    // Parse a playlist, with relative entries relative to `dir`
+    pub fn from_text(text: &str, dir: &Path) -> io::Result<Self> {
+        let discs: Vec<PathBuf> = text
+            .lines()
+            .map(|line| line.trim().trim_start_matches('\u{feff}'))
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| dir.join(line))
+            .collect();
+        if discs.is_empty() {
+            return Err(invalid_data("playlist has no discs"));
+        }
+        Ok(Self { discs, current: 0 })
+    }
+
+    pub fn discs(&self) -> &[PathBuf] {
+        &self.discs
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    // Open disc `index` and make it the current one
+    pub fn open_disc(&mut self, index: usize) -> io::Result<Box<dyn Disc>> {
+        let path = self.discs.get(index).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no disc {} in playlist", index + 1),
+            )
+        })?;
+        let disc = open(path)?;
+        self.current = index;
+        Ok(disc)
+    }
+}
@@ -0,0 +1,194 @@
+use super::{
+    deinterleave_q, invalid_data, Disc, Msf, RawSector, Toc, Track, TrackType, LEAD_IN_SECTORS,
+    SECTOR_SIZE,
+};
+
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const SIGNATURE: &[u8; 16] = b"MEDIA DESCRIPTOR";
+
+// Sizes of the descriptor blocks
+const SESSION_BLOCK_SIZE: usize = 0x18;
+const TRACK_BLOCK_SIZE: usize = 0x50;
+
+// Track modes (low nibble of the mode byte)
+const MODE_AUDIO: u8 = 0x09;
+const MODE_MODE1: u8 = 0x0a;
+
+// Where a track's sectors are in the .mdf file
+struct Layout {
+    // Offset of the sector at index 1
+    offset: u64,
+    // Bytes per sector, 2448 when subchannel data follows each sector
+    sector_size: u64,
+    subchannel: bool,
+}
+
+// Alcohol 120% image: a binary .mds descriptor and the .mdf data file
+pub struct Mds {
+    mdf: File,
+    toc: Toc,
+    layouts: Vec<Layout>,
+}
+
+impl Mds {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let descriptor = fs::read(path.with_extension("mds"))?;
+        let mdf = File::open(path.with_extension("mdf"))?;
+        let (toc, layouts) = parse(&descriptor, mdf.metadata()?.len())?;
+        Ok(Self { mdf, toc, layouts })
+    }
+}
+
+impl Disc for Mds {
+    fn toc(&self) -> &Toc {
+        &self.toc
+    }
+
+    fn read_sector(&mut self, msf: Msf) -> io::Result<RawSector> {
+        let lba = msf.to_lba();
+        let index = match self.toc.tracks.iter().position(|t| t.contains(lba)) {
+            Some(index) => index,
+            None if lba < LEAD_IN_SECTORS => return Ok(RawSector::new()),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("sector {} is past the end of the disc", msf),
+                ))
+            }
+        };
+        let track = &self.toc.tracks[index];
+        let layout = &self.layouts[index];
+
+        // The data file is a continuous dump, so a pregap is stored right
+        // before its track unless it's the lead-in
+        let start = track.start.to_lba() as i64;
+        let offset = layout.offset as i64 + (lba as i64 - start) * layout.sector_size as i64;
+        if lba < LEAD_IN_SECTORS || offset < 0 {
+            return Ok(match track.kind {
+                TrackType::Audio => RawSector::new(),
+                TrackType::Mode1 => RawSector::empty_data(msf, 1),
+                TrackType::Mode2 => RawSector::empty_data(msf, 2),
+            });
+        }
+
+        let mut sector = RawSector::new();
+        self.mdf.seek(SeekFrom::Start(offset as u64))?;
+        self.mdf.read_exact(&mut sector.data)?;
+        if layout.subchannel {
+            let mut sub = [0u8; 96];
+            self.mdf.read_exact(&mut sub)?;
+            sector.subq = Some(deinterleave_q(&sub));
+        }
+        Ok(sector)
+    }
+}
+
+// Build the table of contents from the descriptor. `mdf_len` is the size of
+// the data file.
+fn parse(mds: &[u8], mdf_len: u64) -> io::Result<(Toc, Vec<Layout>)> {
+    let truncated = || invalid_data("truncated MDS descriptor");
+    let u16_at = |offset: usize| -> io::Result<u16> {
+        let bytes = mds.get(offset..offset + 2).ok_or_else(truncated)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    };
+    let u32_at = |offset: usize| -> io::Result<u32> {
+        let bytes = mds.get(offset..offset + 4).ok_or_else(truncated)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+
+    if mds.get(..16) != Some(&SIGNATURE[..]) {
+        return Err(invalid_data("not an MDS descriptor"));
+    }
+
+    // Only the first session holds the PlayStation tracks
+    let session = u32_at(0x50)? as usize;
+    if mds.len() < session + SESSION_BLOCK_SIZE {
+        return Err(truncated());
+    }
+    let blocks = mds[session + 0x0a] as usize;
+    let track_blocks = u32_at(session + 0x14)? as usize;
+
+    let mut toc = Toc::default();
+    let mut layouts = Vec::new();
+    for i in 0..blocks {
+        let block = track_blocks + i * TRACK_BLOCK_SIZE;
+        let block_data = mds
+            .get(block..block + TRACK_BLOCK_SIZE)
+            .ok_or_else(truncated)?;
+        let point = block_data[0x04];
+        // Skip the A0h-A2h lead-in entries
+        if !(1..=99).contains(&point) {
+            continue;
+        }
+
+        let kind = match block_data[0x00] & 0x0f {
+            MODE_AUDIO => TrackType::Audio,
+            MODE_MODE1 => TrackType::Mode1,
+            _ => TrackType::Mode2,
+        };
+        let sector_size = u16_at(block + 0x10)? as u64;
+        if sector_size != SECTOR_SIZE as u64 && sector_size != SECTOR_SIZE as u64 + 96 {
+            return Err(invalid_data(format!(
+                "unsupported MDS sector size {}",
+                sector_size
+            )));
+        }
+        let start = u32_at(block + 0x24)? + LEAD_IN_SECTORS;
+        let offset = u32_at(block + 0x28)? as u64 | (u32_at(block + 0x2c)? as u64) << 32;
+
+        // The extra block holds the pregap and length in sectors
+        let extra = u32_at(block + 0x0c)? as usize;
+        let (pregap, length) = if extra != 0 {
+            (u32_at(extra)?, u32_at(extra + 4)?)
+        } else {
+            (0, 0)
+        };
+
+        toc.tracks.push(Track {
+            number: point,
+            kind,
+            start: Msf::from_lba(start),
+            // Track 1 also owns the 2 second lead-in pregap
+            pregap: if toc.tracks.is_empty() { start } else { pregap },
+            length,
+            indexes: Vec::new(),
+            isrc: None,
+        });
+        layouts.push(Layout {
+            offset,
+            sector_size,
+            subchannel: sector_size != SECTOR_SIZE as u64,
+        });
+    }
+
+    // Sort by position, filling in lengths the extra blocks didn't give
+    let mut entries: Vec<(Track, Layout)> = toc.tracks.drain(..).zip(layouts).collect();
+    entries.sort_by_key(|(track, _)| track.start);
+    for i in 0..entries.len() {
+        if entries[i].0.length != 0 {
+            continue;
+        }
+        let start = entries[i].0.start.to_lba();
+        let end = match entries.get(i + 1) {
+            Some((next, _)) => next.start.to_lba() - next.pregap,
+            None => {
+                let (_, layout) = &entries[i];
+                start + (mdf_len.saturating_sub(layout.offset) / layout.sector_size) as u32
+            }
+        };
+        entries[i].0.length = end.saturating_sub(start);
+    }
+    let (tracks, layouts): (Vec<Track>, Vec<Layout>) = entries.into_iter().unzip();
+    toc.tracks = tracks;
+
+    let last = match toc.tracks.last() {
+        Some(track) => track,
+        None => return Err(invalid_data("MDS descriptor has no tracks")),
+    };
+    toc.lead_out = Msf::from_lba(last.start.to_lba() + last.length);
+    Ok((toc, layouts))
+}
@@ -0,0 +1,298 @@
+#[cfg(any(feature = "zip", feature = "sevenz"))]
+mod archive;
+mod audio;
+mod ccd;
+#[cfg(feature = "chd")]
+mod chd;
+mod cnf;
+mod cue;
+#[cfg(all(feature = "physical-drive", target_os = "linux"))]
+mod drive;
+mod ecm;
+mod edc;
+mod fault;
+mod filesystem;
+mod iso;
+mod m3u;
+mod mds;
+mod msf;
+mod ppf;
+mod prefetch;
+mod redump;
+mod region;
+mod subq;
+
+#[cfg(feature = "chd")]
+pub use self::chd::ChdImage;
+pub use ccd::CloneCd;
+pub use cnf::{read_system_cnf, SystemCnf};
+pub use cue::BinCue;
+#[cfg(all(feature = "physical-drive", target_os = "linux"))]
+pub use drive::PhysicalDrive;
+pub use ecm::EcmFile;
+pub use edc::edc_valid;
+pub use fault::{Fault, FaultInjector};
+pub use filesystem::read_file;
+pub use iso::Iso;
+pub use m3u::Playlist;
+pub use mds::Mds;
+pub use msf::{from_bcd, to_bcd, Msf, SECTORS_PER_SECOND};
+pub use ppf::Ppf;
+pub use prefetch::Prefetch;
+pub use redump::{hash_tracks, Dat, DatGame, DatRom, TrackHash, Verification};
+pub use region::{boot_serial, detect_region, license_region, Region};
+pub use subq::{crc_valid, Subchannel};
+
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::path::Path;
+
+// Size of a raw sector, including the sync pattern and header
+pub const SECTOR_SIZE: usize = 2352;
+
+// Sectors before the first track's data (the 2 second pregap of track 1)
+pub const LEAD_IN_SECTORS: u32 = 150;
+
+// Sync pattern at the start of every data sector
+const SYNC: [u8; 12] = [
+    0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TrackType {
+    Audio,
+    Mode1,
+    Mode2,
+}
+
+#[derive(Clone, Debug)]
+pub struct Track {
+    // Track number (1..99)
+    pub number: u8,
+    pub kind: TrackType,
+    // Absolute position of index 1
+    pub start: Msf,
+    // Sectors of index 0 before the start
+    pub pregap: u32,
+    // Sectors from index 1 to the next track
+    pub length: u32,
+    // Absolute positions of index 2 onwards, when the track has them
+    pub indexes: Vec<Msf>,
+    // International Standard Recording Code of audio tracks, when the image
+    // has one
+    pub isrc: Option<String>,
+}
+
+impl Track {
+    // Absolute sector range covered by the track, including its pregap
+    pub fn contains(&self, lba: u32) -> bool {
+        let start = self.start.to_lba();
+        lba + self.pregap >= start && lba < start + self.length
+    }
+
+    // Index at absolute position `lba` of the track: 0 in the pregap, then 1
+    // and any later ones
+    pub fn index_at(&self, lba: u32) -> u8 {
+        if lba < self.start.to_lba() {
+            return 0;
+        }
+        1 + self
+            .indexes
+            .iter()
+            .filter(|msf| msf.to_lba() <= lba)
+            .count() as u8
+    }
+
+    // Playing time from index 1 to the end of the track
+    pub fn duration(&self) -> Msf {
+        Msf::from_lba(self.length)
+    }
+}
+
+// Table of contents
+#[derive(Clone, Debug, Default)]
+pub struct Toc {
+    pub tracks: Vec<Track>,
+    // Absolute position of the lead-out (end of the last track)
+    pub lead_out: Msf,
+}
+
+impl Toc {
+    pub fn first_track(&self) -> u8 {
+        self.tracks.first().map_or(1, |track| track.number)
+    }
+
+    pub fn last_track(&self) -> u8 {
+        self.tracks.last().map_or(1, |track| track.number)
+    }
+
+    pub fn track(&self, number: u8) -> Option<&Track> {
+        self.tracks.iter().find(|track| track.number == number)
+    }
+
+    // Track containing absolute sector `lba`
+    pub fn find(&self, lba: u32) -> Option<&Track> {
+        self.tracks.iter().find(|track| track.contains(lba))
+    }
+}
+
+// A raw 2352 byte sector
+#[derive(Clone)]
+pub struct RawSector {
+    pub data: [u8; SECTOR_SIZE],
+    // Subchannel Q, when the image stores subchannel data
+    pub subq: Option<[u8; 12]>,
+}
+
+impl RawSector {
+    pub fn new() -> Self {
+        Self {
+            data: [0; SECTOR_SIZE],
+            subq: None,
+        }
+    }
+
+    // Empty data sector with a valid sync pattern, header and error
+    // detection and correction codes
+    pub fn empty_data(msf: Msf, mode: u8) -> Self {
+        let mut sector = Self::new();
+        sector.data[..12].copy_from_slice(&SYNC);
+        sector.data[12..15].copy_from_slice(&msf.to_bcd());
+        sector.data[15] = mode;
+        if mode == 1 {
+            edc::generate_mode1(&mut sector.data);
+        } else {
+            edc::generate_form1(&mut sector.data);
+        }
+        sector
+    }
+
+    // Header: BCD minute, second, sector and mode
+    pub fn header(&self) -> &[u8] {
+        &self.data[12..16]
+    }
+
+    // Mode 2 subheader: file, channel, submode and coding info
+    pub fn subheader(&self) -> &[u8] {
+        &self.data[16..20]
+    }
+
+    pub fn mode(&self) -> u8 {
+        self.data[15]
+    }
+
+    // The 2048 bytes of user data of a Mode 1 or Mode 2 Form 1 sector
+    pub fn user_data(&self) -> &[u8] {
+        let offset = if self.mode() == 1 { 16 } else { 24 };
+        &self.data[offset..offset + 2048]
+    }
+}
+
+impl Default for RawSector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Seekable source of image data
+pub trait ImageFile: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ImageFile for T {}
+
+// Open a data file of an image. ECM compressed files are decoded on the fly,
+// and are used in place of missing files when found with an .ecm suffix.
+pub fn open_file(path: &Path) -> io::Result<Box<dyn ImageFile>> {
+    let is_ecm = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("ecm"));
+    if is_ecm {
+        return Ok(Box::new(EcmFile::open(path)?));
+    }
+    match File::open(path) {
+        Ok(file) => Ok(Box::new(file)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            let mut ecm = path.as_os_str().to_owned();
+            ecm.push(".ecm");
+            match EcmFile::open(&ecm) {
+                Ok(file) => Ok(Box::new(file)),
+                Err(_) => Err(err),
+            }
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// A disc image the CD-ROM controller reads sectors from
+pub trait Disc: Send {
+    fn toc(&self) -> &Toc;
+
+    // Read the raw sector at absolute position `msf`
+    fn read_sector(&mut self, msf: Msf) -> io::Result<RawSector>;
+}
+
+// Open the disc image at `path`, picking the format from its extension.
+// Subchannel Q is generated for images without it, and patched from an .sbi
+// or .lsd file next to the image. A .ppf patch next to the image is applied
+// to the sector data. For an .m3u playlist this is its first disc.
+pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn Disc>> {
+    let path = path.as_ref();
+    let is_playlist = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("m3u"));
+    if is_playlist {
+        return Playlist::open(path)?.open_disc(0);
+    }
+    let mut image = open_image(path)?;
+    let ppf = path.with_extension("ppf");
+    if ppf.is_file() {
+        image = Box::new(Ppf::open(image, &ppf)?);
+    }
+    let mut disc = Subchannel::new(image);
+    disc.load_patches_for(path)?;
+    Ok(Box::new(disc))
+}
+
+fn open_image(path: &Path) -> io::Result<Box<dyn Disc>> {
+    #[cfg(all(feature = "physical-drive", target_os = "linux"))]
+    if path.starts_with("/dev") {
+        return Ok(Box::new(PhysicalDrive::open(path)?));
+    }
+    #[cfg(any(feature = "zip", feature = "sevenz"))]
+    if archive::is_archive(path) {
+        return archive::open(path);
+    }
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("cue") => Ok(Box::new(BinCue::open(path)?)),
+        #[cfg(feature = "chd")]
+        Some("chd") => Ok(Box::new(ChdImage::open(path)?)),
+        Some("mds") | Some("mdf") => Ok(Box::new(Mds::open(path)?)),
+        Some("ccd") => Ok(Box::new(CloneCd::open(path)?)),
+        // CloneCD images are .img files with a .ccd descriptor next to them
+        Some("img") if path.with_extension("ccd").is_file() => Ok(Box::new(CloneCd::open(path)?)),
+        Some("iso") | Some("img") | Some("bin") => Ok(Box::new(Iso::open(path)?)),
+        // ECM compressed BIN or ISO image
+        Some("ecm") => Ok(Box::new(Iso::open(path)?)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported disc image: {}", path.display()),
+        )),
+    }
+}
+
+// Extract subchannel Q from 96 bytes of raw interleaved subchannel data,
+// where each byte holds one bit of channels P to W
+fn deinterleave_q(sub: &[u8]) -> [u8; 12] {
+    let mut q = [0u8; 12];
+    for (i, byte) in sub.iter().take(96).enumerate() {
+        q[i / 8] |= ((byte >> 6) & 1) << (7 - i % 8);
+    }
+    q
+}
+
+fn invalid_data<S: Into<String>>(msg: S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
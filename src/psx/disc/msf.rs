@@ -0,0 +1,65 @@
+use std::fmt;
+
+// Sectors per second of CD audio
+pub const SECTORS_PER_SECOND: u32 = 75;
+
+// Position on a disc in minutes, seconds and frames (sectors)
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+pub struct Msf {
+    pub m: u8,
+    pub s: u8,
+    pub f: u8,
+}
+
+impl Msf {
+    pub fn new(m: u8, s: u8, f: u8) -> Self {
+        Self { m, s, f }
+    }
+
+    // Position of absolute sector `lba`, counted from 00:00:00
+    pub fn from_lba(lba: u32) -> Self {
+        Self {
+            m: (lba / SECTORS_PER_SECOND / 60) as u8,
+            s: (lba / SECTORS_PER_SECOND % 60) as u8,
+            f: (lba % SECTORS_PER_SECOND) as u8,
+        }
+    }
+
+    // Absolute sector number, counted from 00:00:00
+    pub fn to_lba(self) -> u32 {
+        (self.m as u32 * 60 + self.s as u32) * SECTORS_PER_SECOND + self.f as u32
+    }
+
+    // Parse BCD encoded minutes, seconds and frames. Returns None if a field
+    // isn't valid BCD or is out of range.
+    pub fn from_bcd(m: u8, s: u8, f: u8) -> Option<Self> {
+        let msf = Self::new(from_bcd(m)?, from_bcd(s)?, from_bcd(f)?);
+        if msf.s >= 60 || msf.f >= SECTORS_PER_SECOND as u8 {
+            return None;
+        }
+        Some(msf)
+    }
+
+    pub fn to_bcd(self) -> [u8; 3] {
+        [to_bcd(self.m), to_bcd(self.s), to_bcd(self.f)]
+    }
+}
+
+impl fmt::Display for Msf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}:{:02}", self.m, self.s, self.f)
+    }
+}
+
+// Convert a BCD byte to binary
+pub fn from_bcd(val: u8) -> Option<u8> {
+    if val & 0xf > 9 || val >> 4 > 9 {
+        return None;
+    }
+    Some((val >> 4) * 10 + (val & 0xf))
+}
+
+// Convert a binary value (0..99) to BCD
+pub fn to_bcd(val: u8) -> u8 {
+    ((val / 10) << 4) | (val % 10)
+}
@@ -0,0 +1,129 @@
+use super::{invalid_data, Disc, Msf, RawSector, Toc, LEAD_IN_SECTORS, SECTOR_SIZE};
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Size of the header common to all versions: magic, encoding and
+// description
+const HEADER_SIZE: usize = 56;
+
+// Size of the block of image data used to check the patch matches
+const BLOCK_CHECK_SIZE: usize = 1024;
+
+// Marks the start of the optional description file at the end of the patch
+const DIZ_MARKER: &[u8] = b"@BEGIN_FILE_ID.DIZ";
+
+// Disc wrapper applying a PPF 1.0, 2.0 or 3.0 patch to the sectors it reads.
+// Patch offsets are byte offsets into the raw image of the first track.
+pub struct Ppf {
+    disc: Box<dyn Disc>,
+    // Patched bytes by absolute sector: offset in the sector and data
+    patches: HashMap<u32, Vec<(usize, Vec<u8>)>>,
+}
+
+impl Ppf {
+    pub fn open<P: AsRef<Path>>(disc: Box<dyn Disc>, path: P) -> io::Result<Self> {
+        Self::new(disc, &fs::read(path)?)
+    }
+
+    pub fn new(disc: Box<dyn Disc>, patch: &[u8]) -> io::Result<Self> {
+        let mut ppf = Self {
+            disc,
+            patches: HashMap::new(),
+        };
+        for (offset, data) in parse(patch)? {
+            ppf.add(offset, data);
+        }
+        Ok(ppf)
+    }
+
+    // Number of sectors changed by the patch
+    pub fn patched_sectors(&self) -> usize {
+        self.patches.len()
+    }
+
+    // Record `data` at byte `offset` of the image, split at sector boundaries
+    fn add(&mut self, mut offset: u64, mut data: &[u8]) {
+        while !data.is_empty() {
+            let lba = LEAD_IN_SECTORS + (offset / SECTOR_SIZE as u64) as u32;
+            let start = (offset % SECTOR_SIZE as u64) as usize;
+            let len = data.len().min(SECTOR_SIZE - start);
+            self.patches
+                .entry(lba)
+                .or_default()
+                .push((start, data[..len].to_vec()));
+            offset += len as u64;
+            data = &data[len..];
+        }
+    }
+}
+
+impl Disc for Ppf {
+    fn toc(&self) -> &Toc {
+        self.disc.toc()
+    }
+
+    fn read_sector(&mut self, msf: Msf) -> io::Result<RawSector> {
+        let mut sector = self.disc.read_sector(msf)?;
+        if let Some(patches) = self.patches.get(&msf.to_lba()) {
+            for (start, data) in patches.iter() {
+                sector.data[*start..*start + data.len()].copy_from_slice(data);
+            }
+        }
+        Ok(sector)
+    }
+}
+
+// Parse the (offset, data) records of a patch
+fn parse(patch: &[u8]) -> io::Result<Vec<(u64, &[u8])>> {
+    if patch.len() < HEADER_SIZE || &patch[..3] != b"PPF" {
+        return Err(invalid_data("not a PPF patch"));
+    }
+    // Version 3 has 64 bit offsets and optional undo data after each record
+    let (start, wide_offsets, undo) = match &patch[3..5] {
+        b"10" => (HEADER_SIZE, false, false),
+        b"20" => (HEADER_SIZE + 4 + BLOCK_CHECK_SIZE, false, false),
+        b"30" => {
+            let flags = patch
+                .get(56..60)
+                .ok_or_else(|| invalid_data("truncated PPF header"))?;
+            let block_check = flags[1] != 0;
+            let start = 60 + if block_check { BLOCK_CHECK_SIZE } else { 0 };
+            (start, true, flags[2] != 0)
+        }
+        _ => return Err(invalid_data("unsupported PPF version")),
+    };
+
+    // Records end where the description file starts
+    let end = patch
+        .windows(DIZ_MARKER.len())
+        .rposition(|window| window == DIZ_MARKER)
+        .unwrap_or(patch.len());
+    let truncated = || invalid_data("truncated PPF patch");
+
+    let mut records = Vec::new();
+    let mut pos = start;
+    while pos < end {
+        let offset = if wide_offsets {
+            let bytes = patch.get(pos..pos + 8).ok_or_else(truncated)?;
+            pos += 8;
+            u64::from_le_bytes(bytes.try_into().unwrap())
+        } else {
+            let bytes = patch.get(pos..pos + 4).ok_or_else(truncated)?;
+            pos += 4;
+            u32::from_le_bytes(bytes.try_into().unwrap()) as u64
+        };
+        let len = *patch.get(pos).ok_or_else(truncated)? as usize;
+        pos += 1;
+        let data = patch.get(pos..pos + len).ok_or_else(truncated)?;
+        pos += len;
+        if undo {
+            pos += len;
+        }
+        records.push((offset, data));
+    }
+    Ok(records)
+}
@@ -0,0 +1,138 @@
+use super::{Disc, Msf, RawSector, Toc};
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+
+// Sectors read ahead of the head
+const READ_AHEAD: u32 = 32;
+
+// Sectors kept behind the head, for short backward seeks
+const KEEP_BEHIND: u32 = 16;
+
+// Result of a sector read sent back by the worker. io::Error isn't Clone, so
+// errors are kept as their kind and message.
+type ReadResult = Result<RawSector, (io::ErrorKind, String)>;
+
+enum Command {
+    // The emulator is reading this sector, prefetch from here
+    Head(u32),
+    Stop,
+}
+
+// Disc wrapper reading sectors on a background thread ahead of the head, so
+// slow storage or decompression doesn't stall emulation. Only a read of a
+// sector that wasn't prefetched waits for the worker.
+pub struct Prefetch {
+    toc: Toc,
+    commands: Sender<Command>,
+    sectors: Receiver<(u32, ReadResult)>,
+    worker: Option<JoinHandle<()>>,
+    cache: HashMap<u32, ReadResult>,
+}
+
+impl Prefetch {
+    pub fn new(disc: Box<dyn Disc>) -> Self {
+        let toc = disc.toc().clone();
+        let (commands, command_rx) = mpsc::channel();
+        let (sector_tx, sectors) = mpsc::channel();
+        let worker = thread::Builder::new()
+            .name("cd-prefetch".into())
+            .spawn(move || run(disc, command_rx, sector_tx))
+            .expect("failed to spawn prefetch thread");
+        Self {
+            toc,
+            commands,
+            sectors,
+            worker: Some(worker),
+            cache: HashMap::new(),
+        }
+    }
+}
+
+impl Disc for Prefetch {
+    fn toc(&self) -> &Toc {
+        &self.toc
+    }
+
+    fn read_sector(&mut self, msf: Msf) -> io::Result<RawSector> {
+        let lba = msf.to_lba();
+        let stopped = || io::Error::other("prefetch thread stopped");
+        self.commands
+            .send(Command::Head(lba))
+            .map_err(|_| stopped())?;
+
+        while let Ok((sector, result)) = self.sectors.try_recv() {
+            self.cache.insert(sector, result);
+        }
+        while !self.cache.contains_key(&lba) {
+            let (sector, result) = self.sectors.recv().map_err(|_| stopped())?;
+            self.cache.insert(sector, result);
+        }
+
+        // Sectors past the read-ahead window are left over from before a
+        // backward seek
+        let low = lba.saturating_sub(KEEP_BEHIND);
+        self.cache
+            .retain(|&sector, _| sector >= low && sector < lba + READ_AHEAD);
+
+        match &self.cache[&lba] {
+            Ok(sector) => Ok(sector.clone()),
+            Err((kind, message)) => Err(io::Error::new(*kind, message.clone())),
+        }
+    }
+}
+
+impl Drop for Prefetch {
+    fn drop(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            let _ = self.commands.send(Command::Stop);
+            let _ = worker.join();
+        }
+    }
+}
+
+fn run(mut disc: Box<dyn Disc>, commands: Receiver<Command>, sectors: Sender<(u32, ReadResult)>) {
+    let mut head = 0;
+    // Next sector to read. Sectors from the head up to here were sent.
+    let mut cursor = 0;
+    let mut failed = false;
+    loop {
+        // Wait for the head to move once the window is full or reads fail
+        let command = if !failed && cursor < head + READ_AHEAD {
+            match commands.try_recv() {
+                Ok(command) => Some(command),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        } else {
+            match commands.recv() {
+                Ok(command) => Some(command),
+                Err(_) => return,
+            }
+        };
+
+        match command {
+            Some(Command::Stop) => return,
+            Some(Command::Head(lba)) => {
+                if lba < head || lba > cursor {
+                    cursor = lba;
+                }
+                head = lba;
+                failed = false;
+                continue;
+            }
+            None => {}
+        }
+
+        let result = disc
+            .read_sector(Msf::from_lba(cursor))
+            .map_err(|e| (e.kind(), e.to_string()));
+        failed = result.is_err();
+        if sectors.send((cursor, result)).is_err() {
+            return;
+        }
+        cursor += 1;
+    }
+}
@@ -0,0 +1,191 @@
+use super::{Disc, Msf, LEAD_IN_SECTORS, SECTOR_SIZE};
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = (crc >> 1) ^ if crc & 1 != 0 { 0xedb8_8320 } else { 0 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+// Size and CRC-32 of a track as stored in a Redump .bin file
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TrackHash {
+    pub number: u8,
+    pub size: u64,
+    pub crc32: u32,
+}
+
+// Hash every track the way Redump dumps them: raw sectors from the start of
+// the track's pregap, except for track 1 whose 2 second lead-in isn't stored
+pub fn hash_tracks(disc: &mut dyn Disc) -> io::Result<Vec<TrackHash>> {
+    let tracks = disc.toc().tracks.clone();
+    let mut hashes = Vec::with_capacity(tracks.len());
+    for (i, track) in tracks.iter().enumerate() {
+        let start = track.start.to_lba();
+        let end = start + track.length;
+        let first = if i == 0 {
+            LEAD_IN_SECTORS
+        } else {
+            start - track.pregap
+        };
+
+        let mut crc = !0u32;
+        for lba in first..end {
+            let sector = disc.read_sector(Msf::from_lba(lba))?;
+            crc = sector.data.iter().fold(crc, |crc, &byte| {
+                (crc >> 8) ^ CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize]
+            });
+        }
+        hashes.push(TrackHash {
+            number: track.number,
+            size: (end - first) as u64 * SECTOR_SIZE as u64,
+            crc32: !crc,
+        });
+    }
+    Ok(hashes)
+}
+
+// File of a game in a DAT
+#[derive(Clone, Debug)]
+pub struct DatRom {
+    pub name: String,
+    pub size: u64,
+    pub crc32: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct DatGame {
+    pub name: String,
+    pub roms: Vec<DatRom>,
+}
+
+impl DatGame {
+    // The .bin files, one per track
+    fn tracks(&self) -> impl Iterator<Item = &DatRom> {
+        self.roms
+            .iter()
+            .filter(|rom| !rom.name.to_ascii_lowercase().ends_with(".cue"))
+    }
+}
+
+// Redump DAT (Logiqx XML) listing the tracks of known good dumps
+#[derive(Clone, Debug, Default)]
+pub struct Dat {
+    pub games: Vec<DatGame>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verification {
+    // Every track matches this game
+    Verified(String),
+    // Some tracks match this game, the listed ones don't
+    BadDump { game: String, tracks: Vec<u8> },
+    // No game in the DAT has any of the tracks
+    Unknown,
+}
+
+impl Dat {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    // Parse the <game> and <rom> elements of a DAT, ignoring everything else
+    pub fn parse(text: &str) -> Self {
+        let mut games: Vec<DatGame> = Vec::new();
+        for tag in text.split('<').skip(1) {
+            let tag = tag.split('>').next().unwrap_or("");
+            if let Some(attributes) = tag.strip_prefix("game ") {
+                games.push(DatGame {
+                    name: attribute(attributes, "name").unwrap_or_default(),
+                    roms: Vec::new(),
+                });
+            } else if let Some(attributes) = tag.strip_prefix("rom ") {
+                let rom = DatRom {
+                    name: attribute(attributes, "name").unwrap_or_default(),
+                    size: attribute(attributes, "size")
+                        .and_then(|size| size.parse().ok())
+                        .unwrap_or(0),
+                    crc32: attribute(attributes, "crc")
+                        .and_then(|crc| u32::from_str_radix(&crc, 16).ok())
+                        .unwrap_or(0),
+                };
+                if let Some(game) = games.last_mut() {
+                    game.roms.push(rom);
+                }
+            }
+        }
+        Self { games }
+    }
+
+    // Compare track hashes against the DAT
+    pub fn verify(&self, hashes: &[TrackHash]) -> Verification {
+        let mut best: Option<(&DatGame, Vec<u8>)> = None;
+        for game in self.games.iter() {
+            let roms: Vec<&DatRom> = game.tracks().collect();
+            let mut matched = 0;
+            let mut bad = Vec::new();
+            for (i, hash) in hashes.iter().enumerate() {
+                match roms.get(i) {
+                    Some(rom) if rom.size == hash.size && rom.crc32 == hash.crc32 => matched += 1,
+                    _ => bad.push(hash.number),
+                }
+            }
+            if matched == 0 {
+                continue;
+            }
+            if bad.is_empty() && roms.len() == hashes.len() {
+                return Verification::Verified(game.name.clone());
+            }
+            if best.as_ref().is_none_or(|(_, best)| bad.len() < best.len()) {
+                best = Some((game, bad));
+            }
+        }
+        match best {
+            Some((game, tracks)) => Verification::BadDump {
+                game: game.name.clone(),
+                tracks,
+            },
+            None => Verification::Unknown,
+        }
+    }
+}
+
+// Value of attribute `name` in the attributes of an XML tag
+fn attribute(attributes: &str, name: &str) -> Option<String> {
+    let pattern = format!("{}=\"", name);
+    let mut rest = attributes;
+    loop {
+        let start = rest.find(&pattern)?;
+        // Make sure this isn't the end of a longer attribute name
+        let preceded_by_space = start == 0 || rest[..start].ends_with(char::is_whitespace);
+        let value = &rest[start + pattern.len()..];
+        if preceded_by_space {
+            let end = value.find('"')?;
+            return Some(unescape(&value[..end]));
+        }
+        rest = value;
+    }
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
@@ -0,0 +1,70 @@
+use super::{read_system_cnf, Disc, Msf, LEAD_IN_SECTORS};
+
+// Logical sector holding the license text
+const LICENSE_SECTOR: u32 = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Region {
+    NtscJ,
+    NtscU,
+    Pal,
+}
+
+impl Region {
+    // Last letter of the "SCEx" license string
+    pub fn license_letter(self) -> u8 {
+        match self {
+            Region::NtscJ => b'I',
+            Region::NtscU => b'A',
+            Region::Pal => b'E',
+        }
+    }
+
+    // Region of a serial number prefix such as "SLUS" or "SCES"
+    pub fn from_serial(serial: &str) -> Option<Self> {
+        let prefix = serial.get(..4)?.to_ascii_uppercase();
+        match prefix.as_str() {
+            "SCUS" | "SLUS" => Some(Region::NtscU),
+            "SCES" | "SLES" | "SCED" | "SLED" => Some(Region::Pal),
+            "SCPS" | "SLPS" | "SLPM" | "SCPM" | "SIPS" | "SCAJ" | "PAPX" | "SLKA" | "SCKA" => {
+                Some(Region::NtscJ)
+            }
+            _ => None,
+        }
+    }
+}
+
+// Detect the region of a PlayStation disc, from the serial of its boot
+// executable in SYSTEM.CNF or, failing that, from the license text
+pub fn detect_region(disc: &mut dyn Disc) -> Option<Region> {
+    boot_serial(disc)
+        .and_then(|serial| Region::from_serial(&serial))
+        .or_else(|| license_region(disc))
+}
+
+// Executable name from the BOOT line of SYSTEM.CNF, e.g. "SLUS_012.34"
+pub fn boot_serial(disc: &mut dyn Disc) -> Option<String> {
+    read_system_cnf(disc).map(|cnf| cnf.serial)
+}
+
+// Region from the license text, "Sony Computer Entertainment Amer  ica",
+// "Euro pe" or "Inc." for Japan. None for unlicensed discs.
+pub fn license_region(disc: &mut dyn Disc) -> Option<Region> {
+    let sector = disc
+        .read_sector(Msf::from_lba(LEAD_IN_SECTORS + LICENSE_SECTOR))
+        .ok()?;
+    let text: String = String::from_utf8_lossy(sector.user_data())
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    let rest = &text[text.find("SonyComputerEntertainment")? + 25..];
+    if rest.starts_with("America") {
+        Some(Region::NtscU)
+    } else if rest.starts_with("Europe") {
+        Some(Region::Pal)
+    } else if rest.starts_with("Inc") {
+        Some(Region::NtscJ)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,173 @@
+use super::{invalid_data, to_bcd, Disc, Msf, RawSector, Toc, TrackType};
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// CRC-16/CCITT of the first 10 bytes of subchannel Q, stored inverted
+pub fn crc16(data: &[u8]) -> u16 {
+    let crc = data.iter().fold(0u16, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    });
+    !crc
+}
+
+// Does the CRC of subchannel Q `q` match its contents?
+pub fn crc_valid(q: &[u8; 12]) -> bool {
+    crc16(&q[..10]).to_be_bytes() == [q[10], q[11]]
+}
+
+// Subchannel Q of the sector at absolute position `lba` in mode 1 (current
+// position)
+pub fn generate(toc: &Toc, lba: u32) -> [u8; 12] {
+    let mut q = [0u8; 12];
+    let track = toc.find(lba);
+
+    // Control bits: data track
+    let control = match track.map(|track| track.kind) {
+        Some(TrackType::Audio) | None => 0x00,
+        Some(_) => 0x40,
+    };
+    q[0] = control | 0x01;
+
+    match track {
+        Some(track) => {
+            let start = track.start.to_lba();
+            q[1] = to_bcd(track.number);
+            q[2] = to_bcd(track.index_at(lba));
+            // The relative position counts down to index 1 in the pregap
+            let relative = lba.abs_diff(start);
+            q[3..6].copy_from_slice(&Msf::from_lba(relative).to_bcd());
+        }
+        // Lead-out
+        None => {
+            q[1] = 0xaa;
+            q[2] = 0x01;
+            let relative = lba.saturating_sub(toc.lead_out.to_lba());
+            q[3..6].copy_from_slice(&Msf::from_lba(relative).to_bcd());
+        }
+    }
+    q[7..10].copy_from_slice(&Msf::from_lba(lba).to_bcd());
+
+    let crc = crc16(&q[..10]);
+    q[10..12].copy_from_slice(&crc.to_be_bytes());
+    q
+}
+
+// Disc wrapper that provides subchannel Q for every sector: the image's own
+// when it has any, generated otherwise, with SBI/LSD patches applied on top
+// for LibCrypt protected discs
+pub struct Subchannel {
+    disc: Box<dyn Disc>,
+    // Replacement Q data by absolute sector
+    patches: HashMap<u32, [u8; 12]>,
+}
+
+impl Subchannel {
+    pub fn new(disc: Box<dyn Disc>) -> Self {
+        Self {
+            disc,
+            patches: HashMap::new(),
+        }
+    }
+
+    // Load the .sbi or .lsd file next to the image at `path`, if any
+    pub fn load_patches_for(&mut self, path: &Path) -> io::Result<()> {
+        let sbi = path.with_extension("sbi");
+        let lsd = path.with_extension("lsd");
+        if sbi.is_file() {
+            self.load_sbi(&sbi)
+        } else if lsd.is_file() {
+            self.load_lsd(&lsd)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Load an SBI file: "SBI\0" followed by BCD MSF, a type and the patch
+    pub fn load_sbi<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let data = fs::read(path)?;
+        if data.get(..4) != Some(&b"SBI\0"[..]) {
+            return Err(invalid_data("not an SBI file"));
+        }
+
+        let mut pos = 4;
+        while pos < data.len() {
+            let entry = data
+                .get(pos..pos + 4)
+                .ok_or_else(|| invalid_data("truncated SBI file"))?;
+            let msf = Msf::from_bcd(entry[0], entry[1], entry[2])
+                .ok_or_else(|| invalid_data("invalid SBI position"))?;
+            let kind = entry[3];
+            // Type 1 replaces the whole Q data, types 2 and 3 only the
+            // relative or absolute position
+            let (offset, len) = match kind {
+                1 => (0, 10),
+                2 => (3, 3),
+                3 => (7, 3),
+                _ => return Err(invalid_data(format!("unknown SBI entry type {}", kind))),
+            };
+            let patch = data
+                .get(pos + 4..pos + 4 + len)
+                .ok_or_else(|| invalid_data("truncated SBI file"))?;
+
+            let lba = msf.to_lba();
+            let mut q = generate(self.disc.toc(), lba);
+            q[offset..offset + len].copy_from_slice(patch);
+            // Protected sectors have a bad CRC, flipping every bit of the
+            // right one never collides with it
+            let crc = !crc16(&q[..10]);
+            q[10..12].copy_from_slice(&crc.to_be_bytes());
+            self.patches.insert(lba, q);
+            pos += 4 + len;
+        }
+        Ok(())
+    }
+
+    // Load an LSD file: BCD MSF followed by the complete Q data with CRC
+    pub fn load_lsd<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let data = fs::read(path)?;
+        if data.len() % 15 != 0 {
+            return Err(invalid_data("truncated LSD file"));
+        }
+        for entry in data.chunks_exact(15) {
+            let msf = Msf::from_bcd(entry[0], entry[1], entry[2])
+                .ok_or_else(|| invalid_data("invalid LSD position"))?;
+            let mut q = [0u8; 12];
+            q.copy_from_slice(&entry[3..15]);
+            self.patches.insert(msf.to_lba(), q);
+        }
+        Ok(())
+    }
+
+    // Number of sectors with patched subchannel data
+    pub fn patch_count(&self) -> usize {
+        self.patches.len()
+    }
+}
+
+impl Disc for Subchannel {
+    fn toc(&self) -> &Toc {
+        self.disc.toc()
+    }
+
+    fn read_sector(&mut self, msf: Msf) -> io::Result<RawSector> {
+        let mut sector = self.disc.read_sector(msf)?;
+        let lba = msf.to_lba();
+        if let Some(q) = self.patches.get(&lba) {
+            sector.subq = Some(*q);
+        } else if sector.subq.is_none() {
+            sector.subq = Some(generate(self.disc.toc(), lba));
+        }
+        Ok(sector)
+    }
+}
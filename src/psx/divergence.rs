@@ -0,0 +1,126 @@
+use super::frame;
+use super::movie::{self, Movie, MovieMode};
+use super::snapshot::{self, Snapshot};
+use super::{Error, Psx};
+
+// Finding where two runs that should be the same part ways: two machines
+// run side by side, frame by frame, and each component's state is hashed
+// after every frame, pointing to the first frame and the components (CPU,
+// RAM, GPU, SPU, CD-ROM...) that differ. Both run in deterministic mode,
+// so what's left is a bug in the core or in what the runs were given.
+
+// First point where the runs differ
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    // Frame count of the machines when found, the frame that just ended
+    pub frame: u64,
+    // Section tags of the components that differ, as in save states
+    pub components: Vec<String>,
+}
+
+// Hash of the state of each component, by section tag. FNV-1a, the same on
+// every host, so hashes logged by two builds can be compared too.
+pub fn component_hashes(psx: &mut Psx) -> Vec<(String, u64)> {
+    snapshot::components(psx)
+        .into_iter()
+        .map(|(name, data)| {
+            let hash = data.iter().fold(0xcbf29ce484222325u64, |hash, &byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            });
+            (name, hash)
+        })
+        .collect()
+}
+
+// Components whose hashes differ
+pub fn diff(a: &[(String, u64)], b: &[(String, u64)]) -> Vec<String> {
+    a.iter()
+        .zip(b.iter())
+        .filter(|(a, b)| a != b)
+        .map(|(a, _)| a.0.clone())
+        .collect()
+}
+
+// Start `a` and `b` from two snapshots that should be the same and run them
+// for up to `frames` frames. `input` is called on both machines before each
+// frame, with the frame number, to give them the same input.
+pub fn from_snapshots<F>(
+    a: &mut Psx,
+    b: &mut Psx,
+    snapshots: (&Snapshot, &Snapshot),
+    frames: u64,
+    mut input: F,
+) -> Result<Option<Divergence>, Error>
+where
+    F: FnMut(&mut Psx, u64),
+{
+    a.restore(snapshots.0)?;
+    b.restore(snapshots.1)?;
+    lockstep(a, b, frames, |psx| {
+        let frame = psx.frames;
+        input(psx, frame);
+        Ok(true)
+    })
+}
+
+// Play `movie` on `a` and `b`, both just booted the same way, until it ends
+// or they differ. A movie desyncing against its recording can come from the
+// core running differently each time, which this finds.
+pub fn from_movie(a: &mut Psx, b: &mut Psx, movie: &Movie) -> Result<Option<Divergence>, Error> {
+    a.play_movie(movie.clone())?;
+    b.play_movie(movie.clone())?;
+    let result = lockstep(a, b, movie.frames.len() as u64, |psx| {
+        Ok(psx
+            .movie_status()
+            .is_some_and(|status| status.mode == MovieMode::Playing))
+    });
+    movie::stop(a);
+    movie::stop(b);
+    result
+}
+
+// Run both machines a frame at a time, after `before` returns true for
+// both, and compare them after each frame
+fn lockstep<F>(
+    a: &mut Psx,
+    b: &mut Psx,
+    frames: u64,
+    mut before: F,
+) -> Result<Option<Divergence>, Error>
+where
+    F: FnMut(&mut Psx) -> Result<bool, Error>,
+{
+    let deterministic = (a.deterministic, b.deterministic);
+    a.set_deterministic(true);
+    b.set_deterministic(true);
+    let result = (|| {
+        if let Some(divergence) = compare(a, b) {
+            return Ok(Some(divergence));
+        }
+        for _ in 0..frames {
+            if !before(a)? || !before(b)? {
+                break;
+            }
+            frame::run(a);
+            frame::run(b);
+            if let Some(divergence) = compare(a, b) {
+                return Ok(Some(divergence));
+            }
+        }
+        Ok(None)
+    })();
+    a.set_deterministic(deterministic.0);
+    b.set_deterministic(deterministic.1);
+    result
+}
+
+fn compare(a: &mut Psx, b: &mut Psx) -> Option<Divergence> {
+    let components = diff(&component_hashes(a), &component_hashes(b));
+    match components.is_empty() {
+        true => None,
+        false => Some(Divergence {
+            frame: a.frames,
+            components,
+        }),
+    }
+}
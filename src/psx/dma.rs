@@ -0,0 +1,339 @@
+use super::irq::Interrupt;
+use super::mdec;
+use super::spu;
+use super::state::{Savestate, StateError, StateReader, StateWriter};
+use super::Psx;
+
+// Number of DMA channels
+const CHANNELS: usize = 7;
+
+// CPU cycles per word moved from the CD-ROM data FIFO
+const CDROM_WORD_CYCLES: u64 = 40;
+
+// CPU cycles per word moved to or from the MDEC
+const MDEC_WORD_CYCLES: u64 = 1;
+
+// CPU cycles per word moved to or from sound RAM
+const SPU_WORD_CYCLES: u64 = 4;
+
+// DMA channels, by number
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Port {
+    MdecIn = 0,
+    MdecOut = 1,
+    Gpu = 2,
+    Cdrom = 3,
+    Spu = 4,
+    Pio = 5,
+    Otc = 6,
+}
+
+impl Port {
+    fn from_index(index: usize) -> Self {
+        match index {
+            0 => Port::MdecIn,
+            1 => Port::MdecOut,
+            2 => Port::Gpu,
+            3 => Port::Cdrom,
+            4 => Port::Spu,
+            5 => Port::Pio,
+            _ => Port::Otc,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Channel {
+    // D#_MADR base address
+    base: u32,
+    // D#_BCR block size (bits 0-15) and count (bits 16-31)
+    block: u32,
+    // D#_CHCR channel control
+    control: u32,
+}
+
+impl Channel {
+    fn sync_mode(&self) -> u32 {
+        (self.control >> 9) & 3
+    }
+
+    // Is the channel started and, in manual mode, triggered?
+    fn active(&self) -> bool {
+        let start = self.control & 0x0100_0000 != 0;
+        match self.sync_mode() {
+            0 => start && self.control & 0x1000_0000 != 0,
+            _ => start,
+        }
+    }
+
+    // Words to move: the block size in manual mode (0 meaning 0x10000),
+    // size times count in request mode
+    fn words(&self) -> u32 {
+        let size = self.block & 0xffff;
+        let count = self.block >> 16;
+        match self.sync_mode() {
+            0 if size == 0 => 0x10000,
+            0 => size,
+            _ => size * count.max(1),
+        }
+    }
+
+    // Address step: -4 with bit 1 set
+    fn step(&self) -> u32 {
+        if self.control & 0x02 != 0 {
+            (-4i32) as u32
+        } else {
+            4
+        }
+    }
+}
+
+pub struct Dma {
+    channels: [Channel; CHANNELS],
+    // 1F8010F0h DPCR priority and enable bits
+    control: u32,
+    // 1F8010F4h DICR interrupt control
+    interrupt: u32,
+}
+
+impl Dma {
+    pub fn new() -> Self {
+        Self {
+            channels: [Channel::default(); CHANNELS],
+            control: 0x0765_4321,
+            interrupt: 0,
+        }
+    }
+
+    // DICR master flag: forced, or enabled with any enabled channel flag set
+    fn irq_active(&self) -> bool {
+        let force = self.interrupt & 0x8000 != 0;
+        let master = self.interrupt & 0x0080_0000 != 0;
+        let enabled = (self.interrupt >> 16) & 0x7f;
+        let flags = (self.interrupt >> 24) & 0x7f;
+        force || (master && enabled & flags != 0)
+    }
+
+    fn load(&self, offset: u32) -> u32 {
+        match offset {
+            0x70 => self.control,
+            0x74 => {
+                let mut val = self.interrupt;
+                if self.irq_active() {
+                    val |= 0x8000_0000;
+                }
+                val
+            }
+            0x00..=0x6f => {
+                let channel = &self.channels[(offset >> 4) as usize];
+                match offset & 0xf {
+                    0x0 => channel.base,
+                    0x4 => channel.block,
+                    0x8 => channel.control,
+                    _ => 0,
+                }
+            }
+            _ => 0,
+        }
+    }
+}
+
+impl Savestate for Dma {
+    fn save_state(&self, w: &mut StateWriter) {
+        for channel in self.channels.iter() {
+            w.write_u32(channel.base);
+            w.write_u32(channel.block);
+            w.write_u32(channel.control);
+        }
+        w.write_u32(self.control);
+        w.write_u32(self.interrupt);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        for channel in self.channels.iter_mut() {
+            channel.base = r.read_u32()?;
+            channel.block = r.read_u32()?;
+            channel.control = r.read_u32()?;
+        }
+        self.control = r.read_u32()?;
+        self.interrupt = r.read_u32()?;
+        Ok(())
+    }
+}
+
+impl Default for Dma {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Read a DMA register at `offset` from 1F801080h
+pub fn load(psx: &mut Psx, offset: u32) -> u32 {
+    psx.dma.load(offset & !3)
+}
+
+// Write a DMA register at `offset` from 1F801080h
+pub fn store(psx: &mut Psx, offset: u32, val: u32) {
+    let dma = &mut psx.dma;
+    match offset & !3 {
+        0x70 => dma.control = val,
+        0x74 => {
+            // Flags are acknowledged by writing ones
+            let flags = dma.interrupt & !val & 0x7f00_0000;
+            dma.interrupt = (val & 0x00ff_803f) | flags;
+        }
+        0x00..=0x6f => {
+            let index = (offset >> 4) as usize;
+            let channel = &mut dma.channels[index];
+            match offset & 0xc {
+                0x0 => channel.base = val & 0x00ff_fffc,
+                0x4 => channel.block = val,
+                0x8 => {
+                    // Only the OTC channel's direction and step are fixed
+                    channel.control = if index == Port::Otc as usize {
+                        (val & 0x5100_0000) | 0x02
+                    } else {
+                        val & 0x7177_0703
+                    };
+                    if channel.active() && dma.control & (8 << (index * 4)) != 0 {
+                        run(psx, index);
+                    }
+                }
+                _ => {}
+            }
+        }
+        _ => {}
+    }
+}
+
+// Run a started channel's transfer to completion
+fn run(psx: &mut Psx, index: usize) {
+    let channel = psx.dma.channels[index];
+    let words = channel.words();
+    let step = channel.step();
+    let mut addr = channel.base;
+
+    match Port::from_index(index) {
+        Port::Cdrom => {
+            for _ in 0..words {
+                let cd = &mut psx.cdrom;
+                let bytes = [
+                    cd.read_data(),
+                    cd.read_data(),
+                    cd.read_data(),
+                    cd.read_data(),
+                ];
+                psx.ram.store(addr, u32::from_le_bytes(bytes));
+                addr = addr.wrapping_add(step) & 0x00ff_fffc;
+            }
+            tick(psx, words, CDROM_WORD_CYCLES);
+        }
+        Port::MdecIn => {
+            // The MDEC only takes data once its DMA request is enabled
+            if !psx.mdec.dma_in_request() {
+                return;
+            }
+            for _ in 0..words {
+                let val = psx.ram.load(addr);
+                mdec::write(psx, val);
+                addr = addr.wrapping_add(step) & 0x00ff_fffc;
+            }
+            tick(psx, words, MDEC_WORD_CYCLES);
+        }
+        Port::MdecOut => match run_mdec_out(psx, index) {
+            Some(end) => addr = end,
+            None => return,
+        },
+        // Sound RAM, at the SPU's transfer address
+        Port::Spu => {
+            let to_spu = channel.control & 1 != 0;
+            for _ in 0..words {
+                if to_spu {
+                    let val = psx.ram.load(addr);
+                    spu::dma_write(psx, val);
+                } else {
+                    let val = spu::dma_read(psx);
+                    psx.ram.store(addr, val);
+                }
+                addr = addr.wrapping_add(step) & 0x00ff_fffc;
+            }
+            tick(psx, words, SPU_WORD_CYCLES);
+        }
+        // Channels without a device attached complete without moving data
+        Port::Gpu | Port::Pio | Port::Otc => {}
+    }
+
+    finish(psx, index, addr);
+
+    if let Port::MdecIn = Port::from_index(index) {
+        request(psx, Port::MdecOut);
+    }
+}
+
+// Move decoded pixels from the MDEC one block at a time, as they become
+// available. Returns the address past the last word once done, or None while
+// the channel waits for more output.
+fn run_mdec_out(psx: &mut Psx, index: usize) -> Option<u32> {
+    loop {
+        let channel = psx.dma.channels[index];
+        let (size, blocks) = match channel.sync_mode() {
+            0 => (channel.words(), 1),
+            _ => (channel.block & 0xffff, (channel.block >> 16).max(1)),
+        };
+        if psx.mdec.dma_out_words() < size as usize {
+            return None;
+        }
+        let step = channel.step();
+        let mut addr = channel.base;
+        for _ in 0..size {
+            let val = psx.mdec.read_data();
+            psx.ram.store(addr, val);
+            addr = addr.wrapping_add(step) & 0x00ff_fffc;
+        }
+        tick(psx, size, MDEC_WORD_CYCLES);
+        // Request mode moves the address and counts down the blocks as it
+        // goes, so the transfer can pick up where it stopped
+        if channel.sync_mode() == 0 || blocks == 1 {
+            return Some(addr);
+        }
+        let channel = &mut psx.dma.channels[index];
+        channel.base = addr;
+        channel.block = (channel.block & 0xffff) | ((blocks - 1) << 16);
+    }
+}
+
+// Let the time a transfer of `words` takes pass, unless DMA is made instant
+fn tick(psx: &mut Psx, words: u32, word_cycles: u64) {
+    if !psx.active_enhancements.instant_dma {
+        psx.tick(words as u64 * word_cycles);
+    }
+}
+
+// Resume a started channel that waits for its device to be ready
+pub fn request(psx: &mut Psx, port: Port) {
+    let index = port as usize;
+    let channel = psx.dma.channels[index];
+    if channel.active() && psx.dma.control & (8 << (index * 4)) != 0 {
+        run(psx, index);
+    }
+}
+
+// Clear the busy bits and raise the channel's interrupt
+fn finish(psx: &mut Psx, index: usize, addr: u32) {
+    let dma = &mut psx.dma;
+    let channel = &mut dma.channels[index];
+    channel.control &= !0x1100_0000;
+    // Request mode leaves the address past the last block
+    if channel.sync_mode() == 1 {
+        channel.base = addr;
+        channel.block &= 0xffff;
+    }
+
+    let was_active = dma.irq_active();
+    if dma.interrupt & (1 << (16 + index)) != 0 {
+        dma.interrupt |= 1 << (24 + index);
+    }
+    if !was_active && dma.irq_active() {
+        psx.irq.request(Interrupt::Dma);
+    }
+}
@@ -0,0 +1,28 @@
+use super::gamedb::GameSettings;
+
+// Hacks trading accuracy for speed, for machines too slow to run games at
+// full speed otherwise. All are off by default, and games the game database
+// flags as needing accurate timing run without them. Each can break games
+// relying on the timing it changes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Enhancements {
+    // DMA transfers take no time, the CPU doesn't wait for them
+    pub instant_dma: bool,
+    // The CD-ROM head reaches any position in the shortest seek time, so
+    // loading skips the sled moves
+    pub zero_seek: bool,
+}
+
+impl Enhancements {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The hacks that apply to a game with `settings`
+    pub fn for_game(self, settings: &GameSettings) -> Self {
+        match settings.accurate_timing {
+            true => Self::new(),
+            false => self,
+        }
+    }
+}
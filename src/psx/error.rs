@@ -0,0 +1,54 @@
+use super::state::StateError;
+
+use std::error;
+use std::fmt;
+use std::io;
+
+// Errors reported to frontends setting up and running the core
+#[derive(Debug)]
+pub enum Error {
+    // The BIOS image can't be read or isn't usable
+    Bios(io::Error),
+    // The disc image can't be opened, or the program on it can't be read
+    Disc(io::Error),
+    // A save state that can't be restored
+    SaveState(StateError),
+    // A file the frontend asked for, such as a save state or a program to
+    // sideload, can't be read or written
+    Io(io::Error),
+    // Settings that can't work, or don't work together, or a request that
+    // doesn't fit the current setup
+    Config(String),
+    // Something the core should never get into. Reported instead of
+    // panicking, with what went wrong.
+    Internal(String),
+}
+
+impl From<StateError> for Error {
+    fn from(e: StateError) -> Self {
+        Error::SaveState(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Bios(e) => write!(f, "BIOS: {}", e),
+            Error::Disc(e) => write!(f, "disc: {}", e),
+            Error::SaveState(e) => write!(f, "save state: {}", e),
+            Error::Io(e) => write!(f, "I/O: {}", e),
+            Error::Config(message) => write!(f, "configuration: {}", message),
+            Error::Internal(message) => write!(f, "internal error: {}", message),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Bios(e) | Error::Disc(e) | Error::Io(e) => Some(e),
+            Error::SaveState(e) => Some(e),
+            Error::Config(_) | Error::Internal(_) => None,
+        }
+    }
+}
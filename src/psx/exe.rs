@@ -0,0 +1,70 @@
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Size of the header preceding the program text
+pub const HEADER_SIZE: usize = 0x800;
+
+const MAGIC: &[u8] = b"PS-X EXE";
+
+// Stack the BIOS sets up when an EXE doesn't ask for its own
+pub const DEFAULT_SP: u32 = 0x801fff00;
+
+// A PS-X EXE program, as run by the BIOS shell or sideloaded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Exe {
+    pub pc: u32,
+    pub gp: u32,
+    // Address the text is copied to
+    pub load_address: u32,
+    pub text: Vec<u8>,
+    // Area cleared before starting, usually the BSS
+    pub fill_address: u32,
+    pub fill_size: u32,
+    // Initial sp and fp, None to keep the BIOS stack
+    pub sp: Option<u32>,
+    // Region marker, e.g. "Sony Computer Entertainment Inc. for North
+    // America area"
+    pub marker: String,
+}
+
+fn word(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Exe {
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        if data.len() < HEADER_SIZE || !data.starts_with(MAGIC) {
+            return Err(invalid("not a PS-X EXE"));
+        }
+        let size = word(data, 0x1c) as usize;
+        let text = data
+            .get(HEADER_SIZE..HEADER_SIZE + size)
+            .ok_or_else(|| invalid("PS-X EXE is shorter than its header says"))?;
+        let sp_base = word(data, 0x30);
+        let marker = &data[0x4c..HEADER_SIZE];
+        let end = marker.iter().position(|&b| b == 0).unwrap_or(marker.len());
+        Ok(Self {
+            pc: word(data, 0x10),
+            gp: word(data, 0x14),
+            load_address: word(data, 0x18),
+            text: text.to_vec(),
+            fill_address: word(data, 0x28),
+            fill_size: word(data, 0x2c),
+            sp: match sp_base {
+                0 => None,
+                base => Some(base.wrapping_add(word(data, 0x34))),
+            },
+            marker: String::from_utf8_lossy(&marker[..end]).into_owned(),
+        })
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse(&fs::read(path)?)
+    }
+}
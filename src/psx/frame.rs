@@ -0,0 +1,183 @@
+use super::{autosave, cpu, map, movie, rewind, spu, Psx};
+
+// What the GPU sends to the TV. Drawing isn't emulated, so this describes
+// the display mode rather than holding pixels.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct VideoFrame {
+    // Displayed size in pixels, from the display mode and ranges
+    pub width: u32,
+    pub height: u32,
+    pub pal: bool,
+    pub interlaced: bool,
+    // 24 bit color, as used for MDEC video, instead of 15 bit
+    pub depth24: bool,
+    // Display enabled with GP1(03h); a disabled display shows black
+    pub enabled: bool,
+}
+
+// Result of running one frame
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FrameOutput {
+    // Frames completed since power on, this one included
+    pub frame: u64,
+    pub video: VideoFrame,
+    // Interleaved samples waiting to be taken with take_audio_samples
+    pub audio_samples: usize,
+    // The game read a controller during the frame. Input set now is what
+    // it sees next; frames without a poll are lag frames.
+    pub input_polled: bool,
+}
+
+// Why run_until_event stopped
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EmulationEvent {
+    // A frame completed
+    FrameReady(FrameOutput),
+    // The CPU is about to run the instruction at this breakpoint. Running
+    // again continues past it.
+    BreakpointHit(u32),
+    // The machine got into a state it can't run from, and stays there
+    StateError(String),
+    // A line the program printed, without its newline
+    TtyOutput(String),
+    // The game looked for a disc while there was none or the lid was open,
+    // e.g. when asking for the next disc of a multi-disc game
+    DiscChangeRequested,
+}
+
+// State of the run loop between calls
+pub struct Runner {
+    // Frame count when the last frame was reported
+    reported_frame: u64,
+    breakpoints: Vec<u32>,
+    // Stopped at a breakpoint, which the next run steps over
+    at_breakpoint: bool,
+}
+
+impl Runner {
+    pub fn new() -> Self {
+        Self {
+            reported_frame: 0,
+            breakpoints: Vec::new(),
+            at_breakpoint: false,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, pc: u32) {
+        if !self.breakpoints.contains(&pc) {
+            self.breakpoints.push(pc);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: u32) {
+        self.breakpoints.retain(|&breakpoint| breakpoint != pc);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    // Consider frame `frame` reported, after going back to it
+    pub fn set_reported_frame(&mut self, frame: u64) {
+        self.reported_frame = frame;
+    }
+}
+
+impl Default for Runner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Run until the start of the next vblank
+pub fn run(psx: &mut Psx) -> FrameOutput {
+    let frame = psx.frames;
+    psx.sio.take_polled();
+    while psx.frames == frame {
+        cpu::step(psx);
+        // One cycle per instruction until the CPU counts its own
+        psx.tick(1);
+    }
+    frame_output(psx)
+}
+
+// Run `f` with what the machine outputs going nowhere: audio, TTY output,
+// traces, and the rewind, movie and auto-save captures. For frames that are
+// run again for real later, or that already were. Link cable traffic can't
+// be taken back, run-ahead is skipped while a cable is plugged in.
+pub fn unseen<R, F: FnOnce(&mut Psx) -> R>(psx: &mut Psx, f: F) -> R {
+    let audio = std::mem::take(&mut psx.audio);
+    let audio_dump = psx.audio_dump.take();
+    let tty = std::mem::take(&mut psx.tty);
+    let bios_trace = psx.bios_trace.take();
+    let cd_trace = psx.cdrom.take_trace();
+    let kernel_watch = psx.kernel_watch.take();
+    let rewind = psx.rewind.take();
+    let movie = psx.movie.take();
+    let autosave = psx.autosave.take();
+    let result = f(psx);
+    psx.audio = audio;
+    psx.audio_dump = audio_dump;
+    psx.tty = tty;
+    psx.bios_trace = bios_trace;
+    psx.cdrom.set_trace(cd_trace);
+    psx.kernel_watch = kernel_watch;
+    psx.rewind = rewind;
+    psx.movie = movie;
+    psx.autosave = autosave;
+    result
+}
+
+fn frame_output(psx: &mut Psx) -> FrameOutput {
+    psx.runner.reported_frame = psx.frames;
+    spu::collect(psx);
+    movie::frame(psx);
+    rewind::frame(psx);
+    autosave::frame(psx);
+    FrameOutput {
+        frame: psx.frames,
+        video: psx.gpu.video_frame(),
+        audio_samples: psx.audio.len(),
+        input_polled: psx.sio.take_polled(),
+    }
+}
+
+// Run until something the frontend may want to react to happens
+pub fn run_until_event(psx: &mut Psx) -> EmulationEvent {
+    loop {
+        if let Some(line) = psx.tty.take_line() {
+            return EmulationEvent::TtyOutput(line);
+        }
+        if psx.cdrom.take_disc_wanted() {
+            return EmulationEvent::DiscChangeRequested;
+        }
+        let pc = psx.cpu.pc;
+        if !executable(psx, pc) {
+            return EmulationEvent::StateError(format!(
+                "pc {:08x} is outside executable memory",
+                pc
+            ));
+        }
+        let runner = &mut psx.runner;
+        if !runner.at_breakpoint && runner.breakpoints.contains(&pc) {
+            runner.at_breakpoint = true;
+            return EmulationEvent::BreakpointHit(pc);
+        }
+        runner.at_breakpoint = false;
+
+        cpu::step(psx);
+        psx.tick(1);
+        if psx.frames != psx.runner.reported_frame {
+            return EmulationEvent::FrameReady(frame_output(psx));
+        }
+    }
+}
+
+// Can the CPU run code from `pc`: RAM, the BIOS, or a cartridge's ROM?
+fn executable(psx: &Psx, pc: u32) -> bool {
+    match map::mask(pc) {
+        0x00000000..=0x007fffff | 0x1fc00000..=0x1fc7ffff => true,
+        0x1f000000..=0x1f7fffff => psx.cartridge.is_some(),
+        _ => false,
+    }
+}
@@ -0,0 +1,264 @@
+use super::disc::{Region, SystemCnf};
+use super::Error;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Settings games are known to need
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GameSettings {
+    // Breaks unless CD-ROM and DMA timing is close to hardware
+    pub accurate_timing: bool,
+    // LibCrypt protected, needs an .sbi/.lsd file for the subchannel data
+    pub libcrypt: bool,
+    // Relies on the BIOS intro having run, so fast boot is skipped
+    pub full_boot: bool,
+    // Input is read in a way run-ahead gets wrong, so it's turned off
+    pub no_run_ahead: bool,
+}
+
+impl GameSettings {
+    // Change the setting named as in override files. Returns false for
+    // unknown names.
+    pub fn set(&mut self, name: &str, value: bool) -> bool {
+        let setting = match name {
+            "accurate_timing" => &mut self.accurate_timing,
+            "libcrypt" => &mut self.libcrypt,
+            "full_boot" => &mut self.full_boot,
+            "no_run_ahead" => &mut self.no_run_ahead,
+            _ => return false,
+        };
+        *setting = value;
+        true
+    }
+}
+
+struct Entry {
+    serial: &'static str,
+    title: &'static str,
+    settings: GameSettings,
+}
+
+const NONE: GameSettings = GameSettings {
+    accurate_timing: false,
+    libcrypt: false,
+    full_boot: false,
+    no_run_ahead: false,
+};
+
+const LIBCRYPT: GameSettings = GameSettings {
+    accurate_timing: false,
+    libcrypt: true,
+    full_boot: false,
+    no_run_ahead: false,
+};
+
+// Known games by serial, sorted for binary search
+const GAMES: &[Entry] = &[
+    Entry {
+        serial: "SCES-00311",
+        title: "MediEvil",
+        settings: LIBCRYPT,
+    },
+    Entry {
+        serial: "SCES-00344",
+        title: "Crash Bandicoot",
+        settings: NONE,
+    },
+    Entry {
+        serial: "SCUS-94163",
+        title: "Final Fantasy VII (Disc 1)",
+        settings: NONE,
+    },
+    Entry {
+        serial: "SCUS-94164",
+        title: "Final Fantasy VII (Disc 2)",
+        settings: NONE,
+    },
+    Entry {
+        serial: "SCUS-94165",
+        title: "Final Fantasy VII (Disc 3)",
+        settings: NONE,
+    },
+    Entry {
+        serial: "SCUS-94194",
+        title: "Gran Turismo",
+        settings: NONE,
+    },
+    Entry {
+        serial: "SCUS-94228",
+        title: "Spyro the Dragon",
+        settings: NONE,
+    },
+    Entry {
+        serial: "SCUS-94426",
+        title: "Crash Team Racing",
+        settings: NONE,
+    },
+    Entry {
+        serial: "SCUS-94900",
+        title: "Crash Bandicoot",
+        settings: NONE,
+    },
+    Entry {
+        serial: "SLPS-00700",
+        title: "Final Fantasy VII (Disc 1)",
+        settings: NONE,
+    },
+    Entry {
+        serial: "SLPS-00701",
+        title: "Final Fantasy VII (Disc 2)",
+        settings: NONE,
+    },
+    Entry {
+        serial: "SLPS-00702",
+        title: "Final Fantasy VII (Disc 3)",
+        settings: NONE,
+    },
+    Entry {
+        serial: "SLUS-00594",
+        title: "Metal Gear Solid (Disc 1)",
+        settings: NONE,
+    },
+    Entry {
+        serial: "SLUS-00776",
+        title: "Metal Gear Solid (Disc 2)",
+        settings: NONE,
+    },
+];
+
+// What's known about the game on a disc
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GameInfo {
+    // Normalized serial, e.g. "SLUS-01234"
+    pub serial: String,
+    // None for games missing from the database
+    pub title: Option<&'static str>,
+    pub region: Option<Region>,
+    pub settings: GameSettings,
+    // From SYSTEM.CNF, when the info comes from the disc
+    pub version: Option<String>,
+    pub stack: Option<u32>,
+}
+
+impl GameInfo {
+    // Look up the game with the executable name or serial `serial`, e.g.
+    // "SLUS_012.34"
+    pub fn from_serial(serial: &str) -> Self {
+        let serial = normalize_serial(serial);
+        let entry = GAMES
+            .binary_search_by(|entry| entry.serial.cmp(&serial))
+            .ok()
+            .map(|index| &GAMES[index]);
+        Self {
+            region: Region::from_serial(&serial),
+            title: entry.map(|entry| entry.title),
+            settings: entry.map_or(GameSettings::default(), |entry| entry.settings),
+            serial,
+            version: None,
+            stack: None,
+        }
+    }
+
+    // Look up the game a disc boots, with the boot settings of its
+    // SYSTEM.CNF
+    pub fn from_system_cnf(cnf: &SystemCnf) -> Self {
+        Self {
+            version: cnf.version.clone(),
+            stack: cnf.stack,
+            ..Self::from_serial(&cnf.serial)
+        }
+    }
+}
+
+// Settings from the user for games the database gets wrong or lacks, on
+// top of the database's. Override files have a section per serial with the
+// settings to change, the others keep the database's values:
+//   # Comment
+//   [SLUS-01234]
+//   full_boot = true
+//   no_run_ahead = true
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GameOverrides {
+    // Settings to change by normalized serial
+    games: HashMap<String, Vec<(String, bool)>>,
+}
+
+impl GameOverrides {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut overrides = Self::new();
+        let mut serial = None;
+        for (number, line) in text.lines().enumerate() {
+            let invalid =
+                |what: &str| Error::Config(format!("game settings line {}: {}", number + 1, what));
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                serial = Some(normalize_serial(name.trim()));
+                continue;
+            }
+            let serial = serial
+                .as_ref()
+                .ok_or_else(|| invalid("setting before a [serial] line"))?;
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid("expected name = value"))?;
+            let name = name.trim();
+            let value = match value.trim() {
+                "true" | "1" => true,
+                "false" | "0" => false,
+                _ => return Err(invalid("expected true or false")),
+            };
+            if !GameSettings::default().set(name, value) {
+                return Err(invalid(&format!("unknown setting \"{}\"", name)));
+            }
+            overrides.set(serial, name, value);
+        }
+        Ok(overrides)
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::parse(&fs::read_to_string(path).map_err(Error::Io)?)
+    }
+
+    // Change setting `name` for the game `serial`. Unknown names are
+    // ignored when applied.
+    pub fn set(&mut self, serial: &str, name: &str, value: bool) {
+        let settings = self.games.entry(normalize_serial(serial)).or_default();
+        settings.retain(|(old, _)| old != name);
+        settings.push((name.to_string(), value));
+    }
+
+    // Settings of the game `serial`, with the overrides applied to `settings`
+    pub fn apply(&self, serial: &str, mut settings: GameSettings) -> GameSettings {
+        for (name, value) in self.games.get(serial).into_iter().flatten() {
+            settings.set(name, *value);
+        }
+        settings
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.games.is_empty()
+    }
+}
+
+// Turn an executable name like "slus_012.34" into the serial "SLUS-01234"
+pub fn normalize_serial(name: &str) -> String {
+    let name = name.to_ascii_uppercase();
+    let (prefix, number) = match name.find(['_', '-']) {
+        Some(split) => (&name[..split], &name[split + 1..]),
+        None => (name.get(..4).unwrap_or(&name), name.get(4..).unwrap_or("")),
+    };
+    let number: String = number
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect();
+    format!("{}-{}", prefix, number)
+}
@@ -0,0 +1,401 @@
+use super::frame::VideoFrame;
+use super::irq::Interrupt;
+use super::scheduler::Event;
+use super::state::{Savestate, StateError, StateReader, StateWriter};
+use super::{cheats, hle, sio, timers, Psx};
+
+// GPU cycles per scanline and scanlines per frame
+const NTSC_LINE_CYCLES: u64 = 3413;
+const PAL_LINE_CYCLES: u64 = 3406;
+const NTSC_LINES: u64 = 263;
+const PAL_LINES: u64 = 314;
+
+// CPU clock in Hz, the same on consoles of all regions
+const CPU_CLOCK: f64 = 33_868_800.0;
+
+// GPU clock as a ratio of the CPU clock. NTSC consoles run the GPU at 11/7
+// of the CPU clock, PAL ones from their own crystal at 53.203425MHz.
+const NTSC_CLOCK_RATIO: (u64, u64) = (11, 7);
+const PAL_CLOCK_RATIO: (u64, u64) = (709379, 451584);
+
+// Video standard of the console, which decides its GPU clock and the
+// video mode its BIOS sets up
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum VideoStandard {
+    // Follow the region of the disc, or of the BIOS without one
+    #[default]
+    Auto,
+    Ntsc,
+    Pal,
+}
+
+// Displayed part of the frame, from the display ranges
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DisplayArea {
+    // GPU cycles from the start of a line
+    pub left: u64,
+    pub right: u64,
+    // Lines from the start of the frame
+    pub top: u64,
+    pub bottom: u64,
+}
+
+impl DisplayArea {
+    // Beam position (GPU cycle in the line, line) under a lightgun pointer
+    // position, from (0, 0) top left to (FFFFh, FFFFh) bottom right
+    pub fn beam_position(&self, pointer: (u16, u16)) -> (u64, u64) {
+        let x = self.left + (self.right - self.left) * pointer.0 as u64 / 0xffff;
+        let y = self.top + (self.bottom - self.top) * pointer.1 as u64 / 0xffff;
+        (x, y.min(self.bottom.saturating_sub(1)))
+    }
+}
+
+// The GPU's display control and video timing generator. Drawing isn't
+// emulated: GP0 commands are ignored and GPUSTAT always reports ready. The
+// timing drives the vblank IRQ and the timers' dotclock and hblank inputs.
+pub struct Gpu {
+    // GP1(08h) display mode
+    display_mode: u32,
+    // GP1(06h) horizontal display range, in GPU cycles from the start of a
+    // line. Outside of it is hblank.
+    h_range: (u16, u16),
+    // GP1(07h) vertical display range, in lines. Outside of it is vblank.
+    v_range: (u16, u16),
+    // GP1(03h) display disabled
+    display_disabled: bool,
+    // The console is a PAL one, with the slower GPU clock. Games can still
+    // pick either video mode.
+    pal_console: bool,
+}
+
+impl Gpu {
+    pub fn new() -> Self {
+        Self {
+            display_mode: 0,
+            h_range: (0x260, 0xc60),
+            v_range: (0x10, 0x100),
+            display_disabled: true,
+            pal_console: false,
+        }
+    }
+
+    pub fn pal(&self) -> bool {
+        self.display_mode & 0x08 != 0
+    }
+
+    // Switch between the GPU clocks of NTSC and PAL consoles. The video mode
+    // follows, as the console's BIOS sets it at boot.
+    fn set_pal_console(&mut self, pal: bool) {
+        self.pal_console = pal;
+        self.display_mode = (self.display_mode & !0x08) | if pal { 0x08 } else { 0 };
+    }
+
+    fn clock_ratio(&self) -> (u64, u64) {
+        if self.pal_console {
+            PAL_CLOCK_RATIO
+        } else {
+            NTSC_CLOCK_RATIO
+        }
+    }
+
+    // GPU clock at CPU timestamp `cycles`
+    fn to_gpu_cycles(&self, cycles: u64) -> u64 {
+        let (gpu, cpu) = self.clock_ratio();
+        cycles * gpu / cpu
+    }
+
+    // CPU cycles until the GPU clock reaches `gpu_cycles`
+    fn to_cpu_cycles(&self, gpu_cycles: u64) -> u64 {
+        let (gpu, cpu) = self.clock_ratio();
+        (gpu_cycles * cpu).div_ceil(gpu)
+    }
+
+    // Length of a frame in the current video mode, in CPU cycles
+    pub fn frame_cycles(&self) -> u64 {
+        self.to_cpu_cycles(self.line_cycles() * self.frame_lines())
+    }
+
+    // Frames per second in the current video mode, about 59.3 for NTSC and
+    // 49.7 for PAL on a console of the same standard
+    pub fn refresh_rate(&self) -> f64 {
+        let (gpu, cpu) = self.clock_ratio();
+        let frame = (self.line_cycles() * self.frame_lines()) as f64;
+        CPU_CLOCK * gpu as f64 / cpu as f64 / frame
+    }
+
+    fn line_cycles(&self) -> u64 {
+        if self.pal() {
+            PAL_LINE_CYCLES
+        } else {
+            NTSC_LINE_CYCLES
+        }
+    }
+
+    fn frame_lines(&self) -> u64 {
+        if self.pal() {
+            PAL_LINES
+        } else {
+            NTSC_LINES
+        }
+    }
+
+    // GPU cycles per dot for the horizontal resolution
+    fn dot_cycles(&self) -> u64 {
+        if self.display_mode & 0x40 != 0 {
+            // 368 pixels
+            return 7;
+        }
+        match self.display_mode & 3 {
+            0 => 10,
+            1 => 8,
+            2 => 5,
+            _ => 4,
+        }
+    }
+
+    // Dots since power on at CPU timestamp `now`
+    pub fn dots(&self, now: u64) -> u64 {
+        self.to_gpu_cycles(now) / self.dot_cycles()
+    }
+
+    // CPU cycles from `now` until `count` more dots have passed
+    pub fn cycles_until_dots(&self, now: u64, count: u64) -> u64 {
+        let target = (self.dots(now) + count) * self.dot_cycles();
+        self.to_cpu_cycles(target).max(now + 1) - now
+    }
+
+    // Hblanks started since power on at CPU timestamp `now`
+    pub fn hblanks(&self, now: u64) -> u64 {
+        let line = self.line_cycles();
+        let start = (self.h_range.1 as u64).min(line - 1);
+        (self.to_gpu_cycles(now) + line - start) / line
+    }
+
+    // CPU cycles from `now` until `count` more hblanks have started
+    pub fn cycles_until_hblanks(&self, now: u64, count: u64) -> u64 {
+        let line = self.line_cycles();
+        let start = (self.h_range.1 as u64).min(line - 1);
+        let target = (self.hblanks(now) + count - 1) * line + start;
+        self.to_cpu_cycles(target).max(now + 1) - now
+    }
+
+    pub fn in_hblank(&self, now: u64) -> bool {
+        let phase = self.to_gpu_cycles(now) % self.line_cycles();
+        phase < self.h_range.0 as u64 || phase >= self.h_range.1 as u64
+    }
+
+    // Scanline within the frame at CPU timestamp `now`
+    fn line(&self, now: u64) -> u64 {
+        self.to_gpu_cycles(now) / self.line_cycles() % self.frame_lines()
+    }
+
+    pub fn in_vblank(&self, now: u64) -> bool {
+        let line = self.line(now);
+        line < self.v_range.0 as u64 || line >= self.v_range.1 as u64
+    }
+
+    // CPU cycles from `now` until the GPU clock reaches the next `phase` of a
+    // period of `period` GPU cycles
+    fn cycles_until_phase(&self, now: u64, period: u64, phase: u64) -> u64 {
+        let gpu = self.to_gpu_cycles(now);
+        let start = gpu - gpu % period + phase;
+        let target = if start > gpu { start } else { start + period };
+        self.to_cpu_cycles(target).max(now + 1) - now
+    }
+
+    // CPU cycles from `now` until the next start or end of hblank
+    pub fn cycles_until_hblank_edge(&self, now: u64) -> u64 {
+        let line = self.line_cycles();
+        let start = self.cycles_until_phase(now, line, self.h_range.1 as u64 % line);
+        let end = self.cycles_until_phase(now, line, self.h_range.0 as u64 % line);
+        start.min(end)
+    }
+
+    // CPU cycles from `now` until the next start and end of vblank
+    fn cycles_until_vblank(&self, now: u64) -> (u64, u64) {
+        let line = self.line_cycles();
+        let frame = line * self.frame_lines();
+        let lines = self.frame_lines();
+        let start = self.cycles_until_phase(now, frame, self.v_range.1 as u64 % lines * line);
+        let end = self.cycles_until_phase(now, frame, self.v_range.0 as u64 % lines * line);
+        (start, end)
+    }
+
+    pub fn display_area(&self) -> DisplayArea {
+        let line = self.line_cycles();
+        let lines = self.frame_lines();
+        let (left, right) = (self.h_range.0 as u64 % line, self.h_range.1 as u64 % line);
+        let (top, bottom) = (self.v_range.0 as u64 % lines, self.v_range.1 as u64 % lines);
+        DisplayArea {
+            left,
+            right: right.max(left),
+            top,
+            bottom: bottom.max(top),
+        }
+    }
+
+    // CPU cycles from `now` until the beam reaches GPU cycle `x` of line
+    // `line`
+    pub fn cycles_until_beam(&self, now: u64, x: u64, line: u64) -> u64 {
+        let frame = self.line_cycles() * self.frame_lines();
+        self.cycles_until_phase(now, frame, line * self.line_cycles() + x)
+    }
+
+    pub fn video_frame(&self) -> VideoFrame {
+        let mode = self.display_mode;
+        let interlaced = mode & 0x20 != 0;
+        let area = self.display_area();
+        // Widths are rounded to a multiple of 4 pixels
+        let width = ((area.right - area.left) / self.dot_cycles() + 2) & !3;
+        let mut height = area.bottom - area.top;
+        if interlaced && mode & 0x04 != 0 {
+            height *= 2;
+        }
+        VideoFrame {
+            width: width as u32,
+            height: height as u32,
+            pal: self.pal(),
+            interlaced,
+            depth24: mode & 0x10 != 0,
+            enabled: !self.display_disabled,
+        }
+    }
+
+    // 1F801814h GPUSTAT
+    fn status(&self, now: u64) -> u32 {
+        let mode = self.display_mode;
+        let mut stat = 0;
+        // Horizontal resolution, video mode, color depth, interlace
+        stat |= (mode & 0x40) << 10;
+        stat |= (mode & 0x3f) << 17;
+        stat |= ((mode >> 7) & 1) << 14;
+        if self.display_disabled {
+            stat |= 1 << 23;
+        }
+        // Ready to receive commands, send VRAM and receive DMA blocks
+        stat |= 0x1c00_0000;
+        // Odd line being drawn in interlaced mode, 0 during vblank
+        if mode & 0x20 != 0 && !self.in_vblank(now) {
+            stat |= ((self.to_gpu_cycles(now) / self.line_cycles()) as u32 & 1) << 31;
+        }
+        stat
+    }
+}
+
+impl Savestate for Gpu {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u32(self.display_mode);
+        w.write_u16(self.h_range.0);
+        w.write_u16(self.h_range.1);
+        w.write_u16(self.v_range.0);
+        w.write_u16(self.v_range.1);
+        w.write_bool(self.display_disabled);
+        w.write_bool(self.pal_console);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.display_mode = r.read_u32()?;
+        self.h_range = (r.read_u16()?, r.read_u16()?);
+        self.v_range = (r.read_u16()?, r.read_u16()?);
+        self.display_disabled = r.read_bool()?;
+        self.pal_console = r.read_bool()?;
+        Ok(())
+    }
+}
+
+impl Default for Gpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Schedule the first vblank edges after power on
+pub fn init(psx: &mut Psx) {
+    schedule_vblank(psx);
+    timers::timing_changed(psx);
+}
+
+// Change the console's video standard, bringing everything timed by the GPU
+// up to date first
+pub fn set_pal_console(psx: &mut Psx, pal: bool) {
+    if psx.gpu.pal_console == pal {
+        return;
+    }
+    timers::update_all(psx);
+    psx.gpu.set_pal_console(pal);
+    schedule_vblank(psx);
+    timers::timing_changed(psx);
+}
+
+fn schedule_vblank(psx: &mut Psx) {
+    let (start, end) = psx.gpu.cycles_until_vblank(psx.scheduler.now());
+    psx.scheduler.schedule(Event::VblankStart, start);
+    psx.scheduler.schedule(Event::VblankEnd, end);
+}
+
+// Run a video timing scheduler event
+pub fn handle_event(psx: &mut Psx, event: Event) {
+    match event {
+        Event::VblankStart => {
+            psx.irq.request(Interrupt::Vblank);
+            psx.frames += 1;
+            timers::vblank_edge(psx, true);
+            hle::vblank(psx);
+            cheats::vblank(psx);
+            let (start, _) = psx.gpu.cycles_until_vblank(psx.scheduler.now());
+            psx.scheduler.schedule(Event::VblankStart, start);
+        }
+        Event::VblankEnd => {
+            timers::vblank_edge(psx, false);
+            sio::frame(psx);
+            let (_, end) = psx.gpu.cycles_until_vblank(psx.scheduler.now());
+            psx.scheduler.schedule(Event::VblankEnd, end);
+        }
+        Event::Hblank => {
+            let now = psx.scheduler.now();
+            timers::hblank_edge(psx, psx.gpu.in_hblank(now));
+        }
+        _ => {}
+    }
+}
+
+// Read a GPU register at `offset` from 1F801810h
+pub fn load(psx: &mut Psx, offset: u32) -> u32 {
+    match offset & 4 {
+        // GPUREAD: nothing is drawn, nothing to read back
+        0 => 0,
+        _ => psx.gpu.status(psx.scheduler.now()),
+    }
+}
+
+// Write a GPU register at `offset` from 1F801810h
+pub fn store(psx: &mut Psx, offset: u32, val: u32) {
+    if offset & 4 == 0 {
+        // GP0 drawing commands aren't emulated
+        return;
+    }
+    // Timers counting dots or hblanks are brought up to date before the
+    // timing changes under them
+    let timing_changes = matches!(val >> 24, 0x00 | 0x06 | 0x07 | 0x08);
+    if timing_changes {
+        timers::update_all(psx);
+    }
+    let gpu = &mut psx.gpu;
+    match val >> 24 {
+        0x00 => {
+            *gpu = Gpu {
+                pal_console: gpu.pal_console,
+                ..Gpu::new()
+            };
+        }
+        0x03 => gpu.display_disabled = val & 1 != 0,
+        0x06 => gpu.h_range = ((val & 0xfff) as u16, ((val >> 12) & 0xfff) as u16),
+        0x07 => gpu.v_range = ((val & 0x3ff) as u16, ((val >> 10) & 0x3ff) as u16),
+        0x08 => gpu.display_mode = val & 0xff,
+        _ => {}
+    }
+    if timing_changes {
+        schedule_vblank(psx);
+        timers::timing_changed(psx);
+    }
+}
@@ -0,0 +1,419 @@
+use super::kernel::deliver;
+use super::{hle, read_bytes, read_string, write_bytes, Psx, Return};
+use crate::psx::memcard::fs::{self, BLOCK_SIZE};
+use crate::psx::memcard::{SECTORS, SECTOR_SIZE};
+use crate::psx::sio::PORTS;
+use crate::psx::state::{Savestate, StateError, StateReader, StateWriter};
+
+// Kernel file descriptors: 0 and 1 are the TTY, the rest memory card files
+const FILES: usize = 16;
+const FIRST_FILE: usize = 2;
+
+// Open mode flags: create the file, with its size in blocks in the top half,
+// and deliver an event when reads and writes complete
+const CREATE: u32 = 0x200;
+const ASYNC: u32 = 0x8000;
+
+// Memory card events: the hardware's, and the BIOS's own
+const HW_CARD: u32 = 0xf4000001;
+const SW_CARD: u32 = 0xf0000011;
+
+// Event specs of memory card accesses
+const DONE: u32 = 0x0004;
+const TIMEOUT: u32 = 0x0100;
+
+// Size of a directory entry returned by firstfile/nextfile
+const DIRENTRY_SIZE: usize = 40;
+
+#[derive(Clone, Copy)]
+struct File {
+    port: usize,
+    first_block: usize,
+    size: u32,
+    pos: u32,
+    mode: u32,
+}
+
+// State of a firstfile/nextfile search
+struct Search {
+    port: usize,
+    pattern: Vec<u8>,
+    // First block not looked at yet
+    next_block: usize,
+}
+
+// Memory card files open through the kernel
+pub struct Card {
+    files: [Option<File>; FILES],
+    search: Option<Search>,
+}
+
+impl Card {
+    pub fn new() -> Self {
+        Self {
+            files: [None; FILES],
+            search: None,
+        }
+    }
+}
+
+fn read_port(r: &mut StateReader) -> Result<usize, StateError> {
+    match r.read_u8()? as usize {
+        port if port < PORTS => Ok(port),
+        _ => Err(StateError::Invalid("HLE memory card port")),
+    }
+}
+
+impl Savestate for Card {
+    fn save_state(&self, w: &mut StateWriter) {
+        for file in self.files.iter() {
+            w.write_bool(file.is_some());
+            if let Some(file) = file {
+                w.write_u8(file.port as u8);
+                w.write_u32(file.first_block as u32);
+                w.write_u32(file.size);
+                w.write_u32(file.pos);
+                w.write_u32(file.mode);
+            }
+        }
+        w.write_bool(self.search.is_some());
+        if let Some(search) = self.search.as_ref() {
+            w.write_u8(search.port as u8);
+            w.write_u32(search.pattern.len() as u32);
+            w.write_bytes(&search.pattern);
+            w.write_u32(search.next_block as u32);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        for file in self.files.iter_mut() {
+            *file = match r.read_bool()? {
+                true => Some(File {
+                    port: read_port(r)?,
+                    first_block: r.read_u32()? as usize,
+                    size: r.read_u32()?,
+                    pos: r.read_u32()?,
+                    mode: r.read_u32()?,
+                }),
+                false => None,
+            };
+        }
+        self.search = match r.read_bool()? {
+            true => {
+                let port = read_port(r)?;
+                let len = r.read_u32()? as usize;
+                let pattern = r.read_bytes(len)?.to_vec();
+                Some(Search {
+                    port,
+                    pattern,
+                    next_block: r.read_u32()? as usize,
+                })
+            }
+            false => None,
+        };
+        Ok(())
+    }
+}
+
+impl Default for Card {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Port and filename of a "bu00:NAME" path. The second digit picks the slot
+// of a multitap, which the BIOS functions here don't reach.
+fn parse_path(path: &[u8]) -> Option<(usize, Vec<u8>)> {
+    if path.len() < 5 || !path[..2].eq_ignore_ascii_case(b"bu") || path[4] != b':' {
+        return None;
+    }
+    let port = match path[2] {
+        b'0' => 0,
+        b'1' => 1,
+        _ => return None,
+    };
+    Some((port, path[5..].to_vec()))
+}
+
+// First block and size of the save named `name`
+fn find(card: &[u8], name: &[u8]) -> Option<(usize, u32)> {
+    fs::list(card)
+        .ok()?
+        .into_iter()
+        .find(|save| !save.deleted && save.filename.as_bytes() == name)
+        .map(|save| (save.first_block, save.size))
+}
+
+fn file(psx: &mut Psx, fd: u32) -> Option<File> {
+    let index = (fd as usize).checked_sub(FIRST_FILE)?;
+    *hle(psx).card.files.get(index)?
+}
+
+fn deliver_card(psx: &mut Psx, spec: u32) {
+    deliver(psx, HW_CARD, spec);
+    deliver(psx, SW_CARD, spec);
+}
+
+// Returns the file descriptor, or -1 when the file can't be opened
+pub fn open(psx: &mut Psx, path: u32, mode: u32) -> Return {
+    let path = read_string(psx, path);
+    let (port, name) = match parse_path(&path) {
+        Some(path) => path,
+        None => return Return::Value(u32::MAX),
+    };
+    let card = match psx.sio.memory_card_data(port) {
+        Some(card) => card,
+        None => return Return::Value(u32::MAX),
+    };
+    let found = match (find(card, &name), mode & CREATE != 0) {
+        (Some(_), true) | (None, false) => None,
+        (Some(found), false) => Some(found),
+        (None, true) => {
+            let blocks = ((mode >> 16) as usize).max(1);
+            let name = String::from_utf8_lossy(&name);
+            fs::create(card, &name, blocks)
+                .ok()
+                .map(|block| (block, (blocks * BLOCK_SIZE) as u32))
+        }
+    };
+    let (first_block, size) = match found {
+        Some(found) => found,
+        None => return Return::Value(u32::MAX),
+    };
+    let files = &mut hle(psx).card.files;
+    match files.iter().position(Option::is_none) {
+        Some(i) => {
+            files[i] = Some(File {
+                port,
+                first_block,
+                size,
+                pos: 0,
+                mode,
+            });
+            Return::Value((FIRST_FILE + i) as u32)
+        }
+        None => Return::Value(u32::MAX),
+    }
+}
+
+// Seek from the start (0) or the current position (1)
+pub fn lseek(psx: &mut Psx, fd: u32, offset: u32, whence: u32) -> Return {
+    let mut file = match file(psx, fd) {
+        Some(file) => file,
+        None => return Return::Value(u32::MAX),
+    };
+    file.pos = match whence {
+        0 => offset,
+        1 => file.pos.wrapping_add(offset),
+        _ => return Return::Value(u32::MAX),
+    };
+    hle(psx).card.files[fd as usize - FIRST_FILE] = Some(file);
+    Return::Value(file.pos)
+}
+
+// Offsets in the card image of `len` bytes of a save from `pos`
+fn offsets(card: &[u8], file: &File, len: u32) -> Option<Vec<usize>> {
+    let blocks = fs::blocks(card, file.first_block).ok()?;
+    let offsets = (file.pos..file.pos + len)
+        .map(|pos| pos as usize)
+        .map(|pos| {
+            blocks
+                .get(pos / BLOCK_SIZE)
+                .map(|b| b * BLOCK_SIZE + pos % BLOCK_SIZE)
+        })
+        .collect();
+    offsets
+}
+
+pub fn read(psx: &mut Psx, fd: u32, dst: u32, len: u32) -> Return {
+    let mut file = match file(psx, fd) {
+        Some(file) => file,
+        None => return Return::Value(u32::MAX),
+    };
+    let len = len.min(file.size.saturating_sub(file.pos));
+    let data: Vec<u8> = match psx.sio.memory_card_data(file.port) {
+        Some(card) => match offsets(card, &file, len) {
+            Some(offsets) => offsets.iter().map(|&offset| card[offset]).collect(),
+            None => return Return::Value(u32::MAX),
+        },
+        None => return Return::Value(u32::MAX),
+    };
+    write_bytes(psx, dst, &data);
+    file.pos += len;
+    finish(psx, fd, file, len)
+}
+
+pub fn write(psx: &mut Psx, fd: u32, src: u32, len: u32) -> Return {
+    let mut file = match file(psx, fd) {
+        Some(file) => file,
+        None => return Return::Value(u32::MAX),
+    };
+    let len = len.min(file.size.saturating_sub(file.pos));
+    let data = read_bytes(psx, src, len as usize);
+    match psx.sio.memory_card_data(file.port) {
+        Some(card) => match offsets(card, &file, len) {
+            Some(offsets) => {
+                for (&offset, &byte) in offsets.iter().zip(data.iter()) {
+                    card[offset] = byte;
+                }
+            }
+            None => return Return::Value(u32::MAX),
+        },
+        None => return Return::Value(u32::MAX),
+    }
+    file.pos += len;
+    finish(psx, fd, file, len)
+}
+
+// Store the file's new position and return the bytes transferred, telling
+// asynchronous callers they're done
+fn finish(psx: &mut Psx, fd: u32, file: File, len: u32) -> Return {
+    hle(psx).card.files[fd as usize - FIRST_FILE] = Some(file);
+    if file.mode & ASYNC != 0 {
+        deliver_card(psx, DONE);
+    }
+    Return::Value(len)
+}
+
+pub fn close(psx: &mut Psx, fd: u32) -> Return {
+    if file(psx, fd).is_none() {
+        return Return::Value(u32::MAX);
+    }
+    hle(psx).card.files[fd as usize - FIRST_FILE] = None;
+    Return::Value(fd)
+}
+
+// Start listing the saves matching `pattern`, e.g. "bu00:BASLUS*". Returns
+// `direntry` filled with the first one, or 0 when none matches.
+pub fn first_file(psx: &mut Psx, pattern: u32, direntry: u32) -> Return {
+    let pattern = read_string(psx, pattern);
+    hle(psx).card.search = parse_path(&pattern).map(|(port, pattern)| Search {
+        port,
+        pattern,
+        next_block: 1,
+    });
+    next_file(psx, direntry)
+}
+
+pub fn next_file(psx: &mut Psx, direntry: u32) -> Return {
+    let (port, pattern, next_block) = match &hle(psx).card.search {
+        Some(search) => (search.port, search.pattern.clone(), search.next_block),
+        None => return Return::Value(0),
+    };
+    let saves = match psx.sio.memory_card_data(port).map(|card| fs::list(card)) {
+        Some(Ok(saves)) => saves,
+        _ => return Return::Value(0),
+    };
+    let save = saves.into_iter().find(|save| {
+        !save.deleted
+            && save.first_block >= next_block
+            && matches_pattern(&pattern, save.filename.as_bytes())
+    });
+    let save = match save {
+        Some(save) => save,
+        None => {
+            hle(psx).card.search = None;
+            return Return::Value(0);
+        }
+    };
+    if let Some(search) = hle(psx).card.search.as_mut() {
+        search.next_block = save.first_block + 1;
+    }
+    // name[20], attr, size, next, head block and 4 bytes for the system
+    let mut entry = [0u8; DIRENTRY_SIZE];
+    let len = save.filename.len().min(19);
+    entry[..len].copy_from_slice(&save.filename.as_bytes()[..len]);
+    entry[20..24].copy_from_slice(&0x50u32.to_le_bytes());
+    entry[24..28].copy_from_slice(&save.size.to_le_bytes());
+    entry[32..36].copy_from_slice(&(save.first_block as u32).to_le_bytes());
+    write_bytes(psx, direntry, &entry);
+    Return::Value(direntry)
+}
+
+// Match a filename against a pattern where '?' matches any character and
+// '*' the rest of the name
+fn matches_pattern(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (Some(b'*'), _) => true,
+        (Some(b'?'), Some(_)) => matches_pattern(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => matches_pattern(&pattern[1..], &name[1..]),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+// Returns 1 once renamed, 0 on failure
+pub fn rename(psx: &mut Psx, old: u32, new: u32) -> Return {
+    let (old, new) = (read_string(psx, old), read_string(psx, new));
+    let renamed = match (parse_path(&old), parse_path(&new)) {
+        (Some((port, old)), Some((new_port, new))) if port == new_port => psx
+            .sio
+            .memory_card_data(port)
+            .and_then(|card| {
+                let (block, _) = find(card, &old)?;
+                fs::rename(card, block, &String::from_utf8_lossy(&new)).ok()
+            })
+            .is_some(),
+        _ => false,
+    };
+    Return::Value(renamed as u32)
+}
+
+// Returns 1 once deleted, 0 on failure
+pub fn erase(psx: &mut Psx, path: u32) -> Return {
+    let path = read_string(psx, path);
+    let erased = parse_path(&path)
+        .and_then(|(port, name)| {
+            let card = psx.sio.memory_card_data(port)?;
+            let (block, _) = find(card, &name)?;
+            fs::delete(card, block).ok()
+        })
+        .is_some();
+    Return::Value(erased as u32)
+}
+
+// Port of the low level card functions, given as 00h or 10h
+fn card_port(port: u32) -> usize {
+    ((port >> 4) & 1) as usize
+}
+
+// Check for a card, delivering the result as an event
+pub fn card_info(psx: &mut Psx, port: u32) -> Return {
+    let present = psx.sio.memory_card_data(card_port(port)).is_some();
+    deliver_card(psx, if present { DONE } else { TIMEOUT });
+    Return::Value(1)
+}
+
+// Read a 128 byte sector to `dst`
+pub fn card_read(psx: &mut Psx, port: u32, sector: u32, dst: u32) -> Return {
+    let data = match psx.sio.memory_card_data(card_port(port)) {
+        Some(card) if (sector as usize) < SECTORS => {
+            let start = sector as usize * SECTOR_SIZE;
+            card[start..start + SECTOR_SIZE].to_vec()
+        }
+        _ => {
+            deliver_card(psx, TIMEOUT);
+            return Return::Value(0);
+        }
+    };
+    write_bytes(psx, dst, &data);
+    deliver_card(psx, DONE);
+    Return::Value(1)
+}
+
+// Write a 128 byte sector from `src`
+pub fn card_write(psx: &mut Psx, port: u32, sector: u32, src: u32) -> Return {
+    let data = read_bytes(psx, src, SECTOR_SIZE);
+    match psx.sio.memory_card_data(card_port(port)) {
+        Some(card) if (sector as usize) < SECTORS => {
+            let start = sector as usize * SECTOR_SIZE;
+            card[start..start + SECTOR_SIZE].copy_from_slice(&data);
+        }
+        _ => {
+            deliver_card(psx, TIMEOUT);
+            return Return::Value(0);
+        }
+    }
+    deliver_card(psx, DONE);
+    Return::Value(1)
+}
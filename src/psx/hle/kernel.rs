@@ -0,0 +1,311 @@
+use super::{hle, jump, Psx, Return, RA, V0};
+use crate::psx::state::{Savestate, StateError, StateReader, StateWriter};
+
+// Event and thread control blocks the BIOS sets up by default
+pub const EVENTS: usize = 16;
+pub const THREADS: usize = 4;
+
+// Handles are the block index with a type in the top byte
+const EVENT_HANDLE: u32 = 0xf1000000;
+const THREAD_HANDLE: u32 = 0xff000000;
+
+// Event statuses
+const FREE: u32 = 0;
+const DISABLED: u32 = 0x1000;
+const ENABLED: u32 = 0x2000;
+const READY: u32 = 0x4000;
+
+// Mode of events marked ready on delivery, rather than running a handler
+const MODE_READY: u32 = 0x2000;
+
+// Root counter 3, delivered at vertical blank
+pub const RCNT_VBLANK: u32 = 0xf2000003;
+// Spec of interrupt events
+pub const SPEC_INTERRUPT: u32 = 0x0002;
+
+#[derive(Clone, Copy)]
+struct Event {
+    class: u32,
+    spec: u32,
+    mode: u32,
+    status: u32,
+}
+
+impl Event {
+    const fn new() -> Self {
+        Self {
+            class: 0,
+            spec: 0,
+            mode: 0,
+            status: FREE,
+        }
+    }
+}
+
+// CPU state of a thread while another one runs
+#[derive(Clone, Copy)]
+struct Thread {
+    regs: [u32; 32],
+    pc: u32,
+    hi: u32,
+    lo: u32,
+}
+
+pub struct Kernel {
+    events: [Event; EVENTS],
+    threads: [Option<Thread>; THREADS],
+    current_thread: usize,
+}
+
+impl Kernel {
+    pub fn new() -> Self {
+        let mut threads = [None; THREADS];
+        // The game starts on thread 0, which is never closed
+        threads[0] = Some(Thread {
+            regs: [0; 32],
+            pc: 0,
+            hi: 0,
+            lo: 0,
+        });
+        Self {
+            events: [Event::new(); EVENTS],
+            threads,
+            current_thread: 0,
+        }
+    }
+
+    fn event(&mut self, handle: u32) -> Option<&mut Event> {
+        if handle & 0xffff0000 != EVENT_HANDLE {
+            return None;
+        }
+        self.events
+            .get_mut((handle & 0xffff) as usize)
+            .filter(|event| event.status != FREE)
+    }
+
+    // Returns the event's handle, or -1 when all are in use. Handlers aren't
+    // kept, see deliver.
+    pub fn open_event(&mut self, class: u32, spec: u32, mode: u32) -> u32 {
+        match self.events.iter().position(|event| event.status == FREE) {
+            Some(i) => {
+                self.events[i] = Event {
+                    class,
+                    spec,
+                    mode,
+                    status: DISABLED,
+                };
+                EVENT_HANDLE | i as u32
+            }
+            None => u32::MAX,
+        }
+    }
+
+    pub fn close_event(&mut self, handle: u32) -> u32 {
+        match self.event(handle) {
+            Some(event) => {
+                event.status = FREE;
+                1
+            }
+            None => 0,
+        }
+    }
+
+    // Busy-waits until the event is ready, like the BIOS. Returns straight
+    // away if it's disabled, as it would never become ready.
+    pub fn wait_event(&mut self, handle: u32) -> Return {
+        match self.event(handle) {
+            Some(event) if event.status == READY => {
+                event.status = ENABLED;
+                Return::Value(1)
+            }
+            Some(event) if event.status == ENABLED => Return::Again,
+            _ => Return::Value(0),
+        }
+    }
+
+    pub fn test_event(&mut self, handle: u32) -> u32 {
+        match self.event(handle) {
+            Some(event) if event.status == READY => {
+                event.status = ENABLED;
+                1
+            }
+            _ => 0,
+        }
+    }
+
+    pub fn enable_event(&mut self, handle: u32) -> u32 {
+        match self.event(handle) {
+            Some(event) => {
+                if event.status == DISABLED {
+                    event.status = ENABLED;
+                }
+                1
+            }
+            None => 0,
+        }
+    }
+
+    pub fn disable_event(&mut self, handle: u32) -> u32 {
+        match self.event(handle) {
+            Some(event) => {
+                event.status = DISABLED;
+                1
+            }
+            None => 0,
+        }
+    }
+
+    // Mark the enabled events of `class` and `spec` ready. Events with a
+    // handler are left alone: running it needs the CPU to call into the
+    // game, which the kernel can't do from here.
+    fn deliver(&mut self, class: u32, spec: u32) {
+        for event in self.events.iter_mut() {
+            let matches = event.class == class && event.spec == spec;
+            if matches && event.status == ENABLED && event.mode == MODE_READY {
+                event.status = READY;
+            }
+        }
+    }
+
+    // Take back a delivery that wasn't waited for yet
+    pub fn undeliver(&mut self, class: u32, spec: u32) {
+        for event in self.events.iter_mut() {
+            let matches = event.class == class && event.spec == spec;
+            if matches && event.status == READY && event.mode == MODE_READY {
+                event.status = ENABLED;
+            }
+        }
+    }
+
+    // Returns the thread's handle, or -1 when all are in use
+    pub fn open_thread(&mut self, pc: u32, sp: u32, gp: u32) -> u32 {
+        match self.threads.iter().position(Option::is_none) {
+            Some(i) => {
+                let mut regs = [0; 32];
+                regs[28] = gp;
+                regs[29] = sp;
+                regs[30] = sp;
+                self.threads[i] = Some(Thread {
+                    regs,
+                    pc,
+                    hi: 0,
+                    lo: 0,
+                });
+                THREAD_HANDLE | i as u32
+            }
+            None => u32::MAX,
+        }
+    }
+
+    pub fn close_thread(&mut self, handle: u32) -> u32 {
+        match thread_index(handle) {
+            Some(i) if i != 0 && i != self.current_thread => {
+                self.threads[i] = None;
+                1
+            }
+            _ => 0,
+        }
+    }
+}
+
+impl Savestate for Kernel {
+    fn save_state(&self, w: &mut StateWriter) {
+        for event in self.events.iter() {
+            w.write_u32(event.class);
+            w.write_u32(event.spec);
+            w.write_u32(event.mode);
+            w.write_u32(event.status);
+        }
+        for thread in self.threads.iter() {
+            w.write_bool(thread.is_some());
+            if let Some(thread) = thread {
+                for &reg in thread.regs.iter() {
+                    w.write_u32(reg);
+                }
+                w.write_u32(thread.pc);
+                w.write_u32(thread.hi);
+                w.write_u32(thread.lo);
+            }
+        }
+        w.write_u8(self.current_thread as u8);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        for event in self.events.iter_mut() {
+            event.class = r.read_u32()?;
+            event.spec = r.read_u32()?;
+            event.mode = r.read_u32()?;
+            event.status = r.read_u32()?;
+        }
+        for thread in self.threads.iter_mut() {
+            *thread = match r.read_bool()? {
+                true => {
+                    let mut regs = [0; 32];
+                    for reg in regs.iter_mut() {
+                        *reg = r.read_u32()?;
+                    }
+                    Some(Thread {
+                        regs,
+                        pc: r.read_u32()?,
+                        hi: r.read_u32()?,
+                        lo: r.read_u32()?,
+                    })
+                }
+                false => None,
+            };
+        }
+        self.current_thread = r.read_u8()? as usize;
+        if self
+            .threads
+            .get(self.current_thread)
+            .is_none_or(|t| t.is_none())
+        {
+            return Err(StateError::Invalid("HLE current thread"));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Kernel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn thread_index(handle: u32) -> Option<usize> {
+    match handle & 0xffff0000 {
+        THREAD_HANDLE => Some((handle & 0xffff) as usize).filter(|&i| i < THREADS),
+        _ => None,
+    }
+}
+
+// Deliver an event to whoever waits for it
+pub fn deliver(psx: &mut Psx, class: u32, spec: u32) {
+    hle(psx).kernel.deliver(class, spec);
+}
+
+// Save the CPU state in the running thread's block and resume `handle`. The
+// old thread returns 1 from the call once it's resumed.
+pub fn change_thread(psx: &mut Psx, handle: u32) -> Return {
+    let kernel = &mut psx.hle.as_mut().expect("HLE BIOS not active").kernel;
+    let next = match thread_index(handle).and_then(|i| kernel.threads[i].map(|t| (i, t))) {
+        Some(next) => next,
+        None => return Return::Value(0),
+    };
+    let cpu = &mut psx.cpu;
+    let mut regs = cpu.regs;
+    regs[V0] = 1;
+    kernel.threads[kernel.current_thread] = Some(Thread {
+        regs,
+        pc: regs[RA],
+        hi: cpu.hi,
+        lo: cpu.lo,
+    });
+    let (index, thread) = next;
+    kernel.current_thread = index;
+    cpu.regs = thread.regs;
+    cpu.hi = thread.hi;
+    cpu.lo = thread.lo;
+    jump(psx, thread.pc);
+    Return::Jumped
+}
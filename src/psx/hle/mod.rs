@@ -0,0 +1,583 @@
+mod card;
+mod kernel;
+mod pad;
+mod printf;
+
+use super::exe::{self, Exe};
+use super::state::{Savestate, StateError, StateReader, StateWriter};
+use super::{disc, Psx};
+
+use std::io;
+
+// Entry points of the A, B and C kernel function tables. Games jump to them
+// with the function number in t1.
+const TABLE_A: u32 = 0xa0;
+const TABLE_B: u32 = 0xb0;
+const TABLE_C: u32 = 0xc0;
+
+// Where the BIOS keeps the B and C tables, as reported to games that patch
+// them
+const B_TABLE: u32 = 0x874;
+const C_TABLE: u32 = 0x674;
+
+// Table of tables at 100h, where the BIOS lists its kernel structures as
+// address and size pairs. Libraries find the current thread through it.
+const TABLE_OF_TABLES: u32 = 0x100;
+const TOT_EXCB: u32 = 0x00;
+const TOT_PCB: u32 = 0x08;
+const TOT_TCB: u32 = 0x10;
+const TOT_EVCB: u32 = 0x20;
+
+// Kernel memory the structures are put in, where the BIOS allocates them,
+// as a KSEG0 pointer
+const KERNEL_MEMORY: u32 = 0x8000a000;
+
+// Structure sizes: 4 exception control blocks of 8 bytes, the process
+// control block pointing at the current thread, 0C0h bytes per thread and
+// 1Ch per event
+const EXCB_SIZE: u32 = 4 * 8;
+const PCB_SIZE: u32 = 4;
+const TCB_SIZE: u32 = 0xc0;
+const EVCB_SIZE: u32 = 0x1c;
+
+// Thread status of a thread in use
+const TCB_USED: u32 = 0x4000;
+
+// Registers used by the calling convention
+const V0: usize = 2;
+const A0: usize = 4;
+const T1: usize = 9;
+const S0: usize = 16;
+const GP: usize = 28;
+const SP: usize = 29;
+const FP: usize = 30;
+const RA: usize = 31;
+
+// How a kernel function finishes
+enum Return {
+    // Back to the caller with v0 set
+    Value(u32),
+    // Run the function again on the next step, for functions the BIOS
+    // busy-waits in
+    Again,
+    // The function set the pc itself
+    Jumped,
+}
+
+// Kernel state of the high-level emulated BIOS, which implements the kernel
+// calls natively so games boot without a BIOS dump. The CPU runs the game
+// and hands over whenever it reaches one of the tables.
+pub struct Hle {
+    rand_seed: u32,
+    heap: Heap,
+    kernel: kernel::Kernel,
+    card: card::Card,
+    pads: pad::Pads,
+}
+
+impl Hle {
+    pub fn new() -> Self {
+        Self {
+            rand_seed: 0,
+            heap: Heap::new(),
+            kernel: kernel::Kernel::new(),
+            card: card::Card::new(),
+            pads: pad::Pads::new(),
+        }
+    }
+}
+
+impl Savestate for Hle {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u32(self.rand_seed);
+        w.write_u32(self.heap.blocks.len() as u32);
+        for &(addr, size, used) in self.heap.blocks.iter() {
+            w.write_u32(addr);
+            w.write_u32(size);
+            w.write_bool(used);
+        }
+        self.kernel.save_state(w);
+        self.card.save_state(w);
+        self.pads.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.rand_seed = r.read_u32()?;
+        let blocks = r.read_u32()? as usize;
+        if blocks > r.remaining() / 9 {
+            return Err(StateError::UnexpectedEof);
+        }
+        self.heap.blocks.clear();
+        for _ in 0..blocks {
+            let block = (r.read_u32()?, r.read_u32()?, r.read_bool()?);
+            self.heap.blocks.push(block);
+        }
+        self.kernel.load_state(r)?;
+        self.card.load_state(r)?;
+        self.pads.load_state(r)?;
+        Ok(())
+    }
+}
+
+impl Default for Hle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn hle(psx: &mut Psx) -> &mut Hle {
+    psx.hle.as_mut().expect("HLE BIOS not active")
+}
+
+// Set up the kernel and start the disc's executable, as named by its
+// SYSTEM.CNF, or PSX.EXE without one. A cartridge with a bootable ROM gets
+// control instead, as the BIOS calls its boot hook first.
+pub fn boot(psx: &mut Psx) -> io::Result<()> {
+    init(psx);
+
+    if let Some(hook) = psx.cartridge.as_ref().and_then(|c| c.boot_hook()) {
+        jump(psx, hook);
+        psx.cpu.regs[SP] = exe::DEFAULT_SP;
+        psx.cpu.regs[FP] = exe::DEFAULT_SP;
+        return Ok(());
+    }
+
+    let disc = psx
+        .cdrom
+        .disc_mut()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no disc inserted"))?;
+    let (path, stack) = match disc::read_system_cnf(disc) {
+        Some(cnf) => (cnf.boot, cnf.stack),
+        None => ("PSX.EXE".to_string(), None),
+    };
+    let exe = Exe::parse(&disc::read_file(disc, &path)?)?;
+    psx.sideload_exe(&exe);
+    if let (None, Some(stack)) = (exe.sp, stack) {
+        psx.cpu.regs[SP] = stack;
+        psx.cpu.regs[FP] = stack;
+    }
+    Ok(())
+}
+
+// Quick test mode: set up the kernel and start a bare-metal executable, with
+// no disc or BIOS. Its gp and stack are set from its header, sp defaulting
+// to the top of RAM.
+pub fn boot_exe(psx: &mut Psx, exe: &Exe) {
+    init(psx);
+    psx.sideload_exe(exe);
+}
+
+// Set up the kernel state programs expect to find in RAM
+fn init(psx: &mut Psx) {
+    psx.hle = Some(Hle::new());
+    // Calls the CPU core doesn't hand over return straight away
+    for &table in [TABLE_A, TABLE_B, TABLE_C].iter() {
+        psx.store(table, 0x03e00008u32);
+        psx.store(table + 4, 0u32);
+    }
+
+    // The kernel's own structures, with thread 0 running
+    let excb = KERNEL_MEMORY;
+    let pcb = excb + EXCB_SIZE;
+    let tcb = pcb + PCB_SIZE;
+    let tcb_size = kernel::THREADS as u32 * TCB_SIZE;
+    let evcb = tcb + tcb_size;
+    let evcb_size = kernel::EVENTS as u32 * EVCB_SIZE;
+    for addr in (excb..evcb + evcb_size).step_by(4) {
+        psx.store(addr, 0u32);
+    }
+    let tables = [
+        (TOT_EXCB, excb, EXCB_SIZE),
+        (TOT_PCB, pcb, PCB_SIZE),
+        (TOT_TCB, tcb, tcb_size),
+        (TOT_EVCB, evcb, evcb_size),
+    ];
+    for &(entry, addr, size) in tables.iter() {
+        psx.store(TABLE_OF_TABLES + entry, addr);
+        psx.store(TABLE_OF_TABLES + entry + 4, size);
+    }
+    psx.store(pcb, tcb);
+    psx.store(tcb, TCB_USED);
+}
+
+// Is `pc` one of the kernel call entry points?
+pub fn is_call(pc: u32) -> bool {
+    matches!(pc & 0x1fffffff, TABLE_A | TABLE_B | TABLE_C)
+}
+
+// Run the kernel function the CPU is about to enter
+pub fn call(psx: &mut Psx) {
+    let function = psx.cpu.regs[T1] & 0xff;
+    let ret = match psx.cpu.pc & 0x1fffffff {
+        TABLE_A => call_a(psx, function),
+        TABLE_B => call_b(psx, function),
+        _ => Return::Value(0),
+    };
+    match ret {
+        Return::Value(val) => {
+            psx.cpu.regs[V0] = val;
+            jump(psx, psx.cpu.regs[RA]);
+        }
+        Return::Again | Return::Jumped => {}
+    }
+}
+
+// Deliver kernel events at the start of vertical blank
+pub fn vblank(psx: &mut Psx) {
+    if psx.hle.is_some() {
+        pad::vblank(psx);
+        kernel::deliver(psx, kernel::RCNT_VBLANK, kernel::SPEC_INTERRUPT);
+    }
+}
+
+fn jump(psx: &mut Psx, addr: u32) {
+    let cpu = &mut psx.cpu;
+    cpu.current_pc = addr;
+    cpu.pc = addr;
+    cpu.next_pc = addr.wrapping_add(4);
+}
+
+fn arg(psx: &Psx, n: usize) -> u32 {
+    psx.cpu.regs[A0 + n]
+}
+
+fn read_bytes(psx: &mut Psx, addr: u32, len: usize) -> Vec<u8> {
+    (0..len as u32)
+        .map(|i| psx.load::<u8>(addr.wrapping_add(i)))
+        .collect()
+}
+
+fn write_bytes(psx: &mut Psx, addr: u32, data: &[u8]) {
+    for (i, &byte) in data.iter().enumerate() {
+        psx.store(addr.wrapping_add(i as u32), byte);
+    }
+}
+
+// NUL terminated string at `addr`, without the terminator
+fn read_string(psx: &mut Psx, addr: u32) -> Vec<u8> {
+    let mut string = Vec::new();
+    // Give up on runaway strings rather than read all of memory
+    while string.len() < 0x10000 {
+        let c = psx.load::<u8>(addr.wrapping_add(string.len() as u32));
+        if c == 0 {
+            break;
+        }
+        string.push(c);
+    }
+    string
+}
+
+// A0h functions: the C library, heap and CD-ROM setup
+fn call_a(psx: &mut Psx, function: u32) -> Return {
+    let (a0, a1, a2) = (arg(psx, 0), arg(psx, 1), arg(psx, 2));
+    let val = match function {
+        0x00 => return card::open(psx, a0, a1),
+        0x01 => return card::lseek(psx, a0, a1, a2),
+        0x02 => return card::read(psx, a0, a1, a2),
+        0x03 => return card::write(psx, a0, a1, a2),
+        0x04 => return card::close(psx, a0),
+        0x0e | 0x0f => (a0 as i32).wrapping_abs() as u32,
+        0x13 => setjmp(psx, a0),
+        0x14 => return longjmp(psx, a0, a1),
+        0x15 => strncat(psx, a0, a1, u32::MAX),
+        0x16 => strncat(psx, a0, a1, a2),
+        0x17 => strncmp(psx, a0, a1, u32::MAX),
+        0x18 => strncmp(psx, a0, a1, a2),
+        0x19 => strncpy(psx, a0, a1, u32::MAX),
+        0x1a => strncpy(psx, a0, a1, a2),
+        0x1b => match a0 {
+            0 => 0,
+            _ => read_string(psx, a0).len() as u32,
+        },
+        0x1c | 0x1e => strchr(psx, a0, a1 as u8, false),
+        0x1d | 0x1f => strchr(psx, a0, a1 as u8, true),
+        0x25 => (a0 as u8).to_ascii_uppercase() as u32,
+        0x26 => (a0 as u8).to_ascii_lowercase() as u32,
+        0x28 => {
+            write_bytes(psx, a0, &vec![0; a1 as usize]);
+            a0
+        }
+        0x2a | 0x2c => {
+            let data = read_bytes(psx, a1, a2 as usize);
+            write_bytes(psx, a0, &data);
+            a0
+        }
+        0x2b => {
+            write_bytes(psx, a0, &vec![a1 as u8; a2 as usize]);
+            a0
+        }
+        0x2e => {
+            let data = read_bytes(psx, a0, a2 as usize);
+            match data.iter().position(|&c| c == a1 as u8) {
+                Some(i) => a0 + i as u32,
+                None => 0,
+            }
+        }
+        0x2f => {
+            let hle = hle(psx);
+            hle.rand_seed = hle.rand_seed.wrapping_mul(0x41c64e6d).wrapping_add(0x3039);
+            (hle.rand_seed >> 16) & 0x7fff
+        }
+        0x30 => {
+            hle(psx).rand_seed = a0;
+            0
+        }
+        0x33 => hle(psx).heap.alloc(a0).unwrap_or(0),
+        0x34 => {
+            hle(psx).heap.free(a0);
+            0
+        }
+        0x37 => match hle(psx).heap.alloc(a0.wrapping_mul(a1)) {
+            Some(addr) => {
+                write_bytes(psx, addr, &vec![0; (a0 * a1) as usize]);
+                addr
+            }
+            None => 0,
+        },
+        0x38 => realloc(psx, a0, a1),
+        0x39 => {
+            hle(psx).heap.init(a0, a1);
+            0
+        }
+        0x3c => {
+            psx.tty.putchar(a0 as u8);
+            a0
+        }
+        0x3e => puts(psx, a0),
+        0x3f => {
+            let text = printf::format(psx);
+            for &c in text.iter() {
+                psx.tty.putchar(c);
+            }
+            text.len() as u32
+        }
+        0xab | 0xac => return card::card_info(psx, a0),
+        // Cache flushes, CD-ROM and device setup have nothing to do
+        _ => 0,
+    };
+    Return::Value(val)
+}
+
+// B0h functions: the kernel proper, events, threads, pads and files
+fn call_b(psx: &mut Psx, function: u32) -> Return {
+    let (a0, a1, a2, a3) = (arg(psx, 0), arg(psx, 1), arg(psx, 2), arg(psx, 3));
+    let val = match function {
+        0x00 => hle(psx).heap.alloc(a0).unwrap_or(0),
+        0x01 => {
+            hle(psx).heap.free(a0);
+            0
+        }
+        0x07 => {
+            kernel::deliver(psx, a0, a1);
+            0
+        }
+        0x08 => hle(psx).kernel.open_event(a0, a1, a2),
+        0x09 => hle(psx).kernel.close_event(a0),
+        0x0a => return hle(psx).kernel.wait_event(a0),
+        0x0b => hle(psx).kernel.test_event(a0),
+        0x0c => hle(psx).kernel.enable_event(a0),
+        0x0d => hle(psx).kernel.disable_event(a0),
+        0x0e => hle(psx).kernel.open_thread(a0, a1, a2),
+        0x0f => hle(psx).kernel.close_thread(a0),
+        0x10 => return kernel::change_thread(psx, a0),
+        0x12 => return pad::init(psx, a0, a1, a2, a3),
+        0x13 => return pad::start(psx),
+        0x14 => return pad::stop(psx),
+        0x20 => {
+            hle(psx).kernel.undeliver(a0, a1);
+            0
+        }
+        0x32 => return card::open(psx, a0, a1),
+        0x33 => return card::lseek(psx, a0, a1, a2),
+        0x34 => return card::read(psx, a0, a1, a2),
+        0x35 => return card::write(psx, a0, a1, a2),
+        0x36 => return card::close(psx, a0),
+        0x3d => {
+            psx.tty.putchar(a0 as u8);
+            a0
+        }
+        0x3f => puts(psx, a0),
+        0x42 => return card::first_file(psx, a0, a1),
+        0x43 => return card::next_file(psx, a0),
+        0x44 => return card::rename(psx, a0, a1),
+        0x45 => return card::erase(psx, a0),
+        0x4a => 0,
+        0x4b | 0x4c => 1,
+        0x4e => return card::card_write(psx, a0, a1, a2),
+        0x4f => return card::card_read(psx, a0, a1, a2),
+        0x5c | 0x5d => 1,
+        0x56 => C_TABLE,
+        0x57 => B_TABLE,
+        _ => 0,
+    };
+    Return::Value(val)
+}
+
+// Print a string and a newline
+fn puts(psx: &mut Psx, s: u32) -> u32 {
+    let text = match s {
+        0 => b"<NULL>".to_vec(),
+        _ => read_string(psx, s),
+    };
+    for &c in text.iter().chain(b"\n") {
+        psx.tty.putchar(c);
+    }
+    0
+}
+
+// Save the registers a longjmp restores. The buffer holds ra, sp, fp, s0-s7
+// and gp.
+fn setjmp(psx: &mut Psx, buf: u32) -> u32 {
+    for (i, reg) in jmp_regs().enumerate() {
+        let val = psx.cpu.regs[reg];
+        psx.store(buf + i as u32 * 4, val);
+    }
+    0
+}
+
+fn longjmp(psx: &mut Psx, buf: u32, val: u32) -> Return {
+    for (i, reg) in jmp_regs().enumerate() {
+        psx.cpu.regs[reg] = psx.load::<u32>(buf + i as u32 * 4);
+    }
+    Return::Value(val)
+}
+
+fn jmp_regs() -> impl Iterator<Item = usize> {
+    [RA, SP, FP]
+        .iter()
+        .copied()
+        .chain(S0..S0 + 8)
+        .chain(std::iter::once(GP))
+}
+
+fn strncat(psx: &mut Psx, dst: u32, src: u32, max: u32) -> u32 {
+    if dst == 0 || src == 0 {
+        return 0;
+    }
+    let end = dst + read_string(psx, dst).len() as u32;
+    let mut src = read_string(psx, src);
+    src.truncate(max as usize);
+    src.push(0);
+    write_bytes(psx, end, &src);
+    dst
+}
+
+fn strncmp(psx: &mut Psx, a: u32, b: u32, max: u32) -> u32 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    for i in 0..max {
+        let (ca, cb) = (
+            psx.load::<u8>(a.wrapping_add(i)),
+            psx.load::<u8>(b.wrapping_add(i)),
+        );
+        if ca != cb || ca == 0 {
+            return (ca as i32 - cb as i32) as u32;
+        }
+    }
+    0
+}
+
+fn strncpy(psx: &mut Psx, dst: u32, src: u32, max: u32) -> u32 {
+    if dst == 0 || src == 0 {
+        return 0;
+    }
+    let mut src = read_string(psx, src);
+    if max == u32::MAX {
+        src.push(0);
+    } else {
+        src.resize(max as usize, 0);
+    }
+    write_bytes(psx, dst, &src);
+    dst
+}
+
+fn strchr(psx: &mut Psx, s: u32, c: u8, last: bool) -> u32 {
+    if s == 0 {
+        return 0;
+    }
+    let mut string = read_string(psx, s);
+    string.push(0);
+    let found = match last {
+        false => string.iter().position(|&b| b == c),
+        true => string.iter().rposition(|&b| b == c),
+    };
+    found.map_or(0, |i| s + i as u32)
+}
+
+fn realloc(psx: &mut Psx, addr: u32, size: u32) -> u32 {
+    if addr == 0 {
+        return hle(psx).heap.alloc(size).unwrap_or(0);
+    }
+    if size == 0 {
+        hle(psx).heap.free(addr);
+        return 0;
+    }
+    let old_size = hle(psx).heap.size(addr);
+    let new = match hle(psx).heap.alloc(size) {
+        Some(new) => new,
+        None => return 0,
+    };
+    let data = read_bytes(psx, addr, old_size.min(size) as usize);
+    write_bytes(psx, new, &data);
+    hle(psx).heap.free(addr);
+    new
+}
+
+// Heap set up by InitHeap, allocated first fit. Blocks are tracked here
+// rather than in headers in the heap itself.
+struct Heap {
+    // Start, size and use of each block, in address order
+    blocks: Vec<(u32, u32, bool)>,
+}
+
+impl Heap {
+    fn new() -> Self {
+        Self { blocks: Vec::new() }
+    }
+
+    fn init(&mut self, addr: u32, size: u32) {
+        self.blocks = vec![(addr, size & !3, false)];
+    }
+
+    fn alloc(&mut self, size: u32) -> Option<u32> {
+        let size = size.checked_add(3)? & !3;
+        let i = self
+            .blocks
+            .iter()
+            .position(|&(_, len, used)| !used && len >= size)?;
+        let (addr, len, _) = self.blocks[i];
+        self.blocks[i] = (addr, size, true);
+        if len > size {
+            self.blocks.insert(i + 1, (addr + size, len - size, false));
+        }
+        Some(addr)
+    }
+
+    fn free(&mut self, addr: u32) {
+        let i = match self
+            .blocks
+            .iter()
+            .position(|&(a, _, used)| used && a == addr)
+        {
+            Some(i) => i,
+            None => return,
+        };
+        self.blocks[i].2 = false;
+        // Merge with free neighbours
+        if i + 1 < self.blocks.len() && !self.blocks[i + 1].2 {
+            self.blocks[i].1 += self.blocks.remove(i + 1).1;
+        }
+        if i > 0 && !self.blocks[i - 1].2 {
+            let len = self.blocks.remove(i).1;
+            self.blocks[i - 1].1 += len;
+        }
+    }
+
+    fn size(&self, addr: u32) -> u32 {
+        self.blocks
+            .iter()
+            .find(|&&(a, _, used)| used && a == addr)
+            .map_or(0, |&(_, len, _)| len)
+    }
+}
@@ -0,0 +1,90 @@
+use super::{hle, write_bytes, Psx, Return};
+use crate::psx::state::{Savestate, StateError, StateReader, StateWriter};
+
+// Pad polling the BIOS does at every vertical blank, into buffers the game
+// registered with InitPad
+pub struct Pads {
+    // Address and size of the buffer of each port
+    buffers: [(u32, u32); 2],
+    started: bool,
+}
+
+impl Pads {
+    pub fn new() -> Self {
+        Self {
+            buffers: [(0, 0); 2],
+            started: false,
+        }
+    }
+}
+
+impl Savestate for Pads {
+    fn save_state(&self, w: &mut StateWriter) {
+        for &(addr, size) in self.buffers.iter() {
+            w.write_u32(addr);
+            w.write_u32(size);
+        }
+        w.write_bool(self.started);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        for buffer in self.buffers.iter_mut() {
+            *buffer = (r.read_u32()?, r.read_u32()?);
+        }
+        self.started = r.read_bool()?;
+        Ok(())
+    }
+}
+
+impl Default for Pads {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn init(psx: &mut Psx, buf1: u32, size1: u32, buf2: u32, size2: u32) -> Return {
+    let buffers = [(buf1, size1), (buf2, size2)];
+    hle(psx).pads.buffers = buffers;
+    // No controller until the first poll
+    for &(buf, size) in buffers.iter() {
+        if buf != 0 && size != 0 {
+            psx.store(buf, 0xffu8);
+        }
+    }
+    Return::Value(2)
+}
+
+pub fn start(psx: &mut Psx) -> Return {
+    hle(psx).pads.started = true;
+    Return::Value(1)
+}
+
+pub fn stop(psx: &mut Psx) -> Return {
+    hle(psx).pads.started = false;
+    Return::Value(1)
+}
+
+// Read both controllers into their buffers: a status byte, 00h with a
+// controller or FFh without, the ID byte and the data
+pub fn vblank(psx: &mut Psx) {
+    let pads = &hle(psx).pads;
+    if !pads.started {
+        return;
+    }
+    let buffers = pads.buffers;
+    for (port, &(buf, size)) in buffers.iter().enumerate() {
+        if buf == 0 || size == 0 {
+            continue;
+        }
+        let mut reply = match psx.sio.read_pad(port) {
+            Some((id, data)) => {
+                let mut reply = vec![0x00, id];
+                reply.extend_from_slice(&data);
+                reply
+            }
+            None => vec![0xff],
+        };
+        reply.truncate(size as usize);
+        write_bytes(psx, buf, &reply);
+    }
+}
@@ -0,0 +1,76 @@
+use super::{arg, read_string, Psx, SP};
+
+// Format printf's arguments like the BIOS does: flags '-' and '0', a width,
+// and the d, i, u, x, X, o, c, s, p and % conversions. The format string is
+// in a0, the first three arguments in a1-a3 and the rest on the stack.
+pub fn format(psx: &mut Psx) -> Vec<u8> {
+    let fmt = read_string(psx, arg(psx, 0));
+    let mut next_arg = 1;
+    let mut arg = |psx: &mut Psx| {
+        let val = match next_arg {
+            1..=3 => psx.cpu.regs[4 + next_arg],
+            n => {
+                let sp = psx.cpu.regs[SP];
+                psx.load::<u32>(sp + n as u32 * 4)
+            }
+        };
+        next_arg += 1;
+        val
+    };
+
+    let mut out = Vec::new();
+    let mut chars = fmt.iter().copied().peekable();
+    while let Some(c) = chars.next() {
+        if c != b'%' {
+            out.push(c);
+            continue;
+        }
+        let (mut left, mut zero) = (false, false);
+        while let Some(&flag) = chars.peek() {
+            match flag {
+                b'-' => left = true,
+                b'0' => zero = true,
+                _ => break,
+            }
+            chars.next();
+        }
+        let mut width = 0;
+        while let Some(digit) = chars.peek().filter(|c| c.is_ascii_digit()) {
+            width = width * 10 + (digit - b'0') as usize;
+            chars.next();
+        }
+        // Sizes don't matter, all arguments are words
+        while chars.peek().is_some_and(|&c| c == b'l' || c == b'h') {
+            chars.next();
+        }
+        let text = match chars.next() {
+            Some(b'd') | Some(b'i') => (arg(psx) as i32).to_string().into_bytes(),
+            Some(b'u') => arg(psx).to_string().into_bytes(),
+            Some(b'x') | Some(b'p') => format!("{:x}", arg(psx)).into_bytes(),
+            Some(b'X') => format!("{:X}", arg(psx)).into_bytes(),
+            Some(b'o') => format!("{:o}", arg(psx)).into_bytes(),
+            Some(b'c') => vec![arg(psx) as u8],
+            Some(b's') => match arg(psx) {
+                0 => b"(null)".to_vec(),
+                addr => read_string(psx, addr),
+            },
+            Some(b'%') => vec![b'%'],
+            Some(other) => vec![b'%', other],
+            None => vec![b'%'],
+        };
+        let pad = width.saturating_sub(text.len());
+        if left {
+            out.extend_from_slice(&text);
+            out.extend(std::iter::repeat_n(b' ', pad));
+        } else if zero && text.first() == Some(&b'-') {
+            out.push(b'-');
+            out.extend(std::iter::repeat_n(b'0', pad));
+            out.extend_from_slice(&text[1..]);
+        } else {
+            let fill = if zero { b'0' } else { b' ' };
+            out.extend(std::iter::repeat_n(fill, pad));
+            out.extend_from_slice(&text);
+        }
+    }
+    out
+}
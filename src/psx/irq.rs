@@ -0,0 +1,76 @@
+use super::state::{Savestate, StateError, StateReader, StateWriter};
+
+// Interrupt sources, by bit position in I_STAT/I_MASK
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Interrupt {
+    Vblank = 0,
+    Gpu = 1,
+    Cdrom = 2,
+    Dma = 3,
+    Timer0 = 4,
+    Timer1 = 5,
+    Timer2 = 6,
+    Controller = 7,
+    Sio = 8,
+    Spu = 9,
+    Lightpen = 10,
+}
+
+pub struct InterruptController {
+    // 1F801070h I_STAT
+    status: u16,
+    // 1F801074h I_MASK
+    mask: u16,
+}
+
+impl InterruptController {
+    pub fn new() -> Self {
+        Self { status: 0, mask: 0 }
+    }
+
+    // Raise an interrupt request
+    pub fn request(&mut self, interrupt: Interrupt) {
+        self.status |= 1 << interrupt as u16;
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    // Writing to I_STAT acknowledges the bits written as zero
+    pub fn acknowledge(&mut self, val: u16) {
+        self.status &= val;
+    }
+
+    pub fn mask(&self) -> u16 {
+        self.mask
+    }
+
+    pub fn set_mask(&mut self, val: u16) {
+        self.mask = val & 0x7ff;
+    }
+
+    // Is any unmasked interrupt pending?
+    pub fn pending(&self) -> bool {
+        self.status & self.mask != 0
+    }
+}
+
+impl Savestate for InterruptController {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u16(self.status);
+        w.write_u16(self.mask);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.status = r.read_u16()? & 0x7ff;
+        self.mask = r.read_u16()? & 0x7ff;
+        Ok(())
+    }
+}
+
+impl Default for InterruptController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,76 @@
+use super::{map, Psx, RAM_SIZE};
+
+// Kernel area at the start of RAM: exception vectors, kernel variables,
+// function tables and the kernel's own code
+const KERNEL_END: u32 = 0x10000;
+
+// BIOS ROM, where the rest of the kernel runs from
+const BIOS_START: u32 = 0x1fc00000;
+const BIOS_END: u32 = 0x1fc7ffff;
+
+// Writes kept until the frontend takes them, later ones are dropped
+const MAX_WRITES: usize = 1024;
+
+// A write to the kernel area from code outside the kernel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KernelWrite {
+    // CPU cycle of the write
+    pub cycle: u64,
+    // Address of the store instruction
+    pub pc: u32,
+    // Physical address written
+    pub addr: u32,
+    pub val: u32,
+    // Bytes written
+    pub width: u32,
+}
+
+// Debugging aid watching the kernel area for writes by the game, which
+// usually mean a stray pointer in the game or memory routed to the wrong
+// place by the emulator. Start it once the game runs: the BIOS shell sets
+// up the kernel area at boot.
+pub struct KernelWatch {
+    writes: Vec<KernelWrite>,
+}
+
+impl KernelWatch {
+    pub fn new() -> Self {
+        Self { writes: Vec::new() }
+    }
+
+    pub fn take_writes(&mut self) -> Vec<KernelWrite> {
+        std::mem::take(&mut self.writes)
+    }
+}
+
+impl Default for KernelWatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn in_kernel_area(addr: u32) -> bool {
+    addr < 0x800000 && addr & (RAM_SIZE as u32 - 1) < KERNEL_END
+}
+
+// Check a store to RAM at physical address `addr`. Stores by kernel code,
+// in the kernel area or the BIOS ROM, are expected.
+pub fn store(psx: &mut Psx, addr: u32, val: u32, width: u32) {
+    let pc = psx.cpu.current_pc;
+    let code = map::mask(pc);
+    if !in_kernel_area(addr) || in_kernel_area(code) || (BIOS_START..=BIOS_END).contains(&code) {
+        return;
+    }
+    let cycle = psx.scheduler.now();
+    if let Some(watch) = psx.kernel_watch.as_mut() {
+        if watch.writes.len() < MAX_WRITES {
+            watch.writes.push(KernelWrite {
+                cycle,
+                pc,
+                addr,
+                val,
+                width,
+            });
+        }
+    }
+}
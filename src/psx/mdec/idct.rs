@@ -0,0 +1,73 @@
+// IDCT implementation used for decoding
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Idct {
+    // The hardware's fixed-point arithmetic, for output matching real
+    // captures
+    #[default]
+    Hardware,
+    // Floating point, skipping the zero coefficients that make up most of a
+    // typical block. Much cheaper, but pixels can be off by one.
+    Fast,
+}
+
+// Inverse DCT of an 8x8 block of coefficients in place, with the scale table
+// uploaded by the game (the standard one holds the DCT basis in 1.15 fixed
+// point). Two passes, one per dimension, each transposing the block.
+pub fn idct(block: &mut [i16; 64], scale: &[i16; 64]) {
+    let mut temp = [0i16; 64];
+    idct_pass(block, &mut temp, scale);
+    idct_pass(&temp, block, scale);
+}
+
+fn idct_pass(src: &[i16; 64], dst: &mut [i16; 64], scale: &[i16; 64]) {
+    for x in 0..8 {
+        for y in 0..8 {
+            let mut sum = 0i32;
+            for z in 0..8 {
+                sum += src[y + z * 8] as i32 * (scale[x + z * 8] as i32 >> 3);
+            }
+            dst[x + y * 8] = ((sum + 0x1000) >> 13) as i16;
+        }
+    }
+}
+
+// Scale table as floating point factors for idct_fast
+pub fn float_matrix(scale: &[i16; 64]) -> [f32; 64] {
+    let mut matrix = [0.0; 64];
+    for (m, &s) in matrix.iter_mut().zip(scale.iter()) {
+        *m = s as f32 / 65536.0;
+    }
+    matrix
+}
+
+// Same as idct in floating point. Each coefficient adds a scaled row of the
+// matrix, eight lanes at a time, and zero coefficients are skipped.
+pub fn idct_fast(block: &mut [i16; 64], matrix: &[f32; 64]) {
+    let mut input = [0.0f32; 64];
+    for (i, &c) in input.iter_mut().zip(block.iter()) {
+        *i = c as f32;
+    }
+    let mut temp = [0.0f32; 64];
+    idct_fast_pass(&input, &mut temp, matrix);
+    let mut output = [0.0f32; 64];
+    idct_fast_pass(&temp, &mut output, matrix);
+    for (b, &o) in block.iter_mut().zip(output.iter()) {
+        *b = o.round().clamp(-32768.0, 32767.0) as i16;
+    }
+}
+
+fn idct_fast_pass(src: &[f32; 64], dst: &mut [f32; 64], matrix: &[f32; 64]) {
+    for y in 0..8 {
+        let mut row = [0.0f32; 8];
+        for z in 0..8 {
+            let c = src[y + z * 8];
+            if c == 0.0 {
+                continue;
+            }
+            for (r, &m) in row.iter_mut().zip(matrix[z * 8..z * 8 + 8].iter()) {
+                *r += c * m;
+            }
+        }
+        dst[y * 8..y * 8 + 8].copy_from_slice(&row);
+    }
+}
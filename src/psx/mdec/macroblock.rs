@@ -0,0 +1,120 @@
+use super::idct::{self, Idct};
+use super::Depth;
+
+// A macroblock whose coefficients were all received, with the settings it's
+// decoded with. Turning it into pixels needs nothing else from the MDEC, so
+// it can happen on another thread.
+#[derive(Clone)]
+pub struct Macroblock {
+    // Dequantized coefficients of Cr, Cb and Y1-Y4, or of the single Y block
+    // of a monochrome macroblock
+    pub blocks: [[i16; 64]; 6],
+    pub depth: Depth,
+    pub signed: bool,
+    // Set bit 15 of 15 bit pixels, for the GPU's mask bit
+    pub set_bit15: bool,
+    pub idct: Idct,
+    pub scale: [i16; 64],
+    // Scale table for the floating point IDCT
+    pub matrix: [f32; 64],
+}
+
+impl Macroblock {
+    // Run the IDCT and convert to the output format. Returns the words read
+    // back from the MDEC.
+    pub fn decode(&self) -> Vec<u32> {
+        let mut blocks = self.blocks;
+        let count = if self.depth.colored() { 6 } else { 1 };
+        for block in blocks[..count].iter_mut() {
+            match self.idct {
+                Idct::Hardware => idct::idct(block, &self.scale),
+                Idct::Fast => idct::idct_fast(block, &self.matrix),
+            }
+        }
+        let bytes = if self.depth.colored() {
+            self.output_color(&blocks)
+        } else {
+            self.output_mono(&blocks[0])
+        };
+        bytes
+            .chunks(4)
+            .map(|word| {
+                let mut val = [0u8; 4];
+                val[..word.len()].copy_from_slice(word);
+                u32::from_le_bytes(val)
+            })
+            .collect()
+    }
+
+    fn output_mono(&self, block: &[i16; 64]) -> Vec<u8> {
+        let pixels = block.iter().map(|&y| {
+            let y = y.clamp(-128, 127) as u8;
+            if self.signed {
+                y
+            } else {
+                y ^ 0x80
+            }
+        });
+        match self.depth {
+            Depth::Bits4 => {
+                let pixels: Vec<u8> = pixels.collect();
+                pixels
+                    .chunks(2)
+                    .map(|pair| (pair[0] >> 4) | (pair[1] & 0xf0))
+                    .collect()
+            }
+            _ => pixels.collect(),
+        }
+    }
+
+    fn output_color(&self, blocks: &[[i16; 64]; 6]) -> Vec<u8> {
+        let mut rgb = [[0u8; 3]; 256];
+        let [cr, cb, ref y @ ..] = *blocks;
+        for (index, y) in y.iter().enumerate() {
+            let (xx, yy) = ((index & 1) * 8, (index >> 1) * 8);
+            for row in 0..8 {
+                for col in 0..8 {
+                    let (px, py) = (xx + col, yy + row);
+                    let chroma = px / 2 + (py / 2) * 8;
+                    let (r, b) = (cr[chroma] as i32, cb[chroma] as i32);
+                    let g = (-88 * b - 183 * r) >> 8;
+                    let r = (359 * r) >> 8;
+                    let b = (454 * b) >> 8;
+                    let luma = y[col + row * 8] as i32;
+                    let mut pixel = [r, g, b].map(|c| (luma + c).clamp(-128, 127) as u8);
+                    if !self.signed {
+                        pixel = pixel.map(|c| c ^ 0x80);
+                    }
+                    rgb[px + py * 16] = pixel;
+                }
+            }
+        }
+        let mut bytes = Vec::with_capacity(768);
+        match self.depth {
+            Depth::Bits15 => {
+                let mask = if self.set_bit15 { 0x8000 } else { 0 };
+                for [r, g, b] in rgb.iter() {
+                    let pixel =
+                        self.to_5bit(*r) | self.to_5bit(*g) << 5 | self.to_5bit(*b) << 10 | mask;
+                    bytes.extend_from_slice(&pixel.to_le_bytes());
+                }
+            }
+            _ => {
+                for pixel in rgb.iter() {
+                    bytes.extend_from_slice(pixel);
+                }
+            }
+        }
+        bytes
+    }
+
+    // Round an 8 bit color component to 5 bits, saturating instead of
+    // wrapping around at the top of the range
+    fn to_5bit(&self, c: u8) -> u16 {
+        if self.signed {
+            (((c as i8 as i16 + 4) >> 3).min(15) & 0x1f) as u16
+        } else {
+            ((c as u16 + 4) >> 3).min(31)
+        }
+    }
+}
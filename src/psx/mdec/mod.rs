@@ -0,0 +1,611 @@
+use super::dma::{self, Port};
+use super::scheduler::Event;
+use super::state::{Savestate, StateError, StateReader, StateWriter};
+use super::Psx;
+
+use std::collections::VecDeque;
+
+mod idct;
+mod macroblock;
+mod thread;
+
+pub use idct::Idct;
+
+use macroblock::Macroblock;
+use thread::MdecThread;
+
+// CPU cycles to decode an 8x8 block, an estimate of the hardware's speed
+const BLOCK_CYCLES: u64 = 75;
+
+// End of block code, also used as padding between blocks
+const END_OF_BLOCK: u16 = 0xfe00;
+
+// Position in the natural 8x8 order of each coefficient in the order they're
+// sent
+const ZAGZIG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+// Blocks of a colored macroblock in the order they're sent, as reported in
+// MDEC status bits 16-18: Cr, Cb, then the four Y blocks
+const COLOR_BLOCKS: [u32; 6] = [4, 5, 0, 1, 2, 3];
+
+// Status bits
+const STAT_OUT_EMPTY: u32 = 1 << 31;
+const STAT_BUSY: u32 = 1 << 29;
+const STAT_IN_REQUEST: u32 = 1 << 28;
+const STAT_OUT_REQUEST: u32 = 1 << 27;
+
+// Format of decoded pixels, from command bits 27-28
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Depth {
+    // Monochrome, 4 or 8 bits per pixel
+    Bits4,
+    Bits8,
+    // Colored RGB888
+    Bits24,
+    // Colored RGB555
+    Bits15,
+}
+
+impl Depth {
+    fn from_bits(bits: u32) -> Self {
+        match bits & 3 {
+            0 => Depth::Bits4,
+            1 => Depth::Bits8,
+            2 => Depth::Bits24,
+            _ => Depth::Bits15,
+        }
+    }
+
+    fn colored(self) -> bool {
+        matches!(self, Depth::Bits24 | Depth::Bits15)
+    }
+}
+
+// Command being given its parameters
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Command {
+    Idle,
+    Decode,
+    // Quantization tables, with the color table too if set
+    SetQuant(bool),
+    SetScale,
+}
+
+// Decoding state of the block being received
+struct BlockDecoder {
+    coefficients: [i16; 64],
+    // Index of the next coefficient in the order they're sent, None while
+    // waiting for the DC coefficient
+    index: Option<usize>,
+    quant_scale: i32,
+}
+
+impl BlockDecoder {
+    fn new() -> Self {
+        Self {
+            coefficients: [0; 64],
+            index: None,
+            quant_scale: 0,
+        }
+    }
+
+    // Feed the next halfword of run-length coded data. Returns true once the
+    // block is complete.
+    fn push(&mut self, data: u16, quant: &[u8; 64]) -> bool {
+        let value = ((data as i32) << 22) >> 22;
+        let k = match self.index {
+            None => {
+                if data == END_OF_BLOCK {
+                    return false;
+                }
+                self.coefficients = [0; 64];
+                self.quant_scale = (data >> 10) as i32;
+                0
+            }
+            Some(k) => k + (data >> 10) as usize + 1,
+        };
+        if k > 63 {
+            self.index = None;
+            return true;
+        }
+        // Without a quantization scale, coefficients are taken as is, in
+        // natural order
+        let (val, position) = match (k, self.quant_scale) {
+            (_, 0) => (value * 2, k),
+            (0, _) => (value * quant[0] as i32, ZAGZIG[0]),
+            _ => (
+                (value * quant[k] as i32 * self.quant_scale + 4) / 8,
+                ZAGZIG[k],
+            ),
+        };
+        self.coefficients[position] = val.clamp(-0x400, 0x3ff) as i16;
+        self.index = Some(k);
+        false
+    }
+}
+
+// Motion decoder. It takes macroblocks of run-length coded DCT coefficients
+// and outputs them as pixels for the GPU: RLE -> dequantization -> IDCT ->
+// YUV to RGB. A colored macroblock is 16x16 pixels made of Cr, Cb and four Y
+// blocks; a monochrome one is a single 8x8 Y block.
+pub struct Mdec {
+    command: Command,
+    // Parameter words the command still expects
+    remaining: u32,
+    depth: Depth,
+    signed: bool,
+    // Set bit 15 of 15 bit pixels, for the GPU's mask bit
+    set_bit15: bool,
+    // Status bits 23-26, copied from the last command word
+    command_bits: u32,
+    // Luminance and color quantization tables
+    quant_y: [u8; 64],
+    quant_uv: [u8; 64],
+    scale: [i16; 64],
+    // Scale table for the floating point IDCT
+    matrix: [f32; 64],
+    idct: Idct,
+    // Table upload position, in bytes or halfwords
+    upload: usize,
+    decoder: BlockDecoder,
+    // Block of the macroblock being received, in the order they're sent
+    block: usize,
+    // Coefficients of the Cr, Cb and Y1-Y4 blocks received so far
+    blocks: [[i16; 64]; 6],
+    // Macroblocks being decoded: when they're done, and their output unless
+    // it comes from the thread
+    in_flight: VecDeque<(u64, Option<Vec<u32>>)>,
+    // When the last macroblock queued is done
+    busy_until: u64,
+    thread: Option<MdecThread>,
+    output: VecDeque<u32>,
+    // MDEC1 bits 29-30: DMA requests enabled
+    dma_out_enabled: bool,
+    dma_in_enabled: bool,
+}
+
+impl Mdec {
+    pub fn new() -> Self {
+        Self {
+            command: Command::Idle,
+            remaining: 0,
+            depth: Depth::Bits4,
+            signed: false,
+            set_bit15: false,
+            command_bits: 0,
+            quant_y: [0; 64],
+            quant_uv: [0; 64],
+            scale: [0; 64],
+            matrix: [0.0; 64],
+            idct: Idct::default(),
+            upload: 0,
+            decoder: BlockDecoder::new(),
+            block: 0,
+            blocks: [[0; 64]; 6],
+            in_flight: VecDeque::new(),
+            busy_until: 0,
+            thread: None,
+            output: VecDeque::new(),
+            dma_out_enabled: false,
+            dma_in_enabled: false,
+        }
+    }
+
+    pub fn idct(&self) -> Idct {
+        self.idct
+    }
+
+    // Select the IDCT, taking effect with the next block
+    pub fn set_idct(&mut self, idct: Idct) {
+        self.idct = idct;
+    }
+
+    pub fn reset(&mut self) {
+        // Results still coming from the thread are dropped
+        if let Some(thread) = self.thread.as_mut() {
+            for (_, output) in self.in_flight.iter() {
+                if output.is_none() {
+                    thread.receive();
+                }
+            }
+        }
+        self.in_flight.clear();
+        self.command = Command::Idle;
+        self.remaining = 0;
+        self.command_bits = 0;
+        self.decoder = BlockDecoder::new();
+        self.block = 0;
+        self.output.clear();
+    }
+
+    // 1F801824h MDEC status
+    pub fn status(&self) -> u32 {
+        let mut stat = self.command_bits << 23;
+        if self.output.is_empty() {
+            stat |= STAT_OUT_EMPTY;
+        }
+        if self.command != Command::Idle || !self.in_flight.is_empty() || !self.output.is_empty() {
+            stat |= STAT_BUSY;
+        }
+        if self.dma_in_enabled {
+            stat |= STAT_IN_REQUEST;
+        }
+        if self.dma_out_enabled && !self.output.is_empty() {
+            stat |= STAT_OUT_REQUEST;
+        }
+        let block = match (self.command, self.depth.colored()) {
+            (Command::Decode, true) => COLOR_BLOCKS[self.block],
+            _ => 4,
+        };
+        stat |= block << 16;
+        stat | (self.remaining.wrapping_sub(1) & 0xffff)
+    }
+
+    // 1F801820h: a command word, or a parameter of the current command,
+    // written at `now`
+    pub fn write_command(&mut self, val: u32, now: u64) {
+        if self.command == Command::Idle {
+            self.start(val);
+        } else {
+            self.parameter(val, now);
+        }
+    }
+
+    fn start(&mut self, val: u32) {
+        self.command_bits = (val >> 25) & 0xf;
+        self.upload = 0;
+        let (command, remaining) = match val >> 29 {
+            1 => {
+                self.depth = Depth::from_bits(val >> 27);
+                self.signed = val & (1 << 26) != 0;
+                self.set_bit15 = val & (1 << 25) != 0;
+                (Command::Decode, val & 0xffff)
+            }
+            2 if val & 1 != 0 => (Command::SetQuant(true), 32),
+            2 => (Command::SetQuant(false), 16),
+            3 => (Command::SetScale, 32),
+            _ => (Command::Idle, 0),
+        };
+        self.command = command;
+        self.remaining = remaining;
+        if remaining == 0 {
+            self.command = Command::Idle;
+        }
+    }
+
+    fn parameter(&mut self, val: u32, now: u64) {
+        match self.command {
+            Command::Decode => {
+                self.decode(val as u16, now);
+                self.decode((val >> 16) as u16, now);
+            }
+            Command::SetQuant(_) => {
+                for byte in val.to_le_bytes().iter() {
+                    match self.upload {
+                        0..=63 => self.quant_y[self.upload] = *byte,
+                        _ => self.quant_uv[self.upload - 64] = *byte,
+                    }
+                    self.upload += 1;
+                }
+            }
+            Command::SetScale => {
+                for half in [val as i16, (val >> 16) as i16].iter() {
+                    self.scale[self.upload] = *half;
+                    self.upload += 1;
+                }
+                self.matrix = idct::float_matrix(&self.scale);
+            }
+            Command::Idle => {}
+        }
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            self.command = Command::Idle;
+        }
+    }
+
+    fn decode(&mut self, data: u16, now: u64) {
+        let colored = self.depth.colored();
+        // Cr and Cb use the color quantization table
+        let quant = if colored && self.block < 2 {
+            &self.quant_uv
+        } else {
+            &self.quant_y
+        };
+        if !self.decoder.push(data, quant) {
+            return;
+        }
+        self.blocks[self.block] = self.decoder.coefficients;
+        self.block += 1;
+        let count = if colored { 6 } else { 1 };
+        if self.block < count {
+            return;
+        }
+        self.block = 0;
+        let macroblock = Macroblock {
+            blocks: self.blocks,
+            depth: self.depth,
+            signed: self.signed,
+            set_bit15: self.set_bit15,
+            idct: self.idct,
+            scale: self.scale,
+            matrix: self.matrix,
+        };
+        // Macroblocks are decoded one after the other, each taking a fixed
+        // time once all its data arrived
+        let done = now.max(self.busy_until) + count as u64 * BLOCK_CYCLES;
+        self.busy_until = done;
+        let output = match self.thread.as_mut() {
+            Some(thread) => {
+                thread.decode(macroblock);
+                None
+            }
+            None => Some(macroblock.decode()),
+        };
+        self.in_flight.push_back((done, output));
+    }
+
+    // Move the output of the next macroblock to the output FIFO, if it's done
+    // decoding at `now`. Returns when the following one will be.
+    fn finish_macroblock(&mut self, now: u64) -> Option<u64> {
+        match self.in_flight.front() {
+            Some(&(done, _)) if done <= now => {}
+            Some(&(done, _)) => return Some(done),
+            None => return None,
+        }
+        if let Some((_, output)) = self.in_flight.pop_front() {
+            let words = match (output, self.thread.as_mut()) {
+                (Some(words), _) => words,
+                (None, Some(thread)) => thread.receive(),
+                (None, None) => Vec::new(),
+            };
+            self.output.extend(words);
+        }
+        self.in_flight.front().map(|&(done, _)| done)
+    }
+
+    pub fn threaded(&self) -> bool {
+        self.thread.is_some()
+    }
+
+    // Decode macroblocks on a worker thread or on the emulation thread. Output
+    // appears at the same emulated time either way.
+    pub fn set_threaded(&mut self, threaded: bool) {
+        match (threaded, self.thread.take()) {
+            (true, None) => self.thread = Some(MdecThread::spawn()),
+            (false, Some(mut thread)) => {
+                for (_, output) in self.in_flight.iter_mut() {
+                    if output.is_none() {
+                        *output = Some(thread.receive());
+                    }
+                }
+            }
+            (_, thread) => self.thread = thread,
+        }
+    }
+
+    // Wait for the macroblocks the thread is decoding, keeping their output
+    // for when they're due
+    fn collect(&mut self) {
+        if let Some(thread) = self.thread.as_mut() {
+            for (_, output) in self.in_flight.iter_mut() {
+                if output.is_none() {
+                    *output = Some(thread.receive());
+                }
+            }
+        }
+    }
+
+    // Is the MDEC ready for DMA channel 0 to send it data?
+    pub fn dma_in_request(&self) -> bool {
+        self.dma_in_enabled
+    }
+
+    // Words of output ready for DMA channel 1, none unless its request is
+    // enabled
+    pub fn dma_out_words(&self) -> usize {
+        if self.dma_out_enabled {
+            self.output.len()
+        } else {
+            0
+        }
+    }
+
+    // 1F801820h: next word of decoded pixels
+    pub fn read_data(&mut self) -> u32 {
+        self.output.pop_front().unwrap_or(0)
+    }
+
+    // 1F801824h MDEC control: reset and DMA enables
+    pub fn write_control(&mut self, val: u32) {
+        if val & (1 << 31) != 0 {
+            self.reset();
+        }
+        self.dma_in_enabled = val & (1 << 30) != 0;
+        self.dma_out_enabled = val & (1 << 29) != 0;
+    }
+}
+
+fn write_halfwords(w: &mut StateWriter, halfwords: &[i16; 64]) {
+    for &half in halfwords.iter() {
+        w.write_i16(half);
+    }
+}
+
+fn read_halfwords(r: &mut StateReader, halfwords: &mut [i16; 64]) -> Result<(), StateError> {
+    for half in halfwords.iter_mut() {
+        *half = r.read_i16()?;
+    }
+    Ok(())
+}
+
+fn write_words(w: &mut StateWriter, words: &[u32]) {
+    w.write_u32(words.len() as u32);
+    for &word in words.iter() {
+        w.write_u32(word);
+    }
+}
+
+fn read_words(r: &mut StateReader) -> Result<Vec<u32>, StateError> {
+    let len = r.read_u32()? as usize;
+    if len > r.remaining() / 4 {
+        return Err(StateError::UnexpectedEof);
+    }
+    (0..len).map(|_| r.read_u32()).collect()
+}
+
+// The IDCT and threading are settings and aren't saved. Macroblocks still on
+// the thread have to be collected first, see save_state.
+impl Savestate for Mdec {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(match self.command {
+            Command::Idle => 0,
+            Command::Decode => 1,
+            Command::SetQuant(false) => 2,
+            Command::SetQuant(true) => 3,
+            Command::SetScale => 4,
+        });
+        w.write_u32(self.remaining);
+        w.write_u8(self.depth as u8);
+        w.write_bool(self.signed);
+        w.write_bool(self.set_bit15);
+        w.write_u32(self.command_bits);
+        w.write_bytes(&self.quant_y);
+        w.write_bytes(&self.quant_uv);
+        write_halfwords(w, &self.scale);
+        w.write_u32(self.upload as u32);
+        write_halfwords(w, &self.decoder.coefficients);
+        w.write_bool(self.decoder.index.is_some());
+        w.write_u8(self.decoder.index.unwrap_or(0) as u8);
+        w.write_i32(self.decoder.quant_scale);
+        w.write_u8(self.block as u8);
+        for block in self.blocks.iter() {
+            write_halfwords(w, block);
+        }
+        w.write_u32(self.in_flight.len() as u32);
+        for (done, output) in self.in_flight.iter() {
+            w.write_u64(*done);
+            write_words(w, output.as_deref().unwrap_or(&[]));
+        }
+        w.write_u64(self.busy_until);
+        let output: Vec<u32> = self.output.iter().copied().collect();
+        write_words(w, &output);
+        w.write_bool(self.dma_out_enabled);
+        w.write_bool(self.dma_in_enabled);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        // Results still coming from the thread belong to the old state
+        self.reset();
+        self.command = match r.read_u8()? {
+            0 => Command::Idle,
+            1 => Command::Decode,
+            2 => Command::SetQuant(false),
+            3 => Command::SetQuant(true),
+            4 => Command::SetScale,
+            _ => return Err(StateError::Invalid("MDEC command")),
+        };
+        self.remaining = r.read_u32()?;
+        self.depth = Depth::from_bits(r.read_u8()? as u32);
+        self.signed = r.read_bool()?;
+        self.set_bit15 = r.read_bool()?;
+        self.command_bits = r.read_u32()? & 0xf;
+        r.read_into(&mut self.quant_y)?;
+        r.read_into(&mut self.quant_uv)?;
+        read_halfwords(r, &mut self.scale)?;
+        self.matrix = idct::float_matrix(&self.scale);
+        self.upload = r.read_u32()? as usize;
+        let upload_limit = match self.command {
+            Command::SetQuant(_) => 128,
+            _ => 64,
+        };
+        if self.upload > upload_limit {
+            return Err(StateError::Invalid("MDEC upload position"));
+        }
+        read_halfwords(r, &mut self.decoder.coefficients)?;
+        let started = r.read_bool()?;
+        let index = r.read_u8()? as usize;
+        if index > 63 {
+            return Err(StateError::Invalid("MDEC coefficient index"));
+        }
+        self.decoder.index = if started { Some(index) } else { None };
+        self.decoder.quant_scale = r.read_i32()?;
+        self.block = r.read_u8()? as usize;
+        if self.block >= self.blocks.len() {
+            return Err(StateError::Invalid("MDEC block"));
+        }
+        for block in self.blocks.iter_mut() {
+            read_halfwords(r, block)?;
+        }
+        let in_flight = r.read_u32()? as usize;
+        if in_flight > r.remaining() / 12 {
+            return Err(StateError::UnexpectedEof);
+        }
+        for _ in 0..in_flight {
+            let done = r.read_u64()?;
+            let output = read_words(r)?;
+            self.in_flight.push_back((done, Some(output)));
+        }
+        self.busy_until = r.read_u64()?;
+        self.output = read_words(r)?.into();
+        self.dma_out_enabled = r.read_bool()?;
+        self.dma_in_enabled = r.read_bool()?;
+        Ok(())
+    }
+}
+
+// Save the MDEC state, with the output of the macroblocks still decoding
+pub fn save_state(psx: &mut Psx, w: &mut StateWriter) {
+    psx.mdec.collect();
+    psx.mdec.save_state(w);
+}
+
+impl Default for Mdec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Read an MDEC register at `offset` from 1F801820h
+pub fn load(psx: &mut Psx, offset: u32) -> u32 {
+    match offset & !3 {
+        0 => psx.mdec.read_data(),
+        _ => psx.mdec.status(),
+    }
+}
+
+// Write a command or parameter word, from the CPU or DMA channel 0
+pub fn write(psx: &mut Psx, val: u32) {
+    let now = psx.scheduler.now();
+    let idle = psx.mdec.in_flight.is_empty();
+    psx.mdec.write_command(val, now);
+    if idle {
+        if let Some(&(done, _)) = psx.mdec.in_flight.front() {
+            psx.scheduler.schedule(Event::MdecDecode, done - now);
+        }
+    }
+}
+
+// A macroblock finished decoding
+pub fn handle_event(psx: &mut Psx) {
+    let now = psx.scheduler.now();
+    if let Some(next) = psx.mdec.finish_macroblock(now) {
+        psx.scheduler.schedule(Event::MdecDecode, next - now);
+    }
+    dma::request(psx, Port::MdecOut);
+}
+
+// Write an MDEC register at `offset` from 1F801820h
+pub fn store(psx: &mut Psx, offset: u32, val: u32) {
+    match offset & !3 {
+        0 => write(psx, val),
+        _ => {
+            psx.mdec.write_control(val);
+            dma::request(psx, Port::MdecIn);
+        }
+    }
+    dma::request(psx, Port::MdecOut);
+}
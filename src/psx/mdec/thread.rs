@@ -0,0 +1,60 @@
+use super::macroblock::Macroblock;
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+enum Command {
+    Decode(Box<Macroblock>),
+    Stop,
+}
+
+// Worker decoding macroblocks while emulation goes on. Results come back in
+// the order macroblocks were queued.
+pub struct MdecThread {
+    commands: Sender<Command>,
+    output: Receiver<Vec<u32>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl MdecThread {
+    pub fn spawn() -> Self {
+        let (commands, command_rx) = mpsc::channel();
+        let (output_tx, output) = mpsc::channel();
+        let worker = thread::Builder::new()
+            .name("mdec".into())
+            .spawn(move || run(command_rx, output_tx))
+            .expect("failed to spawn MDEC thread");
+        Self {
+            commands,
+            output,
+            worker: Some(worker),
+        }
+    }
+
+    pub fn decode(&mut self, macroblock: Macroblock) {
+        let _ = self.commands.send(Command::Decode(Box::new(macroblock)));
+    }
+
+    // Output of the oldest macroblock not received yet, waiting for it if
+    // needed
+    pub fn receive(&mut self) -> Vec<u32> {
+        self.output.recv().expect("MDEC thread stopped")
+    }
+}
+
+impl Drop for MdecThread {
+    fn drop(&mut self) {
+        let _ = self.commands.send(Command::Stop);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn run(commands: Receiver<Command>, output: Sender<Vec<u32>>) {
+    while let Ok(Command::Decode(macroblock)) = commands.recv() {
+        if output.send(macroblock.decode()).is_err() {
+            break;
+        }
+    }
+}
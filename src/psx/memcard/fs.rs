@@ -0,0 +1,498 @@
+use super::{CARD_SIZE, SECTOR_SIZE};
+
+use std::convert::TryInto;
+use std::io;
+
+// The card is split into 16 blocks of 8 KB: the first holds the directory,
+// the other 15 save data
+pub const BLOCK_SIZE: usize = 8192;
+pub const BLOCKS: usize = 15;
+
+// Directory entry states: the high nibble tells whether the block is in use
+// (5h) or free (Ah), the low one its place in a save. Deleting a save only
+// changes the high nibble, so deleted saves can be recovered until their
+// blocks are reused.
+const STATE_USED: u8 = 0x50;
+const STATE_FREE: u8 = 0xa0;
+const FIRST: u8 = 0x1;
+const MIDDLE: u8 = 0x2;
+const LAST: u8 = 0x3;
+const STATE_FIRST: u8 = STATE_USED | FIRST;
+
+// No next block in a directory entry
+const NO_NEXT: u16 = 0xffff;
+
+// Size of the filename field of directory entries
+const FILENAME_LEN: usize = 20;
+
+// File formats of single saves
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SaveFormat {
+    // PSXGameEdit/MemcardRex: the directory frame, then the data
+    Mcs,
+    // Action Replay/Xplorer: 54 byte header with the filename, then the data
+    Psx,
+    // The data alone, the filename being the file's name
+    Raw,
+}
+
+// A save on a card, as listed in the directory
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SaveInfo {
+    // Block holding the start of the save, 1-15
+    pub first_block: usize,
+    // Name in the directory, e.g. BASLUS-00001GAME01
+    pub filename: String,
+    // Title shown by the BIOS, converted from Shift-JIS
+    pub title: String,
+    // Frames of the animated icon, 1-3
+    pub icon_frames: usize,
+    pub blocks: usize,
+    // Size given in the directory, in bytes
+    pub size: u32,
+    // Deleted but not overwritten yet, so it can still be undeleted
+    pub deleted: bool,
+}
+
+// A save taken out of a card: its filename and blocks
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SaveFile {
+    pub filename: String,
+    pub data: Vec<u8>,
+}
+
+impl SaveFile {
+    // Parse a save file. `filename` names Raw saves, which don't store it.
+    pub fn parse(bytes: &[u8], format: SaveFormat, filename: &str) -> io::Result<Self> {
+        let (filename, data) = match format {
+            SaveFormat::Mcs => {
+                if bytes.len() < SECTOR_SIZE || bytes[0] != STATE_FIRST {
+                    return Err(invalid_data("not an .mcs save"));
+                }
+                (
+                    read_filename(&bytes[0x0a..0x0a + FILENAME_LEN]),
+                    &bytes[SECTOR_SIZE..],
+                )
+            }
+            SaveFormat::Psx => {
+                if bytes.len() < 54 {
+                    return Err(invalid_data("not a .psx save"));
+                }
+                (read_filename(&bytes[..FILENAME_LEN]), &bytes[54..])
+            }
+            SaveFormat::Raw => (filename.to_string(), bytes),
+        };
+        if data.is_empty() || data.len() % BLOCK_SIZE != 0 || data.len() > BLOCKS * BLOCK_SIZE {
+            return Err(invalid_data("save data isn't a whole number of blocks"));
+        }
+        if &data[..2] != b"SC" {
+            return Err(invalid_data("save data has no title frame"));
+        }
+        Ok(Self {
+            filename,
+            data: data.to_vec(),
+        })
+    }
+
+    // Encode the save in `format`
+    pub fn to_bytes(&self, format: SaveFormat) -> Vec<u8> {
+        let mut out = Vec::with_capacity(SECTOR_SIZE + self.data.len());
+        match format {
+            SaveFormat::Mcs => {
+                let mut frame = [0u8; SECTOR_SIZE];
+                frame[0] = STATE_FIRST;
+                frame[4..8].copy_from_slice(&(self.data.len() as u32).to_le_bytes());
+                frame[8..10].copy_from_slice(&NO_NEXT.to_le_bytes());
+                write_filename(&mut frame[0x0a..0x0a + FILENAME_LEN], &self.filename);
+                set_checksum(&mut frame);
+                out.extend_from_slice(&frame);
+            }
+            SaveFormat::Psx => {
+                let mut header = [0u8; 54];
+                write_filename(&mut header[..FILENAME_LEN], &self.filename);
+                let title = self.title();
+                let len = title.len().min(32);
+                header[21..21 + len].copy_from_slice(&title.as_bytes()[..len]);
+                out.extend_from_slice(&header);
+            }
+            SaveFormat::Raw => {}
+        }
+        out.extend_from_slice(&self.data);
+        out
+    }
+
+    pub fn blocks(&self) -> usize {
+        self.data.len() / BLOCK_SIZE
+    }
+
+    pub fn title(&self) -> String {
+        decode_title(&self.data[4..0x44])
+    }
+
+    pub fn icon_frames(&self) -> usize {
+        icon_frames(&self.data)
+    }
+
+    // Frame `frame` of the icon as 16x16 RGBA pixels
+    pub fn icon(&self, frame: usize) -> Vec<u8> {
+        decode_icon(&self.data, frame)
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// Directory entry of block `block` (1-15)
+fn entry(card: &[u8], block: usize) -> &[u8] {
+    &card[block * SECTOR_SIZE..(block + 1) * SECTOR_SIZE]
+}
+
+fn entry_mut(card: &mut [u8], block: usize) -> &mut [u8] {
+    &mut card[block * SECTOR_SIZE..(block + 1) * SECTOR_SIZE]
+}
+
+fn block_data(card: &[u8], block: usize) -> &[u8] {
+    &card[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE]
+}
+
+fn set_checksum(frame: &mut [u8]) {
+    frame[SECTOR_SIZE - 1] = frame[..SECTOR_SIZE - 1].iter().fold(0, |a, b| a ^ b);
+}
+
+fn read_filename(field: &[u8]) -> String {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..len]).into_owned()
+}
+
+fn write_filename(field: &mut [u8], filename: &str) {
+    field.fill(0);
+    let len = filename.len().min(field.len());
+    field[..len].copy_from_slice(&filename.as_bytes()[..len]);
+}
+
+// Next block of a save, from its directory entry
+fn next_block(card: &[u8], block: usize) -> Option<usize> {
+    let next = u16::from_le_bytes([entry(card, block)[8], entry(card, block)[9]]);
+    match next as usize {
+        n if n < BLOCKS => Some(n + 1),
+        _ => None,
+    }
+}
+
+// Blocks of the save starting at `first`, following the directory's links.
+// All blocks of a save are either in use or deleted together.
+fn chain(card: &[u8], first: usize) -> Vec<usize> {
+    let high = entry(card, first)[0] & 0xf0;
+    let mut blocks = vec![first];
+    let mut block = first;
+    while let Some(next) = next_block(card, block) {
+        let state = entry(card, next)[0];
+        let linked = state == high | MIDDLE || state == high | LAST;
+        // Links into unrelated blocks or loops end the chain
+        if !linked || blocks.contains(&next) || blocks.len() == BLOCKS {
+            break;
+        }
+        blocks.push(next);
+        block = next;
+    }
+    blocks
+}
+
+fn check_card(card: &[u8]) -> io::Result<()> {
+    if card.len() != CARD_SIZE || &card[..2] != b"MC" {
+        return Err(invalid_data("not a formatted memory card"));
+    }
+    Ok(())
+}
+
+fn check_first_block(card: &[u8], block: usize, deleted: bool) -> io::Result<()> {
+    let state = match deleted {
+        false => STATE_FIRST,
+        true => STATE_FREE | FIRST,
+    };
+    if !(1..=BLOCKS).contains(&block) || entry(card, block)[0] != state {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no save starts at this block",
+        ));
+    }
+    Ok(())
+}
+
+// List the saves on a card, deleted ones included
+pub fn list(card: &[u8]) -> io::Result<Vec<SaveInfo>> {
+    check_card(card)?;
+    let mut saves = Vec::new();
+    for block in 1..=BLOCKS {
+        let entry = entry(card, block);
+        let deleted = match entry[0] {
+            STATE_FIRST => false,
+            state if state == STATE_FREE | FIRST => true,
+            _ => continue,
+        };
+        let data = block_data(card, block);
+        saves.push(SaveInfo {
+            first_block: block,
+            filename: read_filename(&entry[0x0a..0x0a + FILENAME_LEN]),
+            title: decode_title(&data[4..0x44]),
+            icon_frames: icon_frames(data),
+            blocks: chain(card, block).len(),
+            size: u32::from_le_bytes(entry[4..8].try_into().unwrap()),
+            deleted,
+        });
+    }
+    Ok(saves)
+}
+
+// Blocks that new saves can use: never used, or of deleted saves
+pub fn free_blocks(card: &[u8]) -> usize {
+    (1..=BLOCKS)
+        .filter(|&block| entry(card, block)[0] & 0xf0 == STATE_FREE)
+        .count()
+}
+
+// Copy out the save starting at `first_block`
+pub fn export(card: &[u8], first_block: usize) -> io::Result<SaveFile> {
+    check_card(card)?;
+    check_first_block(card, first_block, false)?;
+    let mut data = Vec::new();
+    for block in chain(card, first_block) {
+        data.extend_from_slice(block_data(card, block));
+    }
+    Ok(SaveFile {
+        filename: read_filename(&entry(card, first_block)[0x0a..0x0a + FILENAME_LEN]),
+        data,
+    })
+}
+
+// Write `save` to free blocks of the card. Returns its first block.
+pub fn import(card: &mut [u8], save: &SaveFile) -> io::Result<usize> {
+    write_save(card, &save.filename, &save.data)
+}
+
+// Make a new save of `blocks` zeroed blocks, as the BIOS does when a game
+// creates a file. Returns its first block.
+pub fn create(card: &mut [u8], filename: &str, blocks: usize) -> io::Result<usize> {
+    if !(1..=BLOCKS).contains(&blocks) {
+        return Err(invalid_data("saves are 1 to 15 blocks"));
+    }
+    write_save(card, filename, &vec![0; blocks * BLOCK_SIZE])
+}
+
+fn write_save(card: &mut [u8], filename: &str, data: &[u8]) -> io::Result<usize> {
+    check_card(card)?;
+    let size = data.len();
+    if size == 0 || !size.is_multiple_of(BLOCK_SIZE) || size > BLOCKS * BLOCK_SIZE {
+        return Err(invalid_data("save data isn't 1 to 15 whole blocks"));
+    }
+    let taken = list(card)?
+        .iter()
+        .any(|info| !info.deleted && info.filename == filename);
+    if taken {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "a save with this filename is already on the card",
+        ));
+    }
+    // Blocks never used go first, to keep deleted saves recoverable
+    let mut free: Vec<usize> = (1..=BLOCKS)
+        .filter(|&block| entry(card, block)[0] == STATE_FREE)
+        .collect();
+    free.extend((1..=BLOCKS).filter(|&block| {
+        let state = entry(card, block)[0];
+        state != STATE_FREE && state & 0xf0 == STATE_FREE
+    }));
+    let count = data.len() / BLOCK_SIZE;
+    if free.len() < count {
+        return Err(io::Error::new(
+            io::ErrorKind::StorageFull,
+            "not enough free blocks on the card",
+        ));
+    }
+    let blocks = &free[..count];
+    for (i, &block) in blocks.iter().enumerate() {
+        let block_data = &data[i * BLOCK_SIZE..(i + 1) * BLOCK_SIZE];
+        card[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE].copy_from_slice(block_data);
+        let entry = entry_mut(card, block);
+        entry.fill(0);
+        entry[0] = match i {
+            0 => STATE_FIRST,
+            _ if i + 1 == count => STATE_USED | LAST,
+            _ => STATE_USED | MIDDLE,
+        };
+        if i == 0 {
+            entry[4..8].copy_from_slice(&(data.len() as u32).to_le_bytes());
+            write_filename(&mut entry[0x0a..0x0a + FILENAME_LEN], filename);
+        }
+        let next = match blocks.get(i + 1) {
+            Some(&next) => (next - 1) as u16,
+            None => NO_NEXT,
+        };
+        entry[8..10].copy_from_slice(&next.to_le_bytes());
+        set_checksum(entry);
+    }
+    Ok(blocks[0])
+}
+
+// Blocks of the save starting at `first_block`, in order
+pub fn blocks(card: &[u8], first_block: usize) -> io::Result<Vec<usize>> {
+    check_card(card)?;
+    check_first_block(card, first_block, false)?;
+    Ok(chain(card, first_block))
+}
+
+// Give the save starting at `first_block` a new filename
+pub fn rename(card: &mut [u8], first_block: usize, filename: &str) -> io::Result<()> {
+    check_card(card)?;
+    check_first_block(card, first_block, false)?;
+    let taken = list(card)?
+        .iter()
+        .any(|info| !info.deleted && info.filename == filename);
+    if taken {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "a save with this filename is already on the card",
+        ));
+    }
+    let entry = entry_mut(card, first_block);
+    write_filename(&mut entry[0x0a..0x0a + FILENAME_LEN], filename);
+    set_checksum(entry);
+    Ok(())
+}
+
+// Mark the blocks of a save deleted. The data stays until they're reused.
+pub fn delete(card: &mut [u8], first_block: usize) -> io::Result<()> {
+    check_card(card)?;
+    check_first_block(card, first_block, false)?;
+    for block in chain(card, first_block) {
+        let entry = entry_mut(card, block);
+        entry[0] = (entry[0] & 0x0f) | STATE_FREE;
+        set_checksum(entry);
+    }
+    Ok(())
+}
+
+// Restore a deleted save whose blocks haven't been reused
+pub fn undelete(card: &mut [u8], first_block: usize) -> io::Result<()> {
+    check_card(card)?;
+    check_first_block(card, first_block, true)?;
+    let blocks = chain(card, first_block);
+    let filename = read_filename(&entry(card, first_block)[0x0a..0x0a + FILENAME_LEN]);
+    let taken = list(card)?
+        .iter()
+        .any(|info| !info.deleted && info.filename == filename);
+    if taken {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "a save with this filename is already on the card",
+        ));
+    }
+    // The size tells how many blocks the save had; fewer means some were
+    // reused
+    let size = u32::from_le_bytes(entry(card, first_block)[4..8].try_into().unwrap());
+    if blocks.len() * BLOCK_SIZE < size as usize {
+        return Err(invalid_data("some blocks of the save were overwritten"));
+    }
+    for block in blocks {
+        let entry = entry_mut(card, block);
+        entry[0] = (entry[0] & 0x0f) | STATE_USED;
+        set_checksum(entry);
+    }
+    Ok(())
+}
+
+// Icon frames given by the title frame's display flag: 11h-13h for 1-3
+fn icon_frames(data: &[u8]) -> usize {
+    match data[2] {
+        0x12 => 2,
+        0x13 => 3,
+        _ => 1,
+    }
+}
+
+// Decode frame `frame` of a save's icon: 16x16 pixels of 4 bits into the
+// title frame's 16 color palette, color 0000h being transparent
+fn decode_icon(data: &[u8], frame: usize) -> Vec<u8> {
+    let palette = &data[0x60..0x80];
+    let pixels = &data[(1 + frame) * SECTOR_SIZE..(2 + frame) * SECTOR_SIZE];
+    let mut out = Vec::with_capacity(16 * 16 * 4);
+    for &byte in pixels {
+        for index in [byte & 0x0f, byte >> 4].iter() {
+            let i = *index as usize * 2;
+            let color = u16::from_le_bytes([palette[i], palette[i + 1]]);
+            let expand = |c: u16| ((c & 0x1f) << 3 | (c & 0x1f) >> 2) as u8;
+            out.push(expand(color));
+            out.push(expand(color >> 5));
+            out.push(expand(color >> 10));
+            out.push(if color == 0 { 0 } else { 0xff });
+        }
+    }
+    out
+}
+
+// Convert a title from Shift-JIS. Titles are mostly full width ASCII, which
+// is converted to ASCII; other characters become '?'.
+fn decode_title(bytes: &[u8]) -> String {
+    const PUNCTUATION: &[(u8, char)] = &[
+        (0x40, ' '),
+        (0x43, ','),
+        (0x44, '.'),
+        (0x46, ':'),
+        (0x47, ';'),
+        (0x48, '?'),
+        (0x49, '!'),
+        (0x4f, '^'),
+        (0x51, '_'),
+        (0x5b, '-'),
+        (0x5d, '-'),
+        (0x5e, '/'),
+        (0x5f, '\\'),
+        (0x60, '~'),
+        (0x62, '|'),
+        (0x66, '\''),
+        (0x68, '"'),
+        (0x69, '('),
+        (0x6a, ')'),
+        (0x6d, '['),
+        (0x6e, ']'),
+        (0x6f, '{'),
+        (0x70, '}'),
+        (0x7b, '+'),
+        (0x7c, '-'),
+        (0x81, '='),
+        (0x83, '<'),
+        (0x84, '>'),
+        (0x90, '$'),
+        (0x93, '%'),
+        (0x94, '#'),
+        (0x95, '&'),
+        (0x96, '*'),
+        (0x97, '@'),
+    ];
+    let mut title = String::new();
+    let mut i = 0;
+    while i < bytes.len() && bytes[i] != 0 {
+        let byte = bytes[i];
+        // Lead bytes of two byte characters
+        if (0x81..=0x9f).contains(&byte) || (0xe0..=0xfc).contains(&byte) {
+            let trail = bytes.get(i + 1).copied().unwrap_or(0);
+            let c = match (byte, trail) {
+                (0x81, _) => PUNCTUATION
+                    .iter()
+                    .find(|&&(code, _)| code == trail)
+                    .map_or('?', |&(_, c)| c),
+                (0x82, 0x4f..=0x58) => (b'0' + trail - 0x4f) as char,
+                (0x82, 0x60..=0x79) => (b'A' + trail - 0x60) as char,
+                (0x82, 0x81..=0x9a) => (b'a' + trail - 0x81) as char,
+                _ => '?',
+            };
+            title.push(c);
+            i += 2;
+        } else {
+            title.push(if byte.is_ascii() { byte as char } else { '?' });
+            i += 1;
+        }
+    }
+    title.trim_end().to_string()
+}
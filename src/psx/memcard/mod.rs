@@ -0,0 +1,452 @@
+use super::sio::Device;
+use super::state::{StateError, StateReader, StateWriter};
+
+pub mod fs;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// A card holds 1024 sectors (frames) of 128 bytes, 128 KB in all
+pub const SECTOR_SIZE: usize = 128;
+pub const SECTORS: usize = 1024;
+pub const CARD_SIZE: usize = SECTOR_SIZE * SECTORS;
+
+// Card IDs sent after the command byte
+const ID1: u8 = 0x5a;
+const ID2: u8 = 0x5d;
+
+// Command acknowledge bytes
+const ACK1: u8 = 0x5c;
+const ACK2: u8 = 0x5d;
+
+// End status of a read or write
+const END_GOOD: u8 = 0x47;
+const END_BAD_CHECKSUM: u8 = 0x4e;
+const END_BAD_SECTOR: u8 = 0xff;
+
+// FLAG byte bits: the last write failed, and no write happened since the
+// card was inserted (games use it to notice a swapped card)
+const FLAG_ERROR: u8 = 0x04;
+const FLAG_FRESH: u8 = 0x08;
+
+// When writes to the card reach its file
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WritebackPolicy {
+    // Every sector as soon as the game writes it
+    Immediate,
+    // Only on flush, as done when the emulator exits or the card is removed
+    OnFlush,
+    // Never: changes last until the card is removed
+    Never,
+}
+
+// What loading a save state does with the card's contents when they differ
+// from the ones in the state, as when the game was saved to the card after
+// the state was made
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum StatePolicy {
+    // Keep the contents, as if the card was taken out and put back in
+    #[default]
+    Keep,
+    // Replace them with the ones in the state, losing what was saved since
+    Restore,
+    // Fail the load, for the frontend to ask what to do
+    Refuse,
+}
+
+// Command in progress
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Command {
+    Read,
+    Write,
+    GetId,
+    None,
+}
+
+// A memory card (SCPH-1020) and its protocol:
+//   read:   81h 52h 00h 00h MSB LSB 00h 00h 00h 00h, 128 x 00h, 00h 00h
+//        -> FFh FLAG 5Ah 5Dh 00h MSB 5Ch 5Dh MSB LSB, data, checksum 47h
+//   write:  81h 57h 00h 00h MSB LSB, data, checksum 00h 00h 00h
+//        -> FFh FLAG 5Ah 5Dh 00h MSB, data echoed one byte late, 5Ch 5Dh end
+//   get ID: 81h 53h then 8 x 00h
+//        -> FFh FLAG 5Ah 5Dh 5Ch 5Dh 04h 00h 00h 80h
+// The checksum is the XOR of the sector number bytes and the data.
+pub struct MemoryCard {
+    data: Box<[u8]>,
+    flag: u8,
+    // Backing .mcr file and when writes reach it
+    path: Option<PathBuf>,
+    policy: WritebackPolicy,
+    // Sectors written since the last flush
+    dirty: Vec<bool>,
+    // Contents handling on the next state load, and whether the last state
+    // loaded had different contents
+    state_policy: StatePolicy,
+    state_conflict: bool,
+    command: Command,
+    // Position in the current command
+    step: usize,
+    // Sector being read or written, as received
+    address: u16,
+    // Last byte received, echoed back one byte late during writes
+    previous: u8,
+    checksum: u8,
+    buffer: [u8; SECTOR_SIZE],
+}
+
+impl MemoryCard {
+    // Formatted card that isn't saved anywhere
+    pub fn new() -> Self {
+        let mut data = vec![0; CARD_SIZE].into_boxed_slice();
+        format(&mut data);
+        Self::from_data(data)
+    }
+
+    fn from_data(data: Box<[u8]>) -> Self {
+        Self {
+            data,
+            flag: FLAG_FRESH,
+            path: None,
+            policy: WritebackPolicy::Never,
+            dirty: vec![false; SECTORS],
+            state_policy: StatePolicy::Restore,
+            state_conflict: false,
+            command: Command::None,
+            step: 0,
+            address: 0,
+            previous: 0,
+            checksum: 0,
+            buffer: [0; SECTOR_SIZE],
+        }
+    }
+
+    // Card backed by a raw 128 KB .mcr image, created formatted when it
+    // doesn't exist yet
+    pub fn open<P: AsRef<Path>>(path: P, policy: WritebackPolicy) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut data = vec![0; CARD_SIZE].into_boxed_slice();
+        match File::open(path) {
+            Ok(mut file) => {
+                if file.metadata()?.len() != CARD_SIZE as u64 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "memory card image isn't 128 KB",
+                    ));
+                }
+                file.read_exact(&mut data)?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                format(&mut data);
+                if policy != WritebackPolicy::Never {
+                    File::create(path)?.write_all(&data)?;
+                }
+            }
+            Err(e) => return Err(e),
+        }
+        let mut card = Self::from_data(data);
+        card.path = Some(path.to_path_buf());
+        card.policy = policy;
+        Ok(card)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    // The card image, for changes through `fs`. All of it is written back
+    // on the next flush.
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        self.dirty.fill(true);
+        &mut self.data
+    }
+
+    pub fn set_policy(&mut self, policy: WritebackPolicy) {
+        self.policy = policy;
+    }
+
+    // Write the sectors changed since the last flush to the card's file
+    pub fn flush(&mut self) -> io::Result<()> {
+        let path = match (&self.path, self.policy) {
+            (Some(path), WritebackPolicy::Immediate) | (Some(path), WritebackPolicy::OnFlush) => {
+                path
+            }
+            _ => return Ok(()),
+        };
+        if !self.dirty.contains(&true) {
+            return Ok(());
+        }
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        for sector in 0..SECTORS {
+            if self.dirty[sector] {
+                let offset = sector * SECTOR_SIZE;
+                file.seek(SeekFrom::Start(offset as u64))?;
+                file.write_all(&self.data[offset..offset + SECTOR_SIZE])?;
+                self.dirty[sector] = false;
+            }
+        }
+        Ok(())
+    }
+
+    fn sector_valid(&self) -> bool {
+        (self.address as usize) < SECTORS
+    }
+
+    fn read_byte(&mut self, index: usize) -> u8 {
+        let offset = self.address as usize * SECTOR_SIZE + index;
+        let byte = self.data[offset];
+        self.checksum ^= byte;
+        byte
+    }
+
+    // Commit a received sector. Returns the end status.
+    fn write_sector(&mut self, checksum: u8) -> u8 {
+        if !self.sector_valid() {
+            self.flag |= FLAG_ERROR;
+            return END_BAD_SECTOR;
+        }
+        if checksum != self.checksum {
+            self.flag |= FLAG_ERROR;
+            return END_BAD_CHECKSUM;
+        }
+        let sector = self.address as usize;
+        let offset = sector * SECTOR_SIZE;
+        self.data[offset..offset + SECTOR_SIZE].copy_from_slice(&self.buffer);
+        self.dirty[sector] = true;
+        self.flag &= !(FLAG_ERROR | FLAG_FRESH);
+        if self.policy == WritebackPolicy::Immediate {
+            // A failed write stays dirty for the next flush to report
+            let _ = self.flush();
+        }
+        END_GOOD
+    }
+
+    fn read(&mut self, step: usize, tx: u8) -> (u8, bool) {
+        match step {
+            2 => (ID1, true),
+            3 => (ID2, true),
+            4 => {
+                self.address = (tx as u16) << 8;
+                (0x00, true)
+            }
+            5 => {
+                self.address |= tx as u16;
+                (self.previous, true)
+            }
+            6 => (ACK1, true),
+            7 => (ACK2, true),
+            // A bad sector number is confirmed as FFFFh and ends the read
+            8 if !self.sector_valid() => (0xff, true),
+            9 if !self.sector_valid() => (0xff, false),
+            8 => {
+                self.checksum = (self.address >> 8) as u8;
+                ((self.address >> 8) as u8, true)
+            }
+            9 => {
+                self.checksum ^= self.address as u8;
+                (self.address as u8, true)
+            }
+            10..=137 => (self.read_byte(step - 10), true),
+            138 => (self.checksum, true),
+            139 => (END_GOOD, false),
+            _ => (0xff, false),
+        }
+    }
+
+    fn write(&mut self, step: usize, tx: u8) -> (u8, bool) {
+        match step {
+            2 => (ID1, true),
+            3 => (ID2, true),
+            4 => {
+                self.address = (tx as u16) << 8;
+                self.checksum = tx;
+                (0x00, true)
+            }
+            5 => {
+                self.address |= tx as u16;
+                self.checksum ^= tx;
+                (self.previous, true)
+            }
+            6..=133 => {
+                self.buffer[step - 6] = tx;
+                self.checksum ^= tx;
+                (self.previous, true)
+            }
+            134 => {
+                let status = self.write_sector(tx);
+                self.buffer[0] = status;
+                (self.previous, true)
+            }
+            135 => (ACK1, true),
+            136 => (ACK2, true),
+            137 => (self.buffer[0], false),
+            _ => (0xff, false),
+        }
+    }
+
+    fn get_id(&mut self, step: usize) -> (u8, bool) {
+        const REPLY: [u8; 8] = [ID1, ID2, ACK1, ACK2, 0x04, 0x00, 0x00, 0x80];
+        match step {
+            2..=9 => (REPLY[step - 2], step < 9),
+            _ => (0xff, false),
+        }
+    }
+}
+
+impl Default for MemoryCard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for MemoryCard {
+    fn drop(&mut self) {
+        if self.policy == WritebackPolicy::OnFlush {
+            let _ = self.flush();
+        }
+    }
+}
+
+impl Device for MemoryCard {
+    fn transfer(&mut self, tx: u8) -> (u8, bool) {
+        let step = self.step;
+        self.step += 1;
+        let reply = match step {
+            0 => (0xff, true),
+            1 => {
+                self.command = match tx {
+                    0x52 => Command::Read,
+                    0x57 => Command::Write,
+                    0x53 => Command::GetId,
+                    _ => Command::None,
+                };
+                (self.flag, self.command != Command::None)
+            }
+            _ => match self.command {
+                Command::Read => self.read(step, tx),
+                Command::Write => self.write(step, tx),
+                Command::GetId => self.get_id(step),
+                Command::None => (0xff, false),
+            },
+        };
+        self.previous = tx;
+        // Bytes after the last acknowledged one are ignored
+        if !reply.1 {
+            self.command = Command::None;
+        }
+        reply
+    }
+
+    fn reset(&mut self) {
+        self.step = 0;
+        self.command = Command::None;
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        MemoryCard::flush(self)
+    }
+
+    fn card_data(&mut self) -> Option<&mut [u8]> {
+        Some(self.data_mut())
+    }
+
+    fn set_state_policy(&mut self, policy: StatePolicy) {
+        self.state_policy = policy;
+    }
+
+    fn state_conflict(&self) -> bool {
+        self.state_conflict
+    }
+
+    // The card image is part of the state, as the game's idea of what's on
+    // the card has to match it
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.data);
+        w.write_u8(self.flag);
+        w.write_u8(match self.command {
+            Command::Read => 0,
+            Command::Write => 1,
+            Command::GetId => 2,
+            Command::None => 3,
+        });
+        w.write_u32(self.step as u32);
+        w.write_u16(self.address);
+        w.write_u8(self.previous);
+        w.write_u8(self.checksum);
+        w.write_bytes(&self.buffer);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let data = r.read_bytes(CARD_SIZE)?;
+        self.state_conflict = data != &self.data[..];
+        if self.state_conflict && self.state_policy == StatePolicy::Refuse {
+            return Err(StateError::Invalid(
+                "memory card contents differ from the ones in the state",
+            ));
+        }
+        self.flag = r.read_u8()?;
+        self.command = match r.read_u8()? {
+            0 => Command::Read,
+            1 => Command::Write,
+            2 => Command::GetId,
+            3 => Command::None,
+            _ => return Err(StateError::Invalid("memory card command")),
+        };
+        self.step = r.read_u32()? as usize;
+        self.address = r.read_u16()?;
+        self.previous = r.read_u8()?;
+        self.checksum = r.read_u8()?;
+        r.read_into(&mut self.buffer)?;
+        if self.command == Command::Read && self.step > 9 && !self.sector_valid() {
+            return Err(StateError::Invalid("memory card sector"));
+        }
+        if self.state_conflict && self.state_policy == StatePolicy::Keep {
+            // The game sees a card swap and reads the directory again
+            self.flag = FLAG_FRESH;
+            self.command = Command::None;
+            self.step = 0;
+            return Ok(());
+        }
+        // Only the sectors that differ are written back
+        for (sector, (old, new)) in self
+            .data
+            .chunks_exact_mut(SECTOR_SIZE)
+            .zip(data.chunks_exact(SECTOR_SIZE))
+            .enumerate()
+        {
+            if old != new {
+                old.copy_from_slice(new);
+                self.dirty[sector] = true;
+            }
+        }
+        if self.policy == WritebackPolicy::Immediate {
+            let _ = self.flush();
+        }
+        Ok(())
+    }
+}
+
+// Format a card image: an empty directory and no broken sectors
+pub fn format(data: &mut [u8]) {
+    data.fill(0);
+    // Header
+    data[..2].copy_from_slice(b"MC");
+    // Directory frames: free, no next block
+    for frame in 1..16 {
+        let frame = &mut data[frame * SECTOR_SIZE..];
+        frame[0] = 0xa0;
+        frame[8..10].copy_from_slice(&[0xff, 0xff]);
+    }
+    // Broken sector list: no entries
+    for frame in 16..36 {
+        let frame = &mut data[frame * SECTOR_SIZE..];
+        frame[..4].copy_from_slice(&[0xff; 4]);
+        frame[8..10].copy_from_slice(&[0xff, 0xff]);
+    }
+    for frame in 0..36 {
+        let frame = &mut data[frame * SECTOR_SIZE..(frame + 1) * SECTOR_SIZE];
+        frame[SECTOR_SIZE - 1] = frame[..SECTOR_SIZE - 1].iter().fold(0, |a, b| a ^ b);
+    }
+    // Write test frame, a copy of the header
+    data.copy_within(..SECTOR_SIZE, 63 * SECTOR_SIZE);
+}
@@ -1,24 +1,1174 @@
+pub mod audio;
+pub mod autosave;
+pub mod bios;
+pub mod bios_trace;
+pub mod builder;
+mod bus;
+pub mod cartridge;
+pub mod cdrom;
+pub mod cheats;
 pub mod cpu;
+pub mod debug_dump;
+pub mod disc;
+pub mod divergence;
+pub mod dma;
+pub mod enhancements;
+pub mod error;
+pub mod exe;
+pub mod frame;
+pub mod gamedb;
+pub mod gpu;
+pub mod hle;
+pub mod irq;
+pub mod kernel_watch;
+pub mod mdec;
+pub mod memcard;
+pub mod movie;
+pub mod patches;
+pub mod ram_search;
+pub mod rewind;
+pub mod rollback;
+pub mod run_ahead;
+pub mod scheduler;
+mod sha1;
+pub mod sio;
+pub mod sio1;
+pub mod slots;
+pub mod snapshot;
+pub mod spu;
+pub mod state;
+pub mod sync;
+pub mod timers;
+pub mod tty;
 
+pub use builder::PsxBuilder;
+pub use error::Error;
+
+use scheduler::Event;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// The whole console. It's Send but not Sync: it can be moved to a thread of
+// its own, but only one thread uses it at a time. A debugger on another
+// thread goes through a Mutex, or asks the emulation thread over a channel.
+// Worker threads (SPU, MDEC, disc prefetch) belong to the instance that
+// started them, and no state is shared between instances.
 pub struct Psx {
     pub cpu: cpu::Cpu,
+    ram: Ram,
     scratchpad: ScratchPad,
     // FFFE0130h Cache Control (R/W)
     cache_control: u32,
+    // Mixed audio output waiting to be pulled by the frontend
+    audio: audio::SampleBuffer,
+    // WAV dump of the audio output, if active
+    audio_dump: Option<audio::AudioDump>,
+    spu: spu::Spu,
+    // Set while the SPU runs on its own thread, which then owns the SPU
+    spu_thread: Option<spu::SpuThread>,
+    bios: Option<bios::Bios>,
+    // Kernel emulated in place of the BIOS, when booted without one
+    hle: Option<hle::Hle>,
+    // Executable started in quick test mode, started again on reset
+    test_exe: Option<exe::Exe>,
+    // Log of kernel calls, if active
+    bios_trace: Option<bios_trace::BiosTrace>,
+    // Writes to the kernel area by the game, if watched
+    kernel_watch: Option<kernel_watch::KernelWatch>,
+    // Skip the BIOS intro, unless the game needs it
+    fast_boot: bool,
+    // User settings for games, on top of the game database's
+    game_overrides: gamedb::GameOverrides,
+    // Speed hacks chosen by the user, and those in effect for the game
+    enhancements: enhancements::Enhancements,
+    active_enhancements: enhancements::Enhancements,
+    // NTSC or PAL console, or following the disc
+    video_standard: gpu::VideoStandard,
+    cdrom: cdrom::CdRom,
+    dma: dma::Dma,
+    gpu: gpu::Gpu,
+    mdec: mdec::Mdec,
+    timers: timers::Timers,
+    sio: sio::Sio,
+    sio1: sio1::Sio1,
+    // Cheat cartridge on the parallel port, if any
+    cartridge: Option<cartridge::CheatCartridge>,
+    // Cheat codes applied at every vblank
+    cheats: cheats::Cheats,
+    // Widescreen, 60 fps and other patches known for games
+    patch_db: patches::PatchDb,
+    // Names of the patches to apply to the games having them
+    selected_patches: HashSet<String>,
+    // Patches of the game inserted, the selected ones enabled
+    patches: cheats::Cheats,
+    // Discs of a multi-disc game, when loaded from a playlist
+    playlist: Option<disc::Playlist>,
+    irq: irq::InterruptController,
+    // Debug output of the running program
+    tty: tty::Tty,
+    scheduler: scheduler::Scheduler,
+    // Audio/video synchronization with the host
+    sync: sync::Sync,
+    // Vblanks since power on
+    frames: u64,
+    // Runs only depend on the inputs, not on host timing
+    deterministic: bool,
+    // Input movie being recorded or played
+    movie: Option<movie::MovieSession>,
+    // Frames run ahead of the present for the video
+    run_ahead: run_ahead::RunAhead,
+    // States captured for going back in time, if enabled
+    rewind: Option<rewind::Rewind>,
+    // Periodic save states, if enabled
+    autosave: Option<autosave::AutoSave>,
+    // Picture saved with states, from the frontend
+    state_thumbnail: Option<snapshot::Thumbnail>,
+    // How save_state compresses states
+    state_compression: snapshot::Compression,
+    // What loading a state does with memory cards saved to since
+    card_state_policy: memcard::StatePolicy,
+    // Port and slot of the memory cards that differed from the last state
+    // loaded
+    card_state_conflicts: Vec<(usize, usize)>,
+    // Breakpoints and what run_until_event last reported
+    runner: frame::Runner,
 }
 
+// Fail the build if a field stops Psx from being Send, e.g. an Rc
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<Psx>();
+};
+
 impl Psx {
     pub fn new() -> Self {
-        Self {
+        let mut psx = Self {
             cpu: cpu::Cpu::new(),
+            ram: Ram::new(),
             scratchpad: ScratchPad::new(),
             cache_control: 0,
+            audio: audio::SampleBuffer::new(),
+            audio_dump: None,
+            spu: spu::Spu::new(),
+            spu_thread: None,
+            bios: None,
+            hle: None,
+            test_exe: None,
+            bios_trace: None,
+            kernel_watch: None,
+            fast_boot: false,
+            game_overrides: gamedb::GameOverrides::new(),
+            enhancements: enhancements::Enhancements::new(),
+            active_enhancements: enhancements::Enhancements::new(),
+            video_standard: gpu::VideoStandard::Auto,
+            cdrom: cdrom::CdRom::new(),
+            dma: dma::Dma::new(),
+            gpu: gpu::Gpu::new(),
+            mdec: mdec::Mdec::new(),
+            timers: timers::Timers::new(),
+            sio: sio::Sio::new(),
+            sio1: sio1::Sio1::new(),
+            cartridge: None,
+            cheats: cheats::Cheats::new(),
+            patch_db: patches::PatchDb::new(),
+            selected_patches: HashSet::new(),
+            patches: cheats::Cheats::new(),
+            playlist: None,
+            irq: irq::InterruptController::new(),
+            tty: tty::Tty::new(),
+            scheduler: scheduler::Scheduler::new(),
+            sync: sync::Sync::new(),
+            deterministic: false,
+            movie: None,
+            run_ahead: run_ahead::RunAhead::new(),
+            rewind: None,
+            autosave: None,
+            state_thumbnail: None,
+            state_compression: snapshot::Compression::None,
+            card_state_policy: memcard::StatePolicy::Keep,
+            card_state_conflicts: Vec::new(),
+            frames: 0,
+            runner: frame::Runner::new(),
+        };
+        psx.scheduler
+            .schedule(Event::SpuSample, spu::CYCLES_PER_SAMPLE);
+        gpu::init(&mut psx);
+        // A digital pad is plugged into port 1 to begin with
+        psx.set_controller(0, Some(Box::new(sio::DigitalPad::new())));
+        psx
+    }
+
+    // Press the reset button. The CPU restarts at the BIOS reset vector and
+    // the hardware goes back to its power on state, but RAM keeps its
+    // contents, which games that detect warm boots look at. Discs,
+    // controllers and memory cards stay in. Games with a reset combo jump to
+    // the BIOS themselves, this is for a frontend's reset hotkey.
+    //
+    // The HLE BIOS has no reset code, it boots the disc again instead.
+    pub fn soft_reset(&mut self) -> Result<(), Error> {
+        self.cpu = cpu::Cpu::new();
+        self.cache_control = 0;
+        self.irq = irq::InterruptController::new();
+        self.dma = dma::Dma::new();
+        for &event in [Event::Timer0, Event::Timer1, Event::Timer2, Event::Hblank].iter() {
+            self.scheduler.cancel(event);
+        }
+        self.timers = timers::Timers::new();
+        self.gpu = gpu::Gpu::new();
+        gpu::init(self);
+        self.scheduler.cancel(Event::MdecDecode);
+        self.mdec.reset();
+        spu::with(self, |spu| spu.reset());
+        cdrom::reset(self);
+        sio::reset(self);
+        sio1::reset(self);
+        if let Some(exe) = self.test_exe.clone() {
+            hle::boot_exe(self, &exe);
+        } else if self.hle.is_some() {
+            hle::boot(self).map_err(Error::Disc)?;
+        }
+        Ok(())
+    }
+
+    // Capture the machine state in memory, to go back to it with restore
+    pub fn snapshot(&mut self) -> snapshot::Snapshot {
+        let mut snapshot = snapshot::Snapshot::new();
+        snapshot::save(self, &mut snapshot);
+        snapshot
+    }
+
+    // Capture the machine state into an earlier snapshot, reusing its memory,
+    // as run-ahead does every frame
+    pub fn snapshot_into(&mut self, snapshot: &mut snapshot::Snapshot) {
+        snapshot::save(self, snapshot);
+    }
+
+    // Go back to a snapshot of this machine. Devices, the disc and settings
+    // stay as they are now. A snapshot that fails to load leaves the machine
+    // in an undefined state, which only happens with corrupted ones.
+    pub fn restore(&mut self, snapshot: &snapshot::Snapshot) -> Result<(), Error> {
+        snapshot::restore(self, snapshot)?;
+        Ok(())
+    }
+
+    // Write a save state, to resume from anywhere later with load_state.
+    // Like a snapshot it doesn't include the disc, which has to be the same
+    // one when loading. The state starts with a description, read with
+    // snapshot::read_info.
+    pub fn save_state<W: Write>(&mut self, mut writer: W) -> Result<(), Error> {
+        let snapshot = self.snapshot();
+        let info = snapshot::StateInfo {
+            serial: self
+                .game_info()
+                .map_or_else(String::new, |info| info.serial),
+            frames: self.frames,
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+            thumbnail: self.state_thumbnail.clone(),
+        };
+        snapshot::write_file(&mut writer, &snapshot, self.state_compression, &info)
+            .map_err(Error::Io)?;
+        writer.flush().map_err(Error::Io)
+    }
+
+    // Picture saved with the states written from now on. The core doesn't
+    // draw, so frontends pass their latest screenshot before saving.
+    pub fn set_state_thumbnail(&mut self, thumbnail: Option<snapshot::Thumbnail>) {
+        self.state_thumbnail = thumbnail;
+    }
+
+    // Capture states while running to go back to with rewind, or stop.
+    // Frontends offering hold-to-rewind call rewind every frame the button
+    // is held.
+    pub fn set_rewind(&mut self, rewind: Option<rewind::Rewind>) {
+        self.rewind = rewind;
+    }
+
+    // Go back about `frames` frames, to the newest captured state at or
+    // before then. Returns how many frames were gone back, 0 once the
+    // oldest state kept is reached or without rewind.
+    pub fn rewind(&mut self, frames: u64) -> Result<u64, Error> {
+        rewind::rewind(self, frames)
+    }
+
+    // States from before a boot or a loaded state don't lead to the present
+    fn clear_rewind(&mut self) {
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.clear();
+        }
+    }
+
+    // Save states on their own every so often, or stop
+    pub fn set_autosave(&mut self, autosave: Option<autosave::AutoSave>) {
+        self.autosave = autosave;
+    }
+
+    // Auto-save now. Frontends call this as they exit, along with
+    // flush_memory_cards. Returns the file written, None without auto-save.
+    pub fn autosave_now(&mut self) -> Result<Option<PathBuf>, Error> {
+        autosave::save(self)
+    }
+
+    // Auto-saves of the running game, latest first
+    pub fn autosaves(&self) -> Result<Vec<slots::SlotInfo>, Error> {
+        match self.autosave.as_ref() {
+            Some(autosave) => autosave.list(&slots::serial(self)?),
+            None => Ok(Vec::new()),
         }
     }
 
+    // Load an auto-save, `index` places from the latest as listed by
+    // autosaves
+    pub fn load_autosave(&mut self, index: usize) -> Result<(), Error> {
+        autosave::load(self, index)
+    }
+
+    // Why the last periodic auto-save failed, if it did
+    pub fn take_autosave_error(&mut self) -> Option<Error> {
+        self.autosave
+            .as_mut()
+            .and_then(|autosave| autosave.take_error())
+    }
+
+    // Compress the save states written from now on. Loading takes states
+    // compressed or not.
+    pub fn set_state_compression(&mut self, compression: snapshot::Compression) {
+        self.state_compression = compression;
+    }
+
+    // What loading a state does with memory cards whose contents changed
+    // since it was saved: keep them by default, so saves made after the
+    // state aren't lost. Going back in time with rewind, run-ahead or
+    // rollback, and loading states during a movie, always restore them.
+    pub fn set_memory_card_state_policy(&mut self, policy: memcard::StatePolicy) {
+        self.card_state_policy = policy;
+    }
+
+    // Port and slot of the memory cards whose contents differed from the
+    // ones in the last state loaded, whether it loaded or was refused
+    pub fn memory_card_state_conflicts(&self) -> &[(usize, usize)] {
+        &self.card_state_conflicts
+    }
+
+    // Resume from a save state. A state that fails to load leaves the
+    // machine as it was.
+    pub fn load_state<R: Read>(&mut self, mut reader: R) -> Result<(), Error> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).map_err(Error::Io)?;
+        let backup = self.snapshot();
+        // Movies need the cards as they were to replay the same
+        let policy = match self.movie {
+            Some(_) => memcard::StatePolicy::Restore,
+            None => self.card_state_policy,
+        };
+        self.sio.set_card_state_policy(policy);
+        let result = snapshot::restore(self, &snapshot::Snapshot::from_bytes(data));
+        self.card_state_conflicts = self.sio.card_state_conflicts();
+        // Snapshots restored to go back in time get the cards as they were
+        self.sio
+            .set_card_state_policy(memcard::StatePolicy::Restore);
+        if let Err(e) = result {
+            snapshot::restore(self, &backup)
+                .map_err(|e| Error::Internal(format!("can't roll back a failed load: {}", e)))?;
+            if let (memcard::StatePolicy::Refuse, Some(&(port, slot))) =
+                (policy, self.card_state_conflicts.first())
+            {
+                return Err(Error::Config(format!(
+                    "memory card in port {} slot {} changed since the state was saved",
+                    port + 1,
+                    (b'A' + slot as u8) as char
+                )));
+            }
+            return Err(e.into());
+        }
+        if let Err(e) = movie::state_loaded(self) {
+            snapshot::restore(self, &backup)
+                .map_err(|e| Error::Internal(format!("can't roll back a failed load: {}", e)))?;
+            return Err(e);
+        }
+        self.clear_rewind();
+        Ok(())
+    }
+
+    // Advance the system clock by `cycles`, running any events that fall due
+    pub fn tick(&mut self, cycles: u64) {
+        let target = self.scheduler.now() + cycles;
+        while let Some(event) = self.scheduler.pop_until(target) {
+            self.handle_event(event);
+        }
+        self.scheduler.advance_to(target);
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::SpuSample => spu::clock(self),
+            Event::CdromCommand
+            | Event::CdromComplete
+            | Event::CdromSector
+            | Event::CdromDeliver => cdrom::handle_event(self, event),
+            Event::Timer0 | Event::Timer1 | Event::Timer2 => timers::handle_event(self, event),
+            Event::VblankStart | Event::VblankEnd | Event::Hblank => gpu::handle_event(self, event),
+            Event::Sio0Transfer | Event::Sio0Ack | Event::Lightpen => {
+                sio::handle_event(self, event)
+            }
+            Event::Sio1Transfer | Event::Sio1Poll => sio1::handle_event(self, event),
+            Event::MdecDecode => mdec::handle_event(self),
+        }
+    }
+
+    // Frames completed since power on, which numbers frames for movies and
+    // netplay
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    // Main RAM, for tools comparing or searching the game's memory
+    pub fn ram(&self) -> &[u8] {
+        &self.ram.dat
+    }
+
+    // Machine state as readable JSON, with the bytes of `ranges` of the
+    // address space, for bug reports. The machine isn't changed.
+    pub fn debug_dump(&mut self, ranges: &[std::ops::Range<u32>]) -> Result<String, Error> {
+        debug_dump::dump(self, ranges)
+    }
+
     pub fn code_cache_enabled(&self) -> bool {
         self.cache_control & 0x800 != 0
     }
+
+    // Select the IDCT the MDEC decodes video with
+    pub fn set_mdec_idct(&mut self, idct: mdec::Idct) {
+        self.mdec.set_idct(idct);
+    }
+
+    // Decode video on a worker thread. Emulation only waits for it when a
+    // macroblock's decoding time is up before the thread is done with it.
+    pub fn set_mdec_threaded(&mut self, threaded: bool) {
+        self.mdec.set_threaded(threaded);
+    }
+
+    // Select how emulation speed is tied to the host
+    pub fn set_sync_mode(&mut self, mode: sync::SyncMode) {
+        self.sync.set_mode(mode);
+    }
+
+    // Host audio rate that take_audio_samples gives output at in video- and
+    // audio-driven sync. Free-running sync gives the SPU's 44100Hz.
+    pub fn set_host_audio_rate(&mut self, rate: u32) {
+        self.sync.set_host_rate(rate);
+    }
+
+    // Run for one host frame. Returns the number of guest frames completed,
+    // which in audio-driven sync may be zero (show the previous frame again).
+    pub fn run_host_frame(&mut self, host: sync::HostStatus) -> u32 {
+        sync::run(self, host)
+    }
+
+    // Run the CPU and peripherals until the next vblank, for frontends
+    // driving emulation one frame at a time. With run-ahead the video is
+    // that of a frame further.
+    pub fn run_frame(&mut self) -> frame::FrameOutput {
+        run_ahead::run_frame(self)
+    }
+
+    // Show frames `frames` ahead of the present in run_frame, cutting input
+    // lag by as many frames, or 0 to stop. Each frame ahead costs a frame
+    // of emulation, and a game only gets as many frames as it has lag.
+    pub fn set_run_ahead(&mut self, frames: u32) {
+        self.run_ahead.set_frames(frames);
+    }
+
+    // Why run-ahead was turned off, if going back to the present failed.
+    // The machine is then left on the last frame run ahead.
+    pub fn take_run_ahead_error(&mut self) -> Option<Error> {
+        self.run_ahead.take_error()
+    }
+
+    // Run until a frame completes or something else the frontend may want
+    // to react to happens, such as a breakpoint or a line of TTY output
+    pub fn run_until_event(&mut self) -> frame::EmulationEvent {
+        frame::run_until_event(self)
+    }
+
+    // Stop run_until_event before the CPU runs the instruction at `pc`
+    pub fn add_breakpoint(&mut self, pc: u32) {
+        self.runner.add_breakpoint(pc);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: u32) {
+        self.runner.remove_breakpoint(pc);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.runner.clear_breakpoints();
+    }
+
+    // Load a PS-X EXE into RAM and start it, as the BIOS shell would, for
+    // homebrew and test programs. With `boot_bios` the BIOS would first be
+    // run up to the point it starts the shell, so the kernel is set up, but
+    // that needs the CPU to execute code and is refused for now.
+    pub fn load_exe<P: AsRef<Path>>(&mut self, path: P, boot_bios: bool) -> Result<(), Error> {
+        let exe = exe::Exe::open(path).map_err(Error::Io)?;
+        if boot_bios {
+            if self.bios.is_none() {
+                return Err(Error::Config(
+                    "booting the BIOS before an EXE needs a BIOS image".into(),
+                ));
+            }
+            return Err(Error::Config(
+                "booting the BIOS before an EXE needs the CPU to execute code".into(),
+            ));
+        }
+        self.sideload_exe(&exe);
+        self.test_exe = None;
+        self.clear_rewind();
+        Ok(())
+    }
+
+    // Copy an EXE's text to RAM, clear its fill area and point the CPU at
+    // its entry with its gp and stack
+    pub fn sideload_exe(&mut self, exe: &exe::Exe) {
+        for (i, &byte) in exe.text.iter().enumerate() {
+            let addr = map::mask(exe.load_address.wrapping_add(i as u32));
+            self.ram.store(addr, byte);
+        }
+        for i in 0..exe.fill_size {
+            let addr = map::mask(exe.fill_address.wrapping_add(i));
+            self.ram.store(addr, 0u8);
+        }
+        let cpu = &mut self.cpu;
+        cpu.current_pc = exe.pc;
+        cpu.pc = exe.pc;
+        cpu.next_pc = exe.pc.wrapping_add(4);
+        cpu.delayed_load = None;
+        cpu.regs[28] = exe.gp;
+        let sp = exe.sp.unwrap_or(exe::DEFAULT_SP);
+        cpu.regs[29] = sp;
+        cpu.regs[30] = sp;
+    }
+
+    // Put a disc in the CD-ROM drive
+    pub fn insert_disc(&mut self, disc: Box<dyn disc::Disc>) {
+        self.playlist = None;
+        self.cdrom.insert_disc(disc);
+        self.update_game_settings();
+        self.update_video_standard();
+    }
+
+    // Insert `disc` and boot it. Without a BIOS image the HLE BIOS sets up
+    // the kernel and starts the executable SYSTEM.CNF names. With one the
+    // console restarts and the BIOS loads it, right after setting up the
+    // kernel with fast boot, after the intro without.
+    pub fn boot_disc(&mut self, disc: Box<dyn disc::Disc>) -> Result<(), Error> {
+        self.insert_disc(disc);
+        self.test_exe = None;
+        self.clear_rewind();
+        match self.bios {
+            Some(_) => self.soft_reset(),
+            None => hle::boot(self).map_err(Error::Disc),
+        }
+    }
+
+    // Open the CD-ROM lid, as when a game asks to change discs
+    pub fn open_lid(&mut self) {
+        cdrom::open_lid(self);
+    }
+
+    // Replace the disc in the drive, returning the previous one. The lid
+    // should be open, games only notice the new disc once it's closed.
+    pub fn swap_disc(&mut self, disc: Option<Box<dyn disc::Disc>>) -> Option<Box<dyn disc::Disc>> {
+        cdrom::swap_disc(self, disc)
+    }
+
+    pub fn close_lid(&mut self) {
+        cdrom::close_lid(self);
+    }
+
+    pub fn lid_open(&self) -> bool {
+        self.cdrom.lid_open()
+    }
+
+    // Load a multi-disc game from an .m3u playlist and insert its first disc
+    pub fn load_playlist<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        let mut playlist = disc::Playlist::open(path).map_err(Error::Disc)?;
+        let disc = playlist.open_disc(0).map_err(Error::Disc)?;
+        self.insert_disc(disc);
+        self.playlist = Some(playlist);
+        Ok(())
+    }
+
+    // Paths of the discs in the loaded playlist
+    pub fn playlist(&self) -> &[PathBuf] {
+        self.playlist
+            .as_ref()
+            .map_or(&[], |playlist| playlist.discs())
+    }
+
+    // Index in the playlist of the disc in the drive
+    pub fn current_disc(&self) -> Option<usize> {
+        self.playlist.as_ref().map(|playlist| playlist.current())
+    }
+
+    // Change to disc `index` of the playlist, opening and closing the lid
+    pub fn select_disc(&mut self, index: usize) -> Result<(), Error> {
+        let playlist = self
+            .playlist
+            .as_mut()
+            .ok_or_else(|| Error::Config("no playlist loaded".into()))?;
+        let disc = playlist.open_disc(index).map_err(Error::Disc)?;
+        self.open_lid();
+        self.swap_disc(Some(disc));
+        self.close_lid();
+        Ok(())
+    }
+
+    // Change to the next disc of the playlist, wrapping around after the last
+    pub fn next_disc(&mut self) -> Result<(), Error> {
+        let count = self.playlist().len().max(1);
+        let index = self.current_disc().map_or(0, |index| (index + 1) % count);
+        self.select_disc(index)
+    }
+
+    // Change to the previous disc of the playlist, wrapping around before the
+    // first
+    pub fn previous_disc(&mut self) -> Result<(), Error> {
+        let count = self.playlist().len().max(1);
+        let index = self
+            .current_disc()
+            .map_or(0, |index| (index + count - 1) % count);
+        self.select_disc(index)
+    }
+
+    // Hash the tracks of the inserted disc and look them up in a Redump
+    // DAT, to catch bad dumps. Reads the whole disc.
+    pub fn verify_disc(&mut self, dat: &disc::Dat) -> Result<disc::Verification, Error> {
+        let disc = self
+            .cdrom
+            .disc_mut()
+            .ok_or_else(|| Error::Config("no disc inserted".into()))?;
+        let hashes = disc::hash_tracks(disc).map_err(Error::Disc)?;
+        Ok(dat.verify(&hashes))
+    }
+
+    // Verify the EDC of each data sector read, failing the read like a
+    // scratched disc does when it doesn't match
+    pub fn set_edc_check(&mut self, check: bool) {
+        self.cdrom.set_edc_check(check);
+    }
+
+    // Region of the console, which decides the discs it accepts
+    pub fn set_console_region(&mut self, region: disc::Region) {
+        self.cdrom.set_console_region(region);
+    }
+
+    // Emulate a modchip: discs that are unlicensed or from another region
+    // are reported as licensed for the console's region
+    pub fn set_modchip(&mut self, modchip: bool) {
+        self.cdrom.set_modchip(modchip);
+    }
+
+    // Serial, title and known settings of the game on the inserted disc
+    pub fn game_info(&self) -> Option<gamedb::GameInfo> {
+        self.cdrom.system_cnf().map(|cnf| {
+            let mut info = gamedb::GameInfo::from_system_cnf(cnf);
+            info.settings = self.game_overrides.apply(&info.serial, info.settings);
+            info
+        })
+    }
+
+    // Replace the user's settings for games, which take precedence over the
+    // game database's, and apply them to the inserted disc
+    pub fn set_game_overrides(&mut self, overrides: gamedb::GameOverrides) {
+        self.game_overrides = overrides;
+        self.update_game_settings();
+    }
+
+    pub fn game_overrides(&self) -> &gamedb::GameOverrides {
+        &self.game_overrides
+    }
+
+    // Load the user's settings for games from an override file, see
+    // gamedb::GameOverrides
+    pub fn load_game_overrides<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        self.set_game_overrides(gamedb::GameOverrides::open(path)?);
+        Ok(())
+    }
+
+    // Turn speed hacks on or off. Games that need accurate timing, per the
+    // game database or the user's overrides, run without them.
+    pub fn set_enhancements(&mut self, enhancements: enhancements::Enhancements) {
+        self.enhancements = enhancements;
+        self.update_game_settings();
+    }
+
+    // The speed hacks chosen with set_enhancements
+    pub fn enhancements(&self) -> enhancements::Enhancements {
+        self.enhancements
+    }
+
+    // The speed hacks in effect for the game inserted
+    pub fn active_enhancements(&self) -> enhancements::Enhancements {
+        self.active_enhancements
+    }
+
+    // Apply the settings of the game on the inserted disc
+    fn update_game_settings(&mut self) {
+        let settings = self
+            .game_info()
+            .map(|info| info.settings)
+            .unwrap_or_default();
+        self.run_ahead.set_blocked(settings.no_run_ahead);
+        self.active_enhancements = self.enhancements.for_game(&settings);
+        self.cdrom.set_zero_seek(self.active_enhancements.zero_seek);
+        self.update_fast_boot();
+        patches::update(self);
+    }
+
+    // Table of contents of the inserted disc, for track lists and CD player
+    // style interfaces
+    pub fn toc(&self) -> Option<&disc::Toc> {
+        self.cdrom.disc().map(|disc| disc.toc())
+    }
+
+    // Region of the inserted disc, if it could be detected
+    pub fn disc_region(&self) -> Option<disc::Region> {
+        self.cdrom.region()
+    }
+
+    // Map the BIOS image at `path`, which must be a 512 KB dump
+    pub fn load_bios<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        self.bios = Some(bios::Bios::open(path).map_err(Error::Bios)?);
+        self.hle = None;
+        self.update_fast_boot();
+        self.update_video_standard();
+        Ok(())
+    }
+
+    // Boot the inserted disc with the HLE BIOS, which implements the kernel
+    // calls natively, for when no BIOS image is configured. A cartridge
+    // with a bootable ROM is started instead, as the BIOS would.
+    pub fn boot_hle(&mut self) -> Result<(), Error> {
+        if self.bios.is_some() {
+            return Err(Error::Config(
+                "the HLE BIOS can't be used with a BIOS image".into(),
+            ));
+        }
+        self.test_exe = None;
+        self.clear_rewind();
+        hle::boot(self).map_err(Error::Disc)
+    }
+
+    // Quick test mode: start a bare-metal executable on the HLE kernel, with
+    // no BIOS and no disc needed. The kernel structures programs look for
+    // are set up, the executable's gp and stack are taken from its header,
+    // and reset starts it again. For tests and small homebrew programs.
+    pub fn boot_exe(&mut self, exe: &exe::Exe) -> Result<(), Error> {
+        if self.bios.is_some() {
+            return Err(Error::Config(
+                "quick test mode can't be used with a BIOS image".into(),
+            ));
+        }
+        hle::boot_exe(self, exe);
+        self.test_exe = Some(exe.clone());
+        self.clear_rewind();
+        Ok(())
+    }
+
+    pub fn hle_active(&self) -> bool {
+        self.hle.is_some()
+    }
+
+    // Pass the characters printed by the program, through BIOS putchar or
+    // the dev kit DUART, to `sink`
+    pub fn set_tty_sink(&mut self, sink: Option<tty::TtySink>) {
+        self.tty.set_sink(sink);
+    }
+
+    // Watch the kernel area, 0h-FFFFh, for writes by code outside the
+    // kernel, or stop watching. Start once the game is running.
+    pub fn set_kernel_watch(&mut self, watch: bool) {
+        self.kernel_watch = match watch {
+            true => Some(kernel_watch::KernelWatch::new()),
+            false => None,
+        };
+    }
+
+    // Kernel area writes seen since the last call
+    pub fn take_kernel_writes(&mut self) -> Vec<kernel_watch::KernelWrite> {
+        self.kernel_watch
+            .as_mut()
+            .map_or_else(Vec::new, |watch| watch.take_writes())
+    }
+
+    pub fn bios_info(&self) -> Option<bios::BiosInfo> {
+        self.bios.as_ref().and_then(|bios| bios.info())
+    }
+
+    // How the loaded BIOS compares to known good dumps, so frontends can warn
+    // about modified or bad ones
+    pub fn bios_check(&self) -> Option<bios::BiosCheck> {
+        self.bios.as_ref().map(|bios| bios.check())
+    }
+
+    // Patch the BIOS shell to jump straight into the disc's executable once
+    // the kernel is set up, skipping the logo and jingle. Games known to need
+    // the full boot are left alone.
+    pub fn set_fast_boot(&mut self, fast_boot: bool) {
+        self.fast_boot = fast_boot;
+        self.update_fast_boot();
+    }
+
+    fn update_fast_boot(&mut self) {
+        let full_boot = self.game_info().is_some_and(|info| info.settings.full_boot);
+        if let Some(bios) = self.bios.as_mut() {
+            bios.set_fast_boot(self.fast_boot && !full_boot);
+        }
+    }
+
+    // Make the console NTSC or PAL, which changes the GPU clock and with it
+    // the frame rate and the timers counting dots and hblanks. In the
+    // automatic mode it follows the region of the disc, or the BIOS's.
+    pub fn set_video_standard(&mut self, standard: gpu::VideoStandard) {
+        self.video_standard = standard;
+        self.update_video_standard();
+    }
+
+    fn update_video_standard(&mut self) {
+        let region = match self.video_standard {
+            gpu::VideoStandard::Auto => self
+                .disc_region()
+                .or_else(|| self.bios_info().map(|info| info.region)),
+            gpu::VideoStandard::Ntsc => None,
+            gpu::VideoStandard::Pal => Some(disc::Region::Pal),
+        };
+        gpu::set_pal_console(self, region == Some(disc::Region::Pal));
+    }
+
+    // Frames per second in the current video mode
+    pub fn refresh_rate(&self) -> f64 {
+        self.gpu.refresh_rate()
+    }
+
+    // Find a BIOS in `dir` matching the region of the inserted disc
+    pub fn find_matching_bios<P: AsRef<Path>>(&self, dir: P) -> Result<Option<PathBuf>, Error> {
+        match self.disc_region() {
+            Some(region) => bios::find_bios(dir, region).map_err(Error::Bios),
+            None => Ok(None),
+        }
+    }
+
+    // Plug a controller into port `port` (0 or 1), or unplug it with None
+    pub fn set_controller(&mut self, port: usize, device: Option<Box<dyn sio::Device>>) {
+        self.sio.set_controller(port, 0, device);
+    }
+
+    // Set the buttons held on the controller in port `port`, read by the game
+    // the next time it polls the controller. Ignored while a movie plays.
+    pub fn set_input(&mut self, port: usize, input: &sio::InputState) {
+        if movie::set_input(self, port, input) {
+            self.sio.set_input(port, 0, input);
+        }
+    }
+
+    // Insert a memory card in slot `port` (0 or 1), or remove it with None
+    pub fn set_memory_card(&mut self, port: usize, device: Option<Box<dyn sio::Device>>) {
+        movie::set_memory_card(self, port, device.is_some());
+        self.sio.set_memory_card(port, 0, device);
+    }
+
+    // Deterministic mode: the same inputs on the same frames always give the
+    // same run, down to the audio of each frame, as movies and netplay need.
+    // Frames wait for the SPU thread to produce their audio. There's no
+    // clock or randomness from the host otherwise. Link cable traffic comes
+    // from outside and isn't covered.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    // Start recording an input movie, from a save state of the current
+    // state, or at power on, which has to be before the first frame runs.
+    // The inputs set with set_input and memory cards put in and taken out
+    // with set_memory_card are recorded.
+    pub fn start_recording(&mut self, from_state: bool) -> Result<(), Error> {
+        movie::record(self, from_state)?;
+        self.clear_rewind();
+        Ok(())
+    }
+
+    // Play an input movie from its start, loading its save state, or on the
+    // freshly booted machine for one starting at power on. Loading a state
+    // while playing continues from there.
+    pub fn play_movie(&mut self, movie: movie::Movie) -> Result<(), Error> {
+        movie::play(self, movie)?;
+        self.clear_rewind();
+        Ok(())
+    }
+
+    // Switch a movie being played to recording from the current frame, as
+    // after loading a state to redo the rest of a run
+    pub fn resume_recording(&mut self) -> Result<(), Error> {
+        movie::resume_recording(self)
+    }
+
+    // Stop recording or playing, returning the movie to save
+    pub fn stop_movie(&mut self) -> Option<movie::Movie> {
+        movie::stop(self)
+    }
+
+    pub fn movie_status(&self) -> Option<movie::MovieStatus> {
+        self.movie
+            .as_ref()
+            .map(|session| session.status(self.frames))
+    }
+
+    // Write the changes made to memory cards to their files, for cards that
+    // aren't written back as soon as they change
+    pub fn flush_memory_cards(&mut self) -> io::Result<()> {
+        self.sio.flush_memory_cards()
+    }
+
+    // Plug a multitap into port `port` or remove it. The controller and
+    // memory card of the port become those of slot A.
+    pub fn set_multitap(&mut self, port: usize, multitap: bool) {
+        self.sio.set_multitap(port, multitap);
+    }
+
+    // Same as set_controller, set_input and set_memory_card for slot `slot`
+    // (0-3 for A-D) of a multitap
+    pub fn set_tap_controller(
+        &mut self,
+        port: usize,
+        slot: usize,
+        device: Option<Box<dyn sio::Device>>,
+    ) {
+        self.sio.set_controller(port, slot, device);
+    }
+
+    // Slot A is the port's controller, recorded in movies as set_input's.
+    // Movies don't cover slots B-D, whose input is neither recorded nor
+    // replaced during playback.
+    pub fn set_tap_input(&mut self, port: usize, slot: usize, input: &sio::InputState) {
+        match slot {
+            0 => self.set_input(port, input),
+            _ => self.sio.set_input(port, slot, input),
+        }
+    }
+
+    pub fn set_tap_memory_card(
+        &mut self,
+        port: usize,
+        slot: usize,
+        device: Option<Box<dyn sio::Device>>,
+    ) {
+        self.sio.set_memory_card(port, slot, device);
+    }
+
+    // Plug a link cable into the serial port, or unplug it with None. A
+    // failing transport counts as the cable being pulled out.
+    pub fn connect_link(&mut self, link: Option<Box<dyn sio1::LinkTransport>>) {
+        sio1::connect(self, link);
+    }
+
+    // Plug a cheat cartridge into the parallel port, or remove it with None.
+    // Returns the cartridge that was plugged in before.
+    pub fn set_cartridge(
+        &mut self,
+        cartridge: Option<cartridge::CheatCartridge>,
+    ) -> Option<cartridge::CheatCartridge> {
+        std::mem::replace(&mut self.cartridge, cartridge)
+    }
+
+    // Flip the switch of the cheat cartridge, if one is plugged in
+    pub fn set_cartridge_switch(&mut self, on: bool) {
+        if let Some(cartridge) = self.cartridge.as_mut() {
+            cartridge.set_switch(on);
+        }
+    }
+
+    // Replace the cheat codes applied at every vblank. Returns the previous
+    // ones.
+    pub fn set_cheats(&mut self, cheats: cheats::Cheats) -> cheats::Cheats {
+        std::mem::replace(&mut self.cheats, cheats)
+    }
+
+    pub fn cheats(&self) -> &cheats::Cheats {
+        &self.cheats
+    }
+
+    // Turn cheat `index` on or off
+    pub fn set_cheat_enabled(&mut self, index: usize, enabled: bool) {
+        self.cheats.set_enabled(index, enabled);
+    }
+
+    // Add a cheat, enabled. Returns its index.
+    pub fn add_cheat(&mut self, cheat: cheats::Cheat) -> usize {
+        let index = self.cheats.add(cheat);
+        self.cheats.set_enabled(index, true);
+        index
+    }
+
+    pub fn remove_cheat(&mut self, index: usize) -> cheats::Cheat {
+        self.cheats.remove(index)
+    }
+
+    // Replace the database of game patches, see patches::PatchDb
+    pub fn set_patch_db(&mut self, db: patches::PatchDb) {
+        self.patch_db = db;
+        patches::update(self);
+    }
+
+    pub fn patch_db(&self) -> &patches::PatchDb {
+        &self.patch_db
+    }
+
+    // Patches known for the game inserted, enabled if selected
+    pub fn patches(&self) -> &[cheats::Cheat] {
+        self.patches.cheats()
+    }
+
+    // Apply the patch named `name`, e.g. "Widescreen 16:9", to the game
+    // inserted and to later ones having a patch of that name, or stop
+    pub fn select_patch(&mut self, name: &str, selected: bool) {
+        patches::select(self, name, selected);
+    }
+
+    // Run `f` on the SPU, e.g. to inspect voices or change debug settings
+    pub fn with_spu<R, F>(&mut self, f: F) -> R
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut spu::Spu) -> R + Send + 'static,
+    {
+        spu::with(self, f)
+    }
+
+    // Run the SPU on its own thread so mixing doesn't count against the
+    // emulation thread's frame budget
+    pub fn set_spu_threaded(&mut self, threaded: bool) {
+        spu::set_threaded(self, threaded);
+    }
+
+    // Number of interleaved stereo samples ready to be taken
+    pub fn audio_samples_available(&self) -> usize {
+        sync::audio_available(self)
+    }
+
+    // Copy pending interleaved stereo samples into `out`, for frontends that
+    // drive audio output themselves. They're at the rate set with
+    // set_host_audio_rate in video- and audio-driven sync, and at 44100Hz
+    // in free-running sync. Returns the number of samples written, always a
+    // multiple of two.
+    pub fn take_audio_samples(&mut self, out: &mut [i16]) -> usize {
+        sync::take_audio(self, out)
+    }
+
+    // Start dumping the audio output to a WAV file, starting with the next
+    // generated sample. With `stems` each voice is also written to its own
+    // file next to `path`. Any dump in progress is finished first.
+    pub fn start_audio_dump<P: AsRef<Path>>(&mut self, path: P, stems: bool) -> io::Result<()> {
+        self.stop_audio_dump()?;
+        // Samples the SPU thread made before now stay out of the dump
+        spu::drain(self);
+        self.audio_dump = Some(audio::AudioDump::create(path, stems)?);
+        Ok(())
+    }
+
+    // Stop dumping after the last generated sample and finalize the files
+    pub fn stop_audio_dump(&mut self) -> io::Result<()> {
+        if self.audio_dump.is_some() {
+            spu::drain(self);
+        }
+        match self.audio_dump.take() {
+            Some(dump) => dump.finish(),
+            None => Ok(()),
+        }
+    }
+
+    // Log every CD-ROM command, response and interrupt to `path` as JSON
+    // lines, to debug games' use of the controller
+    pub fn start_cd_trace<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        self.cdrom.start_trace(Box::new(file))
+    }
+
+    // Same as start_cd_trace, writing to any output such as stderr
+    pub fn start_cd_trace_to(&mut self, out: Box<dyn Write + Send>) -> io::Result<()> {
+        self.cdrom.start_trace(out)
+    }
+
+    pub fn stop_cd_trace(&mut self) -> io::Result<()> {
+        self.cdrom.stop_trace()
+    }
+
+    // Log every A0h, B0h and C0h kernel call to `path` with its name and
+    // arguments, and its return value if `returns` is set, to find where
+    // a game hangs during boot
+    pub fn start_bios_trace<P: AsRef<Path>>(&mut self, path: P, returns: bool) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        self.start_bios_trace_to(Box::new(file), returns)
+    }
+
+    // Same as start_bios_trace, writing to any output such as stderr
+    pub fn start_bios_trace_to(
+        &mut self,
+        out: Box<dyn Write + Send>,
+        returns: bool,
+    ) -> io::Result<()> {
+        self.stop_bios_trace()?;
+        self.bios_trace = Some(bios_trace::BiosTrace::new(out, returns));
+        Ok(())
+    }
+
+    pub fn stop_bios_trace(&mut self) -> io::Result<()> {
+        match self.bios_trace.take() {
+            Some(trace) => trace.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for Psx {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Main RAM is 2 MB, mirrored over the first 8 MB
+const RAM_SIZE: usize = 2 * 1024 * 1024;
+
+struct Ram {
+    dat: Box<[u8]>,
+}
+
+impl Ram {
+    pub fn new() -> Self {
+        Self {
+            dat: vec![0u8; RAM_SIZE].into_boxed_slice(),
+        }
+    }
+
+    // Read a value from RAM with the given width
+    pub fn load<W: Addressable>(&self, offset: u32) -> W {
+        let mut val = 0u32;
+        for i in 0..W::WIDTH as usize {
+            let offset = (offset as usize + i) & (RAM_SIZE - 1);
+            val |= (self.dat[offset] as u32) << (i * 8);
+        }
+        W::from_u32(val)
+    }
+
+    // Write a value to RAM with the given width
+    pub fn store<W: Addressable>(&mut self, offset: u32, val: W) {
+        let val = val.as_u32();
+        for i in 0..W::WIDTH as usize {
+            let offset = (offset as usize + i) & (RAM_SIZE - 1);
+            self.dat[offset] = (val >> (i * 8)) as u8;
+        }
+    }
 }
 
 // Scratchpad is 1 KB
@@ -37,20 +1187,20 @@ impl ScratchPad {
 
     // Read a value from the scratchpad with the given width
     pub fn load<W: Addressable>(&self, offset: u32) -> W {
-        let offset = (offset & 0x7fffff) as usize;
         let mut val = 0u32;
         for i in 0..W::WIDTH as usize {
-            val |= (self.dat[offset + i] as u32) << (i * 8);
+            let offset = (offset as usize + i) & (SCRATCHPAD_SIZE - 1);
+            val |= (self.dat[offset] as u32) << (i * 8);
         }
         W::from_u32(val)
     }
 
     // Write a value to the scratchpad with the given width
     pub fn store<W: Addressable>(&mut self, offset: u32, val: W) {
-        let offset = (offset & 0x7fffff) as usize;
         let val = val.as_u32();
         for i in 0..W::WIDTH as usize {
-            self.dat[offset + i] = (val >> (i * 8)) as u8;
+            let offset = (offset as usize + i) & (SCRATCHPAD_SIZE - 1);
+            self.dat[offset] = (val >> (i * 8)) as u8;
         }
     }
 }
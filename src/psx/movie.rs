@@ -0,0 +1,470 @@
+use super::sio::{Device, InputState};
+use super::state::{Savestate, StateError, StateReader, StateWriter};
+use super::{Error, Psx};
+
+use std::convert::TryInto;
+use std::io::{Read, Write};
+
+// Input movies, for tool-assisted runs: the input of every frame, replayed
+// to get the same run again. Saving and loading states while recording
+// rewrites the movie from the loaded point on, counted as rerecords.
+//
+// Movie files, all numbers little endian:
+//   8 bytes         "PSXMOVIE"
+//   u32             format version, 2
+//   u32             flags, bit 0 set when starting from a save state, clear
+//                   when starting at power on
+//   u32             rerecord count
+//   u16 + bytes     serial of the game, UTF-8, empty without a disc
+//   u32 + bytes     the save state the movie starts from, with flag bit 0
+//   u32             number of frames
+//   then for each frame
+//   u8              bit n set while slot n has a memory card in
+//   2 x 39 bytes    controller input of ports 1 and 2: u16 buttons, 16 u8
+//                   pressures, u8 left stick X, Y, right stick X, Y, u8
+//                   analog button, i32 pointer delta X, Y, u8 mouse left,
+//                   right, u8 pointer on screen, u16 pointer X, Y, u8 gun
+//                   buttons
+//   u32             number of checks, from version 2
+//   then for each check
+//   u32             frame it was made at the end of
+//   u64             hash of RAM, see ram_hash
+//
+// Controllers on multitaps aren't recorded.
+const MAGIC: &[u8; 8] = b"PSXMOVIE";
+const VERSION: u32 = 2;
+
+const FLAG_FROM_STATE: u32 = 1 << 0;
+
+// Frames between checks of the machine state recorded with the input, which
+// playback compares against to find where it went out of sync. The GPU
+// doesn't keep VRAM, so checks cover RAM only.
+const CHECK_INTERVAL: u64 = 60;
+
+// Input of one frame
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MovieFrame {
+    pub inputs: [InputState; 2],
+    pub memory_cards: [bool; 2],
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Movie {
+    // Serial of the game it was recorded with, e.g. "SLUS_012.34"
+    pub serial: String,
+    // Save state it starts from, None when it starts at power on
+    pub start: Option<Vec<u8>>,
+    pub rerecords: u32,
+    pub frames: Vec<MovieFrame>,
+    // RAM hashes recorded every CHECK_INTERVAL frames, by frame
+    pub checks: Vec<(u64, u64)>,
+}
+
+impl Movie {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> Result<Self, Error> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).map_err(Error::Io)?;
+        Self::parse(&data).map_err(|e| Error::Config(format!("invalid movie: {}", e)))
+    }
+
+    fn parse(data: &[u8]) -> Result<Self, StateError> {
+        let mut r = StateReader::new(data);
+        if r.read_bytes(MAGIC.len())? != MAGIC {
+            return Err(StateError::Invalid("not a movie"));
+        }
+        let version = r.read_u32()?;
+        if version == 0 || version > VERSION {
+            return Err(StateError::NewerVersion {
+                part: "movie format".into(),
+                version,
+            });
+        }
+        let flags = r.read_u32()?;
+        let rerecords = r.read_u32()?;
+        let len = r.read_u16()? as usize;
+        let serial = String::from_utf8_lossy(r.read_bytes(len)?).into_owned();
+        let start = match flags & FLAG_FROM_STATE {
+            0 => None,
+            _ => {
+                let len = r.read_u32()? as usize;
+                Some(r.read_bytes(len)?.to_vec())
+            }
+        };
+        let count = r.read_u32()? as usize;
+        let mut frames = Vec::with_capacity(count.min(r.remaining()));
+        for _ in 0..count {
+            let mut frame = MovieFrame::default();
+            let cards = r.read_u8()?;
+            for (i, card) in frame.memory_cards.iter_mut().enumerate() {
+                *card = cards & (1 << i) != 0;
+            }
+            for input in frame.inputs.iter_mut() {
+                input.load_state(&mut r)?;
+            }
+            frames.push(frame);
+        }
+        let mut checks = Vec::new();
+        if version >= 2 {
+            for _ in 0..r.read_u32()? {
+                checks.push((r.read_u32()? as u64, r.read_u64()?));
+            }
+        }
+        Ok(Self {
+            serial,
+            start,
+            rerecords,
+            frames,
+            checks,
+        })
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+        let mut w = StateWriter::new();
+        w.write_bytes(MAGIC);
+        w.write_u32(VERSION);
+        w.write_u32(match self.start {
+            Some(_) => FLAG_FROM_STATE,
+            None => 0,
+        });
+        w.write_u32(self.rerecords);
+        w.write_u16(self.serial.len() as u16);
+        w.write_bytes(self.serial.as_bytes());
+        if let Some(state) = self.start.as_ref() {
+            w.write_u32(state.len() as u32);
+            w.write_bytes(state);
+        }
+        w.write_u32(self.frames.len() as u32);
+        for frame in self.frames.iter() {
+            let cards = frame
+                .memory_cards
+                .iter()
+                .enumerate()
+                .fold(0, |cards, (i, &card)| cards | (card as u8) << i);
+            w.write_u8(cards);
+            for input in frame.inputs.iter() {
+                input.save_state(&mut w);
+            }
+        }
+        w.write_u32(self.checks.len() as u32);
+        for &(frame, hash) in self.checks.iter() {
+            w.write_u32(frame as u32);
+            w.write_u64(hash);
+        }
+        writer.write_all(&w.into_inner()).map_err(Error::Io)?;
+        writer.flush().map_err(Error::Io)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MovieMode {
+    Recording,
+    // Inputs come from the movie, the frontend's are ignored
+    Playing,
+    // Played to the end, the frontend's inputs are used again
+    Finished,
+}
+
+// Playback went out of sync with the recording: RAM matched at the end of
+// frame `last_match` and differed at the end of frame `frame`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Desync {
+    pub last_match: u64,
+    pub frame: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MovieStatus {
+    pub mode: MovieMode,
+    // Frames run since the start of the movie
+    pub frame: u64,
+    // Frames in the movie
+    pub length: u64,
+    pub rerecords: u32,
+    // First check playback failed, if any
+    pub desync: Option<Desync>,
+}
+
+// Movie being recorded or played
+pub struct MovieSession {
+    movie: Movie,
+    mode: MovieMode,
+    // Frame count of the machine when the movie starts
+    start_frame: u64,
+    // Input set by the frontend, as recorded
+    current: MovieFrame,
+    // Memory cards the movie took out, to put back in
+    removed: [Option<Box<dyn Device>>; 2],
+    // Last frame whose check passed during playback, and the first that
+    // failed
+    last_match: u64,
+    desync: Option<Desync>,
+}
+
+impl MovieSession {
+    pub fn status(&self, psx_frames: u64) -> MovieStatus {
+        MovieStatus {
+            mode: self.mode,
+            frame: psx_frames - self.start_frame,
+            length: self.movie.frames.len() as u64,
+            rerecords: self.movie.rerecords,
+            desync: self.desync,
+        }
+    }
+
+    // Forget the checks past frame `position`, which is recorded again
+    fn truncate(&mut self, position: u64) {
+        self.movie.frames.truncate(position as usize);
+        self.movie.checks.retain(|&(frame, _)| frame <= position);
+    }
+
+    // Start checking playback again from frame `position`
+    fn seek(&mut self, position: u64) {
+        self.last_match = position;
+        if self.desync.is_some_and(|desync| desync.frame > position) {
+            self.desync = None;
+        }
+    }
+}
+
+// Hash of main RAM, recorded in movies to check playback against. FNV-1a
+// over 64-bit words, the same on every host.
+pub fn ram_hash(psx: &Psx) -> u64 {
+    psx.ram()
+        .chunks_exact(8)
+        .fold(0xcbf29ce484222325, |hash, word| {
+            let word = u64::from_le_bytes(word.try_into().unwrap());
+            (hash ^ word).wrapping_mul(0x100000001b3)
+        })
+}
+
+fn memory_cards(psx: &Psx) -> [bool; 2] {
+    [psx.sio.has_memory_card(0, 0), psx.sio.has_memory_card(1, 0)]
+}
+
+// Start recording from now. A movie starting at power on has to start
+// before the first frame runs, one starting from a save state anywhere.
+pub fn record(psx: &mut Psx, from_state: bool) -> Result<(), Error> {
+    stop(psx);
+    let start = match from_state {
+        true => {
+            let mut state = Vec::new();
+            psx.save_state(&mut state)?;
+            Some(state)
+        }
+        false if psx.frames != 0 => {
+            return Err(Error::Config(
+                "a movie starting at power on has to start before the first frame".into(),
+            ))
+        }
+        false => None,
+    };
+    let movie = Movie {
+        serial: psx.game_info().map_or_else(String::new, |info| info.serial),
+        start,
+        rerecords: 0,
+        frames: Vec::new(),
+        checks: Vec::new(),
+    };
+    let current = MovieFrame {
+        inputs: Default::default(),
+        memory_cards: memory_cards(psx),
+    };
+    psx.movie = Some(MovieSession {
+        movie,
+        mode: MovieMode::Recording,
+        start_frame: psx.frames,
+        current,
+        removed: Default::default(),
+        last_match: 0,
+        desync: None,
+    });
+    Ok(())
+}
+
+// Play `movie` from its start: its save state is loaded, or for a movie
+// starting at power on the machine has to be freshly booted
+pub fn play(psx: &mut Psx, movie: Movie) -> Result<(), Error> {
+    stop(psx);
+    let serial = psx.game_info().map_or_else(String::new, |info| info.serial);
+    if serial != movie.serial {
+        return Err(Error::Config(format!(
+            "movie recorded with {}, not {}",
+            movie.serial, serial
+        )));
+    }
+    match movie.start.as_ref() {
+        Some(state) => psx.load_state(&state[..])?,
+        None if psx.frames != 0 => {
+            return Err(Error::Config(
+                "a movie starting at power on has to be played before the first frame".into(),
+            ))
+        }
+        None => {}
+    }
+    psx.movie = Some(MovieSession {
+        movie,
+        mode: MovieMode::Playing,
+        start_frame: psx.frames,
+        current: MovieFrame::default(),
+        removed: Default::default(),
+        last_match: 0,
+        desync: None,
+    });
+    apply(psx, 0);
+    Ok(())
+}
+
+// Record from the current frame on, replacing the rest of the movie
+pub fn resume_recording(psx: &mut Psx) -> Result<(), Error> {
+    let frames = psx.frames;
+    let cards = memory_cards(psx);
+    let session = psx
+        .movie
+        .as_mut()
+        .ok_or_else(|| Error::Config("no movie to record".into()))?;
+    let position = frames - session.start_frame;
+    if session.mode != MovieMode::Recording {
+        session.current = session
+            .movie
+            .frames
+            .get(position as usize)
+            .copied()
+            .unwrap_or_default();
+        session.current.memory_cards = cards;
+        session.movie.rerecords += 1;
+    }
+    session.truncate(position);
+    session.mode = MovieMode::Recording;
+    Ok(())
+}
+
+// Stop recording or playing, returning the movie. Memory cards taken out
+// by the movie go back in.
+pub fn stop(psx: &mut Psx) -> Option<Movie> {
+    let mut session = psx.movie.take()?;
+    for (port, card) in session.removed.iter_mut().enumerate() {
+        if let Some(card) = card.take() {
+            psx.sio.set_memory_card(port, 0, Some(card));
+        }
+    }
+    Some(session.movie)
+}
+
+// Controller input set by the frontend. Returns whether it reaches the
+// controller, which it doesn't while a movie plays.
+pub fn set_input(psx: &mut Psx, port: usize, input: &InputState) -> bool {
+    match psx.movie.as_mut() {
+        Some(session) if session.mode == MovieMode::Playing => false,
+        Some(session) => {
+            session.current.inputs[port] = *input;
+            true
+        }
+        None => true,
+    }
+}
+
+pub fn set_memory_card(psx: &mut Psx, port: usize, inserted: bool) {
+    if let Some(session) = psx.movie.as_mut() {
+        session.current.memory_cards[port] = inserted;
+        session.removed[port] = None;
+    }
+}
+
+// Record the frame that just ended, or check it and set up the input of the
+// next one
+pub fn frame(psx: &mut Psx) {
+    let (mode, position) = match psx.movie.as_ref() {
+        Some(session) => (session.mode, psx.frames - session.start_frame),
+        None => return,
+    };
+    let hash = match mode != MovieMode::Finished && position.is_multiple_of(CHECK_INTERVAL) {
+        true => Some(ram_hash(psx)),
+        false => None,
+    };
+    let session = psx.movie.as_mut().unwrap();
+    match mode {
+        MovieMode::Recording => {
+            session.truncate(position.saturating_sub(1));
+            session.movie.frames.push(session.current);
+            if let Some(hash) = hash {
+                session.movie.checks.push((position, hash));
+            }
+        }
+        MovieMode::Playing => {
+            let recorded = session
+                .movie
+                .checks
+                .iter()
+                .find(|&&(frame, _)| frame == position);
+            if let (Some(&(_, recorded)), Some(hash), None) = (recorded, hash, session.desync) {
+                match recorded == hash {
+                    true => session.last_match = position,
+                    false => {
+                        session.desync = Some(Desync {
+                            last_match: session.last_match,
+                            frame: position,
+                        })
+                    }
+                }
+            }
+            apply(psx, position as usize);
+        }
+        MovieMode::Finished => {}
+    }
+}
+
+// Continue the movie from a loaded state or after going back in time. A
+// recording is rewritten from there.
+pub fn state_loaded(psx: &mut Psx) -> Result<(), Error> {
+    let frames = psx.frames;
+    let session = match psx.movie.as_mut() {
+        Some(session) => session,
+        None => return Ok(()),
+    };
+    let position = match frames.checked_sub(session.start_frame) {
+        Some(position) if position as usize <= session.movie.frames.len() => position,
+        _ => return Err(Error::Config("the state is outside the movie".into())),
+    };
+    session.seek(position);
+    match session.mode {
+        MovieMode::Recording => {
+            session.truncate(position);
+            session.movie.rerecords += 1;
+            for (port, input) in session.current.inputs.iter().enumerate() {
+                psx.sio.set_input(port, 0, input);
+            }
+        }
+        MovieMode::Playing | MovieMode::Finished => {
+            session.mode = MovieMode::Playing;
+            apply(psx, position as usize);
+        }
+    }
+    Ok(())
+}
+
+// Give the controllers and memory card slots the input of frame `position`
+fn apply(psx: &mut Psx, position: usize) {
+    let session = psx.movie.as_mut().unwrap();
+    let frame = match session.movie.frames.get(position) {
+        Some(&frame) => frame,
+        None => {
+            session.mode = MovieMode::Finished;
+            return;
+        }
+    };
+    for port in 0..2 {
+        psx.sio.set_input(port, 0, &frame.inputs[port]);
+        let inserted = psx.sio.has_memory_card(port, 0);
+        if frame.memory_cards[port] && !inserted {
+            if let Some(card) = session.removed[port].take() {
+                psx.sio.set_memory_card(port, 0, Some(card));
+            }
+        } else if !frame.memory_cards[port] && inserted {
+            session.removed[port] = psx.sio.take_memory_card(port, 0);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use super::cheats::{Cheat, Cheats};
+use super::gamedb::normalize_serial;
+use super::{Error, Psx};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Game patches: widescreen hacks, 60 fps patches and the like, run by the
+// cheat engine for the game inserted, looked up by serial. No database is
+// built in, frontends load one with PatchDb::open. Patch database files
+// list each game's serial on a ":SERIAL" line, then its patches as
+// in cheat files:
+//   # Comment
+//   :SLUS-01234
+//   [Widescreen 16:9]
+//   80012345 0C00
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PatchDb {
+    // Patches by normalized serial
+    games: HashMap<String, Cheats>,
+}
+
+impl PatchDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut db = Self::new();
+        let mut game: Option<(String, String)> = None;
+        for line in text.lines().map(str::trim) {
+            if line.starts_with('#') {
+                continue;
+            }
+            if let Some(serial) = line.strip_prefix(':') {
+                db.push(game.take())?;
+                game = Some((normalize_serial(serial.trim()), String::new()));
+            } else if !line.is_empty() {
+                let patches = match game.as_mut() {
+                    Some((_, patches)) => patches,
+                    None => return Err(Error::Config("patch before a :serial line".into())),
+                };
+                patches.push_str(line);
+                patches.push('\n');
+            }
+        }
+        db.push(game)?;
+        Ok(db)
+    }
+
+    fn push(&mut self, game: Option<(String, String)>) -> Result<(), Error> {
+        if let Some((serial, patches)) = game {
+            for patch in Cheats::parse(&patches)?.cheats() {
+                self.add(&serial, patch.clone());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::parse(&fs::read_to_string(path).map_err(Error::Io)?)
+    }
+
+    // Add a patch for the game `serial`, replacing one with the same name
+    pub fn add(&mut self, serial: &str, patch: Cheat) {
+        let patches = self.games.entry(normalize_serial(serial)).or_default();
+        if let Some(index) = patches.cheats().iter().position(|p| p.name == patch.name) {
+            patches.remove(index);
+        }
+        patches.add(patch);
+    }
+
+    // Add the patches of `other`, which replace those with the same name
+    pub fn merge(&mut self, other: &PatchDb) {
+        for (serial, patches) in other.games.iter() {
+            for patch in patches.cheats() {
+                self.add(serial, patch.clone());
+            }
+        }
+    }
+
+    // Patches of the game `serial`, any form of it
+    pub fn patches(&self, serial: &str) -> Option<&Cheats> {
+        self.games.get(&normalize_serial(serial))
+    }
+}
+
+// Load the patches of the game inserted, enabling the selected ones
+pub fn update(psx: &mut Psx) {
+    let serial = psx.game_info().map(|info| info.serial);
+    psx.patches = serial
+        .and_then(|serial| psx.patch_db.patches(&serial))
+        .cloned()
+        .unwrap_or_default();
+    for index in 0..psx.patches.len() {
+        let selected = psx
+            .selected_patches
+            .contains(&psx.patches.cheats()[index].name);
+        psx.patches.set_enabled(index, selected);
+    }
+}
+
+// Select the patch named `name` or not, for the game inserted and the ones
+// after it
+pub fn select(psx: &mut Psx, name: &str, selected: bool) {
+    match selected {
+        true => psx.selected_patches.insert(name.to_string()),
+        false => psx.selected_patches.remove(name),
+    };
+    if let Some(index) = psx.patches.cheats().iter().position(|p| p.name == name) {
+        psx.patches.set_enabled(index, selected);
+    }
+}
@@ -0,0 +1,143 @@
+use super::cheats::{Cheat, Code};
+use super::Psx;
+
+use std::convert::TryInto;
+
+// Cheat search: narrowing down where the game keeps a value by comparing
+// main RAM over successive searches, e.g. lives going down by one after
+// each death. Found values are frozen with a cheat writing them at every
+// vblank.
+
+// Type the values searched for are stored as, little endian and aligned
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ValueType {
+    #[default]
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+}
+
+impl ValueType {
+    pub fn size(self) -> usize {
+        match self {
+            ValueType::U8 | ValueType::I8 => 1,
+            ValueType::U16 | ValueType::I16 => 2,
+            ValueType::U32 | ValueType::I32 => 4,
+        }
+    }
+
+    // Value at `offset` in `ram`
+    pub fn read(self, ram: &[u8], offset: u32) -> i64 {
+        let bytes = &ram[offset as usize..offset as usize + self.size()];
+        match self {
+            ValueType::U8 => bytes[0] as i64,
+            ValueType::I8 => bytes[0] as i8 as i64,
+            ValueType::U16 => u16::from_le_bytes(bytes.try_into().unwrap()) as i64,
+            ValueType::I16 => i16::from_le_bytes(bytes.try_into().unwrap()) as i64,
+            ValueType::U32 => u32::from_le_bytes(bytes.try_into().unwrap()) as i64,
+            ValueType::I32 => i32::from_le_bytes(bytes.try_into().unwrap()) as i64,
+        }
+    }
+}
+
+// What values are kept by a search: compared to a given value, or to what
+// they were at the previous search
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Condition {
+    Equal(i64),
+    NotEqual(i64),
+    Greater(i64),
+    Less(i64),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+    // Changed by this much, negative when it went down
+    ChangedBy(i64),
+}
+
+impl Condition {
+    fn test(self, value: i64, previous: i64) -> bool {
+        match self {
+            Condition::Equal(n) => value == n,
+            Condition::NotEqual(n) => value != n,
+            Condition::Greater(n) => value > n,
+            Condition::Less(n) => value < n,
+            Condition::Changed => value != previous,
+            Condition::Unchanged => value == previous,
+            Condition::Increased => value > previous,
+            Condition::Decreased => value < previous,
+            Condition::ChangedBy(n) => value - previous == n,
+        }
+    }
+}
+
+pub struct RamSearch {
+    value_type: ValueType,
+    // RAM at the last search
+    previous: Vec<u8>,
+    // RAM offsets still matching
+    results: Vec<u32>,
+}
+
+impl RamSearch {
+    // Start a search with every value of RAM as a candidate
+    pub fn new(psx: &Psx, value_type: ValueType) -> Self {
+        let ram = psx.ram();
+        let results = (0..ram.len() as u32).step_by(value_type.size()).collect();
+        Self {
+            value_type,
+            previous: ram.to_vec(),
+            results,
+        }
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        self.value_type
+    }
+
+    // Keep the candidates meeting `condition`. Returns how many are left.
+    pub fn search(&mut self, psx: &Psx, condition: Condition) -> usize {
+        let ram = psx.ram();
+        let value_type = self.value_type;
+        let previous = &self.previous;
+        self.results.retain(|&offset| {
+            condition.test(
+                value_type.read(ram, offset),
+                value_type.read(previous, offset),
+            )
+        });
+        self.previous.copy_from_slice(ram);
+        self.results.len()
+    }
+
+    // RAM offsets of the candidates left, 80000000h up in the CPU's address
+    // space
+    pub fn results(&self) -> &[u32] {
+        &self.results
+    }
+
+    // Value of a candidate now, and at the last search
+    pub fn value(&self, psx: &Psx, offset: u32) -> (i64, i64) {
+        (
+            self.value_type.read(psx.ram(), offset),
+            self.value_type.read(&self.previous, offset),
+        )
+    }
+
+    // Cheat keeping the value at `offset` at `value`, for Psx::add_cheat
+    pub fn freeze(&self, offset: u32, value: i64) -> Cheat {
+        let codes = match self.value_type.size() {
+            1 => vec![Code::Write8(offset, value as u8)],
+            2 => vec![Code::Write16(offset, value as u16)],
+            _ => vec![
+                Code::Write16(offset, value as u16),
+                Code::Write16(offset + 2, (value >> 16) as u16),
+            ],
+        };
+        Cheat::from_codes(&format!("{:08X} = {}", 0x80000000 | offset, value), codes)
+    }
+}
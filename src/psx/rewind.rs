@@ -0,0 +1,198 @@
+use super::snapshot::Snapshot;
+use super::{movie, Error, Psx};
+
+use std::collections::VecDeque;
+
+// Going back in time while playing. The machine state is captured every
+// `interval` frames. The newest state is kept whole and each older one as
+// the difference to the state after it, which is small: most of RAM doesn't
+// change in a few frames. The oldest states are dropped to stay within the
+// memory budget.
+pub struct Rewind {
+    interval: u32,
+    budget: usize,
+    // Frames run since the last capture
+    elapsed: u32,
+    // Newest state and its frame number
+    current: Option<(u64, Snapshot)>,
+    // Older states, oldest first, as the frame number and the delta to it
+    // from the next newer state
+    deltas: VecDeque<(u64, Vec<u8>)>,
+    // Bytes taken by the deltas
+    size: usize,
+    // Buffer taking the next capture
+    scratch: Snapshot,
+}
+
+impl Rewind {
+    // Capture every `interval` frames, keeping states within `budget` bytes
+    pub fn new(interval: u32, budget: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            budget,
+            elapsed: 0,
+            current: None,
+            deltas: VecDeque::new(),
+            size: 0,
+            scratch: Snapshot::new(),
+        }
+    }
+
+    // Forget all captured states
+    pub fn clear(&mut self) {
+        self.elapsed = 0;
+        self.current = None;
+        self.deltas.clear();
+        self.size = 0;
+    }
+
+    // Frame number of the oldest state that can be gone back to
+    pub fn oldest_frame(&self) -> Option<u64> {
+        match self.deltas.front() {
+            Some(&(frame, _)) => Some(frame),
+            None => self.current.as_ref().map(|&(frame, _)| frame),
+        }
+    }
+
+    // Number of states kept
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.current.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.current.is_none()
+    }
+
+    // Memory taken by the states in bytes
+    pub fn size(&self) -> usize {
+        self.size + self.current.as_ref().map_or(0, |(_, state)| state.len())
+    }
+
+    fn push(&mut self, frame: u64) {
+        let state = std::mem::take(&mut self.scratch);
+        if let Some((previous_frame, previous)) = self.current.replace((frame, state)) {
+            let (_, state) = self.current.as_ref().unwrap();
+            let delta = encode_delta(previous.as_bytes(), state.as_bytes());
+            self.size += delta.len();
+            self.deltas.push_back((previous_frame, delta));
+            self.scratch = previous;
+        }
+        while self.size() > self.budget {
+            match self.deltas.pop_front() {
+                Some((_, delta)) => self.size -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    // Step back to the state before the current one
+    fn pop(&mut self) -> bool {
+        let (frame, delta) = match self.deltas.pop_back() {
+            Some(entry) => entry,
+            None => return false,
+        };
+        self.size -= delta.len();
+        let (_, state) = self.current.take().unwrap();
+        let mut data = state.into_bytes();
+        apply_delta(&mut data, &delta);
+        self.current = Some((frame, Snapshot::from_bytes(data)));
+        true
+    }
+}
+
+// Capture a state if it's time, at the end of each frame
+pub fn frame(psx: &mut Psx) {
+    let mut rewind = match psx.rewind.take() {
+        Some(rewind) => rewind,
+        None => return,
+    };
+    rewind.elapsed += 1;
+    if rewind.current.is_none() || rewind.elapsed >= rewind.interval {
+        rewind.elapsed = 0;
+        psx.snapshot_into(&mut rewind.scratch);
+        rewind.push(psx.frames);
+    }
+    psx.rewind = Some(rewind);
+}
+
+// Go back `frames` frames, to the newest state captured at or before then,
+// or the oldest one kept. Returns how many frames were actually gone back.
+pub fn rewind(psx: &mut Psx, frames: u64) -> Result<u64, Error> {
+    let mut rewind = match psx.rewind.take() {
+        Some(rewind) => rewind,
+        None => return Ok(0),
+    };
+    let target = psx.frames.saturating_sub(frames);
+    while rewind
+        .current
+        .as_ref()
+        .is_some_and(|&(frame, _)| frame > target)
+        && rewind.pop()
+    {}
+    let now = psx.frames;
+    let result = match rewind.current.as_ref() {
+        Some((_, state)) => psx.restore(state),
+        None => Ok(()),
+    };
+    rewind.elapsed = 0;
+    psx.rewind = Some(rewind);
+    result?;
+    // Going back while recording a movie is a rerecord
+    movie::state_loaded(psx)?;
+    Ok(now.saturating_sub(psx.frames))
+}
+
+// The delta turning `new` back into `old`: the length of `old`, then runs of
+// unchanged bytes and the XOR of changed ones, as the length of each
+// followed by the changed bytes. States are compared as if padded with
+// zeros to the same length.
+fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let len = old.len().max(new.len());
+    let byte = |data: &[u8], i: usize| data.get(i).copied().unwrap_or(0);
+    let mut delta = Vec::new();
+    delta.extend_from_slice(&(old.len() as u32).to_le_bytes());
+    let mut i = 0;
+    while i < len {
+        let start = i;
+        // Skip unchanged bytes a word at a time while both states have them
+        while i + 8 <= old.len().min(new.len()) && old[i..i + 8] == new[i..i + 8] {
+            i += 8;
+        }
+        while i < len && byte(old, i) == byte(new, i) {
+            i += 1;
+        }
+        let same = i - start;
+        let start = i;
+        while i < len && byte(old, i) != byte(new, i) {
+            i += 1;
+        }
+        delta.extend_from_slice(&(same as u32).to_le_bytes());
+        delta.extend_from_slice(&((i - start) as u32).to_le_bytes());
+        delta.extend((start..i).map(|i| byte(old, i) ^ byte(new, i)));
+    }
+    delta
+}
+
+fn apply_delta(data: &mut Vec<u8>, delta: &[u8]) {
+    let word =
+        |at: usize| u32::from_le_bytes([delta[at], delta[at + 1], delta[at + 2], delta[at + 3]]);
+    let len = word(0) as usize;
+    let (mut i, mut at) = (0, 4);
+    while at < delta.len() {
+        i += word(at) as usize;
+        let changed = word(at + 4) as usize;
+        at += 8;
+        if data.len() < i + changed {
+            data.resize(i + changed, 0);
+        }
+        for (byte, x) in data[i..i + changed]
+            .iter_mut()
+            .zip(&delta[at..at + changed])
+        {
+            *byte ^= x;
+        }
+        i += changed;
+        at += changed;
+    }
+    data.resize(len, 0);
+}
@@ -0,0 +1,148 @@
+use super::frame::{self, FrameOutput};
+use super::sio::InputState;
+use super::snapshot::Snapshot;
+use super::{Error, Psx};
+
+use std::collections::{BTreeMap, VecDeque};
+
+// Rollback netplay. Each peer runs its own console without waiting for the
+// others: frames run with the local input and a prediction of the remote
+// one, the last input received. When the real input of a past frame comes
+// in and differs, the machine goes back to that frame and runs the frames
+// since again, unseen. Frames are numbered by the frame count of the
+// machine, the same on every peer as they all start from the same state.
+//
+// Netplay libraries that do their own bookkeeping, like GGRS, only need the
+// primitives this is built on: snapshot_into and restore to save and load
+// states, well under a millisecond each, and set_input and run_frame to
+// advance, in deterministic mode.
+pub struct Rollback {
+    // Frames that can be gone back to
+    max_frames: usize,
+    // Frames run and kept, oldest first
+    frames: VecDeque<Frame>,
+    // Inputs received, by frame and port
+    confirmed: BTreeMap<u64, [Option<InputState>; 2]>,
+    // Inputs of each port as of the oldest frame kept
+    baseline: [InputState; 2],
+    // Oldest frame run with an input that turned out wrong
+    mispredicted: Option<u64>,
+}
+
+struct Frame {
+    number: u64,
+    // State at the start of the frame
+    state: Snapshot,
+    // Input it ran with
+    inputs: [InputState; 2],
+}
+
+impl Rollback {
+    // Keep the last `max_frames` frames to go back to
+    pub fn new(max_frames: usize) -> Self {
+        Self {
+            max_frames: max_frames.max(1),
+            frames: VecDeque::new(),
+            confirmed: BTreeMap::new(),
+            baseline: Default::default(),
+            mispredicted: None,
+        }
+    }
+
+    // Set the input of port `port` (0 or 1) for frame `frame`: the local
+    // input of the frame about to run or a later one, or remote input for
+    // any frame still kept
+    pub fn set_input(&mut self, frame: u64, port: usize, input: InputState) -> Result<(), Error> {
+        if port >= self.baseline.len() {
+            return Err(Error::Config(format!("no controller port {}", port)));
+        }
+        if let Some(oldest) = self.frames.front() {
+            if frame < oldest.number {
+                return Err(Error::Config(format!(
+                    "input for frame {} is too old to go back to",
+                    frame
+                )));
+            }
+        }
+        self.confirmed.entry(frame).or_default()[port] = Some(input);
+        let ran = self.frames.iter().find(|f| f.number == frame);
+        if ran.is_some_and(|f| f.inputs[port] != input) {
+            self.mispredicted = Some(self.mispredicted.map_or(frame, |f| f.min(frame)));
+        }
+        Ok(())
+    }
+
+    // Input of a port for a frame, the last one received if none was yet
+    fn input(&self, frame: u64, port: usize) -> InputState {
+        self.confirmed
+            .range(..=frame)
+            .rev()
+            .find_map(|(_, inputs)| inputs[port])
+            .unwrap_or(self.baseline[port])
+    }
+
+    // Run the next frame, first going back to fix frames that ran with the
+    // wrong input
+    pub fn advance(&mut self, psx: &mut Psx) -> Result<FrameOutput, Error> {
+        if !psx.deterministic {
+            return Err(Error::Config("rollback needs deterministic mode".into()));
+        }
+        if let Some(from) = self.mispredicted.take() {
+            self.resimulate(psx, from)?;
+        }
+
+        let number = psx.frames;
+        let mut state = match self.frames.len() < self.max_frames {
+            true => Snapshot::new(),
+            false => self.frames.pop_front().unwrap().state,
+        };
+        psx.snapshot_into(&mut state);
+        let inputs = [self.input(number, 0), self.input(number, 1)];
+        for (port, input) in inputs.iter().enumerate() {
+            psx.set_input(port, input);
+        }
+        self.frames.push_back(Frame {
+            number,
+            state,
+            inputs,
+        });
+        self.forget_before(self.frames.front().unwrap().number);
+        Ok(frame::run(psx))
+    }
+
+    // Go back to the start of frame `from` and run up to the present again
+    fn resimulate(&mut self, psx: &mut Psx, from: u64) -> Result<(), Error> {
+        let start = match self.frames.iter().position(|f| f.number == from) {
+            Some(start) => start,
+            None => return Ok(()),
+        };
+        psx.restore(&self.frames[start].state)?;
+        for i in start..self.frames.len() {
+            let number = self.frames[i].number;
+            let inputs = [self.input(number, 0), self.input(number, 1)];
+            let frame = &mut self.frames[i];
+            psx.snapshot_into(&mut frame.state);
+            frame.inputs = inputs;
+            for (port, input) in inputs.iter().enumerate() {
+                psx.set_input(port, input);
+            }
+            // Its audio was heard already
+            frame::unseen(psx, frame::run);
+        }
+        Ok(())
+    }
+
+    // Drop inputs of frames that can't be gone back to anymore
+    fn forget_before(&mut self, oldest: u64) {
+        while let Some(entry) = self.confirmed.first_entry() {
+            if *entry.key() >= oldest {
+                break;
+            }
+            for (baseline, input) in self.baseline.iter_mut().zip(entry.remove().iter()) {
+                if let Some(input) = input {
+                    *baseline = *input;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use super::frame::{self, FrameOutput};
+use super::snapshot::Snapshot;
+use super::{Error, Psx};
+
+// Run-ahead hides the lag games have between reading input and showing its
+// effect. After each frame the machine runs a few frames further with the
+// same input, the last of those is shown, and it goes back to where it was.
+// Input then shows up on screen as many frames sooner. Audio stays that of
+// the real frames.
+pub struct RunAhead {
+    frames: u32,
+    // Off for the game running, which gets it wrong
+    blocked: bool,
+    // Machine state to go back to, kept to reuse its memory
+    snapshot: Snapshot,
+    // Why run-ahead was turned off, if it failed
+    error: Option<Error>,
+}
+
+impl RunAhead {
+    pub fn new() -> Self {
+        Self {
+            frames: 0,
+            blocked: false,
+            snapshot: Snapshot::new(),
+            error: None,
+        }
+    }
+
+    pub fn set_frames(&mut self, frames: u32) {
+        self.frames = frames;
+    }
+
+    pub fn set_blocked(&mut self, blocked: bool) {
+        self.blocked = blocked;
+    }
+
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+}
+
+impl Default for RunAhead {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Run a frame, and the frames ahead of it for the video if run-ahead is on.
+// Not while a link cable is plugged in, whose traffic can't be taken back.
+pub fn run_frame(psx: &mut Psx) -> FrameOutput {
+    let mut output = frame::run(psx);
+    let frames = psx.run_ahead.frames;
+    if frames == 0 || psx.run_ahead.blocked || psx.sio1.linked() {
+        return output;
+    }
+    let mut snapshot = std::mem::take(&mut psx.run_ahead.snapshot);
+    psx.snapshot_into(&mut snapshot);
+
+    // What the frames ahead output goes nowhere, they're run again for real
+    let restored = frame::unseen(psx, |psx| {
+        for _ in 0..frames {
+            output.video = frame::run(psx).video;
+        }
+        psx.restore(&snapshot)
+    });
+    psx.run_ahead.snapshot = snapshot;
+    // The machine is left ahead, turn run-ahead off rather than carry on
+    // from there again
+    if let Err(e) = restored {
+        psx.run_ahead.frames = 0;
+        psx.run_ahead.error = Some(Error::Internal(format!(
+            "run-ahead can't go back to the present, turned off: {}",
+            e
+        )));
+    }
+    output
+}
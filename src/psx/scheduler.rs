@@ -0,0 +1,166 @@
+use super::state::{Savestate, StateError, StateReader, StateWriter};
+
+// Events that peripherals schedule to run at a given timestamp
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Event {
+    // Generate the next 44100Hz SPU output sample
+    SpuSample,
+    // Send the first response to the CD-ROM command just written
+    CdromCommand,
+    // Send the second response of a CD-ROM command
+    CdromComplete,
+    // Read the next sector under the CD-ROM head
+    CdromSector,
+    // Deliver a queued CD-ROM response after the previous one was acknowledged
+    CdromDeliver,
+    // Bring a root counter up to date when it reaches an IRQ condition
+    Timer0,
+    Timer1,
+    Timer2,
+    // Start and end of vblank
+    VblankStart,
+    VblankEnd,
+    // Start or end of hblank, only while a timer synchronizes with it
+    Hblank,
+    // End of the byte being shifted out on SIO0
+    Sio0Transfer,
+    // A controller port device pulls /ACK low or releases it
+    Sio0Ack,
+    // The beam passes where a lightgun points
+    Lightpen,
+    // End of the byte being sent on SIO1
+    Sio1Transfer,
+    // Check the link cable for bytes from the other console
+    Sio1Poll,
+    // The MDEC finishes decoding a macroblock
+    MdecDecode,
+}
+
+// Every event, in the order of their number in save states
+const ALL_EVENTS: [Event; 17] = [
+    Event::SpuSample,
+    Event::CdromCommand,
+    Event::CdromComplete,
+    Event::CdromSector,
+    Event::CdromDeliver,
+    Event::Timer0,
+    Event::Timer1,
+    Event::Timer2,
+    Event::VblankStart,
+    Event::VblankEnd,
+    Event::Hblank,
+    Event::Sio0Transfer,
+    Event::Sio0Ack,
+    Event::Lightpen,
+    Event::Sio1Transfer,
+    Event::Sio1Poll,
+    Event::MdecDecode,
+];
+
+pub struct Scheduler {
+    // Current timestamp in CPU cycles
+    now: u64,
+    // Pending events, latest first so the next one can be popped off the end
+    events: Vec<(u64, Event)>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            now: 0,
+            events: Vec::new(),
+        }
+    }
+
+    // Current timestamp in CPU cycles
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    // Schedule `event` to fire `delay` cycles from now, replacing any pending
+    // occurrence of the same event
+    pub fn schedule(&mut self, event: Event, delay: u64) {
+        self.cancel(event);
+        let timestamp = self.now + delay;
+        let pos = self
+            .events
+            .iter()
+            .position(|&(t, _)| t < timestamp)
+            .unwrap_or(self.events.len());
+        self.events.insert(pos, (timestamp, event));
+    }
+
+    // Remove a pending event
+    pub fn cancel(&mut self, event: Event) {
+        self.events.retain(|&(_, e)| e != event);
+    }
+
+    // Is the event pending?
+    pub fn is_scheduled(&self, event: Event) -> bool {
+        self.events.iter().any(|&(_, e)| e == event)
+    }
+
+    // Cycles until the event fires, if pending
+    pub fn remaining(&self, event: Event) -> Option<u64> {
+        self.events
+            .iter()
+            .find(|&&(_, e)| e == event)
+            .map(|&(t, _)| t.saturating_sub(self.now))
+    }
+
+    // Pop the next event due at or before `target`, moving the current time
+    // to the event's timestamp so handlers schedule relative to it
+    pub fn pop_until(&mut self, target: u64) -> Option<Event> {
+        match self.events.last() {
+            Some(&(timestamp, event)) if timestamp <= target => {
+                self.events.pop();
+                self.now = self.now.max(timestamp);
+                Some(event)
+            }
+            _ => None,
+        }
+    }
+
+    // Move the current time forward to `target`
+    pub fn advance_to(&mut self, target: u64) {
+        self.now = self.now.max(target);
+    }
+}
+
+// Timestamps are saved as they are, the components saving theirs alongside
+impl Savestate for Scheduler {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u64(self.now);
+        w.write_u32(self.events.len() as u32);
+        for &(timestamp, event) in self.events.iter() {
+            w.write_u64(timestamp);
+            w.write_u8(event as u8);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.now = r.read_u64()?;
+        let count = r.read_u32()? as usize;
+        if count > ALL_EVENTS.len() {
+            return Err(StateError::Invalid("scheduler event count"));
+        }
+        self.events.clear();
+        for _ in 0..count {
+            let timestamp = r.read_u64()?;
+            let event = *ALL_EVENTS
+                .get(r.read_u8()? as usize)
+                .ok_or(StateError::Invalid("scheduler event"))?;
+            self.events.push((timestamp, event));
+        }
+        // Keep the latest first order schedule relies on
+        self.events
+            .sort_by_key(|&(timestamp, _)| std::cmp::Reverse(timestamp));
+        Ok(())
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
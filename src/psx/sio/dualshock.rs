@@ -0,0 +1,251 @@
+use super::{Device, InputState};
+use crate::psx::state::{Savestate, StateError, StateReader, StateWriter};
+
+// Controller IDs in each mode: 1, 3 and 3 halfwords of data
+const DIGITAL_ID: u16 = 0x5a41;
+const ANALOG_ID: u16 = 0x5a73;
+const CONFIG_ID: u16 = 0x5af3;
+
+// Rumble map entries routing a command byte to a motor, from command 4Dh
+const RUMBLE_SMALL: u8 = 0x00;
+const RUMBLE_LARGE: u8 = 0x01;
+const RUMBLE_NONE: u8 = 0xff;
+
+// Step once the current command is over or was ignored
+const DONE: usize = 16;
+
+// DualShock analog controller (SCPH-1200). It starts in digital mode, where
+// it answers like a digital pad; the Analog button or command 44h switch it
+// to analog mode, which adds both sticks to the report and lights the LED.
+// Command 43h enters the config mode where the mode, lock and rumble mapping
+// are set:
+//   42h  read buttons and sticks, driving the motors mapped by 4Dh
+//   43h  enter (01h) or leave (00h) config mode, otherwise as 42h
+//   44h  set mode (00h digital, 01h analog) and lock (03h) the Analog button
+//   45h  get status, including the LED
+//   46h, 47h, 4Ch  constant tables
+//   4Dh  map command bytes of 42h to the motors
+pub struct DualShock {
+    input: InputState,
+    analog: bool,
+    // The Analog button is disabled by command 44h
+    locked: bool,
+    config: bool,
+    // Which motor each byte after the ID of command 42h drives
+    rumble_map: [u8; 6],
+    // Motor speeds: the small motor is on or off, the large one variable
+    small_motor: u8,
+    large_motor: u8,
+    rumble_callback: Option<Box<dyn FnMut(u8, u8) + Send>>,
+    // Position in the current command
+    step: usize,
+    command: u8,
+    // ID sent for the current command, from the mode when it started
+    reply_id: u16,
+    // Bytes sent after the ID for the current command
+    reply: [u8; 6],
+    reply_len: usize,
+}
+
+impl DualShock {
+    pub fn new() -> Self {
+        Self {
+            input: InputState::new(),
+            analog: false,
+            locked: false,
+            config: false,
+            rumble_map: [RUMBLE_NONE; 6],
+            small_motor: 0,
+            large_motor: 0,
+            rumble_callback: None,
+            step: 0,
+            command: 0,
+            reply_id: DIGITAL_ID,
+            reply: [0; 6],
+            reply_len: 0,
+        }
+    }
+
+    // Call `f` with the small and large motor speeds whenever they change
+    pub fn set_rumble_callback<F: FnMut(u8, u8) + Send + 'static>(&mut self, f: F) {
+        self.rumble_callback = Some(Box::new(f));
+    }
+
+    // Analog mode, shown by the controller's LED
+    pub fn analog(&self) -> bool {
+        self.analog
+    }
+
+    pub fn set_analog(&mut self, analog: bool) {
+        self.analog = analog;
+    }
+
+    fn id(&self) -> u16 {
+        if self.config {
+            CONFIG_ID
+        } else if self.analog {
+            ANALOG_ID
+        } else {
+            DIGITAL_ID
+        }
+    }
+
+    // Buttons, then sticks in analog and config modes
+    fn report(&mut self) {
+        let buttons = self.input.button_bits();
+        let (lx, ly) = self.input.left_stick();
+        let (rx, ry) = self.input.right_stick();
+        self.reply = [buttons as u8, (buttons >> 8) as u8, rx, ry, lx, ly];
+        self.reply_len = if self.analog || self.config { 6 } else { 2 };
+    }
+
+    // Prepare the reply to `command`. Returns false for unknown commands.
+    fn start(&mut self, command: u8) -> bool {
+        self.command = command;
+        match (self.config, command) {
+            (_, 0x42) | (_, 0x43) => self.report(),
+            (true, 0x44) => self.reply = [0; 6],
+            (true, 0x45) => self.reply = [0x01, 0x02, self.analog as u8, 0x02, 0x01, 0x00],
+            (true, 0x46) => self.reply = [0x00, 0x00, 0x01, 0x02, 0x00, 0x0a],
+            (true, 0x47) => self.reply = [0x00, 0x00, 0x02, 0x00, 0x01, 0x00],
+            (true, 0x4c) => self.reply = [0x00, 0x00, 0x00, 0x04, 0x00, 0x00],
+            (true, 0x4d) => self.reply = self.rumble_map,
+            // Other config commands reply with zeros
+            (true, _) => self.reply = [0; 6],
+            (false, _) => return false,
+        }
+        if self.config {
+            self.reply_len = 6;
+        }
+        true
+    }
+
+    // Act on byte `index` after the ID of the current command
+    fn receive(&mut self, index: usize, tx: u8) {
+        match self.command {
+            0x42 => match self.rumble_map[index] {
+                RUMBLE_SMALL => self.small_motor = if tx & 1 != 0 { 0xff } else { 0 },
+                RUMBLE_LARGE => self.large_motor = tx,
+                _ => {}
+            },
+            0x43 if index == 0 => self.config = tx == 1,
+            0x44 if self.config => match index {
+                0 => self.analog = tx == 1,
+                1 => self.locked = tx == 3,
+                _ => {}
+            },
+            // The rest of the table depends on the first byte
+            0x46 if self.config && index == 0 && tx == 1 => {
+                self.reply[2..].copy_from_slice(&[0x01, 0x01, 0x01, 0x14]);
+            }
+            0x4c if self.config && index == 0 && tx == 1 => self.reply[3] = 0x07,
+            0x4d if self.config => self.rumble_map[index] = tx,
+            _ => {}
+        }
+    }
+
+    fn update_rumble(&mut self) {
+        let (small, large) = (self.small_motor, self.large_motor);
+        if let Some(callback) = self.rumble_callback.as_mut() {
+            callback(small, large);
+        }
+    }
+}
+
+impl Default for DualShock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for DualShock {
+    fn transfer(&mut self, tx: u8) -> (u8, bool) {
+        let step = self.step;
+        self.step += 1;
+        match step {
+            0 => (0xff, true),
+            1 => {
+                self.reply_id = self.id();
+                if self.start(tx) {
+                    (self.reply_id as u8, true)
+                } else {
+                    self.step = DONE;
+                    (0xff, false)
+                }
+            }
+            2 => ((self.reply_id >> 8) as u8, true),
+            _ if step - 3 < self.reply_len => {
+                let index = step - 3;
+                let last = index + 1 == self.reply_len;
+                let motors = (self.small_motor, self.large_motor);
+                let reply = self.reply[index];
+                self.receive(index, tx);
+                if (self.small_motor, self.large_motor) != motors {
+                    self.update_rumble();
+                }
+                if last {
+                    self.step = DONE;
+                }
+                (reply, !last)
+            }
+            _ => {
+                self.step = DONE;
+                (0xff, false)
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.step = 0;
+    }
+
+    fn set_input(&mut self, input: &InputState) {
+        // The Analog button toggles the mode when pressed, unless locked
+        if input.analog_button() && !self.input.analog_button() && !self.locked {
+            self.analog = !self.analog;
+        }
+        self.input = *input;
+    }
+
+    fn input(&self) -> Option<&InputState> {
+        Some(&self.input)
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        self.input.save_state(w);
+        w.write_bool(self.analog);
+        w.write_bool(self.locked);
+        w.write_bool(self.config);
+        w.write_bytes(&self.rumble_map);
+        w.write_u8(self.small_motor);
+        w.write_u8(self.large_motor);
+        w.write_u32(self.step as u32);
+        w.write_u8(self.command);
+        w.write_u16(self.reply_id);
+        w.write_bytes(&self.reply);
+        w.write_u8(self.reply_len as u8);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let motors = (self.small_motor, self.large_motor);
+        self.input.load_state(r)?;
+        self.analog = r.read_bool()?;
+        self.locked = r.read_bool()?;
+        self.config = r.read_bool()?;
+        r.read_into(&mut self.rumble_map)?;
+        self.small_motor = r.read_u8()?;
+        self.large_motor = r.read_u8()?;
+        self.step = r.read_u32()? as usize;
+        self.command = r.read_u8()?;
+        self.reply_id = r.read_u16()?;
+        r.read_into(&mut self.reply)?;
+        self.reply_len = r.read_u8()? as usize;
+        if self.reply_len > self.reply.len() {
+            return Err(StateError::Invalid("DualShock reply length"));
+        }
+        if (self.small_motor, self.large_motor) != motors {
+            self.update_rumble();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use crate::psx::state::{Savestate, StateError, StateReader, StateWriter};
+
+// Controller buttons, by bit position in the pad's button report
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Button {
+    Select = 0,
+    L3 = 1,
+    R3 = 2,
+    Start = 3,
+    Up = 4,
+    Right = 5,
+    Down = 6,
+    Left = 7,
+    L2 = 8,
+    R2 = 9,
+    L1 = 10,
+    R1 = 11,
+    Triangle = 12,
+    Circle = 13,
+    Cross = 14,
+    Square = 15,
+}
+
+// Mouse buttons
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MouseButton {
+    Left,
+    Right,
+}
+
+// Lightgun buttons
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GunButton {
+    Trigger = 0,
+    A = 1,
+    B = 2,
+}
+
+// Stick axis value at rest
+pub const AXIS_CENTER: u8 = 0x80;
+
+// State of a controller's inputs as set by the frontend. Controllers read it
+// whenever the game polls them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct InputState {
+    // Pressed buttons, one bit per Button
+    buttons: u16,
+    // How far each button is pressed, for analog buttons. 0 leaves it to the
+    // pressed state.
+    pressures: [u8; 16],
+    // Analog stick positions (X, Y), 00h left/up to FFh right/down
+    left_stick: (u8, u8),
+    right_stick: (u8, u8),
+    // DualShock Analog button, which switches between digital and analog
+    // modes
+    analog_button: bool,
+    // Mouse motion since the previous state, positive right and down
+    pointer_delta: (i32, i32),
+    mouse_left: bool,
+    mouse_right: bool,
+    // Where a lightgun points in the displayed picture, from (0, 0) top left
+    // to (FFFFh, FFFFh) bottom right; None when away from the screen
+    pointer: Option<(u16, u16)>,
+    // Pressed lightgun buttons, one bit per GunButton
+    gun_buttons: u8,
+}
+
+impl InputState {
+    pub fn new() -> Self {
+        Self {
+            buttons: 0,
+            pressures: [0; 16],
+            left_stick: (AXIS_CENTER, AXIS_CENTER),
+            right_stick: (AXIS_CENTER, AXIS_CENTER),
+            analog_button: false,
+            pointer_delta: (0, 0),
+            mouse_left: false,
+            mouse_right: false,
+            pointer: None,
+            gun_buttons: 0,
+        }
+    }
+
+    pub fn set(&mut self, button: Button, pressed: bool) {
+        let bit = 1 << button as u16;
+        if pressed {
+            self.buttons |= bit;
+        } else {
+            self.buttons &= !bit;
+        }
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.set(button, true);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.set(button, false);
+    }
+
+    pub fn pressed(&self, button: Button) -> bool {
+        self.buttons & (1 << button as u16) != 0
+    }
+
+    // How far `button` is pressed, from 00h to FFh, for controllers with
+    // analog buttons
+    pub fn set_pressure(&mut self, button: Button, pressure: u8) {
+        self.pressures[button as usize] = pressure;
+    }
+
+    // How far `button` is pressed: the analog pressure if set, otherwise
+    // FFh when pressed
+    pub fn pressure(&self, button: Button) -> u8 {
+        match self.pressures[button as usize] {
+            0 if self.pressed(button) => 0xff,
+            pressure => pressure,
+        }
+    }
+
+    // Button report as sent by the pad: a bit per button, 0 when pressed
+    pub fn button_bits(&self) -> u16 {
+        !self.buttons
+    }
+
+    pub fn set_left_stick(&mut self, x: u8, y: u8) {
+        self.left_stick = (x, y);
+    }
+
+    pub fn set_right_stick(&mut self, x: u8, y: u8) {
+        self.right_stick = (x, y);
+    }
+
+    pub fn left_stick(&self) -> (u8, u8) {
+        self.left_stick
+    }
+
+    pub fn right_stick(&self) -> (u8, u8) {
+        self.right_stick
+    }
+
+    pub fn set_analog_button(&mut self, pressed: bool) {
+        self.analog_button = pressed;
+    }
+
+    pub fn analog_button(&self) -> bool {
+        self.analog_button
+    }
+
+    // Relative pointer motion since the last set_input, added up by the
+    // mouse until the game reads it
+    pub fn set_pointer_delta(&mut self, dx: i32, dy: i32) {
+        self.pointer_delta = (dx, dy);
+    }
+
+    pub fn pointer_delta(&self) -> (i32, i32) {
+        self.pointer_delta
+    }
+
+    pub fn set_mouse_button(&mut self, button: MouseButton, pressed: bool) {
+        match button {
+            MouseButton::Left => self.mouse_left = pressed,
+            MouseButton::Right => self.mouse_right = pressed,
+        }
+    }
+
+    pub fn mouse_button(&self, button: MouseButton) -> bool {
+        match button {
+            MouseButton::Left => self.mouse_left,
+            MouseButton::Right => self.mouse_right,
+        }
+    }
+
+    // Aim a lightgun at (`x`, `y`), from 0.0 to 1.0 across the displayed
+    // picture. Positions outside of it are off screen.
+    pub fn set_pointer(&mut self, x: f32, y: f32) {
+        let range = 0.0..=1.0;
+        self.pointer = match range.contains(&x) && range.contains(&y) {
+            true => Some(((x * 65535.0) as u16, (y * 65535.0) as u16)),
+            false => None,
+        };
+    }
+
+    // Point a lightgun away from the screen, as done to reload
+    pub fn set_pointer_offscreen(&mut self) {
+        self.pointer = None;
+    }
+
+    pub fn pointer(&self) -> Option<(u16, u16)> {
+        self.pointer
+    }
+
+    pub fn set_gun_button(&mut self, button: GunButton, pressed: bool) {
+        let bit = 1 << button as u8;
+        if pressed {
+            self.gun_buttons |= bit;
+        } else {
+            self.gun_buttons &= !bit;
+        }
+    }
+
+    pub fn gun_button(&self, button: GunButton) -> bool {
+        self.gun_buttons & (1 << button as u8) != 0
+    }
+}
+
+impl Savestate for InputState {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u16(self.buttons);
+        w.write_bytes(&self.pressures);
+        w.write_u8(self.left_stick.0);
+        w.write_u8(self.left_stick.1);
+        w.write_u8(self.right_stick.0);
+        w.write_u8(self.right_stick.1);
+        w.write_bool(self.analog_button);
+        w.write_i32(self.pointer_delta.0);
+        w.write_i32(self.pointer_delta.1);
+        w.write_bool(self.mouse_left);
+        w.write_bool(self.mouse_right);
+        w.write_bool(self.pointer.is_some());
+        let (x, y) = self.pointer.unwrap_or((0, 0));
+        w.write_u16(x);
+        w.write_u16(y);
+        w.write_u8(self.gun_buttons);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.buttons = r.read_u16()?;
+        r.read_into(&mut self.pressures)?;
+        self.left_stick = (r.read_u8()?, r.read_u8()?);
+        self.right_stick = (r.read_u8()?, r.read_u8()?);
+        self.analog_button = r.read_bool()?;
+        self.pointer_delta = (r.read_i32()?, r.read_i32()?);
+        self.mouse_left = r.read_bool()?;
+        self.mouse_right = r.read_bool()?;
+        let on_screen = r.read_bool()?;
+        let pointer = (r.read_u16()?, r.read_u16()?);
+        self.pointer = if on_screen { Some(pointer) } else { None };
+        self.gun_buttons = r.read_u8()?;
+        Ok(())
+    }
+}
+
+impl Default for InputState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,200 @@
+use super::{Device, DisplayArea, GunButton, InputState};
+use crate::psx::state::{Savestate, StateError, StateReader, StateWriter};
+
+// Controller IDs: GunCon 3 halfwords of data, Justifier 1
+const GUNCON_ID: u16 = 0x5a63;
+const JUSTIFIER_ID: u16 = 0x5a31;
+
+// GunCon position reported away from the screen
+const GUNCON_OFFSCREEN: (u16, u16) = (0x0001, 0x000a);
+
+// GPU clock in Hz, and the clock the GunCon counts X positions with
+const GPU_CLOCK: u64 = 53_693_175;
+const GUNCON_CLOCK: u64 = 8_000_000;
+
+// Button report with the gun's buttons at bits `trigger`, `a` and `b`, 0 when
+// pressed
+fn gun_buttons(input: &InputState, trigger: u16, a: u16, b: u16) -> u16 {
+    let mut buttons = 0xffff;
+    let bits = [
+        (GunButton::Trigger, trigger),
+        (GunButton::A, a),
+        (GunButton::B, b),
+    ];
+    for &(button, bit) in bits.iter() {
+        if input.gun_button(button) {
+            buttons &= !(1 << bit);
+        }
+    }
+    buttons
+}
+
+// Namco GunCon (NPC-103). It times the beam itself and reports where it
+// points in the frame, X in 8 MHz clocks from hsync and Y in lines:
+//   01h -> FFh, 42h -> 63h, 00h -> 5Ah
+//   then buttons (trigger bit 13, A bit 3, B bit 14), X and Y, 16 bit each
+// Away from the screen X and Y are 0001h and 000Ah; games reload when the
+// trigger is pulled there.
+pub struct GunCon {
+    input: InputState,
+    // Position measured during the last frame
+    position: (u16, u16),
+    reply: [u8; 6],
+    // Position in the current command
+    step: usize,
+}
+
+impl GunCon {
+    pub fn new() -> Self {
+        Self {
+            input: InputState::new(),
+            position: GUNCON_OFFSCREEN,
+            reply: [0; 6],
+            step: 0,
+        }
+    }
+}
+
+impl Default for GunCon {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for GunCon {
+    fn transfer(&mut self, tx: u8) -> (u8, bool) {
+        let step = self.step;
+        self.step += 1;
+        match step {
+            0 => (0xff, true),
+            1 if tx == 0x42 => {
+                let buttons = gun_buttons(&self.input, 13, 3, 14);
+                let (x, y) = self.position;
+                for (i, half) in [buttons, x, y].iter().enumerate() {
+                    self.reply[i * 2..i * 2 + 2].copy_from_slice(&half.to_le_bytes());
+                }
+                (GUNCON_ID as u8, true)
+            }
+            2 => ((GUNCON_ID >> 8) as u8, true),
+            3..=8 => (self.reply[step - 3], step < 8),
+            _ => {
+                self.step = 9;
+                (0xff, false)
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.step = 0;
+    }
+
+    fn set_input(&mut self, input: &InputState) {
+        self.input = *input;
+    }
+
+    fn frame(&mut self, area: &DisplayArea) -> Option<(u64, u64)> {
+        self.position = match self.input.pointer() {
+            Some(pointer) => {
+                let (x, line) = area.beam_position(pointer);
+                ((x * GUNCON_CLOCK / GPU_CLOCK) as u16, line as u16)
+            }
+            None => GUNCON_OFFSCREEN,
+        };
+        None
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        self.input.save_state(w);
+        w.write_u16(self.position.0);
+        w.write_u16(self.position.1);
+        w.write_bytes(&self.reply);
+        w.write_u32(self.step as u32);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.input.load_state(r)?;
+        self.position = (r.read_u16()?, r.read_u16()?);
+        r.read_into(&mut self.reply)?;
+        self.step = r.read_u32()? as usize;
+        Ok(())
+    }
+}
+
+// Konami Justifier. It strobes the lightpen input when it sees the beam, the
+// game reading the timers from the interrupt to find the position. Command
+// 42h with a TAP byte of 10h turns the sensor on:
+//   01h -> FFh, 42h -> 31h, TAP -> 5Ah
+//   then buttons (trigger bit 15, A bit 3, B bit 14)
+// Away from the screen there's no interrupt.
+pub struct Justifier {
+    input: InputState,
+    sensor: bool,
+    // Position in the current command
+    step: usize,
+}
+
+impl Justifier {
+    pub fn new() -> Self {
+        Self {
+            input: InputState::new(),
+            sensor: false,
+            step: 0,
+        }
+    }
+}
+
+impl Default for Justifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for Justifier {
+    fn transfer(&mut self, tx: u8) -> (u8, bool) {
+        let step = self.step;
+        self.step += 1;
+        let buttons = gun_buttons(&self.input, 15, 3, 14);
+        match step {
+            0 => (0xff, true),
+            1 if tx == 0x42 => (JUSTIFIER_ID as u8, true),
+            2 => {
+                self.sensor = tx & 0x10 != 0;
+                ((JUSTIFIER_ID >> 8) as u8, true)
+            }
+            3 => (buttons as u8, true),
+            4 => ((buttons >> 8) as u8, false),
+            _ => {
+                self.step = 5;
+                (0xff, false)
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.step = 0;
+    }
+
+    fn set_input(&mut self, input: &InputState) {
+        self.input = *input;
+    }
+
+    fn frame(&mut self, area: &DisplayArea) -> Option<(u64, u64)> {
+        match (self.sensor, self.input.pointer()) {
+            (true, Some(pointer)) => Some(area.beam_position(pointer)),
+            _ => None,
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        self.input.save_state(w);
+        w.write_bool(self.sensor);
+        w.write_u32(self.step as u32);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.input.load_state(r)?;
+        self.sensor = r.read_bool()?;
+        self.step = r.read_u32()? as usize;
+        Ok(())
+    }
+}
@@ -0,0 +1,659 @@
+use super::gpu::DisplayArea;
+use super::irq::Interrupt;
+use super::memcard::StatePolicy;
+use super::scheduler::Event;
+use super::state::{Savestate, StateError, StateReader, StateWriter};
+use super::Psx;
+
+use std::collections::VecDeque;
+use std::io;
+
+mod dualshock;
+mod input;
+mod lightgun;
+mod mouse;
+mod multitap;
+mod negcon;
+mod pad;
+
+pub use dualshock::DualShock;
+pub use input::{Button, GunButton, InputState, MouseButton, AXIS_CENTER};
+pub use lightgun::{GunCon, Justifier};
+pub use mouse::Mouse;
+pub use negcon::NeGcon;
+pub use pad::DigitalPad;
+
+// Number of controller ports
+pub const PORTS: usize = 2;
+
+// Slots A-D of a multitap. Without one only slot A is connected.
+pub const SLOTS: usize = 4;
+
+// Size of the receive FIFO
+const RX_FIFO_SIZE: usize = 8;
+
+// CPU cycles from the end of a byte until a device pulls /ACK low, and how
+// long it holds it
+const ACK_DELAY_CYCLES: u64 = 338;
+const ACK_CYCLES: u64 = 96;
+
+// JOY_STAT bits
+const STAT_TX_READY: u32 = 0x0001;
+const STAT_RX_NOT_EMPTY: u32 = 0x0002;
+const STAT_TX_FINISHED: u32 = 0x0004;
+const STAT_ACK_LOW: u32 = 0x0080;
+const STAT_IRQ: u32 = 0x0200;
+
+// JOY_CTRL bits
+const CTRL_TX_ENABLE: u16 = 0x0001;
+const CTRL_SELECT: u16 = 0x0002;
+const CTRL_RX_ENABLE: u16 = 0x0004;
+const CTRL_ACKNOWLEDGE: u16 = 0x0010;
+const CTRL_RESET: u16 = 0x0040;
+const CTRL_TX_IRQ: u16 = 0x0400;
+const CTRL_RX_IRQ: u16 = 0x0800;
+const CTRL_ACK_IRQ: u16 = 0x1000;
+const CTRL_PORT_2: u16 = 0x2000;
+
+// Something plugged into a controller port: a controller or a memory card.
+// The console sends a byte and receives one at the same time, the device
+// pulling /ACK afterwards while it expects more bytes.
+pub trait Device: Send {
+    // Exchange a byte. Returns the byte sent back and whether the device
+    // acknowledges it.
+    fn transfer(&mut self, tx: u8) -> (u8, bool);
+
+    // The port was deselected, ending any command in progress
+    fn reset(&mut self);
+
+    // New inputs from the frontend, for controllers
+    fn set_input(&mut self, _input: &InputState) {}
+
+    // Inputs held, for controllers
+    fn input(&self) -> Option<&InputState> {
+        None
+    }
+
+    // A frame starts being displayed in `area`. Lightguns that see the beam
+    // through the lightpen input return where: GPU cycle in the line, line.
+    fn frame(&mut self, _area: &DisplayArea) -> Option<(u64, u64)> {
+        None
+    }
+
+    // Save pending changes, for memory cards
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    // The card image, for memory cards accessed directly by the HLE BIOS
+    fn card_data(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    // How the next state loaded treats the card contents, for memory cards
+    fn set_state_policy(&mut self, _policy: StatePolicy) {}
+
+    // Whether the card contents in the last state loaded differed from the
+    // card's, for memory cards
+    fn state_conflict(&self) -> bool {
+        false
+    }
+
+    // Save and restore the device's own state along with the console's
+    fn save_state(&self, _w: &mut StateWriter) {}
+
+    fn load_state(&mut self, _r: &mut StateReader) -> Result<(), StateError> {
+        Ok(())
+    }
+}
+
+// Device a transfer sequence was addressed to by its first byte
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Target {
+    Controller,
+    MemoryCard,
+    // No device answers to the address, the rest of the sequence is ignored
+    None,
+}
+
+// A controller port, with a controller and a memory card slot sharing the
+// same select line. A multitap adds three more of each.
+#[derive(Default)]
+struct Port {
+    controllers: [Option<Box<dyn Device>>; SLOTS],
+    memory_cards: [Option<Box<dyn Device>>; SLOTS],
+    multitap: Option<multitap::Multitap>,
+    // Device addressed since the port was selected
+    target: Option<Target>,
+    // A controller was addressed since the last take_polled
+    polled: bool,
+}
+
+impl Port {
+    fn transfer(&mut self, tx: u8) -> (u8, bool) {
+        if self.target.is_none() && tx == 0x01 {
+            self.polled = true;
+        }
+        if let Some(multitap) = self.multitap.as_mut() {
+            return multitap.transfer(&mut self.controllers, &mut self.memory_cards, tx);
+        }
+        let target = *self.target.get_or_insert(match tx {
+            0x01 => Target::Controller,
+            0x81 => Target::MemoryCard,
+            _ => Target::None,
+        });
+        let device = match target {
+            Target::Controller => self.controllers[0].as_mut(),
+            Target::MemoryCard => self.memory_cards[0].as_mut(),
+            Target::None => None,
+        };
+        match device {
+            Some(device) => device.transfer(tx),
+            // Nothing drives the line, it floats high
+            None => (0xff, false),
+        }
+    }
+
+    fn deselect(&mut self) {
+        self.target = None;
+        if let Some(multitap) = self.multitap.as_mut() {
+            multitap.reset();
+        }
+        let devices = self.controllers.iter_mut().chain(&mut self.memory_cards);
+        for device in devices.flatten() {
+            device.reset();
+        }
+    }
+}
+
+// Devices are saved in their slots. A device is only restored if the state
+// has one in its slot too, assuming it's the same kind: the devices plugged
+// in are up to the frontend.
+fn save_slots(w: &mut StateWriter, slots: &[Option<Box<dyn Device>>; SLOTS]) {
+    for slot in slots.iter() {
+        w.write_bool(slot.is_some());
+        w.write_nested(|w| {
+            if let Some(device) = slot.as_ref() {
+                device.save_state(w);
+            }
+        });
+    }
+}
+
+fn load_slots(
+    r: &mut StateReader,
+    slots: &mut [Option<Box<dyn Device>>; SLOTS],
+) -> Result<(), StateError> {
+    for slot in slots.iter_mut() {
+        let present = r.read_bool()?;
+        let mut state = r.read_nested()?;
+        if let (true, Some(device)) = (present, slot.as_mut()) {
+            device.load_state(&mut state)?;
+        }
+    }
+    Ok(())
+}
+
+impl Savestate for Port {
+    fn save_state(&self, w: &mut StateWriter) {
+        save_slots(w, &self.controllers);
+        save_slots(w, &self.memory_cards);
+        w.write_bool(self.multitap.is_some());
+        w.write_nested(|w| {
+            if let Some(multitap) = self.multitap.as_ref() {
+                multitap.save_state(w);
+            }
+        });
+        w.write_u8(match self.target {
+            Some(Target::Controller) => 0,
+            Some(Target::MemoryCard) => 1,
+            Some(Target::None) => 2,
+            None => 3,
+        });
+        w.write_bool(self.polled);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        load_slots(r, &mut self.controllers)?;
+        load_slots(r, &mut self.memory_cards)?;
+        let multitap = r.read_bool()?;
+        let mut state = r.read_nested()?;
+        if let (true, Some(current)) = (multitap, self.multitap.as_mut()) {
+            current.load_state(&mut state)?;
+        }
+        self.target = match r.read_u8()? {
+            0 => Some(Target::Controller),
+            1 => Some(Target::MemoryCard),
+            2 => Some(Target::None),
+            3 => None,
+            _ => return Err(StateError::Invalid("SIO0 port target")),
+        };
+        self.polled = r.read_bool()?;
+        Ok(())
+    }
+}
+
+// SIO0, the serial interface to the controller and memory card ports
+pub struct Sio {
+    ports: [Port; PORTS],
+    // Port selected by the last JOY_CTRL write, if its select line is active
+    selected: Option<usize>,
+    // Byte written to JOY_TX_DATA waiting for the transfer in progress, or
+    // for transfers to be enabled
+    tx_pending: Option<u8>,
+    // Byte being sent, and whether the device will acknowledge it
+    transfer: Option<(u8, bool)>,
+    rx_fifo: VecDeque<u8>,
+    // /ACK input currently held low by a device
+    ack_low: bool,
+    // JOY_STAT interrupt flag
+    irq: bool,
+    // 1F801048h JOY_MODE
+    mode: u16,
+    // 1F80104Ah JOY_CTRL
+    control: u16,
+    // 1F80104Eh JOY_BAUD
+    baud: u16,
+}
+
+impl Sio {
+    pub fn new() -> Self {
+        Self {
+            ports: Default::default(),
+            selected: None,
+            tx_pending: None,
+            transfer: None,
+            rx_fifo: VecDeque::with_capacity(RX_FIFO_SIZE),
+            ack_low: false,
+            irq: false,
+            mode: 0,
+            control: 0,
+            baud: 0,
+        }
+    }
+
+    // Plug a controller into slot `slot` of port `port`, or unplug it
+    pub fn set_controller(&mut self, port: usize, slot: usize, device: Option<Box<dyn Device>>) {
+        self.ports[port].controllers[slot] = device;
+    }
+
+    // Pass the frontend's inputs to a controller
+    pub fn set_input(&mut self, port: usize, slot: usize, input: &InputState) {
+        if let Some(controller) = self.ports[port].controllers[slot].as_mut() {
+            controller.set_input(input);
+        }
+    }
+
+    // Inputs held on the controller in slot A of `port`, if it has any
+    pub fn input(&self, port: usize) -> Option<InputState> {
+        self.ports[port].controllers[0].as_ref()?.input().copied()
+    }
+
+    // Insert a memory card in slot `slot` of port `port`, or remove it
+    pub fn set_memory_card(&mut self, port: usize, slot: usize, device: Option<Box<dyn Device>>) {
+        self.ports[port].memory_cards[slot] = device;
+    }
+
+    pub fn take_memory_card(&mut self, port: usize, slot: usize) -> Option<Box<dyn Device>> {
+        self.ports[port].memory_cards[slot].take()
+    }
+
+    pub fn has_memory_card(&self, port: usize, slot: usize) -> bool {
+        self.ports[port].memory_cards[slot].is_some()
+    }
+
+    // Read the controller in slot A of `port` outside of the serial protocol,
+    // as the HLE BIOS does every frame. Returns the ID byte and the data
+    // bytes, None without a controller.
+    pub fn read_pad(&mut self, port: usize) -> Option<(u8, Vec<u8>)> {
+        self.ports[port].polled = true;
+        let controller = self.ports[port].controllers[0].as_mut()?;
+        controller.reset();
+        let mut reply = Vec::new();
+        let mut ack = controller.transfer(0x01).1;
+        for &tx in [0x42, 0x00].iter() {
+            if ack {
+                let (rx, next) = controller.transfer(tx);
+                reply.push(rx);
+                ack = next;
+            }
+        }
+        if reply.len() < 2 {
+            controller.reset();
+            return None;
+        }
+        let id = reply[0];
+        let halfwords = match id & 0xf {
+            0 => 16,
+            n => n as usize,
+        };
+        let mut data = Vec::with_capacity(halfwords * 2);
+        while ack && data.len() < halfwords * 2 {
+            let (rx, next) = controller.transfer(0x00);
+            data.push(rx);
+            ack = next;
+        }
+        controller.reset();
+        Some((id, data))
+    }
+
+    // Whether the game read a controller since the last call
+    pub fn take_polled(&mut self) -> bool {
+        let mut polled = false;
+        for port in self.ports.iter_mut() {
+            polled |= std::mem::take(&mut port.polled);
+        }
+        polled
+    }
+
+    // Image of the memory card in slot A of `port`
+    pub fn memory_card_data(&mut self, port: usize) -> Option<&mut [u8]> {
+        self.ports[port].memory_cards[0].as_mut()?.card_data()
+    }
+
+    // Save the changes made to all memory cards
+    pub fn flush_memory_cards(&mut self) -> io::Result<()> {
+        for port in self.ports.iter_mut() {
+            for card in port.memory_cards.iter_mut().flatten() {
+                card.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    // How the next state loaded treats the contents of every memory card
+    pub fn set_card_state_policy(&mut self, policy: StatePolicy) {
+        for port in self.ports.iter_mut() {
+            for card in port.memory_cards.iter_mut().flatten() {
+                card.set_state_policy(policy);
+            }
+        }
+    }
+
+    // Port and slot of the memory cards whose contents differed from the
+    // ones in the last state loaded
+    pub fn card_state_conflicts(&self) -> Vec<(usize, usize)> {
+        let mut conflicts = Vec::new();
+        for (i, port) in self.ports.iter().enumerate() {
+            for (slot, card) in port.memory_cards.iter().enumerate() {
+                if card.as_ref().is_some_and(|card| card.state_conflict()) {
+                    conflicts.push((i, slot));
+                }
+            }
+        }
+        conflicts
+    }
+
+    // Plug a multitap into port `port` or remove it. The devices in slot A
+    // stay connected either way.
+    pub fn set_multitap(&mut self, port: usize, multitap: bool) {
+        self.ports[port].multitap = match multitap {
+            true => Some(multitap::Multitap::new()),
+            false => None,
+        };
+    }
+
+    fn status(&self) -> u32 {
+        let mut stat = 0;
+        if self.tx_pending.is_none() {
+            stat |= STAT_TX_READY;
+        }
+        if !self.rx_fifo.is_empty() {
+            stat |= STAT_RX_NOT_EMPTY;
+        }
+        if self.tx_pending.is_none() && self.transfer.is_none() {
+            stat |= STAT_TX_FINISHED;
+        }
+        if self.ack_low {
+            stat |= STAT_ACK_LOW;
+        }
+        if self.irq {
+            stat |= STAT_IRQ;
+        }
+        stat
+    }
+
+    // CPU cycles to shift out a byte: 8 bits at the baud rate, whose reload
+    // value is multiplied by the JOY_MODE factor
+    fn byte_cycles(&self) -> u64 {
+        let factor = match self.mode & 3 {
+            2 => 16,
+            3 => 64,
+            _ => 1,
+        };
+        (self.baud as u64 * factor * 8).max(1)
+    }
+
+    // Bytes received before the RX interrupt fires, from JOY_CTRL bits 8-9
+    fn rx_irq_level(&self) -> usize {
+        1 << ((self.control >> 8) & 3)
+    }
+}
+
+impl Savestate for Sio {
+    fn save_state(&self, w: &mut StateWriter) {
+        for port in self.ports.iter() {
+            port.save_state(w);
+        }
+        w.write_u8(self.selected.map_or(0xff, |port| port as u8));
+        w.write_bool(self.tx_pending.is_some());
+        w.write_u8(self.tx_pending.unwrap_or(0));
+        let (rx, ack) = self.transfer.unwrap_or((0, false));
+        w.write_bool(self.transfer.is_some());
+        w.write_u8(rx);
+        w.write_bool(ack);
+        w.write_u8(self.rx_fifo.len() as u8);
+        for &byte in self.rx_fifo.iter() {
+            w.write_u8(byte);
+        }
+        w.write_bool(self.ack_low);
+        w.write_bool(self.irq);
+        w.write_u16(self.mode);
+        w.write_u16(self.control);
+        w.write_u16(self.baud);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        for port in self.ports.iter_mut() {
+            port.load_state(r)?;
+        }
+        self.selected = match r.read_u8()? {
+            0xff => None,
+            port if (port as usize) < PORTS => Some(port as usize),
+            _ => return Err(StateError::Invalid("SIO0 selected port")),
+        };
+        let pending = r.read_bool()?;
+        let tx = r.read_u8()?;
+        self.tx_pending = if pending { Some(tx) } else { None };
+        let transferring = r.read_bool()?;
+        let transfer = (r.read_u8()?, r.read_bool()?);
+        self.transfer = if transferring { Some(transfer) } else { None };
+        let len = r.read_u8()? as usize;
+        if len > RX_FIFO_SIZE {
+            return Err(StateError::Invalid("SIO0 receive FIFO length"));
+        }
+        self.rx_fifo.clear();
+        for _ in 0..len {
+            self.rx_fifo.push_back(r.read_u8()?);
+        }
+        self.ack_low = r.read_bool()?;
+        self.irq = r.read_bool()?;
+        self.mode = r.read_u16()?;
+        self.control = r.read_u16()?;
+        self.baud = r.read_u16()?;
+        Ok(())
+    }
+}
+
+impl Default for Sio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn raise_irq(psx: &mut Psx) {
+    if !psx.sio.irq {
+        psx.sio.irq = true;
+        psx.irq.request(Interrupt::Controller);
+    }
+}
+
+// Start sending the pending byte if transfers are enabled and the line is
+// free
+fn start_transfer(psx: &mut Psx) {
+    let sio = &mut psx.sio;
+    if sio.transfer.is_some() || sio.control & CTRL_TX_ENABLE == 0 {
+        return;
+    }
+    let tx = match sio.tx_pending.take() {
+        Some(tx) => tx,
+        None => return,
+    };
+    let (rx, ack) = match sio.selected {
+        Some(port) => sio.ports[port].transfer(tx),
+        None => (0xff, false),
+    };
+    sio.transfer = Some((rx, ack));
+    let cycles = sio.byte_cycles();
+    psx.scheduler.schedule(Event::Sio0Transfer, cycles);
+    if psx.sio.control & CTRL_TX_IRQ != 0 {
+        raise_irq(psx);
+    }
+}
+
+// Run an SIO0 scheduler event
+pub fn handle_event(psx: &mut Psx, event: Event) {
+    match event {
+        Event::Sio0Transfer => {
+            let sio = &mut psx.sio;
+            let (rx, ack) = match sio.transfer.take() {
+                Some(transfer) => transfer,
+                None => return,
+            };
+            // Bytes are only received while a port is selected or reception
+            // is forced on
+            if sio.selected.is_some() || sio.control & CTRL_RX_ENABLE != 0 {
+                if sio.rx_fifo.len() == RX_FIFO_SIZE {
+                    // Overrun: the last byte is overwritten
+                    sio.rx_fifo.pop_back();
+                }
+                sio.rx_fifo.push_back(rx);
+                if sio.control & CTRL_RX_IRQ != 0 && sio.rx_fifo.len() >= sio.rx_irq_level() {
+                    raise_irq(psx);
+                }
+            }
+            if ack {
+                psx.scheduler.schedule(Event::Sio0Ack, ACK_DELAY_CYCLES);
+            }
+            start_transfer(psx);
+        }
+        Event::Lightpen => psx.irq.request(Interrupt::Lightpen),
+        Event::Sio0Ack => {
+            // The first event pulls /ACK low, the second releases it
+            if psx.sio.ack_low {
+                psx.sio.ack_low = false;
+            } else {
+                psx.sio.ack_low = true;
+                if psx.sio.control & CTRL_ACK_IRQ != 0 {
+                    raise_irq(psx);
+                }
+                psx.scheduler.schedule(Event::Sio0Ack, ACK_CYCLES);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Show the frame about to be displayed to the controllers, and trigger the
+// lightpen interrupt where a lightgun sees the beam
+pub fn frame(psx: &mut Psx) {
+    let area = psx.gpu.display_area();
+    let mut beam = None;
+    for port in psx.sio.ports.iter_mut() {
+        let slots = if port.multitap.is_some() { SLOTS } else { 1 };
+        for device in port.controllers[..slots].iter_mut().flatten() {
+            beam = beam.or(device.frame(&area));
+        }
+    }
+    match beam {
+        Some((x, line)) => {
+            let cycles = psx.gpu.cycles_until_beam(psx.scheduler.now(), x, line);
+            psx.scheduler.schedule(Event::Lightpen, cycles);
+        }
+        None => psx.scheduler.cancel(Event::Lightpen),
+    }
+}
+
+// Reset the interface on JOY_CTRL bit 6
+pub fn reset(psx: &mut Psx) {
+    psx.scheduler.cancel(Event::Sio0Transfer);
+    psx.scheduler.cancel(Event::Sio0Ack);
+    let sio = &mut psx.sio;
+    sio.tx_pending = None;
+    sio.transfer = None;
+    sio.rx_fifo.clear();
+    sio.ack_low = false;
+    sio.irq = false;
+    sio.mode = 0;
+    sio.control = 0;
+    select(sio, None);
+}
+
+// Change the selected port, ending the sequence of the previous one
+fn select(sio: &mut Sio, port: Option<usize>) {
+    if sio.selected != port {
+        if let Some(previous) = sio.selected {
+            sio.ports[previous].deselect();
+        }
+        sio.selected = port;
+    }
+}
+
+// Read an SIO0 register at `offset` from 1F801040h
+pub fn load(psx: &mut Psx, offset: u32) -> u32 {
+    let sio = &mut psx.sio;
+    match offset {
+        // An empty FIFO reads as FFh
+        0x0..=0x3 => match sio.rx_fifo.pop_front() {
+            Some(rx) => rx as u32,
+            None => 0xff,
+        },
+        0x4..=0x7 => sio.status() >> ((offset & 3) * 8),
+        0x8 | 0x9 => (sio.mode >> ((offset & 1) * 8)) as u32,
+        0xa | 0xb => (sio.control >> ((offset & 1) * 8)) as u32,
+        0xe | 0xf => (sio.baud >> ((offset & 1) * 8)) as u32,
+        _ => 0,
+    }
+}
+
+// Write an SIO0 register at `offset` from 1F801040h
+pub fn store(psx: &mut Psx, offset: u32, val: u32) {
+    match offset {
+        0x0 => {
+            psx.sio.tx_pending = Some(val as u8);
+            start_transfer(psx);
+        }
+        0x8 => psx.sio.mode = val as u16,
+        0xa => {
+            let control = val as u16;
+            if control & CTRL_RESET != 0 {
+                reset(psx);
+                return;
+            }
+            let sio = &mut psx.sio;
+            if control & CTRL_ACKNOWLEDGE != 0 {
+                sio.irq = false;
+            }
+            // Acknowledge and reset are write-only
+            sio.control = control & !(CTRL_ACKNOWLEDGE | CTRL_RESET);
+            let port = match control & CTRL_SELECT {
+                0 => None,
+                _ => Some((control & CTRL_PORT_2 != 0) as usize),
+            };
+            select(sio, port);
+            start_transfer(psx);
+        }
+        0xe => psx.sio.baud = val as u16,
+        _ => {}
+    }
+}
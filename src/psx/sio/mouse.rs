@@ -0,0 +1,110 @@
+use super::{Device, InputState, MouseButton};
+use crate::psx::state::{Savestate, StateError, StateReader, StateWriter};
+
+// Controller ID: mouse, 2 halfwords of data
+const MOUSE_ID: u16 = 0x5a12;
+
+// PlayStation Mouse (SCPH-1090). It answers command 42h like a pad, with the
+// buttons in bits 10 (right) and 11 (left) of the report followed by the
+// motion since the last read:
+//   01h -> FFh, 42h -> 12h, 00h -> 5Ah
+//   00h -> FFh   unused buttons
+//   00h -> btns  bit 2 right, bit 3 left, 0 when pressed
+//   00h -> X     signed motion, positive right
+//   00h -> Y     signed motion, positive down
+pub struct Mouse {
+    input: InputState,
+    // Motion not reported yet
+    delta: (i32, i32),
+    // Motion in the report being sent, taken when the command starts
+    report: (i8, i8),
+    // Position in the current command
+    step: usize,
+}
+
+impl Mouse {
+    pub fn new() -> Self {
+        Self {
+            input: InputState::new(),
+            delta: (0, 0),
+            report: (0, 0),
+            step: 0,
+        }
+    }
+
+    // Take up to a byte's worth of the pending motion
+    fn take_motion(&mut self) -> (i8, i8) {
+        let (dx, dy) = self.delta;
+        let x = dx.clamp(i8::MIN as i32, i8::MAX as i32);
+        let y = dy.clamp(i8::MIN as i32, i8::MAX as i32);
+        // What doesn't fit is left for the next read
+        self.delta = (dx - x, dy - y);
+        (x as i8, y as i8)
+    }
+}
+
+impl Default for Mouse {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for Mouse {
+    fn transfer(&mut self, tx: u8) -> (u8, bool) {
+        let step = self.step;
+        self.step += 1;
+        match step {
+            0 => (0xff, true),
+            1 if tx == 0x42 => {
+                self.report = self.take_motion();
+                (MOUSE_ID as u8, true)
+            }
+            2 => ((MOUSE_ID >> 8) as u8, true),
+            3 => (0xff, true),
+            4 => {
+                let mut buttons = 0xff;
+                if self.input.mouse_button(MouseButton::Right) {
+                    buttons &= !0x04;
+                }
+                if self.input.mouse_button(MouseButton::Left) {
+                    buttons &= !0x08;
+                }
+                (buttons, true)
+            }
+            5 => (self.report.0 as u8, true),
+            6 => (self.report.1 as u8, false),
+            _ => {
+                self.step = 7;
+                (0xff, false)
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.step = 0;
+    }
+
+    fn set_input(&mut self, input: &InputState) {
+        let (dx, dy) = input.pointer_delta();
+        self.delta.0 = self.delta.0.saturating_add(dx);
+        self.delta.1 = self.delta.1.saturating_add(dy);
+        self.input = *input;
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        self.input.save_state(w);
+        w.write_i32(self.delta.0);
+        w.write_i32(self.delta.1);
+        w.write_u8(self.report.0 as u8);
+        w.write_u8(self.report.1 as u8);
+        w.write_u32(self.step as u32);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.input.load_state(r)?;
+        self.delta = (r.read_i32()?, r.read_i32()?);
+        self.report = (r.read_u8()? as i8, r.read_u8()? as i8);
+        self.step = r.read_u32()? as usize;
+        Ok(())
+    }
+}
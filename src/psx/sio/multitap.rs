@@ -0,0 +1,167 @@
+use super::{Device, SLOTS};
+use crate::psx::state::{Savestate, StateError, StateReader, StateWriter};
+
+// Bytes of each slot's reply in a read of all slots: ID and 6 data bytes
+const SLOT_BYTES: usize = 8;
+
+type Slots = [Option<Box<dyn Device>>; SLOTS];
+
+// What the current transfer sequence is addressed to
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Target {
+    // One device passed through: 01h-04h address the controllers of slots
+    // A-D, 81h-84h their memory cards
+    Controller(usize),
+    MemoryCard(usize),
+    // The controllers of all four slots in one sequence
+    All,
+    None,
+}
+
+// Multitap adapter (SCPH-1070). Each device can be addressed on its own
+// through it; with the TAP byte (the byte after the command) of the previous
+// sequence set to 01h, address 01h instead reads the four controllers:
+//   01h -> FFh, 42h -> 80h, TAP -> 5Ah
+// then 8 bytes per slot, the multitap forwarding each slot's command bytes
+// and sending back its ID and data, FFh for missing ones.
+pub struct Multitap {
+    // Read all slots on the next 01h address
+    all: bool,
+    target: Target,
+    // Position in the current sequence
+    step: usize,
+    // The controller of the slot being read in a read of all slots
+    // acknowledged its last byte
+    slot_ack: bool,
+}
+
+impl Multitap {
+    pub fn new() -> Self {
+        Self {
+            all: false,
+            target: Target::None,
+            step: 0,
+            slot_ack: false,
+        }
+    }
+
+    pub fn transfer(
+        &mut self,
+        controllers: &mut Slots,
+        memory_cards: &mut Slots,
+        tx: u8,
+    ) -> (u8, bool) {
+        let step = self.step;
+        self.step += 1;
+        if step == 0 {
+            self.target = match tx {
+                0x01 if self.all => Target::All,
+                0x01..=0x04 => Target::Controller((tx - 0x01) as usize),
+                0x81..=0x84 => Target::MemoryCard((tx - 0x81) as usize),
+                _ => Target::None,
+            };
+        }
+        match self.target {
+            Target::Controller(slot) => {
+                if step == 2 {
+                    self.all = tx == 0x01;
+                }
+                // The device sees the address it would directly on the port
+                let tx = if step == 0 { 0x01 } else { tx };
+                forward(&mut controllers[slot], tx)
+            }
+            Target::MemoryCard(slot) => {
+                let tx = if step == 0 { 0x81 } else { tx };
+                forward(&mut memory_cards[slot], tx)
+            }
+            Target::All => self.transfer_all(controllers, step, tx),
+            Target::None => (0xff, false),
+        }
+    }
+
+    fn transfer_all(&mut self, controllers: &mut Slots, step: usize, tx: u8) -> (u8, bool) {
+        match step {
+            0 => (0xff, true),
+            1 => (0x80, true),
+            2 => {
+                self.all = tx == 0x01;
+                (0x5a, true)
+            }
+            _ if step - 3 < SLOTS * SLOT_BYTES => {
+                let index = step - 3;
+                let slot = &mut controllers[index / SLOT_BYTES];
+                if index.is_multiple_of(SLOT_BYTES) {
+                    // Address the slot's controller before its command
+                    if let Some(device) = slot.as_mut() {
+                        device.reset();
+                    }
+                    self.slot_ack = forward(slot, 0x01).1;
+                }
+                let rx = if self.slot_ack {
+                    let (rx, ack) = forward(slot, tx);
+                    self.slot_ack = ack;
+                    rx
+                } else {
+                    0xff
+                };
+                // The multitap acknowledges every byte but the last
+                (rx, index + 1 < SLOTS * SLOT_BYTES)
+            }
+            _ => (0xff, false),
+        }
+    }
+
+    // The port was deselected
+    pub fn reset(&mut self) {
+        self.step = 0;
+        self.target = Target::None;
+    }
+}
+
+impl Savestate for Multitap {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.all);
+        let (target, slot) = match self.target {
+            Target::Controller(slot) => (0, slot),
+            Target::MemoryCard(slot) => (1, slot),
+            Target::All => (2, 0),
+            Target::None => (3, 0),
+        };
+        w.write_u8(target);
+        w.write_u8(slot as u8);
+        w.write_u32(self.step as u32);
+        w.write_bool(self.slot_ack);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.all = r.read_bool()?;
+        let target = r.read_u8()?;
+        let slot = r.read_u8()? as usize;
+        if slot >= SLOTS {
+            return Err(StateError::Invalid("multitap slot"));
+        }
+        self.target = match target {
+            0 => Target::Controller(slot),
+            1 => Target::MemoryCard(slot),
+            2 => Target::All,
+            3 => Target::None,
+            _ => return Err(StateError::Invalid("multitap target")),
+        };
+        self.step = r.read_u32()? as usize;
+        self.slot_ack = r.read_bool()?;
+        Ok(())
+    }
+}
+
+impl Default for Multitap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn forward(device: &mut Option<Box<dyn Device>>, tx: u8) -> (u8, bool) {
+    match device.as_mut() {
+        Some(device) => device.transfer(tx),
+        None => (0xff, false),
+    }
+}
@@ -0,0 +1,92 @@
+use super::{Button, Device, InputState};
+use crate::psx::state::{Savestate, StateError, StateReader, StateWriter};
+
+// Controller ID: neGcon, 3 halfwords of data
+const NEGCON_ID: u16 = 0x5a23;
+
+// Digital buttons of the neGcon, by their bits in a pad's report: Start,
+// the D-pad, R (R1), B (Triangle) and A (Circle)
+const NEGCON_BUTTONS: u16 = 0x38f8;
+
+// Namco neGcon. The two halves twist around the middle for steering, and I,
+// II and L are analog:
+//   01h -> FFh, 42h -> 23h, 00h -> 5Ah
+//   then the buttons, twist (00h left, 80h center, FFh right), I, II and L
+// Twist comes from the left stick's X axis; I, II and L from the pressure of
+// Cross, Square and L1.
+pub struct NeGcon {
+    input: InputState,
+    reply: [u8; 6],
+    // Position in the current command
+    step: usize,
+}
+
+impl NeGcon {
+    pub fn new() -> Self {
+        Self {
+            input: InputState::new(),
+            reply: [0; 6],
+            step: 0,
+        }
+    }
+}
+
+impl Default for NeGcon {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for NeGcon {
+    fn transfer(&mut self, tx: u8) -> (u8, bool) {
+        let step = self.step;
+        self.step += 1;
+        match step {
+            0 => (0xff, true),
+            1 if tx == 0x42 => {
+                let input = &self.input;
+                let buttons = input.button_bits() | !NEGCON_BUTTONS;
+                self.reply = [
+                    buttons as u8,
+                    (buttons >> 8) as u8,
+                    input.left_stick().0,
+                    input.pressure(Button::Cross),
+                    input.pressure(Button::Square),
+                    input.pressure(Button::L1),
+                ];
+                (NEGCON_ID as u8, true)
+            }
+            2 => ((NEGCON_ID >> 8) as u8, true),
+            3..=8 => (self.reply[step - 3], step < 8),
+            _ => {
+                self.step = 9;
+                (0xff, false)
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.step = 0;
+    }
+
+    fn set_input(&mut self, input: &InputState) {
+        self.input = *input;
+    }
+
+    fn input(&self) -> Option<&InputState> {
+        Some(&self.input)
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        self.input.save_state(w);
+        w.write_bytes(&self.reply);
+        w.write_u32(self.step as u32);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.input.load_state(r)?;
+        r.read_into(&mut self.reply)?;
+        self.step = r.read_u32()? as usize;
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use super::{Device, InputState};
+use crate::psx::state::{Savestate, StateError, StateReader, StateWriter};
+
+// Controller ID: digital pad, 1 halfword of data
+const DIGITAL_PAD_ID: u16 = 0x5a41;
+
+// Standard digital controller (SCPH-1080). The only command it knows is 42h,
+// read buttons:
+//   01h -> FFh   address
+//   42h -> 41h   ID low byte
+//   00h -> 5Ah   ID high byte
+//   00h -> btns  Select, L3, R3, Start, Up, Right, Down, Left
+//   00h -> btns  L2, R2, L1, R1, Triangle, Circle, Cross, Square
+pub struct DigitalPad {
+    input: InputState,
+    // Position in the current command
+    step: usize,
+}
+
+impl DigitalPad {
+    pub fn new() -> Self {
+        Self {
+            input: InputState::new(),
+            step: 0,
+        }
+    }
+}
+
+impl Default for DigitalPad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for DigitalPad {
+    fn transfer(&mut self, tx: u8) -> (u8, bool) {
+        let step = self.step;
+        self.step += 1;
+        let buttons = self.input.button_bits();
+        match step {
+            0 => (0xff, true),
+            1 if tx == 0x42 => (DIGITAL_PAD_ID as u8, true),
+            2 => ((DIGITAL_PAD_ID >> 8) as u8, true),
+            3 => (buttons as u8, true),
+            // The last byte isn't acknowledged
+            4 => ((buttons >> 8) as u8, false),
+            // Unknown command or past the end: ignore the rest
+            _ => {
+                self.step = 5;
+                (0xff, false)
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.step = 0;
+    }
+
+    fn set_input(&mut self, input: &InputState) {
+        self.input = *input;
+    }
+
+    fn input(&self) -> Option<&InputState> {
+        Some(&self.input)
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        self.input.save_state(w);
+        w.write_u32(self.step as u32);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.input.load_state(r)?;
+        self.step = r.read_u32()? as usize;
+        Ok(())
+    }
+}
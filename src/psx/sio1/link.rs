@@ -0,0 +1,121 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+
+// What travels over the link cable: data bytes, and changes of the DTR and
+// RTS outputs which are the other side's DSR and CTS inputs
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LinkMessage {
+    Data(u8),
+    Lines { dtr: bool, rts: bool },
+}
+
+// Connection to the other console of a link cable
+pub trait LinkTransport: Send {
+    fn send(&mut self, message: LinkMessage) -> io::Result<()>;
+
+    // Next message from the other side, without waiting
+    fn poll(&mut self) -> io::Result<Option<LinkMessage>>;
+}
+
+// Both ends of a link between two emulators in the same process
+pub struct Loopback {
+    tx: Sender<LinkMessage>,
+    rx: Receiver<LinkMessage>,
+}
+
+impl Loopback {
+    // Two transports connected to each other
+    pub fn pair() -> (Self, Self) {
+        let (a_tx, b_rx) = channel();
+        let (b_tx, a_rx) = channel();
+        (Self { tx: a_tx, rx: a_rx }, Self { tx: b_tx, rx: b_rx })
+    }
+}
+
+fn disconnected() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "link cable disconnected")
+}
+
+impl LinkTransport for Loopback {
+    fn send(&mut self, message: LinkMessage) -> io::Result<()> {
+        self.tx.send(message).map_err(|_| disconnected())
+    }
+
+    fn poll(&mut self) -> io::Result<Option<LinkMessage>> {
+        match self.rx.try_recv() {
+            Ok(message) => Ok(Some(message)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(disconnected()),
+        }
+    }
+}
+
+// Link to an emulator on another machine. Messages are two bytes: 00h and a
+// data byte, or 01h and the lines (bit 0 DTR, bit 1 RTS).
+pub struct TcpLink {
+    stream: TcpStream,
+    // Bytes of a partly received message
+    pending: Vec<u8>,
+}
+
+impl TcpLink {
+    // Wait for the other side to connect
+    pub fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        Self::new(stream)
+    }
+
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(Self {
+            stream,
+            pending: Vec::with_capacity(2),
+        })
+    }
+}
+
+impl LinkTransport for TcpLink {
+    fn send(&mut self, message: LinkMessage) -> io::Result<()> {
+        let bytes = match message {
+            LinkMessage::Data(byte) => [0x00, byte],
+            LinkMessage::Lines { dtr, rts } => [0x01, dtr as u8 | (rts as u8) << 1],
+        };
+        // The socket doesn't block, but two bytes always fit unless the
+        // other side stopped reading altogether
+        self.stream.set_nonblocking(false)?;
+        let result = self.stream.write_all(&bytes);
+        self.stream.set_nonblocking(true)?;
+        result
+    }
+
+    fn poll(&mut self) -> io::Result<Option<LinkMessage>> {
+        while self.pending.len() < 2 {
+            let mut byte = [0u8];
+            match self.stream.read(&mut byte) {
+                Ok(0) => return Err(disconnected()),
+                Ok(_) => self.pending.push(byte[0]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+        let (kind, value) = (self.pending[0], self.pending[1]);
+        self.pending.clear();
+        match kind {
+            0x00 => Ok(Some(LinkMessage::Data(value))),
+            0x01 => Ok(Some(LinkMessage::Lines {
+                dtr: value & 1 != 0,
+                rts: value & 2 != 0,
+            })),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "bad link cable message",
+            )),
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use super::irq::Interrupt;
+use super::scheduler::Event;
+use super::state::{Savestate, StateError, StateReader, StateWriter};
+use super::Psx;
+
+use std::collections::VecDeque;
+
+mod link;
+
+pub use link::{LinkMessage, LinkTransport, Loopback, TcpLink};
+
+// Size of the receive FIFO
+const RX_FIFO_SIZE: usize = 8;
+
+// CPU cycles between checks for bytes from the other console
+const POLL_CYCLES: u64 = 2048;
+
+// SIO_STAT bits
+const STAT_TX_READY: u32 = 0x0001;
+const STAT_RX_NOT_EMPTY: u32 = 0x0002;
+const STAT_TX_FINISHED: u32 = 0x0004;
+const STAT_RX_OVERRUN: u32 = 0x0010;
+const STAT_DSR: u32 = 0x0080;
+const STAT_CTS: u32 = 0x0100;
+const STAT_IRQ: u32 = 0x0200;
+
+// SIO_CTRL bits
+const CTRL_TX_ENABLE: u16 = 0x0001;
+const CTRL_DTR: u16 = 0x0002;
+const CTRL_RX_ENABLE: u16 = 0x0004;
+const CTRL_ACKNOWLEDGE: u16 = 0x0010;
+const CTRL_RTS: u16 = 0x0020;
+const CTRL_RESET: u16 = 0x0040;
+const CTRL_TX_IRQ: u16 = 0x0400;
+const CTRL_RX_IRQ: u16 = 0x0800;
+const CTRL_DSR_IRQ: u16 = 0x1000;
+
+// SIO1, the serial port used by the link cable. It's a plain UART: bytes
+// written are sent to the other console through the transport, and bytes
+// from it land in the receive FIFO. DTR and RTS are wired to the other
+// side's DSR and CTS.
+pub struct Sio1 {
+    link: Option<Box<dyn LinkTransport>>,
+    // Byte waiting for the one being sent
+    tx_pending: Option<u8>,
+    // Byte being sent
+    transfer: Option<u8>,
+    rx_fifo: VecDeque<u8>,
+    overrun: bool,
+    // Inputs driven by the other side
+    dsr: bool,
+    cts: bool,
+    irq: bool,
+    // 1F801058h SIO_MODE
+    mode: u16,
+    // 1F80105Ah SIO_CTRL
+    control: u16,
+    // 1F80105Ch SIO_MISC
+    misc: u16,
+    // 1F80105Eh SIO_BAUD
+    baud: u16,
+}
+
+impl Sio1 {
+    pub fn new() -> Self {
+        Self {
+            link: None,
+            tx_pending: None,
+            transfer: None,
+            rx_fifo: VecDeque::with_capacity(RX_FIFO_SIZE),
+            overrun: false,
+            dsr: false,
+            cts: false,
+            irq: false,
+            mode: 0,
+            control: 0,
+            misc: 0,
+            baud: 0,
+        }
+    }
+
+    // Is a link cable plugged in?
+    pub fn linked(&self) -> bool {
+        self.link.is_some()
+    }
+
+    fn status(&self) -> u32 {
+        let mut stat = 0;
+        if self.tx_pending.is_none() {
+            stat |= STAT_TX_READY;
+        }
+        if !self.rx_fifo.is_empty() {
+            stat |= STAT_RX_NOT_EMPTY;
+        }
+        if self.tx_pending.is_none() && self.transfer.is_none() {
+            stat |= STAT_TX_FINISHED;
+        }
+        if self.overrun {
+            stat |= STAT_RX_OVERRUN;
+        }
+        if self.dsr {
+            stat |= STAT_DSR;
+        }
+        if self.cts {
+            stat |= STAT_CTS;
+        }
+        if self.irq {
+            stat |= STAT_IRQ;
+        }
+        stat
+    }
+
+    // CPU cycles to send a byte: start bit, 8 data bits and stop bit at the
+    // baud rate, whose reload value is multiplied by the SIO_MODE factor
+    fn byte_cycles(&self) -> u64 {
+        let factor = match self.mode & 3 {
+            2 => 16,
+            3 => 64,
+            _ => 1,
+        };
+        (self.baud as u64 * factor * 10).max(1)
+    }
+
+    // Bytes received before the RX interrupt fires, from SIO_CTRL bits 8-9
+    fn rx_irq_level(&self) -> usize {
+        1 << ((self.control >> 8) & 3)
+    }
+
+    // Send a message, unplugging the cable if the transport fails
+    fn send(&mut self, message: LinkMessage) {
+        if let Some(link) = self.link.as_mut() {
+            if link.send(message).is_err() {
+                self.disconnect();
+            }
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.link = None;
+        self.dsr = false;
+        self.cts = false;
+    }
+
+    fn send_lines(&mut self) {
+        let dtr = self.control & CTRL_DTR != 0;
+        let rts = self.control & CTRL_RTS != 0;
+        self.send(LinkMessage::Lines { dtr, rts });
+    }
+}
+
+// The link and the DSR and CTS lines it drives belong to the other console,
+// which isn't rolled back with this one
+impl Savestate for Sio1 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.tx_pending.is_some());
+        w.write_u8(self.tx_pending.unwrap_or(0));
+        w.write_bool(self.transfer.is_some());
+        w.write_u8(self.transfer.unwrap_or(0));
+        w.write_u8(self.rx_fifo.len() as u8);
+        for &byte in self.rx_fifo.iter() {
+            w.write_u8(byte);
+        }
+        w.write_bool(self.overrun);
+        w.write_bool(self.irq);
+        w.write_u16(self.mode);
+        w.write_u16(self.control);
+        w.write_u16(self.misc);
+        w.write_u16(self.baud);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let pending = r.read_bool()?;
+        let tx = r.read_u8()?;
+        self.tx_pending = if pending { Some(tx) } else { None };
+        let transferring = r.read_bool()?;
+        let byte = r.read_u8()?;
+        self.transfer = if transferring { Some(byte) } else { None };
+        let len = r.read_u8()? as usize;
+        if len > RX_FIFO_SIZE {
+            return Err(StateError::Invalid("SIO1 receive FIFO length"));
+        }
+        self.rx_fifo.clear();
+        for _ in 0..len {
+            self.rx_fifo.push_back(r.read_u8()?);
+        }
+        self.overrun = r.read_bool()?;
+        self.irq = r.read_bool()?;
+        self.mode = r.read_u16()?;
+        self.control = r.read_u16()?;
+        self.misc = r.read_u16()?;
+        self.baud = r.read_u16()?;
+        Ok(())
+    }
+}
+
+// Restore the port after the scheduler, keeping the cable polled as long as
+// one is plugged in and telling the other side about the restored lines
+pub fn load_state(psx: &mut Psx, r: &mut StateReader) -> Result<(), StateError> {
+    psx.sio1.load_state(r)?;
+    if psx.sio1.link.is_some() {
+        psx.sio1.send_lines();
+        if !psx.scheduler.is_scheduled(Event::Sio1Poll) {
+            psx.scheduler.schedule(Event::Sio1Poll, POLL_CYCLES);
+        }
+    } else {
+        psx.scheduler.cancel(Event::Sio1Poll);
+    }
+    Ok(())
+}
+
+impl Default for Sio1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Plug a link cable into the serial port, or unplug it with None
+pub fn connect(psx: &mut Psx, link: Option<Box<dyn LinkTransport>>) {
+    psx.sio1.disconnect();
+    psx.sio1.link = link;
+    if psx.sio1.link.is_some() {
+        psx.sio1.send_lines();
+        psx.scheduler.schedule(Event::Sio1Poll, POLL_CYCLES);
+    } else {
+        psx.scheduler.cancel(Event::Sio1Poll);
+    }
+}
+
+fn raise_irq(psx: &mut Psx) {
+    if !psx.sio1.irq {
+        psx.sio1.irq = true;
+        psx.irq.request(Interrupt::Sio);
+    }
+}
+
+fn start_transfer(psx: &mut Psx) {
+    let sio = &mut psx.sio1;
+    if sio.transfer.is_some() || sio.control & CTRL_TX_ENABLE == 0 {
+        return;
+    }
+    if let Some(tx) = sio.tx_pending.take() {
+        sio.transfer = Some(tx);
+        let cycles = sio.byte_cycles();
+        psx.scheduler.schedule(Event::Sio1Transfer, cycles);
+        if psx.sio1.control & CTRL_TX_IRQ != 0 {
+            raise_irq(psx);
+        }
+    }
+}
+
+fn receive(psx: &mut Psx, message: LinkMessage) {
+    let sio = &mut psx.sio1;
+    match message {
+        LinkMessage::Data(byte) => {
+            if sio.control & CTRL_RX_ENABLE == 0 {
+                return;
+            }
+            if sio.rx_fifo.len() == RX_FIFO_SIZE {
+                sio.rx_fifo.pop_back();
+                sio.overrun = true;
+            }
+            sio.rx_fifo.push_back(byte);
+            if sio.control & CTRL_RX_IRQ != 0 && sio.rx_fifo.len() >= sio.rx_irq_level() {
+                raise_irq(psx);
+            }
+        }
+        LinkMessage::Lines { dtr, rts } => {
+            let raised = dtr && !sio.dsr;
+            sio.dsr = dtr;
+            sio.cts = rts;
+            if raised && sio.control & CTRL_DSR_IRQ != 0 {
+                raise_irq(psx);
+            }
+        }
+    }
+}
+
+// Run an SIO1 scheduler event
+pub fn handle_event(psx: &mut Psx, event: Event) {
+    match event {
+        Event::Sio1Transfer => {
+            if let Some(tx) = psx.sio1.transfer.take() {
+                psx.sio1.send(LinkMessage::Data(tx));
+            }
+            start_transfer(psx);
+        }
+        Event::Sio1Poll => {
+            loop {
+                let message = match psx.sio1.link.as_mut().map(|link| link.poll()) {
+                    Some(Ok(Some(message))) => message,
+                    Some(Ok(None)) => break,
+                    Some(Err(_)) => {
+                        psx.sio1.disconnect();
+                        break;
+                    }
+                    None => return,
+                };
+                receive(psx, message);
+            }
+            if psx.sio1.link.is_some() {
+                psx.scheduler.schedule(Event::Sio1Poll, POLL_CYCLES);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn reset(psx: &mut Psx) {
+    psx.scheduler.cancel(Event::Sio1Transfer);
+    let sio = &mut psx.sio1;
+    sio.tx_pending = None;
+    sio.transfer = None;
+    sio.rx_fifo.clear();
+    sio.overrun = false;
+    sio.irq = false;
+    sio.mode = 0;
+    sio.control = 0;
+    sio.misc = 0;
+    sio.send_lines();
+}
+
+// Read an SIO1 register at `offset` from 1F801050h
+pub fn load(psx: &mut Psx, offset: u32) -> u32 {
+    let sio = &mut psx.sio1;
+    match offset {
+        0x0..=0x3 => sio.rx_fifo.pop_front().map_or(0xff, |rx| rx as u32),
+        0x4..=0x7 => sio.status() >> ((offset & 3) * 8),
+        0x8 | 0x9 => (sio.mode >> ((offset & 1) * 8)) as u32,
+        0xa | 0xb => (sio.control >> ((offset & 1) * 8)) as u32,
+        0xc | 0xd => (sio.misc >> ((offset & 1) * 8)) as u32,
+        0xe | 0xf => (sio.baud >> ((offset & 1) * 8)) as u32,
+        _ => 0,
+    }
+}
+
+// Write an SIO1 register at `offset` from 1F801050h
+pub fn store(psx: &mut Psx, offset: u32, val: u32) {
+    match offset {
+        0x0 => {
+            psx.sio1.tx_pending = Some(val as u8);
+            start_transfer(psx);
+        }
+        0x8 => psx.sio1.mode = val as u16,
+        0xa => {
+            let control = val as u16;
+            if control & CTRL_RESET != 0 {
+                reset(psx);
+                return;
+            }
+            let sio = &mut psx.sio1;
+            if control & CTRL_ACKNOWLEDGE != 0 {
+                sio.irq = false;
+                sio.overrun = false;
+            }
+            let lines = CTRL_DTR | CTRL_RTS;
+            let changed = (sio.control ^ control) & lines != 0;
+            sio.control = control & !(CTRL_ACKNOWLEDGE | CTRL_RESET);
+            if changed {
+                sio.send_lines();
+            }
+            start_transfer(psx);
+        }
+        0xc => psx.sio1.misc = val as u16,
+        0xe => psx.sio1.baud = val as u16,
+        _ => {}
+    }
+}
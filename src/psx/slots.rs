@@ -0,0 +1,131 @@
+use super::{Error, Psx};
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// Numbered save state slots, kept in a directory as files named after the
+// game: SLUS-01234.03.state for slot 3. States are written to a temporary
+// file first and renamed over the slot, so a crash while saving leaves the
+// previous state.
+pub struct StateSlots {
+    dir: PathBuf,
+}
+
+// A slot holding a state
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlotInfo {
+    pub slot: u32,
+    pub path: PathBuf,
+    // When the state was saved
+    pub modified: SystemTime,
+    pub size: u64,
+}
+
+impl StateSlots {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self, serial: &str, slot: u32) -> PathBuf {
+        self.dir
+            .join(format!("{}.{:02}.state", file_name(serial), slot))
+    }
+
+    // Save the state of `psx` in slot `slot` of the running game
+    pub fn save(&self, psx: &mut Psx, slot: u32) -> Result<PathBuf, Error> {
+        let path = self.path(&serial(psx)?, slot);
+        fs::create_dir_all(&self.dir).map_err(Error::Io)?;
+        write_atomic(&path, |file| psx.save_state(file))?;
+        Ok(path)
+    }
+
+    pub fn load(&self, psx: &mut Psx, slot: u32) -> Result<(), Error> {
+        let path = self.path(&serial(psx)?, slot);
+        let file = File::open(path).map_err(Error::Io)?;
+        psx.load_state(BufReader::new(file))
+    }
+
+    // Empty a slot. Deleting an empty slot isn't an error.
+    pub fn delete(&self, serial: &str, slot: u32) -> Result<(), Error> {
+        match fs::remove_file(self.path(serial, slot)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(Error::Io(e)),
+            _ => Ok(()),
+        }
+    }
+
+    // Slots of a game holding a state, by slot number
+    pub fn list(&self, serial: &str) -> Result<Vec<SlotInfo>, Error> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Error::Io(e)),
+        };
+        let prefix = format!("{}.", file_name(serial));
+        let mut slots = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(Error::Io)?;
+            let name = entry.file_name();
+            let slot = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(&prefix))
+                .and_then(|rest| rest.strip_suffix(".state"))
+                .and_then(|slot| slot.parse().ok());
+            if let Some(slot) = slot {
+                let metadata = entry.metadata().map_err(Error::Io)?;
+                slots.push(SlotInfo {
+                    slot,
+                    path: entry.path(),
+                    modified: metadata.modified().map_err(Error::Io)?,
+                    size: metadata.len(),
+                });
+            }
+        }
+        slots.sort_by_key(|info| info.slot);
+        Ok(slots)
+    }
+}
+
+// Serial of the running game, which states are filed under
+pub fn serial(psx: &Psx) -> Result<String, Error> {
+    psx.game_info()
+        .map(|info| info.serial)
+        .ok_or_else(|| Error::Config("no game to keep save states for".into()))
+}
+
+// Serials are file name safe, but keep anything else out of the path
+pub fn file_name(serial: &str) -> String {
+    serial
+        .chars()
+        .map(|c| match c {
+            'A'..='Z' | 'a'..='z' | '0'..='9' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+// Write a file through a temporary one renamed over it once complete
+pub fn write_atomic<F>(path: &Path, write: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<(), Error>,
+{
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let result = File::create(&tmp).map_err(Error::Io).and_then(|file| {
+        let mut file = BufWriter::new(file);
+        write(&mut file)?;
+        let file = file.into_inner().map_err(|e| Error::Io(e.into_error()))?;
+        file.sync_all().map_err(Error::Io)
+    });
+    match result.and_then(|()| fs::rename(&tmp, path).map_err(Error::Io)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
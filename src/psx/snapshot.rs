@@ -0,0 +1,453 @@
+use super::state::{Savestate, StateError, StateReader, StateWriter};
+use super::{hle, mdec, sio1, spu, Error, Psx};
+
+use std::borrow::Cow;
+use std::io::{self, Read, Write};
+
+// Save states start with a magic, the format version, how the sections are
+// compressed and the length of a description of the state for state
+// pickers, the description, followed by a section per component: a 4 byte
+// tag, the section's version, the length of its data, the data. Version 1
+// had no section versions, versions 1 and 2 no compression, versions 1 to 3
+// no description.
+const MAGIC: &[u8; 8] = b"PSXSTATE";
+const VERSION: u32 = 4;
+// Header of in-memory snapshots, which have an empty description
+const SNAPSHOT_HEADER_LEN: usize = 17;
+
+// Picture of the game saved with a state, 24 bit RGB rows top to bottom
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Thumbnail {
+    pub width: u16,
+    pub height: u16,
+    pub pixels: Vec<u8>,
+}
+
+// Description of a save state file, readable without loading the state
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StateInfo {
+    // Serial of the game, empty without a disc
+    pub serial: String,
+    // Frames run since power on, the emulated play time
+    pub frames: u64,
+    // When the state was saved, in seconds since the Unix epoch
+    pub created: u64,
+    pub thumbnail: Option<Thumbnail>,
+}
+
+impl StateInfo {
+    fn write(&self, w: &mut StateWriter) {
+        w.write_u16(self.serial.len() as u16);
+        w.write_bytes(self.serial.as_bytes());
+        w.write_u64(self.frames);
+        w.write_u64(self.created);
+        w.write_bool(self.thumbnail.is_some());
+        if let Some(thumbnail) = self.thumbnail.as_ref() {
+            w.write_u16(thumbnail.width);
+            w.write_u16(thumbnail.height);
+            w.write_bytes(&thumbnail.pixels);
+        }
+    }
+
+    fn read(r: &mut StateReader) -> Result<Self, StateError> {
+        let len = r.read_u16()? as usize;
+        let serial = String::from_utf8_lossy(r.read_bytes(len)?).into_owned();
+        let frames = r.read_u64()?;
+        let created = r.read_u64()?;
+        let thumbnail = match r.read_bool()? {
+            true => {
+                let width = r.read_u16()?;
+                let height = r.read_u16()?;
+                let len = width as usize * height as usize * 3;
+                let pixels = r.read_bytes(len)?.to_vec();
+                Some(Thumbnail {
+                    width,
+                    height,
+                    pixels,
+                })
+            }
+            false => None,
+        };
+        Ok(Self {
+            serial,
+            frames,
+            created,
+            thumbnail,
+        })
+    }
+}
+
+// Compression of the sections of save state files. In-memory snapshots are
+// never compressed, to keep them fast.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    // Zstandard at its fastest level, a few times smaller: RAM and the SPU
+    // RAM are mostly zeros and repeated data
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+    fn id(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => 1,
+        }
+    }
+}
+
+// Sections in the order they're loaded, with their current version. The
+// scheduler comes first, for the components that fix up their events after
+// it. A component whose saved data changes gets its version bumped and a
+// migration from the previous one.
+const SECTIONS: [(&[u8; 4], u16); 16] = [
+    (b"SCHD", 1),
+    (b"CPU ", 1),
+    (b"RAM ", 1),
+    (b"SPAD", 1),
+    (b"SYS ", 1),
+    (b"IRQ ", 1),
+    (b"DMA ", 1),
+    (b"TIMR", 1),
+    (b"GPU ", 1),
+    (b"MDEC", 1),
+    (b"SPU ", 1),
+    (b"CDRM", 1),
+    (b"SIO0", 1),
+    (b"SIO1", 1),
+    (b"CART", 1),
+    (b"HLE ", 1),
+];
+
+// Converts a section's data to the next version
+type Migration = fn(&[u8]) -> Result<Vec<u8>, StateError>;
+
+// Migrations by section and the version they convert from. Each one takes
+// the data one version up, until the section is current.
+const MIGRATIONS: &[(&[u8; 4], u16, Migration)] = &[];
+
+// A section as found in a state
+struct Section<'a> {
+    tag: &'a [u8],
+    version: u16,
+    data: &'a [u8],
+}
+
+fn tag_name(tag: &[u8]) -> String {
+    format!("{} section", String::from_utf8_lossy(tag).trim_end())
+}
+
+// Bring a section's data up to the current version
+fn migrate<'a>(section: &Section<'a>, version: u16) -> Result<Cow<'a, [u8]>, StateError> {
+    let mut data = Cow::Borrowed(section.data);
+    if section.version > version {
+        return Err(StateError::NewerVersion {
+            part: tag_name(section.tag),
+            version: section.version as u32,
+        });
+    }
+    for from in section.version..version {
+        let migration = MIGRATIONS
+            .iter()
+            .find(|&&(tag, v, _)| tag == section.tag && v == from)
+            .map(|&(_, _, migration)| migration)
+            .ok_or_else(|| StateError::OlderVersion {
+                part: tag_name(section.tag),
+                version: section.version as u32,
+            })?;
+        data = Cow::Owned(migration(&data)?);
+    }
+    Ok(data)
+}
+
+// Copy of the machine state in memory, to return to later: the building
+// block of run-ahead and rollback, and what save states are made of. Taking
+// or restoring one copies RAM and the SPU RAM, a few MB, well under a
+// millisecond.
+//
+// The state of the console is covered: CPU, memory, every peripheral with
+// its pending events, the controllers and memory cards in their slots and
+// the HLE kernel. What the frontend set up isn't: the disc, which devices
+// are plugged in, sinks, settings, and audio not pulled yet.
+#[derive(Clone, Default)]
+pub struct Snapshot {
+    data: Vec<u8>,
+}
+
+impl Snapshot {
+    pub fn new() -> Self {
+        Self { data: Vec::new() }
+    }
+
+    // Snapshot of a save state file's contents, checked when restored
+    pub fn from_bytes(data: Vec<u8>) -> Self {
+        Self { data }
+    }
+
+    // Size of the state in bytes
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+// Capture the state into `snapshot`, reusing its memory
+pub fn save(psx: &mut Psx, snapshot: &mut Snapshot) {
+    let mut w = StateWriter::with_buffer(std::mem::take(&mut snapshot.data));
+    w.write_bytes(MAGIC);
+    w.write_u32(VERSION);
+    w.write_u8(Compression::None.id());
+    w.write_u32(0);
+    for &(tag, version) in SECTIONS.iter() {
+        w.write_bytes(tag);
+        w.write_u16(version);
+        w.write_nested(|w| save_section(psx, tag, w));
+    }
+    snapshot.data = w.into_inner();
+}
+
+// State of each component, by section tag without padding ("CPU", "GPU"),
+// for tools comparing machines part by part
+pub fn components(psx: &mut Psx) -> Vec<(String, Vec<u8>)> {
+    SECTIONS
+        .iter()
+        .map(|&(tag, _)| {
+            let mut w = StateWriter::new();
+            save_section(psx, tag, &mut w);
+            let name = String::from_utf8_lossy(tag).trim_end().to_string();
+            (name, w.into_inner())
+        })
+        .collect()
+}
+
+fn save_section(psx: &mut Psx, tag: &[u8; 4], w: &mut StateWriter) {
+    match tag {
+        b"SCHD" => psx.scheduler.save_state(w),
+        // The CPU core has no COP0 or GTE state of its own yet
+        b"CPU " => psx.cpu.save_state(w),
+        b"RAM " => w.write_bytes(&psx.ram.dat),
+        b"SPAD" => w.write_bytes(&psx.scratchpad.dat[..]),
+        b"SYS " => {
+            w.write_u32(psx.cache_control);
+            w.write_u64(psx.frames);
+        }
+        b"IRQ " => psx.irq.save_state(w),
+        b"DMA " => psx.dma.save_state(w),
+        b"TIMR" => psx.timers.save_state(w),
+        b"GPU " => psx.gpu.save_state(w),
+        b"MDEC" => mdec::save_state(psx, w),
+        b"SPU " => spu::save_state(psx, w),
+        b"CDRM" => psx.cdrom.save_state(w),
+        b"SIO0" => psx.sio.save_state(w),
+        b"SIO1" => psx.sio1.save_state(w),
+        b"CART" => {
+            w.write_bool(psx.cartridge.is_some());
+            if let Some(cartridge) = psx.cartridge.as_ref() {
+                cartridge.save_state(w);
+            }
+        }
+        b"HLE " => {
+            w.write_bool(psx.hle.is_some());
+            if let Some(hle) = psx.hle.as_ref() {
+                hle.save_state(w);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Write a save state file of `snapshot`, described by `info`, with its
+// sections compressed
+pub fn write_file<W: Write>(
+    writer: &mut W,
+    snapshot: &Snapshot,
+    compression: Compression,
+    info: &StateInfo,
+) -> io::Result<()> {
+    let mut header = StateWriter::new();
+    header.write_bytes(MAGIC);
+    header.write_u32(VERSION);
+    header.write_u8(compression.id());
+    header.write_nested(|w| info.write(w));
+    writer.write_all(&header.into_inner())?;
+    let sections = &snapshot.data[SNAPSHOT_HEADER_LEN..];
+    match compression {
+        Compression::None => writer.write_all(sections),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => {
+            use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+            writer.write_all(&compress_to_vec(sections, CompressionLevel::Fastest))
+        }
+    }
+}
+
+// Read the description at the start of a save state file. States from
+// before descriptions have an empty one.
+pub fn read_info<R: Read>(mut reader: R) -> Result<StateInfo, Error> {
+    let read = |reader: &mut R, len: usize| -> Result<Vec<u8>, Error> {
+        let mut data = vec![0; len];
+        reader.read_exact(&mut data).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => Error::SaveState(StateError::UnexpectedEof),
+            _ => Error::Io(e),
+        })?;
+        Ok(data)
+    };
+    let header = read(&mut reader, MAGIC.len() + 4)?;
+    let mut r = StateReader::new(&header);
+    if r.read_bytes(MAGIC.len())? != MAGIC {
+        return Err(StateError::Invalid("not a save state").into());
+    }
+    let format = r.read_u32()?;
+    if format < 4 {
+        return Ok(StateInfo::default());
+    }
+    let header = read(&mut reader, 5)?;
+    let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if len == 0 {
+        return Ok(StateInfo::default());
+    }
+    let info = read(&mut reader, len)?;
+    Ok(StateInfo::read(&mut StateReader::new(&info))?)
+}
+
+// Sections of a state, decompressed if needed
+fn decompress(id: u8, data: &[u8]) -> Result<Cow<'_, [u8]>, StateError> {
+    match id {
+        0 => Ok(Cow::Borrowed(data)),
+        #[cfg(feature = "zstd")]
+        1 => {
+            use std::io::Read;
+            let corrupted = StateError::Invalid("corrupted compressed state");
+            let mut decoder =
+                ruzstd::decoding::StreamingDecoder::new(data).map_err(|_| corrupted.clone())?;
+            let mut sections = Vec::new();
+            decoder.read_to_end(&mut sections).map_err(|_| corrupted)?;
+            Ok(Cow::Owned(sections))
+        }
+        #[cfg(not(feature = "zstd"))]
+        1 => Err(StateError::Invalid(
+            "state is zstd compressed, which needs the zstd feature",
+        )),
+        _ => Err(StateError::Invalid("save state compression")),
+    }
+}
+
+// Go back to the state in `snapshot`
+pub fn restore(psx: &mut Psx, snapshot: &Snapshot) -> Result<(), StateError> {
+    let mut r = StateReader::new(&snapshot.data);
+    if r.read_bytes(MAGIC.len())? != MAGIC {
+        return Err(StateError::Invalid("not a save state"));
+    }
+    let format = r.read_u32()?;
+    match format {
+        1..=VERSION => {}
+        0 => return Err(StateError::Invalid("save state version")),
+        _ => {
+            return Err(StateError::NewerVersion {
+                part: "save state format".into(),
+                version: format,
+            })
+        }
+    }
+    let compression = if format >= 3 { r.read_u8()? } else { 0 };
+    // The description isn't needed to load, and in-memory snapshots have
+    // none
+    if format >= 4 {
+        r.read_nested()?;
+    }
+    let data = decompress(compression, r.read_bytes(r.remaining())?)?;
+    let mut r = StateReader::new(&data);
+    let mut sections = Vec::with_capacity(SECTIONS.len());
+    while r.remaining() > 0 {
+        let tag = r.read_bytes(4)?;
+        // Sections of version 1 states are all at their first version
+        let version = if format == 1 { 1 } else { r.read_u16()? };
+        let len = r.read_u32()? as usize;
+        let data = r.read_bytes(len)?;
+        sections.push(Section { tag, version, data });
+    }
+    // Check and migrate every section before touching the machine
+    let mut current = Vec::with_capacity(SECTIONS.len());
+    for &(tag, version) in SECTIONS.iter() {
+        let index = sections
+            .iter()
+            .position(|section| section.tag == tag)
+            .ok_or(StateError::Invalid("missing section"))?;
+        let section = sections.swap_remove(index);
+        current.push((tag, migrate(&section, version)?));
+    }
+    if let Some(section) = sections.first() {
+        // Sections are only added along with a format version bump
+        return Err(StateError::NewerVersion {
+            part: tag_name(section.tag),
+            version: section.version as u32,
+        });
+    }
+    for (tag, data) in current.iter() {
+        let mut section = StateReader::new(data);
+        load_section(psx, tag, &mut section)?;
+        if section.remaining() != 0 {
+            return Err(StateError::Invalid("section longer than its contents"));
+        }
+    }
+    // The frame at the restored point was already shown
+    psx.runner.set_reported_frame(psx.frames);
+    Ok(())
+}
+
+fn load_section(psx: &mut Psx, tag: &[u8; 4], r: &mut StateReader) -> Result<(), StateError> {
+    match tag {
+        b"SCHD" => psx.scheduler.load_state(r)?,
+        b"CPU " => psx.cpu.load_state(r)?,
+        b"RAM " => r.read_into(&mut psx.ram.dat)?,
+        b"SPAD" => r.read_into(&mut psx.scratchpad.dat[..])?,
+        b"SYS " => {
+            psx.cache_control = r.read_u32()?;
+            psx.frames = r.read_u64()?;
+        }
+        b"IRQ " => psx.irq.load_state(r)?,
+        b"DMA " => psx.dma.load_state(r)?,
+        b"TIMR" => psx.timers.load_state(r)?,
+        b"GPU " => psx.gpu.load_state(r)?,
+        b"MDEC" => psx.mdec.load_state(r)?,
+        b"SPU " => spu::load_state(psx, r)?,
+        b"CDRM" => psx.cdrom.load_state(r)?,
+        b"SIO0" => psx.sio.load_state(r)?,
+        b"SIO1" => sio1::load_state(psx, r)?,
+        // A cartridge is only restored into one that's plugged in, the rest
+        // of the section is then skipped
+        b"CART" => {
+            if let (true, Some(cartridge)) = (r.read_bool()?, psx.cartridge.as_mut()) {
+                cartridge.load_state(r)?;
+            } else {
+                r.read_bytes(r.remaining())?;
+            }
+        }
+        b"HLE " => {
+            psx.hle = match r.read_bool()? {
+                true => {
+                    let mut hle = hle::Hle::new();
+                    hle.load_state(r)?;
+                    Some(hle)
+                }
+                false => None,
+            };
+        }
+        _ => {}
+    }
+    Ok(())
+}
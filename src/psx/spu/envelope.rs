@@ -0,0 +1,265 @@
+use crate::psx::state::{Savestate, StateError, StateReader, StateWriter};
+
+// ADSR envelope phases
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AdsrPhase {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+
+// Parameters of a single envelope phase
+struct Slope {
+    exponential: bool,
+    decreasing: bool,
+    shift: u32,
+    step: i32,
+}
+
+#[derive(Clone, Copy)]
+pub struct Adsr {
+    // ADSR configuration (low/high registers)
+    pub config: u32,
+    // Current envelope level (0..7FFFh)
+    pub level: i16,
+    pub phase: AdsrPhase,
+    // Samples left until the next level change
+    counter: u32,
+}
+
+impl Adsr {
+    pub fn new() -> Self {
+        Self {
+            config: 0,
+            level: 0,
+            phase: AdsrPhase::Off,
+            counter: 0,
+        }
+    }
+
+    pub fn attack(&mut self) {
+        self.level = 0;
+        self.phase = AdsrPhase::Attack;
+        self.counter = 0;
+    }
+
+    pub fn release(&mut self) {
+        if self.phase != AdsrPhase::Off {
+            self.phase = AdsrPhase::Release;
+            self.counter = 0;
+        }
+    }
+
+    // Silence the voice immediately
+    pub fn off(&mut self) {
+        self.level = 0;
+        self.phase = AdsrPhase::Off;
+    }
+
+    // Level the decay phase stops at
+    fn sustain_level(&self) -> i32 {
+        (((self.config & 0xf) + 1) * 0x800) as i32
+    }
+
+    fn slope(&self) -> Option<Slope> {
+        let config = self.config;
+        let slope = match self.phase {
+            AdsrPhase::Attack => Slope {
+                exponential: config & 0x8000 != 0,
+                decreasing: false,
+                shift: (config >> 10) & 0x1f,
+                step: 7 - ((config >> 8) & 3) as i32,
+            },
+            AdsrPhase::Decay => Slope {
+                exponential: true,
+                decreasing: true,
+                shift: (config >> 4) & 0xf,
+                step: -8,
+            },
+            AdsrPhase::Sustain => {
+                let decreasing = config & (1 << 30) != 0;
+                let step = ((config >> 22) & 3) as i32;
+                Slope {
+                    exponential: config & (1 << 31) != 0,
+                    decreasing,
+                    shift: (config >> 24) & 0x1f,
+                    step: if decreasing { -8 + step } else { 7 - step },
+                }
+            }
+            AdsrPhase::Release => Slope {
+                exponential: config & (1 << 21) != 0,
+                decreasing: true,
+                shift: (config >> 16) & 0x1f,
+                step: -8,
+            },
+            AdsrPhase::Off => return None,
+        };
+        Some(slope)
+    }
+
+    // Advance the envelope by one sample
+    pub fn tick(&mut self) {
+        let slope = match self.slope() {
+            Some(slope) => slope,
+            None => return,
+        };
+
+        if self.counter > 0 {
+            self.counter -= 1;
+            return;
+        }
+
+        let (cycles, level) = envelope_step(
+            self.level,
+            slope.exponential,
+            slope.decreasing,
+            slope.shift,
+            slope.step,
+        );
+        self.counter = cycles - 1;
+        self.level = level;
+
+        match self.phase {
+            AdsrPhase::Attack if self.level == 0x7fff => self.phase = AdsrPhase::Decay,
+            AdsrPhase::Decay if (self.level as i32) <= self.sustain_level() => {
+                self.phase = AdsrPhase::Sustain
+            }
+            AdsrPhase::Release if self.level == 0 => self.phase = AdsrPhase::Off,
+            _ => {}
+        }
+    }
+}
+
+impl Savestate for Adsr {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u32(self.config);
+        w.write_i16(self.level);
+        w.write_u8(self.phase as u8);
+        w.write_u32(self.counter);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.config = r.read_u32()?;
+        self.level = r.read_i16()?;
+        self.phase = match r.read_u8()? {
+            0 => AdsrPhase::Attack,
+            1 => AdsrPhase::Decay,
+            2 => AdsrPhase::Sustain,
+            3 => AdsrPhase::Release,
+            4 => AdsrPhase::Off,
+            _ => return Err(StateError::Invalid("ADSR phase")),
+        };
+        self.counter = r.read_u32()?;
+        Ok(())
+    }
+}
+
+impl Default for Adsr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Volume register, either a fixed level or a sweep
+#[derive(Clone, Copy)]
+pub struct Volume {
+    // Raw register value
+    pub reg: u16,
+    // Current level
+    pub level: i16,
+    // Samples left until the next sweep step
+    counter: u32,
+}
+
+impl Volume {
+    pub fn new() -> Self {
+        Self {
+            reg: 0,
+            level: 0,
+            counter: 0,
+        }
+    }
+
+    pub fn set(&mut self, val: u16) {
+        self.reg = val;
+        self.counter = 0;
+        // Fixed mode: bits 0-14 hold volume/2
+        if val & 0x8000 == 0 {
+            self.level = (val << 1) as i16;
+        }
+    }
+
+    // Advance a sweep by one sample
+    pub fn tick(&mut self) {
+        if self.reg & 0x8000 == 0 {
+            return;
+        }
+        if self.counter > 0 {
+            self.counter -= 1;
+            return;
+        }
+
+        let exponential = self.reg & 0x4000 != 0;
+        let decreasing = self.reg & 0x2000 != 0;
+        let negative = self.reg & 0x1000 != 0;
+        let shift = ((self.reg >> 2) & 0x1f) as u32;
+        let step = (self.reg & 3) as i32;
+        let step = if decreasing { -8 + step } else { 7 - step };
+
+        // The sweep runs on the magnitude, the phase bit selects the sign
+        let magnitude = if negative {
+            (-(self.level as i32)).clamp(0, 0x7fff) as i16
+        } else {
+            self.level.max(0)
+        };
+        let (cycles, magnitude) = envelope_step(magnitude, exponential, decreasing, shift, step);
+        self.counter = cycles - 1;
+        self.level = if negative { -magnitude } else { magnitude };
+    }
+}
+
+impl Default for Volume {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Savestate for Volume {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u16(self.reg);
+        w.write_i16(self.level);
+        w.write_u32(self.counter);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.reg = r.read_u16()?;
+        self.level = r.read_i16()?;
+        self.counter = r.read_u32()?;
+        Ok(())
+    }
+}
+
+// Compute one envelope step shared by the ADSR and volume sweeps. Returns the
+// number of samples to wait before the next step and the new level.
+pub fn envelope_step(
+    level: i16,
+    exponential: bool,
+    decreasing: bool,
+    shift: u32,
+    step: i32,
+) -> (u32, i16) {
+    let level = level as i32;
+    let mut cycles = 1u32 << shift.saturating_sub(11);
+    let mut step = step << 11u32.saturating_sub(shift);
+
+    if exponential && !decreasing && level > 0x6000 {
+        cycles *= 4;
+    }
+    if exponential && decreasing {
+        step = step * level / 0x8000;
+    }
+
+    (cycles, (level + step).clamp(0, 0x7fff) as i16)
+}
@@ -0,0 +1,114 @@
+// Interpolation used when resampling voices to the output rate
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Interpolation {
+    // Cheapest, audibly aliased
+    Linear,
+    // The hardware's 4-tap Gaussian filter
+    #[default]
+    Gaussian,
+    // Catmull-Rom spline, sharper than the hardware
+    Cubic,
+}
+
+// Interpolate between s[1] and s[2] at the position given by the 12 bit
+// fractional part of the pitch counter. `s` holds the last four samples,
+// oldest first.
+pub fn interpolate(mode: Interpolation, s: &[i16], counter: u32) -> i16 {
+    let s0 = s[0] as i32;
+    let s1 = s[1] as i32;
+    let s2 = s[2] as i32;
+    let s3 = s[3] as i32;
+
+    match mode {
+        Interpolation::Linear => {
+            let t = (counter & 0xfff) as i32;
+            (s1 + (((s2 - s1) * t) >> 12)) as i16
+        }
+        Interpolation::Gaussian => {
+            let i = ((counter >> 4) & 0xff) as usize;
+            let out = ((GAUSS_TABLE[0xff - i] as i32 * s0) >> 15)
+                + ((GAUSS_TABLE[0x1ff - i] as i32 * s1) >> 15)
+                + ((GAUSS_TABLE[0x100 + i] as i32 * s2) >> 15)
+                + ((GAUSS_TABLE[i] as i32 * s3) >> 15);
+            out.clamp(-0x8000, 0x7fff) as i16
+        }
+        Interpolation::Cubic => {
+            let t = (counter & 0xfff) as f32 / 4096.0;
+            let (s0, s1, s2, s3) = (s0 as f32, s1 as f32, s2 as f32, s3 as f32);
+            let a = -0.5 * s0 + 1.5 * s1 - 1.5 * s2 + 0.5 * s3;
+            let b = s0 - 2.5 * s1 + 2.0 * s2 - 0.5 * s3;
+            let c = -0.5 * s0 + 0.5 * s2;
+            let out = ((a * t + b) * t + c) * t + s1;
+            out.clamp(-32768.0, 32767.0) as i16
+        }
+    }
+}
+
+// Gaussian interpolation table
+#[rustfmt::skip]
+const GAUSS_TABLE: [i16; 512] = [
+    -1, -1, -1, -1, -1, -1, -1, -1,
+    -1, -1, -1, -1, -1, -1, -1, -1,
+    0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0001,
+    0x0001, 0x0001, 0x0001, 0x0002, 0x0002, 0x0002, 0x0003, 0x0003,
+    0x0003, 0x0004, 0x0004, 0x0005, 0x0005, 0x0006, 0x0007, 0x0007,
+    0x0008, 0x0009, 0x0009, 0x000a, 0x000b, 0x000c, 0x000d, 0x000e,
+    0x000f, 0x0010, 0x0011, 0x0012, 0x0013, 0x0015, 0x0016, 0x0018,
+    0x0019, 0x001b, 0x001c, 0x001e, 0x0020, 0x0021, 0x0023, 0x0025,
+    0x0027, 0x0029, 0x002c, 0x002e, 0x0030, 0x0033, 0x0035, 0x0038,
+    0x003a, 0x003d, 0x0040, 0x0043, 0x0046, 0x0049, 0x004d, 0x0050,
+    0x0054, 0x0057, 0x005b, 0x005f, 0x0063, 0x0067, 0x006b, 0x006f,
+    0x0074, 0x0078, 0x007d, 0x0082, 0x0087, 0x008c, 0x0091, 0x0096,
+    0x009c, 0x00a1, 0x00a7, 0x00ad, 0x00b3, 0x00ba, 0x00c0, 0x00c7,
+    0x00cd, 0x00d4, 0x00db, 0x00e3, 0x00ea, 0x00f2, 0x00fa, 0x0101,
+    0x010a, 0x0112, 0x011b, 0x0123, 0x012c, 0x0135, 0x013f, 0x0148,
+    0x0152, 0x015c, 0x0166, 0x0171, 0x017b, 0x0186, 0x0191, 0x019c,
+    0x01a8, 0x01b4, 0x01c0, 0x01cc, 0x01d9, 0x01e5, 0x01f2, 0x0200,
+    0x020d, 0x021b, 0x0229, 0x0237, 0x0246, 0x0255, 0x0264, 0x0273,
+    0x0283, 0x0293, 0x02a3, 0x02b4, 0x02c4, 0x02d6, 0x02e7, 0x02f9,
+    0x030b, 0x031d, 0x0330, 0x0343, 0x0356, 0x036a, 0x037e, 0x0392,
+    0x03a7, 0x03bc, 0x03d1, 0x03e7, 0x03fc, 0x0413, 0x042a, 0x0441,
+    0x0458, 0x0470, 0x0488, 0x04a0, 0x04b9, 0x04d2, 0x04ec, 0x0506,
+    0x0520, 0x053b, 0x0556, 0x0572, 0x058e, 0x05aa, 0x05c7, 0x05e4,
+    0x0601, 0x061f, 0x063e, 0x065c, 0x067c, 0x069b, 0x06bb, 0x06dc,
+    0x06fd, 0x071e, 0x0740, 0x0762, 0x0784, 0x07a7, 0x07cb, 0x07ef,
+    0x0813, 0x0838, 0x085d, 0x0883, 0x08a9, 0x08d0, 0x08f7, 0x091e,
+    0x0946, 0x096f, 0x0998, 0x09c1, 0x09eb, 0x0a16, 0x0a40, 0x0a6c,
+    0x0a98, 0x0ac4, 0x0af1, 0x0b1e, 0x0b4c, 0x0b7a, 0x0ba9, 0x0bd8,
+    0x0c07, 0x0c38, 0x0c68, 0x0c99, 0x0ccb, 0x0cfd, 0x0d30, 0x0d63,
+    0x0d97, 0x0dcb, 0x0e00, 0x0e35, 0x0e6b, 0x0ea1, 0x0ed7, 0x0f0f,
+    0x0f46, 0x0f7f, 0x0fb7, 0x0ff1, 0x102a, 0x1065, 0x109f, 0x10db,
+    0x1116, 0x1153, 0x118f, 0x11cd, 0x120b, 0x1249, 0x1288, 0x12c7,
+    0x1307, 0x1347, 0x1388, 0x13c9, 0x140b, 0x144d, 0x1490, 0x14d4,
+    0x1517, 0x155c, 0x15a0, 0x15e6, 0x162c, 0x1672, 0x16b9, 0x1700,
+    0x1747, 0x1790, 0x17d8, 0x1821, 0x186b, 0x18b5, 0x1900, 0x194b,
+    0x1996, 0x19e2, 0x1a2e, 0x1a7b, 0x1ac8, 0x1b16, 0x1b64, 0x1bb3,
+    0x1c02, 0x1c51, 0x1ca1, 0x1cf1, 0x1d42, 0x1d93, 0x1de5, 0x1e37,
+    0x1e89, 0x1edc, 0x1f2f, 0x1f82, 0x1fd6, 0x202a, 0x207f, 0x20d4,
+    0x2129, 0x217f, 0x21d5, 0x222c, 0x2282, 0x22da, 0x2331, 0x2389,
+    0x23e1, 0x2439, 0x2492, 0x24eb, 0x2545, 0x259e, 0x25f8, 0x2653,
+    0x26ad, 0x2708, 0x2763, 0x27be, 0x281a, 0x2876, 0x28d2, 0x292e,
+    0x298a, 0x29e7, 0x2a43, 0x2aa0, 0x2afd, 0x2b5a, 0x2bb8, 0x2c15,
+    0x2c73, 0x2cd1, 0x2d2f, 0x2d8c, 0x2dea, 0x2e48, 0x2ea7, 0x2f05,
+    0x2f64, 0x2fc2, 0x3020, 0x307f, 0x30dd, 0x313c, 0x319b, 0x31f9,
+    0x3258, 0x32b6, 0x3315, 0x3373, 0x33d2, 0x3430, 0x348f, 0x34ed,
+    0x354b, 0x35a9, 0x3607, 0x3665, 0x36c3, 0x3720, 0x377e, 0x37db,
+    0x3838, 0x3895, 0x38f2, 0x394f, 0x39ab, 0x3a07, 0x3a63, 0x3abf,
+    0x3b1a, 0x3b75, 0x3bd0, 0x3c2b, 0x3c85, 0x3cdf, 0x3d39, 0x3d92,
+    0x3deb, 0x3e44, 0x3e9c, 0x3ef4, 0x3f4c, 0x3fa3, 0x3ffa, 0x4050,
+    0x40a6, 0x40fc, 0x4151, 0x41a6, 0x41fa, 0x424e, 0x42a1, 0x42f4,
+    0x4346, 0x4398, 0x43e9, 0x443a, 0x448a, 0x44da, 0x4529, 0x4578,
+    0x45c6, 0x4613, 0x4660, 0x46ac, 0x46f8, 0x4743, 0x478d, 0x47d7,
+    0x4820, 0x4869, 0x48b1, 0x48f8, 0x493e, 0x4984, 0x49c9, 0x4a0e,
+    0x4a52, 0x4a95, 0x4ad7, 0x4b19, 0x4b5a, 0x4b9a, 0x4bd9, 0x4c18,
+    0x4c56, 0x4c93, 0x4cd0, 0x4d0b, 0x4d46, 0x4d80, 0x4db9, 0x4df1,
+    0x4e29, 0x4e5f, 0x4e95, 0x4eca, 0x4efe, 0x4f31, 0x4f64, 0x4f95,
+    0x4fc6, 0x4ff6, 0x5025, 0x5053, 0x5080, 0x50ac, 0x50d7, 0x5102,
+    0x512b, 0x5154, 0x517c, 0x51a3, 0x51c9, 0x51ee, 0x5212, 0x5235,
+    0x5257, 0x5278, 0x5298, 0x52b7, 0x52d5, 0x52f2, 0x530f, 0x532a,
+    0x5344, 0x535e, 0x5376, 0x538e, 0x53a4, 0x53ba, 0x53ce, 0x53e2,
+    0x53f5, 0x5408, 0x541a, 0x542c, 0x543d, 0x544d, 0x545d, 0x546c,
+    0x547b, 0x5489, 0x5496, 0x54a3, 0x54af, 0x54ba, 0x54c5, 0x54d0,
+    0x54d9, 0x54e2, 0x54eb, 0x54f3, 0x54fa, 0x5501, 0x5507, 0x550c,
+    0x5511, 0x5515, 0x5519, 0x551c, 0x551e, 0x5520, 0x5521, 0x5522,
+];
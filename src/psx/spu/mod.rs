@@ -0,0 +1,789 @@
+mod envelope;
+mod interpolation;
+mod thread;
+mod voice;
+
+use super::irq::Interrupt;
+use super::scheduler::Event;
+use super::state::{Savestate, StateError, StateReader, StateWriter};
+use super::Psx;
+
+use envelope::Volume;
+use std::collections::VecDeque;
+use voice::Voice;
+
+pub use envelope::AdsrPhase;
+pub use interpolation::Interpolation;
+pub use thread::SpuThread;
+
+// Sound RAM is 512 KB
+const SPU_RAM_SIZE: usize = 512 * 1024;
+
+// CPU cycles per 44100Hz output sample
+pub const CYCLES_PER_SAMPLE: u64 = 0x300;
+
+pub const NUM_VOICES: usize = 24;
+
+// Size of the data transfer FIFO in halfwords
+const FIFO_SIZE: usize = 32;
+
+// Samples per capture buffer
+const CAPTURE_SAMPLES: u32 = 0x200;
+
+// Capture buffers in sound RAM
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Capture {
+    // CD audio left, before volume processing
+    CdLeft = 0x000,
+    // CD audio right, before volume processing
+    CdRight = 0x400,
+    // Voice 1 output after the envelope
+    Voice1 = 0x800,
+    // Voice 3 output after the envelope
+    Voice3 = 0xc00,
+}
+
+pub struct Spu {
+    // Sound RAM
+    ram: Box<[u8]>,
+    voices: [Voice; NUM_VOICES],
+    // 1F801D80h Main Volume Left/Right
+    main_volume: [Volume; 2],
+    // 1F801D84h Reverb Output Volume Left/Right
+    reverb_volume: [u16; 2],
+    // 1F801D90h Pitch Modulation Enable Flags
+    pitch_mod: u32,
+    // 1F801D94h Noise Mode Enable
+    noise_mode: u32,
+    // 1F801D98h Reverb Mode (Echo On)
+    echo_on: u32,
+    // 1F801D9Ch Voice Status (ENDX)
+    endx: u32,
+    // 1F801DA2h Sound RAM Reverb Work Area Start Address
+    reverb_base: u16,
+    // Current position in the reverb work area (in bytes)
+    reverb_address: u32,
+    // 1F801DA4h Sound RAM IRQ Address
+    irq_address: u16,
+    // 1F801DA6h Sound RAM Data Transfer Address
+    transfer_address: u16,
+    // Current transfer position (in bytes)
+    transfer_current: u32,
+    // 1F801DA8h Sound RAM Data Transfer Fifo
+    fifo: VecDeque<u16>,
+    // 1F801DAAh SPU Control Register (SPUCNT)
+    control: u16,
+    // 1F801DACh Sound RAM Data Transfer Control
+    transfer_control: u16,
+    // 1F801DAEh SPU Status Register (SPUSTAT)
+    status: u16,
+    // SPUCNT bits 0-5 as mirrored in SPUSTAT, latched at the next sample
+    status_mode: u16,
+    // A manual write is draining the FIFO into sound RAM
+    transfer_busy: bool,
+    // 1F801DB0h CD Audio Input Volume Left/Right
+    cd_volume: [i16; 2],
+    // 1F801DB4h External Audio Input Volume Left/Right
+    ext_volume: [i16; 2],
+    // 1F801DC0h-1F801DFFh Reverb configuration
+    reverb_regs: [u16; 32],
+    // Position in the capture buffers
+    capture_index: u32,
+    // Pending CD audio input at 44100Hz (left, right)
+    cd_input: VecDeque<(i16, i16)>,
+    // Set when the IRQ address was hit, cleared once delivered
+    irq_pending: bool,
+    // Output of each voice after volume in the last sample (left, right)
+    voice_mix: [[i16; 2]; NUM_VOICES],
+    // Interpolation used for voice pitch conversion
+    interpolation: Interpolation,
+    // Debug: voices excluded from the mix
+    muted: u32,
+    // Debug: if non-zero, only these voices are mixed
+    solo: u32,
+}
+
+// Snapshot of a voice for debuggers and music rippers
+#[derive(Clone, Copy, Debug)]
+pub struct VoiceState {
+    // Address of the ADPCM block being played (in bytes)
+    pub address: u32,
+    pub start_address: u32,
+    pub repeat_address: u32,
+    pub phase: AdsrPhase,
+    // Envelope level (0..7FFFh)
+    pub envelope: i16,
+    // Left/right volume
+    pub volume: [i16; 2],
+    // ADPCM sample rate (1000h = 44100Hz)
+    pub pitch: u16,
+    // Is the voice excluded from the mix by mute/solo?
+    pub silenced: bool,
+}
+
+impl Spu {
+    pub fn new() -> Self {
+        Self {
+            ram: vec![0u8; SPU_RAM_SIZE].into_boxed_slice(),
+            voices: [Voice::new(); NUM_VOICES],
+            main_volume: [Volume::new(); 2],
+            reverb_volume: [0; 2],
+            pitch_mod: 0,
+            noise_mode: 0,
+            echo_on: 0,
+            endx: 0,
+            reverb_base: 0,
+            reverb_address: 0,
+            irq_address: 0,
+            transfer_address: 0,
+            transfer_current: 0,
+            fifo: VecDeque::with_capacity(FIFO_SIZE),
+            control: 0,
+            transfer_control: 0,
+            status: 0,
+            status_mode: 0,
+            transfer_busy: false,
+            cd_volume: [0; 2],
+            ext_volume: [0; 2],
+            reverb_regs: [0; 32],
+            capture_index: 0,
+            cd_input: VecDeque::new(),
+            irq_pending: false,
+            voice_mix: [[0; 2]; NUM_VOICES],
+            interpolation: Interpolation::default(),
+            muted: 0,
+            solo: 0,
+        }
+    }
+
+    // Back to the power on state, keeping sound RAM, which isn't cleared by
+    // a reset, and the debug settings
+    pub fn reset(&mut self) {
+        let ram = std::mem::take(&mut self.ram);
+        *self = Self {
+            ram,
+            interpolation: self.interpolation,
+            muted: self.muted,
+            solo: self.solo,
+            ..Self::new()
+        };
+    }
+
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+
+    // Select the voice interpolation, taking effect with the next sample
+    pub fn set_interpolation(&mut self, interpolation: Interpolation) {
+        self.interpolation = interpolation;
+    }
+
+    // Exclude a voice from the mix without affecting emulation. Voices past
+    // the last are ignored.
+    pub fn set_voice_muted(&mut self, voice: usize, muted: bool) {
+        if voice < NUM_VOICES {
+            set_bit(&mut self.muted, voice, muted);
+        }
+    }
+
+    // Solo a voice. While any voice is soloed only soloed voices are mixed.
+    pub fn set_voice_solo(&mut self, voice: usize, solo: bool) {
+        if voice < NUM_VOICES {
+            set_bit(&mut self.solo, voice, solo);
+        }
+    }
+
+    // Is the voice audible in the mix?
+    fn voice_audible(&self, voice: usize) -> bool {
+        let mask = 1 << voice;
+        self.muted & mask == 0 && (self.solo == 0 || self.solo & mask != 0)
+    }
+
+    // State of a voice, None past the last
+    pub fn voice_state(&self, voice: usize) -> Option<VoiceState> {
+        let v = self.voices.get(voice)?;
+        Some(VoiceState {
+            address: v.current_address,
+            start_address: v.start_address,
+            repeat_address: v.repeat_address,
+            phase: v.adsr.phase,
+            envelope: v.adsr.level,
+            volume: [v.volume[0].level, v.volume[1].level],
+            pitch: v.pitch,
+            silenced: !self.voice_audible(voice),
+        })
+    }
+
+    fn enabled(&self) -> bool {
+        self.control & 0x8000 != 0
+    }
+
+    fn irq_enabled(&self) -> bool {
+        self.control & 0x40 != 0
+    }
+
+    // Raise the SPU IRQ if `addr` (in bytes) falls in the given range
+    fn check_irq(&mut self, addr: u32, len: u32) {
+        let irq_addr = (self.irq_address as u32) << 3;
+        if self.irq_enabled() && irq_addr.wrapping_sub(addr) < len {
+            self.status |= 0x40;
+            self.irq_pending = true;
+        }
+    }
+
+    fn write_ram(&mut self, addr: u32, val: u16) {
+        let addr = addr as usize & (SPU_RAM_SIZE - 2);
+        self.ram[addr] = val as u8;
+        self.ram[addr + 1] = (val >> 8) as u8;
+        self.check_irq(addr as u32, 2);
+    }
+
+    fn read_ram(&mut self, addr: u32) -> u16 {
+        let addr = addr as usize & (SPU_RAM_SIZE - 2);
+        self.check_irq(addr as u32, 2);
+        self.ram[addr] as u16 | (self.ram[addr + 1] as u16) << 8
+    }
+
+    // Queue a stereo CD audio sample for mixing
+    pub fn push_cd_sample(&mut self, left: i16, right: i16) {
+        self.cd_input.push_back((left, right));
+    }
+
+    // Number of queued CD audio samples
+    pub fn cd_samples_queued(&self) -> usize {
+        self.cd_input.len()
+    }
+
+    // Read a 16 bit register at `offset` from 1F801C00h
+    pub fn load(&mut self, offset: u32) -> u16 {
+        let offset = offset & 0x3fe;
+        match offset {
+            0x000..=0x17f => {
+                let voice = &self.voices[(offset >> 4) as usize];
+                match offset & 0xf {
+                    0x0 => voice.volume[0].reg,
+                    0x2 => voice.volume[1].reg,
+                    0x4 => voice.pitch,
+                    0x6 => (voice.start_address >> 3) as u16,
+                    0x8 => voice.adsr.config as u16,
+                    0xa => (voice.adsr.config >> 16) as u16,
+                    0xc => voice.adsr.level as u16,
+                    _ => (voice.repeat_address >> 3) as u16,
+                }
+            }
+            0x180 => self.main_volume[0].reg,
+            0x182 => self.main_volume[1].reg,
+            0x184 => self.reverb_volume[0],
+            0x186 => self.reverb_volume[1],
+            0x188..=0x18f => 0,
+            0x190 => self.pitch_mod as u16,
+            0x192 => (self.pitch_mod >> 16) as u16,
+            0x194 => self.noise_mode as u16,
+            0x196 => (self.noise_mode >> 16) as u16,
+            0x198 => self.echo_on as u16,
+            0x19a => (self.echo_on >> 16) as u16,
+            0x19c => self.endx as u16,
+            0x19e => (self.endx >> 16) as u16,
+            0x1a2 => self.reverb_base,
+            0x1a4 => self.irq_address,
+            0x1a6 => self.transfer_address,
+            0x1a8 => 0,
+            0x1aa => self.control,
+            0x1ac => self.transfer_control,
+            0x1ae => self.status(),
+            0x1b0 => self.cd_volume[0] as u16,
+            0x1b2 => self.cd_volume[1] as u16,
+            0x1b4 => self.ext_volume[0] as u16,
+            0x1b6 => self.ext_volume[1] as u16,
+            0x1b8 => self.main_volume[0].level as u16,
+            0x1ba => self.main_volume[1].level as u16,
+            0x1c0..=0x1ff => self.reverb_regs[((offset - 0x1c0) >> 1) as usize],
+            0x200..=0x25f => {
+                let voice = &self.voices[((offset - 0x200) >> 2) as usize];
+                voice.volume[((offset >> 1) & 1) as usize].level as u16
+            }
+            _ => 0,
+        }
+    }
+
+    // Write a 16 bit register at `offset` from 1F801C00h
+    pub fn store(&mut self, offset: u32, val: u16) {
+        let offset = offset & 0x3fe;
+        match offset {
+            0x000..=0x17f => {
+                let voice = &mut self.voices[(offset >> 4) as usize];
+                match offset & 0xf {
+                    0x0 => voice.volume[0].set(val),
+                    0x2 => voice.volume[1].set(val),
+                    0x4 => voice.pitch = val,
+                    0x6 => voice.start_address = (val as u32) << 3,
+                    0x8 => voice.adsr.config = (voice.adsr.config & 0xffff0000) | val as u32,
+                    0xa => voice.adsr.config = (voice.adsr.config & 0xffff) | (val as u32) << 16,
+                    0xc => voice.adsr.level = val as i16,
+                    _ => voice.repeat_address = (val as u32) << 3,
+                }
+            }
+            0x180 => self.main_volume[0].set(val),
+            0x182 => self.main_volume[1].set(val),
+            0x184 => self.reverb_volume[0] = val,
+            0x186 => self.reverb_volume[1] = val,
+            0x188 => self.key_on(val as u32),
+            0x18a => self.key_on((val as u32) << 16),
+            0x18c => self.key_off(val as u32),
+            0x18e => self.key_off((val as u32) << 16),
+            0x190 => self.pitch_mod = set_lo(self.pitch_mod, val) & !1,
+            0x192 => self.pitch_mod = set_hi(self.pitch_mod, val),
+            0x194 => self.noise_mode = set_lo(self.noise_mode, val),
+            0x196 => self.noise_mode = set_hi(self.noise_mode, val),
+            0x198 => self.echo_on = set_lo(self.echo_on, val),
+            0x19a => self.echo_on = set_hi(self.echo_on, val),
+            0x19c | 0x19e => {}
+            0x1a2 => {
+                self.reverb_base = val;
+                self.reverb_address = (val as u32) << 3;
+            }
+            0x1a4 => self.irq_address = val,
+            0x1a6 => {
+                self.transfer_address = val;
+                self.transfer_current = (val as u32) << 3;
+            }
+            0x1a8 if self.fifo.len() < FIFO_SIZE => self.fifo.push_back(val),
+            0x1aa => self.set_control(val),
+            0x1ac => self.transfer_control = val,
+            0x1ae => {}
+            0x1b0 => self.cd_volume[0] = val as i16,
+            0x1b2 => self.cd_volume[1] = val as i16,
+            0x1b4 => self.ext_volume[0] = val as i16,
+            0x1b6 => self.ext_volume[1] = val as i16,
+            0x1c0..=0x1ff => self.reverb_regs[((offset - 0x1c0) >> 1) as usize] = val,
+            _ => {}
+        }
+    }
+
+    fn status(&self) -> u16 {
+        let mut status = self.status & 0x40;
+        status |= self.status_mode;
+        // DMA request (bit 7), with bit 8 for writes and bit 9 for reads
+        match (self.status_mode >> 4) & 3 {
+            2 => status |= 0x180,
+            3 => status |= 0x280,
+            _ => {}
+        }
+        if self.transfer_busy {
+            status |= 0x400;
+        }
+        if self.capture_index >= CAPTURE_SAMPLES / 2 {
+            status |= 0x800;
+        }
+        status
+    }
+
+    fn set_control(&mut self, val: u16) {
+        self.control = val;
+        // Acknowledge the IRQ by disabling it
+        if val & 0x40 == 0 {
+            self.status &= !0x40;
+        }
+        // Manual write starts draining the FIFO to sound RAM
+        if (val >> 4) & 3 == 1 && !self.fifo.is_empty() {
+            self.transfer_busy = true;
+        }
+    }
+
+    // Register changes only become visible to the SPU's own logic at the
+    // sample clock: the SPUSTAT mirror updates and manual transfers complete
+    fn latch_registers(&mut self) {
+        self.status_mode = self.control & 0x3f;
+        if self.transfer_busy {
+            while let Some(half) = self.fifo.pop_front() {
+                self.write_ram(self.transfer_current, half);
+                self.transfer_current = (self.transfer_current + 2) & (SPU_RAM_SIZE as u32 - 1);
+            }
+            self.transfer_busy = false;
+        }
+    }
+
+    fn key_on(&mut self, mask: u32) {
+        for i in 0..NUM_VOICES {
+            if mask & (1 << i) != 0 {
+                self.voices[i].key_on(&self.ram);
+                self.endx &= !(1 << i);
+            }
+        }
+    }
+
+    fn key_off(&mut self, mask: u32) {
+        for i in 0..NUM_VOICES {
+            if mask & (1 << i) != 0 {
+                self.voices[i].key_off();
+            }
+        }
+    }
+
+    // DMA write of one word to sound RAM
+    pub fn dma_write(&mut self, val: u32) {
+        for half in [val as u16, (val >> 16) as u16].iter() {
+            self.write_ram(self.transfer_current, *half);
+            self.transfer_current = (self.transfer_current + 2) & (SPU_RAM_SIZE as u32 - 1);
+        }
+    }
+
+    // DMA read of one word from sound RAM
+    pub fn dma_read(&mut self) -> u32 {
+        let lo = self.read_ram(self.transfer_current);
+        self.transfer_current = (self.transfer_current + 2) & (SPU_RAM_SIZE as u32 - 1);
+        let hi = self.read_ram(self.transfer_current);
+        self.transfer_current = (self.transfer_current + 2) & (SPU_RAM_SIZE as u32 - 1);
+        lo as u32 | (hi as u32) << 16
+    }
+
+    // Write a sample to one of the capture buffers
+    fn capture(&mut self, buffer: Capture, sample: i16) {
+        let addr = buffer as u32 + self.capture_index * 2;
+        self.write_ram(addr, sample as u16);
+    }
+
+    // Generate one stereo output sample
+    pub fn tick(&mut self) -> (i16, i16) {
+        self.latch_registers();
+
+        let mut left = 0i32;
+        let mut right = 0i32;
+
+        for i in 0..NUM_VOICES {
+            let audible = self.voice_audible(i);
+            let mut step = self.voices[i].pitch as u32;
+            if i > 0 && self.pitch_mod & (1 << i) != 0 {
+                let factor = self.voices[i - 1].output as i32 + 0x8000;
+                step = ((step as i32 * factor) >> 15) as u32 & 0xffff;
+            }
+            step = step.min(0x4000);
+
+            let voice = &mut self.voices[i];
+            let sample = if voice.adsr.phase == AdsrPhase::Off {
+                0
+            } else {
+                voice.sample(self.interpolation)
+            };
+            voice.output = ((sample as i32 * voice.adsr.level as i32) >> 15) as i16;
+            voice.adsr.tick();
+
+            let voice_left = (voice.output as i32 * voice.volume[0].level as i32) >> 15;
+            let voice_right = (voice.output as i32 * voice.volume[1].level as i32) >> 15;
+            voice.volume[0].tick();
+            voice.volume[1].tick();
+            self.voice_mix[i] = [voice_left as i16, voice_right as i16];
+            if audible {
+                left += voice_left;
+                right += voice_right;
+            }
+
+            if voice.advance(step) {
+                if voice.next_block(&self.ram) {
+                    self.endx |= 1 << i;
+                }
+                let addr = voice.current_address;
+                self.check_irq(addr, 16);
+            }
+        }
+
+        let (cd_left, cd_right) = self.cd_input.pop_front().unwrap_or((0, 0));
+        if self.control & 0x1 != 0 {
+            left += (cd_left as i32 * self.cd_volume[0] as i32) >> 15;
+            right += (cd_right as i32 * self.cd_volume[1] as i32) >> 15;
+        }
+
+        if self.enabled() {
+            self.capture(Capture::CdLeft, cd_left);
+            self.capture(Capture::CdRight, cd_right);
+            self.capture(Capture::Voice1, self.voices[1].output);
+            self.capture(Capture::Voice3, self.voices[3].output);
+            self.capture_index = (self.capture_index + 1) % CAPTURE_SAMPLES;
+        }
+
+        let main_left = self.main_volume[0].level as i32;
+        let main_right = self.main_volume[1].level as i32;
+        self.main_volume[0].tick();
+        self.main_volume[1].tick();
+
+        // Bit 15 enables the SPU, bit 14 unmutes it
+        if self.control & 0xc000 != 0xc000 {
+            return (0, 0);
+        }
+
+        let left = left.clamp(-0x8000, 0x7fff);
+        let right = right.clamp(-0x8000, 0x7fff);
+        let left = (left * main_left) >> 15;
+        let right = (right * main_right) >> 15;
+        (left as i16, right as i16)
+    }
+}
+
+impl Savestate for Spu {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.ram);
+        for voice in self.voices.iter() {
+            voice.save_state(w);
+        }
+        self.main_volume[0].save_state(w);
+        self.main_volume[1].save_state(w);
+        w.write_u16(self.reverb_volume[0]);
+        w.write_u16(self.reverb_volume[1]);
+        w.write_u32(self.pitch_mod);
+        w.write_u32(self.noise_mode);
+        w.write_u32(self.echo_on);
+        w.write_u32(self.endx);
+        w.write_u16(self.reverb_base);
+        w.write_u32(self.reverb_address);
+        w.write_u16(self.irq_address);
+        w.write_u16(self.transfer_address);
+        w.write_u32(self.transfer_current);
+        w.write_u8(self.fifo.len() as u8);
+        for &half in self.fifo.iter() {
+            w.write_u16(half);
+        }
+        w.write_u16(self.control);
+        w.write_u16(self.transfer_control);
+        w.write_u16(self.status);
+        w.write_u16(self.status_mode);
+        w.write_bool(self.transfer_busy);
+        w.write_i16(self.cd_volume[0]);
+        w.write_i16(self.cd_volume[1]);
+        w.write_i16(self.ext_volume[0]);
+        w.write_i16(self.ext_volume[1]);
+        for &reg in self.reverb_regs.iter() {
+            w.write_u16(reg);
+        }
+        w.write_u32(self.capture_index);
+        w.write_u32(self.cd_input.len() as u32);
+        for &(left, right) in self.cd_input.iter() {
+            w.write_i16(left);
+            w.write_i16(right);
+        }
+        w.write_bool(self.irq_pending);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.read_into(&mut self.ram)?;
+        for voice in self.voices.iter_mut() {
+            voice.load_state(r)?;
+        }
+        self.main_volume[0].load_state(r)?;
+        self.main_volume[1].load_state(r)?;
+        self.reverb_volume[0] = r.read_u16()?;
+        self.reverb_volume[1] = r.read_u16()?;
+        self.pitch_mod = r.read_u32()?;
+        self.noise_mode = r.read_u32()?;
+        self.echo_on = r.read_u32()?;
+        self.endx = r.read_u32()?;
+        self.reverb_base = r.read_u16()?;
+        self.reverb_address = r.read_u32()? & (SPU_RAM_SIZE as u32 - 1);
+        self.irq_address = r.read_u16()?;
+        self.transfer_address = r.read_u16()?;
+        self.transfer_current = r.read_u32()? & (SPU_RAM_SIZE as u32 - 1);
+        let fifo_len = r.read_u8()? as usize;
+        if fifo_len > FIFO_SIZE {
+            return Err(StateError::Invalid("SPU FIFO length"));
+        }
+        self.fifo.clear();
+        for _ in 0..fifo_len {
+            self.fifo.push_back(r.read_u16()?);
+        }
+        self.control = r.read_u16()?;
+        self.transfer_control = r.read_u16()?;
+        self.status = r.read_u16()?;
+        self.status_mode = r.read_u16()? & 0x3f;
+        self.transfer_busy = r.read_bool()?;
+        self.cd_volume[0] = r.read_i16()?;
+        self.cd_volume[1] = r.read_i16()?;
+        self.ext_volume[0] = r.read_i16()?;
+        self.ext_volume[1] = r.read_i16()?;
+        for reg in self.reverb_regs.iter_mut() {
+            *reg = r.read_u16()?;
+        }
+        self.capture_index = r.read_u32()? % CAPTURE_SAMPLES;
+        let cd_len = r.read_u32()? as usize;
+        if cd_len > r.remaining() / 4 {
+            return Err(StateError::UnexpectedEof);
+        }
+        self.cd_input.clear();
+        for _ in 0..cd_len {
+            let left = r.read_i16()?;
+            let right = r.read_i16()?;
+            self.cd_input.push_back((left, right));
+        }
+        self.irq_pending = r.read_bool()?;
+        Ok(())
+    }
+}
+
+impl Default for Spu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Generate the next output sample and push it to the audio buffer
+pub fn clock(psx: &mut Psx) {
+    match psx.spu_thread.as_mut() {
+        Some(thread) => {
+            let stems = psx.audio_dump.as_ref().is_some_and(|d| d.has_stems());
+            for output in thread.tick(stems) {
+                output_samples(psx, output);
+            }
+        }
+        None => {
+            let (left, right) = psx.spu.tick();
+            psx.audio.push(left, right);
+            if let Some(dump) = psx.audio_dump.as_mut() {
+                dump.write([left, right], &psx.spu.voice_mix);
+            }
+            if psx.spu.irq_pending {
+                psx.spu.irq_pending = false;
+                psx.irq.request(Interrupt::Spu);
+            }
+        }
+    }
+
+    psx.scheduler.schedule(Event::SpuSample, CYCLES_PER_SAMPLE);
+}
+
+fn output_samples(psx: &mut Psx, output: thread::Output) {
+    let no_voices = [[0; 2]; NUM_VOICES];
+    for (i, &[left, right]) in output.samples.iter().enumerate() {
+        psx.audio.push(left, right);
+        if let Some(dump) = psx.audio_dump.as_mut() {
+            dump.write([left, right], output.voices.get(i).unwrap_or(&no_voices));
+        }
+    }
+    if output.irq {
+        psx.irq.request(Interrupt::Spu);
+    }
+}
+
+// Push output the SPU thread finished since the last sample. In
+// deterministic mode it waits for every sample due so far, so the audio of
+// a frame doesn't depend on how fast the thread runs.
+pub fn collect(psx: &mut Psx) {
+    if let Some(thread) = psx.spu_thread.as_mut() {
+        let outputs = match psx.deterministic {
+            true => thread.finish(),
+            false => thread.collect(),
+        };
+        for output in outputs {
+            output_samples(psx, output);
+        }
+    }
+}
+
+// Push every sample due so far, waiting for the SPU thread to produce them
+pub fn drain(psx: &mut Psx) {
+    if let Some(thread) = psx.spu_thread.as_mut() {
+        for output in thread.finish() {
+            output_samples(psx, output);
+        }
+    }
+}
+
+// Read a register, waiting for the SPU thread to catch up if needed
+pub fn load(psx: &mut Psx, offset: u32) -> u16 {
+    with(psx, move |spu| spu.load(offset))
+}
+
+pub fn store(psx: &mut Psx, offset: u32, val: u16) {
+    match psx.spu_thread.as_mut() {
+        Some(thread) => thread.store(offset, val),
+        None => psx.spu.store(offset, val),
+    }
+}
+
+pub fn dma_write(psx: &mut Psx, val: u32) {
+    match psx.spu_thread.as_mut() {
+        Some(thread) => thread.dma_write(val),
+        None => psx.spu.dma_write(val),
+    }
+}
+
+pub fn dma_read(psx: &mut Psx) -> u32 {
+    with(psx, |spu| spu.dma_read())
+}
+
+// Queue a stereo CD audio sample for mixing
+pub fn push_cd_sample(psx: &mut Psx, left: i16, right: i16) {
+    match psx.spu_thread.as_mut() {
+        Some(thread) => thread.push_cd_sample(left, right),
+        None => psx.spu.push_cd_sample(left, right),
+    }
+}
+
+// Run `f` on the SPU, wherever it currently runs
+pub fn with<R, F>(psx: &mut Psx, f: F) -> R
+where
+    R: Send + 'static,
+    F: FnOnce(&mut Spu) -> R + Send + 'static,
+{
+    match psx.spu_thread.as_mut() {
+        Some(thread) => thread.call(f),
+        None => f(&mut psx.spu),
+    }
+}
+
+// Save the SPU state, wherever it currently runs. It's prefixed with its
+// length, for the SPU thread to be handed exactly its part when loading.
+pub fn save_state(psx: &mut Psx, w: &mut StateWriter) {
+    let state = with(psx, |spu| {
+        let mut w = StateWriter::new();
+        spu.save_state(&mut w);
+        w.into_inner()
+    });
+    w.write_u32(state.len() as u32);
+    w.write_bytes(&state);
+}
+
+pub fn load_state(psx: &mut Psx, r: &mut StateReader) -> Result<(), StateError> {
+    let len = r.read_u32()? as usize;
+    let state = r.read_bytes(len)?;
+    match psx.spu_thread.as_mut() {
+        Some(thread) => {
+            let state = state.to_vec();
+            thread.call(move |spu| spu.load_state(&mut StateReader::new(&state)))
+        }
+        None => psx.spu.load_state(&mut StateReader::new(state)),
+    }
+}
+
+// Move the SPU to its own thread or back onto the emulation thread
+pub fn set_threaded(psx: &mut Psx, threaded: bool) {
+    match (threaded, psx.spu_thread.is_some()) {
+        (true, false) => {
+            let spu = std::mem::take(&mut psx.spu);
+            psx.spu_thread = Some(SpuThread::spawn(spu));
+        }
+        (false, true) => {
+            if let Some(thread) = psx.spu_thread.take() {
+                let (spu, outputs) = thread.join();
+                psx.spu = spu;
+                for output in outputs {
+                    output_samples(psx, output);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn set_bit(reg: &mut u32, bit: usize, set: bool) {
+    if set {
+        *reg |= 1 << bit;
+    } else {
+        *reg &= !(1 << bit);
+    }
+}
+
+fn set_lo(reg: u32, val: u16) -> u32 {
+    (reg & 0xffff0000) | val as u32
+}
+
+fn set_hi(reg: u32, val: u16) -> u32 {
+    (reg & 0xffff) | (val as u32) << 16
+}
@@ -0,0 +1,207 @@
+use super::{Spu, NUM_VOICES};
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+// Samples generated before the worker is told to catch up
+const BATCH_SAMPLES: u32 = 32;
+
+// Work queued for the SPU thread. Register writes are ordered between the
+// sample runs, so each one lands on the same sample as it would inline.
+enum Command {
+    Store(u32, u16),
+    DmaWrite(u32),
+    CdSample(i16, i16),
+    // Generate samples, optionally recording each voice's output
+    Run { samples: u32, stems: bool },
+    // Run a closure on the SPU once everything before it was processed
+    Call(Box<dyn FnOnce(&mut Spu) + Send>),
+    Stop,
+}
+
+// Samples generated by one run
+pub struct Output {
+    pub samples: Vec<[i16; 2]>,
+    pub voices: Vec<[[i16; 2]; NUM_VOICES]>,
+    // Was the SPU IRQ raised during the run?
+    pub irq: bool,
+}
+
+// SPU running on its own thread. The emulation thread only waits for it on
+// register reads and, while SPU IRQs are enabled, on every sample.
+pub struct SpuThread {
+    commands: Sender<Command>,
+    output: Receiver<Output>,
+    worker: Option<JoinHandle<Spu>>,
+    // Samples due since the last run was queued
+    pending: u32,
+    // Runs queued whose output hasn't been received
+    outstanding: u32,
+    // Mirror of SPUCNT, to know when IRQs need lockstep execution
+    control: u16,
+    // Record each voice's output for the WAV dump
+    stems: bool,
+}
+
+impl SpuThread {
+    pub fn spawn(spu: Spu) -> Self {
+        let (commands, command_rx) = mpsc::channel();
+        let (output_tx, output) = mpsc::channel();
+        let control = spu.control;
+        let worker = thread::Builder::new()
+            .name("spu".into())
+            .spawn(move || run(spu, command_rx, output_tx))
+            .expect("failed to spawn SPU thread");
+        Self {
+            commands,
+            output,
+            worker: Some(worker),
+            pending: 0,
+            outstanding: 0,
+            control,
+            stems: false,
+        }
+    }
+
+    fn send(&self, command: Command) {
+        // The worker only exits on Stop, so this can't fail
+        let _ = self.commands.send(command);
+    }
+
+    pub fn store(&mut self, offset: u32, val: u16) {
+        if offset & 0x3fe == 0x1aa {
+            self.control = val;
+        }
+        self.send(Command::Store(offset, val));
+    }
+
+    pub fn dma_write(&mut self, val: u32) {
+        self.send(Command::DmaWrite(val));
+    }
+
+    pub fn push_cd_sample(&mut self, left: i16, right: i16) {
+        self.send(Command::CdSample(left, right));
+    }
+
+    // Account for one output sample. Returns the output of any finished runs.
+    pub fn tick(&mut self, stems: bool) -> Vec<Output> {
+        self.pending += 1;
+        self.stems = stems;
+        let lockstep = self.control & 0x40 != 0;
+        if lockstep || self.pending >= BATCH_SAMPLES {
+            self.flush();
+        }
+
+        let mut outputs = Vec::new();
+        if lockstep {
+            while self.outstanding > 0 {
+                outputs.push(self.recv());
+            }
+        }
+        outputs.extend(self.collect());
+        outputs
+    }
+
+    // Output of runs that finished so far, without waiting
+    pub fn collect(&mut self) -> Vec<Output> {
+        let mut outputs = Vec::new();
+        while let Ok(output) = self.output.try_recv() {
+            self.outstanding -= 1;
+            outputs.push(output);
+        }
+        outputs
+    }
+
+    // Output of all the samples due so far, waiting for the worker
+    pub fn finish(&mut self) -> Vec<Output> {
+        self.flush();
+        let mut outputs = Vec::new();
+        while self.outstanding > 0 {
+            outputs.push(self.recv());
+        }
+        outputs
+    }
+
+    // Queue the samples due so far
+    fn flush(&mut self) {
+        if self.pending > 0 {
+            self.send(Command::Run {
+                samples: self.pending,
+                stems: self.stems,
+            });
+            self.pending = 0;
+            self.outstanding += 1;
+        }
+    }
+
+    fn recv(&mut self) -> Output {
+        let output = self.output.recv().expect("SPU thread died");
+        self.outstanding -= 1;
+        output
+    }
+
+    // Run `f` on the SPU after all queued work, waiting for the result
+    pub fn call<R, F>(&mut self, f: F) -> R
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut Spu) -> R + Send + 'static,
+    {
+        self.flush();
+        let (tx, rx) = mpsc::channel();
+        self.send(Command::Call(Box::new(move |spu| {
+            let _ = tx.send(f(spu));
+        })));
+        rx.recv().expect("SPU thread died")
+    }
+
+    // Stop the worker, returning the SPU and the output it produced
+    pub fn join(mut self) -> (Spu, Vec<Output>) {
+        let outputs = self.finish();
+        self.send(Command::Stop);
+        let spu = self
+            .worker
+            .take()
+            .and_then(|worker| worker.join().ok())
+            .expect("SPU thread died");
+        (spu, outputs)
+    }
+}
+
+impl Drop for SpuThread {
+    fn drop(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            self.send(Command::Stop);
+            let _ = worker.join();
+        }
+    }
+}
+
+fn run(mut spu: Spu, commands: Receiver<Command>, output: Sender<Output>) -> Spu {
+    while let Ok(command) = commands.recv() {
+        match command {
+            Command::Store(offset, val) => spu.store(offset, val),
+            Command::DmaWrite(val) => spu.dma_write(val),
+            Command::CdSample(left, right) => spu.push_cd_sample(left, right),
+            Command::Run { samples, stems } => {
+                let mut out = Output {
+                    samples: Vec::with_capacity(samples as usize),
+                    voices: Vec::new(),
+                    irq: false,
+                };
+                for _ in 0..samples {
+                    let (left, right) = spu.tick();
+                    out.samples.push([left, right]);
+                    if stems {
+                        out.voices.push(spu.voice_mix);
+                    }
+                    out.irq |= spu.irq_pending;
+                    spu.irq_pending = false;
+                }
+                let _ = output.send(out);
+            }
+            Command::Call(f) => f(&mut spu),
+            Command::Stop => break,
+        }
+    }
+    spu
+}
@@ -0,0 +1,194 @@
+use super::envelope::{Adsr, Volume};
+use super::interpolation::{interpolate, Interpolation};
+use crate::psx::state::{Savestate, StateError, StateReader, StateWriter};
+
+// Samples per ADPCM block
+const BLOCK_SAMPLES: usize = 28;
+
+// Samples of the previous block kept for interpolation
+const HISTORY_SAMPLES: usize = 3;
+
+// ADPCM prediction filter coefficients
+const POS_TABLE: [i32; 5] = [0, 60, 115, 98, 122];
+const NEG_TABLE: [i32; 5] = [0, 0, -52, -55, -60];
+
+// ADPCM block flags
+const FLAG_LOOP_END: u8 = 1 << 0;
+const FLAG_LOOP_REPEAT: u8 = 1 << 1;
+const FLAG_LOOP_START: u8 = 1 << 2;
+
+#[derive(Clone, Copy)]
+pub struct Voice {
+    // 1F801C00h+N*10h Volume Left/Right
+    pub volume: [Volume; 2],
+    // 1F801C04h+N*10h ADPCM Sample Rate
+    pub pitch: u16,
+    // 1F801C06h+N*10h ADPCM Start Address (in bytes)
+    pub start_address: u32,
+    // 1F801C0Eh+N*10h ADPCM Repeat Address (in bytes)
+    pub repeat_address: u32,
+    // 1F801C08h+N*10h ADSR and current envelope volume
+    pub adsr: Adsr,
+    // Address of the block currently being played (in bytes)
+    pub current_address: u32,
+    // Pitch counter, 12 fractional bits
+    pub counter: u32,
+    // Flags of the current block
+    flags: u8,
+    // Last samples of the previous block followed by the decoded samples of
+    // the current block
+    decoded: [i16; HISTORY_SAMPLES + BLOCK_SAMPLES],
+    // Last two decoded samples, used by the prediction filter
+    history: [i16; 2],
+    // Output after the envelope, before volume (used for pitch modulation
+    // and the capture buffers)
+    pub output: i16,
+}
+
+impl Voice {
+    pub fn new() -> Self {
+        Self {
+            volume: [Volume::new(); 2],
+            pitch: 0,
+            start_address: 0,
+            repeat_address: 0,
+            adsr: Adsr::new(),
+            current_address: 0,
+            counter: 0,
+            flags: 0,
+            decoded: [0; HISTORY_SAMPLES + BLOCK_SAMPLES],
+            history: [0; 2],
+            output: 0,
+        }
+    }
+
+    pub fn key_on(&mut self, ram: &[u8]) {
+        self.current_address = self.start_address;
+        self.counter = 0;
+        self.history = [0; 2];
+        self.decoded = [0; HISTORY_SAMPLES + BLOCK_SAMPLES];
+        self.adsr.attack();
+        self.decode_block(ram);
+    }
+
+    pub fn key_off(&mut self) {
+        self.adsr.release();
+    }
+
+    // Current sample, before the envelope
+    pub fn sample(&self, mode: Interpolation) -> i16 {
+        let index = (self.counter >> 12) as usize;
+        interpolate(mode, &self.decoded[index..index + 4], self.counter)
+    }
+
+    // Advance the pitch counter by `step`. Returns true when the end of the
+    // current block was crossed.
+    pub fn advance(&mut self, step: u32) -> bool {
+        self.counter += step;
+        if (self.counter >> 12) as usize >= BLOCK_SAMPLES {
+            self.counter -= (BLOCK_SAMPLES as u32) << 12;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Move to the block following the current one and decode it. Returns true
+    // when the loop end flag was reached.
+    pub fn next_block(&mut self, ram: &[u8]) -> bool {
+        let loop_end = self.flags & FLAG_LOOP_END != 0;
+        if loop_end {
+            self.current_address = self.repeat_address;
+            if self.flags & FLAG_LOOP_REPEAT == 0 {
+                self.adsr.off();
+            }
+        } else {
+            self.current_address = (self.current_address + 16) & 0x7fff8;
+        }
+        self.decode_block(ram);
+        loop_end
+    }
+
+    fn decode_block(&mut self, ram: &[u8]) {
+        let addr = self.current_address as usize;
+        let mut block = [0u8; 16];
+        for (i, b) in block.iter_mut().enumerate() {
+            *b = ram[(addr + i) & 0x7ffff];
+        }
+
+        let mut shift = (block[0] & 0xf) as u32;
+        if shift > 12 {
+            shift = 9;
+        }
+        let filter = ((block[0] >> 4) & 7).min(4) as usize;
+        self.flags = block[1];
+        if self.flags & FLAG_LOOP_START != 0 {
+            self.repeat_address = self.current_address;
+        }
+
+        self.decoded
+            .copy_within(BLOCK_SAMPLES..BLOCK_SAMPLES + HISTORY_SAMPLES, 0);
+
+        for i in 0..BLOCK_SAMPLES {
+            let byte = block[2 + i / 2];
+            let nibble = if i & 1 == 0 { byte & 0xf } else { byte >> 4 };
+            let sample = (((nibble as u16) << 12) as i16 as i32) >> shift;
+            let predicted = (self.history[0] as i32 * POS_TABLE[filter]
+                + self.history[1] as i32 * NEG_TABLE[filter]
+                + 32)
+                >> 6;
+            let sample = (sample + predicted).clamp(-0x8000, 0x7fff) as i16;
+            self.history[1] = self.history[0];
+            self.history[0] = sample;
+            self.decoded[HISTORY_SAMPLES + i] = sample;
+        }
+    }
+}
+
+impl Savestate for Voice {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.volume[0].save_state(w);
+        self.volume[1].save_state(w);
+        w.write_u16(self.pitch);
+        w.write_u32(self.start_address);
+        w.write_u32(self.repeat_address);
+        self.adsr.save_state(w);
+        w.write_u32(self.current_address);
+        w.write_u32(self.counter);
+        w.write_u8(self.flags);
+        for &sample in self.decoded.iter() {
+            w.write_i16(sample);
+        }
+        w.write_i16(self.history[0]);
+        w.write_i16(self.history[1]);
+        w.write_i16(self.output);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.volume[0].load_state(r)?;
+        self.volume[1].load_state(r)?;
+        self.pitch = r.read_u16()?;
+        self.start_address = r.read_u32()? & 0x7fff8;
+        self.repeat_address = r.read_u32()? & 0x7fff8;
+        self.adsr.load_state(r)?;
+        self.current_address = r.read_u32()? & 0x7fff8;
+        self.counter = r.read_u32()?;
+        if (self.counter >> 12) as usize >= BLOCK_SAMPLES {
+            return Err(StateError::Invalid("voice pitch counter"));
+        }
+        self.flags = r.read_u8()?;
+        for sample in self.decoded.iter_mut() {
+            *sample = r.read_i16()?;
+        }
+        self.history[0] = r.read_i16()?;
+        self.history[1] = r.read_i16()?;
+        self.output = r.read_i16()?;
+        Ok(())
+    }
+}
+
+impl Default for Voice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,192 @@
+use std::fmt;
+
+// Errors while restoring serialized state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    // The state ended before all fields were read
+    UnexpectedEof,
+    // A field held a value that can't be restored
+    Invalid(&'static str),
+    // Saved by a newer version of the core, in a format this one doesn't know
+    NewerVersion { part: String, version: u32 },
+    // Saved by an old version of the core, in a format there's no migration
+    // from anymore
+    OlderVersion { part: String, version: u32 },
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::UnexpectedEof => write!(f, "unexpected end of state data"),
+            StateError::Invalid(what) => write!(f, "invalid state data: {}", what),
+            StateError::NewerVersion { part, version } => write!(
+                f,
+                "{} version {} is from a newer version of the emulator",
+                part, version
+            ),
+            StateError::OlderVersion { part, version } => {
+                write!(f, "{} version {} is too old to be loaded", part, version)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+// Components that can be saved to and restored from a binary state
+pub trait Savestate {
+    fn save_state(&self, w: &mut StateWriter);
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError>;
+}
+
+// Little endian binary state writer
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    // Writer reusing the memory of `buf`, whose contents are discarded
+    pub fn with_buffer(mut buf: Vec<u8>) -> Self {
+        buf.clear();
+        Self { buf }
+    }
+
+    pub fn write_u8(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+
+    pub fn write_bool(&mut self, val: bool) {
+        self.write_u8(val as u8);
+    }
+
+    pub fn write_u16(&mut self, val: u16) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn write_i16(&mut self, val: i16) {
+        self.write_u16(val as u16);
+    }
+
+    pub fn write_u32(&mut self, val: u32) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn write_i32(&mut self, val: i32) {
+        self.write_u32(val as u32);
+    }
+
+    pub fn write_u64(&mut self, val: u64) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    // Write what `f` writes prefixed with its length, for parts that may be
+    // skipped when loading
+    pub fn write_nested<F: FnOnce(&mut StateWriter)>(&mut self, f: F) {
+        let start = self.buf.len();
+        self.write_u32(0);
+        f(self);
+        let len = (self.buf.len() - start - 4) as u32;
+        self.buf[start..start + 4].copy_from_slice(&len.to_le_bytes());
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Little endian binary state reader
+pub struct StateReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let end = self.pos.checked_add(len).ok_or(StateError::UnexpectedEof)?;
+        let bytes = self
+            .buf
+            .get(self.pos..end)
+            .ok_or(StateError::UnexpectedEof)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    // Fill `dst` from the state
+    pub fn read_into(&mut self, dst: &mut [u8]) -> Result<(), StateError> {
+        let bytes = self.read_bytes(dst.len())?;
+        dst.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        let mut bytes = [0u8; 2];
+        self.read_into(&mut bytes)?;
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn read_i16(&mut self) -> Result<i16, StateError> {
+        Ok(self.read_u16()? as i16)
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        let mut bytes = [0u8; 4];
+        self.read_into(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32, StateError> {
+        Ok(self.read_u32()? as i32)
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0u8; 8];
+        self.read_into(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    // A part written by write_nested, to be read on its own
+    pub fn read_nested(&mut self) -> Result<StateReader<'a>, StateError> {
+        let len = self.read_u32()? as usize;
+        Ok(StateReader::new(self.read_bytes(len)?))
+    }
+
+    // Bytes left to read
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+}
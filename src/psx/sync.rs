@@ -0,0 +1,160 @@
+use super::audio::{Resampler, SampleBuffer, SPU_SAMPLE_RATE};
+use super::spu;
+use super::{run_ahead, Psx};
+
+// Host audio rate assumed until the frontend sets one
+const DEFAULT_HOST_RATE: u32 = 48000;
+
+// Most frames audio-driven sync runs in a call, a second's worth
+const MAX_FRAMES_PER_RUN: u32 = 60;
+
+// How emulation speed is tied to the host
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SyncMode {
+    // One guest frame per host frame, with audio resampled to track the
+    // host's audio buffer. Smooth video, slight pitch variation.
+    #[default]
+    Video,
+    // Emulate whole frames until the host has the audio it asked for, up
+    // to a second's worth. Perfect audio, but a call completes no frame
+    // when the host already has enough, in which case it shows the
+    // previous frame again.
+    Audio,
+    // One guest frame per call with no audio adjustment
+    FreeRun,
+}
+
+// Host state reported before each run
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HostStatus {
+    // Fill level of the host audio buffer in [0, 1], for video-driven sync
+    pub audio_fill: f64,
+    // Interleaved samples the host wants to receive, for audio-driven sync
+    pub audio_wanted: usize,
+}
+
+pub struct Sync {
+    mode: SyncMode,
+    host_rate: u32,
+    // Converts 44100Hz output to the host rate in video- and audio-driven
+    // modes
+    resampler: Resampler,
+    // Output at the host rate waiting to be taken, in those modes
+    host_audio: SampleBuffer,
+    input: Vec<i16>,
+    output: Vec<i16>,
+}
+
+impl Sync {
+    pub fn new() -> Self {
+        Self {
+            mode: SyncMode::default(),
+            host_rate: DEFAULT_HOST_RATE,
+            resampler: Resampler::new(DEFAULT_HOST_RATE),
+            host_audio: SampleBuffer::with_capacity(DEFAULT_HOST_RATE as usize),
+            input: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
+    pub fn mode(&self) -> SyncMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: SyncMode) {
+        self.mode = mode;
+        self.resampler.reset();
+        self.host_audio.clear();
+    }
+
+    pub fn set_host_rate(&mut self, rate: u32) {
+        self.host_rate = rate;
+        self.resampler.set_host_rate(rate);
+        // Half a second, as for the 44100Hz output
+        self.host_audio = SampleBuffer::with_capacity(rate as usize);
+    }
+
+    // Is the output given at the host rate rather than at 44100Hz?
+    fn host_rate_output(&self) -> bool {
+        self.mode != SyncMode::FreeRun
+    }
+}
+
+impl Default for Sync {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Run the core for one host frame according to the sync mode, in whole
+// guest frames so input set between calls lands on frame boundaries.
+// Returns the number of guest frames completed.
+pub fn run(psx: &mut Psx, host: HostStatus) -> u32 {
+    match psx.sync.mode {
+        SyncMode::Video => {
+            run_ahead::run_frame(psx);
+            resample_output(psx, host.audio_fill);
+            1
+        }
+        SyncMode::Audio => {
+            // More than the queue holds would never be reached
+            let wanted = host.audio_wanted.min(psx.sync.host_audio.capacity());
+            let mut frames = 0;
+            while psx.sync.host_audio.len() < wanted && frames < MAX_FRAMES_PER_RUN {
+                run_ahead::run_frame(psx);
+                // The host's buffer is kept filled, no rate adjustment
+                resample_output(psx, 0.5);
+                frames += 1;
+            }
+            frames
+        }
+        SyncMode::FreeRun => {
+            run_ahead::run_frame(psx);
+            1
+        }
+    }
+}
+
+// Interleaved samples waiting to be taken with take_audio, at its rate
+pub fn audio_available(psx: &Psx) -> usize {
+    let sync = &psx.sync;
+    match sync.host_rate_output() {
+        // Counting the 44100Hz output not converted yet
+        true => {
+            let pending = psx.audio.len() as u64 * sync.host_rate as u64 / SPU_SAMPLE_RATE as u64;
+            sync.host_audio.len() + (pending as usize & !1)
+        }
+        false => psx.audio.len(),
+    }
+}
+
+// Take output into `out`, at the host rate in video- and audio-driven sync
+// and at 44100Hz in free-running sync. Returns the number of samples.
+pub fn take_audio(psx: &mut Psx, out: &mut [i16]) -> usize {
+    match psx.sync.host_rate_output() {
+        true => {
+            // Output of frames run with run_frame rather than run_host_frame
+            resample_output(psx, 0.5);
+            psx.sync.host_audio.take(out)
+        }
+        false => {
+            spu::collect(psx);
+            psx.audio.take(out)
+        }
+    }
+}
+
+// Convert the 44100Hz output produced since the last call to the host
+// rate, adding it to the host rate queue
+fn resample_output(psx: &mut Psx, fill: f64) {
+    spu::collect(psx);
+    let sync = &mut psx.sync;
+    sync.input.resize(psx.audio.len(), 0);
+    let len = psx.audio.take(&mut sync.input);
+    sync.output.clear();
+    sync.resampler
+        .process(&sync.input[..len], fill, &mut sync.output);
+    for frame in sync.output.chunks_exact(2) {
+        sync.host_audio.push(frame[0], frame[1]);
+    }
+}
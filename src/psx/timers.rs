@@ -0,0 +1,438 @@
+use super::gpu::Gpu;
+use super::irq::Interrupt;
+use super::scheduler::Event;
+use super::state::{Savestate, StateError, StateReader, StateWriter};
+use super::Psx;
+
+// Number of root counters
+const TIMERS: usize = 3;
+
+// Scheduler events bringing each timer up to date when it may raise an IRQ
+const EVENTS: [Event; TIMERS] = [Event::Timer0, Event::Timer1, Event::Timer2];
+
+const INTERRUPTS: [Interrupt; TIMERS] = [Interrupt::Timer0, Interrupt::Timer1, Interrupt::Timer2];
+
+// Mode register bits
+const MODE_RESET_AT_TARGET: u16 = 0x0008;
+const MODE_IRQ_AT_TARGET: u16 = 0x0010;
+const MODE_IRQ_AT_OVERFLOW: u16 = 0x0020;
+const MODE_IRQ_REPEAT: u16 = 0x0040;
+const MODE_IRQ_TOGGLE: u16 = 0x0080;
+const MODE_IRQ: u16 = 0x0400;
+const MODE_REACHED_TARGET: u16 = 0x0800;
+const MODE_REACHED_OVERFLOW: u16 = 0x1000;
+
+// Root counter. Counters aren't ticked every cycle: they are brought up to
+// date from the time elapsed since the last update whenever they're
+// accessed, and by a scheduler event when they're due to raise an IRQ.
+#[derive(Clone, Copy, Default)]
+struct Timer {
+    // 1F801100h+N*10h current value
+    counter: u16,
+    // 1F801104h+N*10h counter mode
+    mode: u16,
+    // 1F801108h+N*10h counter target
+    target: u16,
+    // Timestamp the counter was last brought up to date at
+    updated: u64,
+    // An IRQ was raised since the mode was written, for one-shot mode
+    irq_done: bool,
+    // Waiting for the first blank in synchronization mode 3
+    waiting: bool,
+}
+
+impl Timer {
+    // Synchronization mode (bits 1-2) when enabled (bit 0)
+    fn sync_mode(&self) -> Option<u16> {
+        match self.mode & 1 {
+            0 => None,
+            _ => Some((self.mode >> 1) & 3),
+        }
+    }
+
+    // Clock source (bits 8-9)
+    fn clock_source(&self) -> u16 {
+        (self.mode >> 8) & 3
+    }
+
+    // Timer 2 has no blank to synchronize with: its modes 0 and 3 stop the
+    // counter at its current value, 1 and 2 let it run freely
+    fn stopped(&self, index: usize) -> bool {
+        index == 2 && matches!(self.sync_mode(), Some(0) | Some(3))
+    }
+
+    // Is the counter stopped by its synchronization mode? `blank` is the
+    // hblank state for timer 0 and the vblank state for timer 1.
+    fn paused(&self, index: usize, blank: bool) -> bool {
+        if index == 2 {
+            return self.stopped(index);
+        }
+        match self.sync_mode() {
+            None => false,
+            // Pause during blank
+            Some(0) => blank,
+            // Reset at blank start only
+            Some(1) => false,
+            // Reset at blank start and pause outside of blank
+            Some(2) => !blank,
+            // Pause until the first blank, then run freely
+            _ => self.waiting,
+        }
+    }
+
+    // Blank started: reset the counter or stop waiting depending on the
+    // synchronization mode
+    fn blank_started(&mut self) {
+        match self.sync_mode() {
+            Some(1) | Some(2) => self.counter = 0,
+            Some(3) => self.waiting = false,
+            _ => {}
+        }
+    }
+
+    // Value after which the counter wraps to 0: the target when resetting at
+    // the target, unless the counter is already past it
+    fn limit(&self) -> u16 {
+        if self.mode & MODE_RESET_AT_TARGET != 0 && self.counter <= self.target {
+            self.target
+        } else {
+            0xffff
+        }
+    }
+
+    // Set the reached flags for the current value, and signal the IRQ if
+    // one of them is enabled. Returns true if I_STAT is to be set.
+    fn check_reached(&mut self) -> bool {
+        let mut irq = false;
+        if self.counter == self.target {
+            self.mode |= MODE_REACHED_TARGET;
+            irq |= self.mode & MODE_IRQ_AT_TARGET != 0;
+        }
+        if self.counter == 0xffff {
+            self.mode |= MODE_REACHED_OVERFLOW;
+            irq |= self.mode & MODE_IRQ_AT_OVERFLOW != 0;
+        }
+        irq && self.signal_irq()
+    }
+
+    // Signal an IRQ on bit 10, which is active low. In pulse mode it only
+    // drops for a few cycles, too short to read it as 0; in toggle mode it
+    // flips on every IRQ. I_STAT is set on the falling edge. One-shot mode
+    // signals once until the mode is written again.
+    fn signal_irq(&mut self) -> bool {
+        if self.mode & MODE_IRQ_REPEAT == 0 && self.irq_done {
+            return false;
+        }
+        self.irq_done = true;
+        if self.mode & MODE_IRQ_TOGGLE != 0 {
+            self.mode ^= MODE_IRQ;
+            self.mode & MODE_IRQ == 0
+        } else {
+            true
+        }
+    }
+
+    // Count `ticks` increments. Returns true if an IRQ condition was met.
+    fn advance(&mut self, mut ticks: u64) -> bool {
+        let mut irq = false;
+        while ticks > 0 {
+            let limit = self.limit();
+            if self.counter == limit {
+                self.counter = 0;
+                ticks -= 1;
+                irq |= self.check_reached();
+                // Whole periods from 0 change nothing more when they don't
+                // signal IRQs
+                let period = self.limit() as u64 + 1;
+                if ticks > period && self.ticks_to_irq().is_none() {
+                    ticks = period + ticks % period;
+                }
+                continue;
+            }
+            // Stop at the target on the way to the limit
+            let mark = if self.target > self.counter && self.target < limit {
+                self.target
+            } else {
+                limit
+            };
+            let step = ticks.min((mark - self.counter) as u64);
+            self.counter += step as u16;
+            ticks -= step;
+            irq |= self.check_reached();
+        }
+        irq
+    }
+
+    // Increments until the counter next equals `value`, if it ever does
+    fn ticks_until(&self, value: u16) -> Option<u64> {
+        let limit = self.limit();
+        if value > self.counter && value <= limit {
+            return Some((value - self.counter) as u64);
+        }
+        // Up to the limit, then from 0
+        let to_zero = (limit - self.counter) as u64 + 1;
+        let limit = if self.mode & MODE_RESET_AT_TARGET != 0 {
+            self.target
+        } else {
+            0xffff
+        };
+        if value <= limit {
+            Some(to_zero + value as u64)
+        } else {
+            None
+        }
+    }
+
+    // Increments until the next enabled IRQ condition
+    fn ticks_to_irq(&self) -> Option<u64> {
+        if self.mode & MODE_IRQ_REPEAT == 0 && self.irq_done {
+            return None;
+        }
+        let target = match self.mode & MODE_IRQ_AT_TARGET {
+            0 => None,
+            _ => self.ticks_until(self.target),
+        };
+        let overflow = match self.mode & MODE_IRQ_AT_OVERFLOW {
+            0 => None,
+            _ => self.ticks_until(0xffff),
+        };
+        match (target, overflow) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+pub struct Timers {
+    timers: [Timer; TIMERS],
+    // Blanking state timers 0 and 1 synchronize with. Hblank is only
+    // followed while timer 0 needs it.
+    hblank: bool,
+    vblank: bool,
+}
+
+impl Timers {
+    pub fn new() -> Self {
+        Self {
+            timers: [Timer::default(); TIMERS],
+            hblank: false,
+            vblank: false,
+        }
+    }
+}
+
+impl Savestate for Timers {
+    fn save_state(&self, w: &mut StateWriter) {
+        for timer in self.timers.iter() {
+            w.write_u16(timer.counter);
+            w.write_u16(timer.mode);
+            w.write_u16(timer.target);
+            w.write_u64(timer.updated);
+            w.write_bool(timer.irq_done);
+            w.write_bool(timer.waiting);
+        }
+        w.write_bool(self.hblank);
+        w.write_bool(self.vblank);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        for timer in self.timers.iter_mut() {
+            timer.counter = r.read_u16()?;
+            timer.mode = r.read_u16()?;
+            timer.target = r.read_u16()?;
+            timer.updated = r.read_u64()?;
+            timer.irq_done = r.read_bool()?;
+            timer.waiting = r.read_bool()?;
+        }
+        self.hblank = r.read_bool()?;
+        self.vblank = r.read_bool()?;
+        Ok(())
+    }
+}
+
+impl Default for Timers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Does timer `index` count the system clock? Otherwise timer 0 counts dots,
+// timer 1 hblanks and timer 2 the system clock divided by 8.
+fn counts_sysclk(index: usize, source: u16) -> bool {
+    match index {
+        2 => source & 2 == 0,
+        _ => source & 1 == 0,
+    }
+}
+
+// Value of the clock timer `index` counts at CPU timestamp `time`
+fn clock(gpu: &Gpu, index: usize, source: u16, time: u64) -> u64 {
+    if counts_sysclk(index, source) {
+        time
+    } else if index == 2 {
+        time / 8
+    } else if index == 0 {
+        gpu.dots(time)
+    } else {
+        gpu.hblanks(time)
+    }
+}
+
+// CPU cycles from `now` until timer `index` has counted `ticks` more
+fn cycles_for(gpu: &Gpu, index: usize, source: u16, now: u64, ticks: u64) -> u64 {
+    if counts_sysclk(index, source) {
+        ticks
+    } else if index == 2 {
+        (now / 8 + ticks) * 8 - now
+    } else if index == 0 {
+        gpu.cycles_until_dots(now, ticks)
+    } else {
+        gpu.cycles_until_hblanks(now, ticks)
+    }
+}
+
+// Bring timer `index` up to the current time, raising its IRQ if due
+fn update(psx: &mut Psx, index: usize) {
+    let now = psx.scheduler.now();
+    let blank = match index {
+        0 => psx.timers.hblank,
+        1 => psx.timers.vblank,
+        _ => false,
+    };
+    let timer = &mut psx.timers.timers[index];
+    let source = timer.clock_source();
+    let ticks = clock(&psx.gpu, index, source, now) - clock(&psx.gpu, index, source, timer.updated);
+    timer.updated = now;
+    if !timer.paused(index, blank) && timer.advance(ticks) {
+        psx.irq.request(INTERRUPTS[index]);
+    }
+}
+
+// Bring all timers up to date
+pub fn update_all(psx: &mut Psx) {
+    for index in 0..TIMERS {
+        update(psx, index);
+    }
+}
+
+// Schedule the update at the next IRQ of timer `index`. Pauses only make it
+// come early, the update then finds nothing to do and schedules again.
+fn reschedule(psx: &mut Psx, index: usize) {
+    let event = EVENTS[index];
+    let timer = &psx.timers.timers[index];
+    // A stopped timer 2 waits for its mode to change
+    let ticks = match timer.stopped(index) {
+        true => None,
+        false => timer.ticks_to_irq(),
+    };
+    match ticks {
+        Some(ticks) => {
+            let now = psx.scheduler.now();
+            let cycles = cycles_for(&psx.gpu, index, timer.clock_source(), now, ticks);
+            psx.scheduler.schedule(event, cycles);
+        }
+        None => psx.scheduler.cancel(event),
+    }
+}
+
+// Follow hblank edges while timer 0 synchronizes with them
+fn schedule_hblank(psx: &mut Psx) {
+    if psx.timers.timers[0].sync_mode().is_some() {
+        let now = psx.scheduler.now();
+        let cycles = psx.gpu.cycles_until_hblank_edge(now);
+        psx.scheduler.schedule(Event::Hblank, cycles);
+    } else {
+        psx.scheduler.cancel(Event::Hblank);
+    }
+}
+
+// Hblank started or ended, for timer 0's synchronization
+pub fn hblank_edge(psx: &mut Psx, start: bool) {
+    update(psx, 0);
+    psx.timers.hblank = start;
+    if start {
+        psx.timers.timers[0].blank_started();
+    }
+    reschedule(psx, 0);
+    schedule_hblank(psx);
+}
+
+// Vblank started or ended, for timer 1's synchronization
+pub fn vblank_edge(psx: &mut Psx, start: bool) {
+    update(psx, 1);
+    psx.timers.vblank = start;
+    if start {
+        psx.timers.timers[1].blank_started();
+    }
+    reschedule(psx, 1);
+}
+
+// The video timing changed: pick up the blanking state and reschedule the
+// timers counting it
+pub fn timing_changed(psx: &mut Psx) {
+    let now = psx.scheduler.now();
+    psx.timers.hblank = psx.gpu.in_hblank(now);
+    psx.timers.vblank = psx.gpu.in_vblank(now);
+    for index in 0..TIMERS {
+        reschedule(psx, index);
+    }
+    schedule_hblank(psx);
+}
+
+// Run a timer's scheduler event
+pub fn handle_event(psx: &mut Psx, event: Event) {
+    if let Some(index) = EVENTS.iter().position(|&e| e == event) {
+        update(psx, index);
+        reschedule(psx, index);
+    }
+}
+
+// Read a timer register at `offset` from 1F801100h
+pub fn load(psx: &mut Psx, offset: u32) -> u32 {
+    let index = (offset >> 4) as usize;
+    if index >= TIMERS {
+        return 0;
+    }
+    update(psx, index);
+    let timer = &mut psx.timers.timers[index];
+    match offset & 0xc {
+        0x0 => timer.counter as u32,
+        0x4 => {
+            // The reached flags clear once read
+            let mode = timer.mode;
+            timer.mode &= !(MODE_REACHED_TARGET | MODE_REACHED_OVERFLOW);
+            mode as u32
+        }
+        0x8 => timer.target as u32,
+        _ => 0,
+    }
+}
+
+// Write a timer register at `offset` from 1F801100h
+pub fn store(psx: &mut Psx, offset: u32, val: u32) {
+    let index = (offset >> 4) as usize;
+    if index >= TIMERS {
+        return;
+    }
+    update(psx, index);
+    let timer = &mut psx.timers.timers[index];
+    match offset & 0xc {
+        0x0 => timer.counter = val as u16,
+        0x4 => {
+            // Writing the mode resets the counter and the IRQ bit, and
+            // rearms one-shot IRQs
+            let flags = timer.mode & (MODE_REACHED_TARGET | MODE_REACHED_OVERFLOW);
+            timer.mode = (val as u16 & 0x3ff) | flags | MODE_IRQ;
+            timer.counter = 0;
+            timer.irq_done = false;
+            timer.waiting = true;
+            if index == 0 {
+                psx.timers.hblank = psx.gpu.in_hblank(psx.scheduler.now());
+                schedule_hblank(psx);
+            }
+        }
+        0x8 => timer.target = val as u16,
+        _ => {}
+    }
+    reschedule(psx, index);
+}
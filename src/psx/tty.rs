@@ -0,0 +1,106 @@
+use super::Psx;
+
+use std::collections::VecDeque;
+
+// Receives the characters programs print, through the BIOS or the DUART
+pub type TtySink = Box<dyn FnMut(u8) + Send>;
+
+// Status of DUART channel A: transmitter ready and empty, nothing received
+const DUART_STATUS: u8 = 0x0c;
+
+// Complete lines kept for run_until_event, older ones are dropped
+const MAX_LINES: usize = 64;
+// Longest line kept, longer ones are split
+const MAX_LINE_LEN: usize = 1024;
+
+// Registers of the SCN2681 DUART on the expansion 2 port of dev kits, at
+// 1F802020h. Only channel A's transmitter is emulated, as debug output.
+const DUART_STATUS_A: u32 = 0x1;
+const DUART_TX_A: u32 = 0x3;
+
+// Debug output of programs
+pub struct Tty {
+    sink: Option<TtySink>,
+    // Line being printed, and the lines printed since run_until_event last
+    // reported one
+    line: Vec<u8>,
+    lines: VecDeque<String>,
+}
+
+impl Tty {
+    pub fn new() -> Self {
+        Self {
+            sink: None,
+            line: Vec::new(),
+            lines: VecDeque::new(),
+        }
+    }
+
+    pub fn set_sink(&mut self, sink: Option<TtySink>) {
+        self.sink = sink;
+    }
+
+    pub fn putchar(&mut self, c: u8) {
+        if let Some(sink) = self.sink.as_mut() {
+            sink(c);
+        }
+        match c {
+            b'\r' => {}
+            b'\n' => self.end_line(),
+            _ => {
+                self.line.push(c);
+                if self.line.len() >= MAX_LINE_LEN {
+                    self.end_line();
+                }
+            }
+        }
+    }
+
+    fn end_line(&mut self) {
+        if self.lines.len() == MAX_LINES {
+            self.lines.pop_front();
+        }
+        let line = String::from_utf8_lossy(&self.line).into_owned();
+        self.lines.push_back(line);
+        self.line.clear();
+    }
+
+    // Oldest line printed and not taken yet, without its newline
+    pub fn take_line(&mut self) -> Option<String> {
+        self.lines.pop_front()
+    }
+}
+
+impl Default for Tty {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Catch the BIOS putchar calls, A0h 3Ch and B0h 3Dh, as the CPU enters the
+// function tables. The BIOS still runs them.
+pub fn bios_call(psx: &mut Psx) {
+    let function = psx.cpu.regs[9];
+    let putchar = match psx.cpu.pc & 0x1fffffff {
+        0xa0 => function == 0x3c,
+        0xb0 => function == 0x3d,
+        _ => false,
+    };
+    if putchar {
+        let c = psx.cpu.regs[4] as u8;
+        psx.tty.putchar(c);
+    }
+}
+
+pub fn duart_load(_psx: &mut Psx, offset: u32) -> u8 {
+    match offset {
+        DUART_STATUS_A => DUART_STATUS,
+        _ => 0,
+    }
+}
+
+pub fn duart_store(psx: &mut Psx, offset: u32, val: u8) {
+    if offset == DUART_TX_A {
+        psx.tty.putchar(val);
+    }
+}
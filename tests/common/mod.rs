@@ -0,0 +1,20 @@
+// Helpers shared by the integration tests
+
+use psx::psx::exe::Exe;
+
+// Bare-metal executable, with a gp that can tell instances apart
+pub fn test_exe(gp: u32) -> Exe {
+    let mut data = vec![0u8; 0x1000];
+    data[..8].copy_from_slice(b"PS-X EXE");
+    for &(offset, val) in [
+        (0x10, 0x80010000),
+        (0x14, gp),
+        (0x18, 0x80010000),
+        (0x1c, 0x800),
+    ]
+    .iter()
+    {
+        data[offset..offset + 4].copy_from_slice(&u32::to_le_bytes(val));
+    }
+    Exe::parse(&data).unwrap()
+}
@@ -0,0 +1,175 @@
+// Deterministic mode: a movie played twice gives the same machine, frame by
+// frame, even with the SPU on its own thread, and so does going back to a
+// snapshot. Runs that differ are told apart by component.
+
+mod common;
+
+use common::test_exe;
+use psx::psx::divergence::{self, Divergence};
+use psx::psx::movie::{Desync, Movie, MovieMode};
+use psx::psx::sio::{Button, DigitalPad, InputState};
+use psx::psx::sync::SyncMode;
+use psx::psx::Psx;
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+fn booted() -> Psx {
+    let mut psx = Psx::new();
+    psx.set_deterministic(true);
+    psx.set_spu_threaded(true);
+    // The SPU's own output, without host rate conversion
+    psx.set_sync_mode(SyncMode::FreeRun);
+    psx.set_controller(0, Some(Box::new(DigitalPad::new())));
+    psx.boot_exe(&test_exe(0)).unwrap();
+    psx
+}
+
+fn hash<T: Hash + ?Sized>(data: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Whole machine hash, and the number and hash of the audio samples
+// produced, of each frame. The machine hash covers the frame counter and
+// timers, so it changes every frame.
+fn frame_hashes(psx: &mut Psx) -> (u64, usize, u64) {
+    let audio = psx.audio_samples_available();
+    let mut samples = vec![0; audio];
+    psx.take_audio_samples(&mut samples);
+    (hash(psx.snapshot().as_bytes()), audio, hash(&samples[..]))
+}
+
+fn record(frames: u64) -> (Movie, Vec<(u64, usize, u64)>) {
+    let mut psx = booted();
+    psx.start_recording(false).unwrap();
+    let mut hashes = Vec::new();
+    for frame in 0..frames {
+        let mut input = InputState::new();
+        input.set(Button::Cross, frame % 3 == 0);
+        input.set(Button::Left, frame % 5 < 2);
+        psx.set_input(0, &input);
+        psx.run_frame();
+        hashes.push(frame_hashes(&mut psx));
+    }
+    (psx.stop_movie().unwrap(), hashes)
+}
+
+fn play(movie: &Movie) -> Vec<(u64, usize, u64)> {
+    let mut psx = booted();
+    psx.play_movie(movie.clone()).unwrap();
+    let mut hashes = Vec::new();
+    while psx.movie_status().unwrap().mode == MovieMode::Playing {
+        psx.run_frame();
+        hashes.push(frame_hashes(&mut psx));
+    }
+    assert_eq!(psx.movie_status().unwrap().desync, None);
+    hashes
+}
+
+#[test]
+fn movie_replays_identically() {
+    let (movie, recorded) = record(40);
+    let mut file = Vec::new();
+    movie.write(&mut file).unwrap();
+    let movie = Movie::read(&file[..]).unwrap();
+
+    let first = play(&movie);
+    let second = play(&movie);
+    assert_eq!(first.len(), 40);
+    // Every frame leaves the machine in a new state, so equal hashes mean
+    // the runs went the same way rather than nothing happened
+    for (frame, pair) in first.windows(2).enumerate() {
+        assert_ne!(pair[0].0, pair[1].0, "frame {} changed nothing", frame + 1);
+        assert!(pair[1].1 > 0, "frame {} produced no audio", frame + 1);
+    }
+    for (frame, hashes) in first.iter().enumerate() {
+        assert_eq!(*hashes, second[frame], "runs differ at frame {}", frame);
+        // Playback sets the input of the next frame as a frame ends, the
+        // recording when the frontend does, so controllers can differ
+        let (_, audio, samples) = *hashes;
+        let (_, recorded_audio, recorded_samples) = recorded[frame];
+        assert_eq!(
+            (audio, samples),
+            (recorded_audio, recorded_samples),
+            "differs from the recording at frame {}",
+            frame
+        );
+    }
+}
+
+#[test]
+fn movie_reports_desync() {
+    let (mut movie, _) = record(125);
+    assert_eq!(
+        movie.checks.iter().map(|check| check.0).collect::<Vec<_>>(),
+        [60, 120]
+    );
+    movie.checks[1].1 ^= 1;
+    let mut psx = booted();
+    psx.play_movie(movie).unwrap();
+    for _ in 0..125 {
+        psx.run_frame();
+    }
+    let desync = psx.movie_status().unwrap().desync;
+    assert_eq!(
+        desync,
+        Some(Desync {
+            last_match: 60,
+            frame: 120
+        })
+    );
+}
+
+#[test]
+fn restored_snapshot_runs_identically() {
+    let mut psx = booted();
+    for _ in 0..10 {
+        psx.run_frame();
+    }
+    frame_hashes(&mut psx);
+    let snapshot = psx.snapshot();
+    let first: Vec<_> = (0..10)
+        .map(|_| {
+            psx.run_frame();
+            frame_hashes(&mut psx)
+        })
+        .collect();
+    psx.restore(&snapshot).unwrap();
+    let second: Vec<_> = (0..10)
+        .map(|_| {
+            psx.run_frame();
+            frame_hashes(&mut psx)
+        })
+        .collect();
+    assert_eq!(first, second);
+}
+
+#[test]
+fn divergence_is_found_by_component() {
+    let (movie, _) = record(20);
+    assert_eq!(
+        divergence::from_movie(&mut booted(), &mut booted(), &movie).unwrap(),
+        None
+    );
+
+    let mut a = booted();
+    let mut b = booted();
+    for _ in 0..10 {
+        a.run_frame();
+    }
+    let snapshot = a.snapshot();
+    a.store::<u32>(0x80000100, 0x12345678);
+    let changed = a.snapshot();
+    let same = divergence::from_snapshots(&mut a, &mut b, (&snapshot, &snapshot), 5, |_, _| {});
+    assert_eq!(same.unwrap(), None);
+    let changed = divergence::from_snapshots(&mut a, &mut b, (&snapshot, &changed), 5, |_, _| {});
+    assert_eq!(
+        changed.unwrap(),
+        Some(Divergence {
+            frame: 10,
+            components: vec!["RAM".to_string()]
+        })
+    );
+}
@@ -0,0 +1,84 @@
+// Several consoles in one process: each has its own state, whether they run
+// on one thread or side by side on several.
+
+mod common;
+
+use common::test_exe;
+use psx::psx::Psx;
+
+use std::thread;
+
+fn booted(gp: u32) -> Psx {
+    let mut psx = Psx::new();
+    psx.boot_exe(&test_exe(gp)).unwrap();
+    psx
+}
+
+#[test]
+fn instances_run_concurrently() {
+    let threads: Vec<_> = (0..4u32)
+        .map(|i| {
+            thread::spawn(move || {
+                let mut psx = booted(0x80100000 + i);
+                psx.set_spu_threaded(i % 2 == 1);
+                // A different amount of work on each thread
+                let mut last = 0;
+                for _ in 0..5 + i {
+                    last = psx.run_frame().frame;
+                }
+                (i, last, psx.cpu.regs[28], psx.snapshot())
+            })
+        })
+        .collect();
+    let results: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+    for (i, frames, gp, _) in results.iter() {
+        assert_eq!(*frames, 5 + *i as u64);
+        assert_eq!(*gp, 0x80100000 + i);
+    }
+
+    // Running the same program alone gives the same state as it had while
+    // the others ran next to it
+    let (i, _, _, snapshot) = &results[2];
+    let mut psx = booted(0x80100000 + i);
+    for _ in 0..5 + i {
+        psx.run_frame();
+    }
+    assert_eq!(psx.snapshot().as_bytes(), snapshot.as_bytes());
+}
+
+#[test]
+fn interleaved_instances_are_independent() {
+    let mut a = booted(0x80100000);
+    let mut b = booted(0x80100000);
+    for _ in 0..3 {
+        a.run_frame();
+    }
+    // Writing to one console's memory leaves the other's alone
+    a.store(0x80020000, 0x12345678u32);
+    assert_eq!(b.load::<u32>(0x80020000), 0);
+
+    for _ in 0..3 {
+        b.run_frame();
+    }
+    b.store(0x80020000, 0x12345678u32);
+    assert_eq!(a.snapshot().as_bytes(), b.snapshot().as_bytes());
+
+    a.run_frame();
+    assert_ne!(a.snapshot().as_bytes(), b.snapshot().as_bytes());
+}
+
+#[test]
+fn instances_move_between_threads() {
+    let mut psx = booted(0x80100000);
+    psx.run_frame();
+    let mut psx = thread::spawn(move || {
+        psx.run_frame();
+        psx
+    })
+    .join()
+    .unwrap();
+    let mut other = booted(0x80100000);
+    other.run_frame();
+    other.run_frame();
+    assert_eq!(psx.snapshot().as_bytes(), other.snapshot().as_bytes());
+}